@@ -0,0 +1,91 @@
+//! Compares the data path of a write and a read of 1 MiB, from a received gRPC message to
+//! SimpleFakeDevice and back, with and without copying the blocks.
+//!
+//! `copy` follows the path before DataBlock was reference counted, which copied each block out
+//! of the message, into the device and back into the response.
+//!
+//! Blocks sliced out of a message are not aligned, so devices opened with O_DIRECT (raw) still
+//! copy them once into an aligned buffer. Blocks those devices read are aligned and are written
+//! without copying.
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use prost::Message;
+
+use ministore::block_device::simple_fake_device::SimpleFakeDevice;
+use ministore::block_device::BlockDevice;
+use ministore::block_device_common::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
+use ministore::grpc_server::ministore_proto::{Data, WriteRequest};
+
+const NUM_BLOCKS: u64 = 256;
+
+fn encoded_write_request() -> Bytes {
+    let request = WriteRequest {
+        name: "bench".to_string(),
+        lba: 0,
+        num_blocks: NUM_BLOCKS,
+        data: Some(Data {
+            data: (0..NUM_BLOCKS)
+                .map(|lba| Bytes::from(vec![lba as u8; DEFAULT_BLOCK_SIZE]))
+                .collect(),
+            checksums: Vec::new(),
+        }),
+    };
+    Bytes::from(request.encode_to_vec())
+}
+
+fn write_and_read(device: &mut SimpleFakeDevice, message: &Bytes, copy: bool) -> Data {
+    let request = WriteRequest::decode(message.clone()).unwrap();
+    let blocks = request
+        .data
+        .unwrap()
+        .data
+        .into_iter()
+        .map(|block| match copy {
+            true => DataBlock::from(block.to_vec()),
+            false => DataBlock::from(block),
+        })
+        .collect();
+    device
+        .write(request.lba, request.num_blocks, blocks)
+        .unwrap();
+
+    let blocks = device.read(request.lba, request.num_blocks).unwrap();
+    Data {
+        checksums: Vec::new(),
+        data: blocks
+            .into_iter()
+            .map(|block| match copy {
+                true => Bytes::from(block.as_slice().to_vec()),
+                false => block.0,
+            })
+            .collect(),
+    }
+}
+
+fn data_path(c: &mut Criterion) {
+    let location = std::env::temp_dir();
+    let mut device = SimpleFakeDevice::new(
+        "ministore_data_path_bench".to_string(),
+        DEFAULT_BLOCK_SIZE as u64 * NUM_BLOCKS,
+        DEFAULT_BLOCK_SIZE as u64,
+        location.clone(),
+    )
+    .unwrap();
+    let message = encoded_write_request();
+
+    let mut group = c.benchmark_group("write_and_read_1MiB");
+    group.throughput(Throughput::Bytes(DEFAULT_BLOCK_SIZE as u64 * NUM_BLOCKS));
+    group.bench_function("zero_copy", |b| {
+        b.iter(|| black_box(write_and_read(&mut device, &message, false)))
+    });
+    group.bench_function("copy", |b| {
+        b.iter(|| black_box(write_and_read(&mut device, &message, true)))
+    });
+    group.finish();
+
+    std::fs::remove_file(location.join("ministore_data_path_bench")).unwrap();
+}
+
+criterion_group!(benches, data_path);
+criterion_main!(benches);
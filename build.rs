@@ -1,7 +1,15 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = prost_build::Config::new();
+    // Blocks are sliced out of the received message without copying
+    config.bytes(["."]);
+
     tonic_build::configure()
         .protoc_arg("--experimental_allow_proto3_optional")
-        .compile(&["proto/ministore.proto"], &["."])
+        .compile_with_config(
+            config,
+            &["proto/ministore.proto", "proto/ministore_v2.proto"],
+            &["."],
+        )
         .expect("Failed to build proto buffer");
     Ok(())
 }
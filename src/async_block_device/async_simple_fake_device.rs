@@ -1,47 +1,151 @@
 use std::path::PathBuf;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::block_device_common::{
-    data_type::{DataBlock, UNMAP_BLOCK},
-    device_info::DeviceInfo,
-    BlockDeviceType,
-};
+use super::AsyncBlockDevice;
+use crate::block_device_common::{data_type::DataBlock, device_info::DeviceInfo, BlockDeviceType};
+use crate::error::{MinistoreError, Result};
+use async_trait::async_trait;
 use tokio::{fs, io::AsyncWriteExt};
 
-pub struct AsyncSimpleFakeDevice {}
+/// Async version of SimpleFakeDevice, which uses tokio fs to persist its data
+pub struct AsyncSimpleFakeDevice {
+    device_info: DeviceInfo,
+    data: RwLock<Vec<DataBlock>>,
+    filepath: PathBuf,
+}
 
 impl AsyncSimpleFakeDevice {
     pub async fn new(
         device_type: BlockDeviceType,
         name: String,
         size: u64,
+        block_size: u64,
         filepath: PathBuf,
-    ) -> Result<Self, String> {
-        todo!()
+    ) -> Result<Self> {
+        if device_type != BlockDeviceType::AsyncSimpleFakeDevice {
+            return Err(MinistoreError::InvalidDeviceType {
+                device_type: device_type.to_string(),
+            });
+        }
+
+        let device_info = DeviceInfo::new(device_type, name.clone(), size, block_size)?;
+        let filepath = filepath.join(&name);
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&filepath)
+            .await
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", filepath), e)
+            })?;
+
+        Ok(AsyncSimpleFakeDevice {
+            data: RwLock::new(vec![
+                DataBlock::unmapped(block_size as usize);
+                device_info.num_blocks() as usize
+            ]),
+            device_info,
+            filepath,
+        })
+    }
+
+    fn data(&self) -> Result<RwLockReadGuard<'_, Vec<DataBlock>>> {
+        self.data
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn data_mut(&self) -> Result<RwLockWriteGuard<'_, Vec<DataBlock>>> {
+        self.data
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for AsyncSimpleFakeDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+
+        let start = lba as usize;
+        self.data_mut()?[start..start + buffer.len()].clone_from_slice(&buffer);
+        Ok(())
     }
 
-    pub fn info(&self) -> &DeviceInfo {
-        todo!()
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let start = lba as usize;
+        let end = start + num_blocks as usize;
+        Ok(self.data()?[start..end].to_vec())
     }
 
-    pub async fn write(
-        &mut self,
-        lba: u64,
-        num_blocks: u64,
-        buffer: Vec<DataBlock>,
-    ) -> Result<(), String> {
-        todo!()
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let start = lba as usize;
+        let end = start + num_blocks as usize;
+        let block_size = self.device_info.block_size() as usize;
+        self.data_mut()?[start..end].fill(DataBlock::unmapped(block_size));
+        Ok(())
     }
 
-    pub async fn read(&mut self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>, String> {
-        todo!()
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let start = lba as usize;
+        let end = start + num_blocks as usize;
+        let block_size = self.device_info.block_size() as usize;
+        self.data_mut()?[start..end].fill(DataBlock::zeroed(block_size));
+        Ok(())
     }
 
-    pub async fn load(&mut self) -> Result<(), String> {
-        todo!()
+    async fn load(&mut self) -> Result<()> {
+        let serialized = fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (device_info, data): (DeviceInfo, Vec<DataBlock>) = bincode::deserialize(&serialized)?;
+
+        if data.len() as u64 != device_info.num_blocks() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "num_blocks={}, data_len={}",
+                    device_info.num_blocks(),
+                    data.len()
+                ),
+            });
+        }
+
+        self.device_info = device_info;
+        self.data = RwLock::new(data);
+        Ok(())
     }
 
-    pub async fn flush(&mut self) -> Result<(), String> {
-        todo!()
+    async fn flush(&self) -> Result<()> {
+        let serialized = bincode::serialize(&(&self.device_info, &*self.data()?))?;
+
+        let mut file = fs::File::create(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(
+                format!("Failed to create file, path={:?}", self.filepath),
+                e,
+            )
+        })?;
+        file.write_all(&serialized).await?;
+        file.sync_all().await.map_err(|e| {
+            MinistoreError::io(format!("Failed to sync file, path={:?}", self.filepath), e)
+        })
     }
 }
 
@@ -61,11 +165,12 @@ mod tests {
             BlockDeviceType::AsyncSimpleFakeDevice,
             "create_async_block_device_with_unaligned_size_should_fail".to_string(),
             1000000,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await;
 
-        assert!(device.is_err() == true);
+        assert!(device.is_err());
     }
 
     #[tokio::test]
@@ -75,11 +180,12 @@ mod tests {
             BlockDeviceType::SimpleFakeDevice,
             "create_async_block_device_with_wrong_type_should_fail".to_string(),
             1000000,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await;
 
-        assert_eq!(device.is_err(), true);
+        assert!(device.is_err());
     }
 
     #[tokio::test]
@@ -89,13 +195,14 @@ mod tests {
         let _device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1000,
+            DEFAULT_BLOCK_SIZE as u64 * 1000,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
         .expect("Failed to create a device, type={}");
 
-        assert_eq!(Path::new(&device_name).exists(), true);
+        assert!(Path::new(&device_name).exists());
         tokio::fs::remove_file(device_name)
             .await
             .expect("Failed to remove file");
@@ -108,7 +215,8 @@ mod tests {
         let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1000,
+            DEFAULT_BLOCK_SIZE as u64 * 1000,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
@@ -116,7 +224,7 @@ mod tests {
 
         let info = device.info();
         assert_eq!(info.name(), &device_name);
-        assert_eq!(info.device_size(), BLOCK_SIZE as u64 * 1000);
+        assert_eq!(info.device_size(), DEFAULT_BLOCK_SIZE as u64 * 1000);
         assert_eq!(info.num_blocks(), 1000);
         assert_eq!(info.device_type(), BlockDeviceType::AsyncSimpleFakeDevice);
 
@@ -129,10 +237,11 @@ mod tests {
     #[traced_test]
     async fn write_and_read_async_should_success() {
         let device_name = "write_and_read_async_should_success".to_string();
-        let mut device = AsyncSimpleFakeDevice::new(
+        let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
@@ -142,19 +251,16 @@ mod tests {
         let num_blocks = 5;
         let mut buffers = Vec::new();
         for num in 0..num_blocks {
-            let block_buffer = DataBlock([num as u8 as u8; BLOCK_SIZE]);
+            let block_buffer = DataBlock::from(vec![num as u8; DEFAULT_BLOCK_SIZE]);
             buffers.push(block_buffer);
         }
-        assert_eq!(
-            device
-                .write(lba, num_blocks, buffers.clone().to_vec())
-                .await
-                .is_ok(),
-            true
-        );
+        assert!(device
+            .write(lba, num_blocks, buffers.clone().to_vec())
+            .await
+            .is_ok());
 
         let read_result = device.read(lba, num_blocks).await;
-        assert_eq!(read_result.is_ok(), true);
+        assert!(read_result.is_ok());
         assert_eq!(read_result.unwrap(), buffers);
 
         tokio::fs::remove_file(device_name)
@@ -166,18 +272,19 @@ mod tests {
     #[traced_test]
     async fn write_with_invalid_lba_range_async_should_fail() {
         let device_name = "write_with_invalid_lba_range_async_should_fail".to_string();
-        let mut device = AsyncSimpleFakeDevice::new(
+        let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
         .expect("Failed to create fake device");
 
         let buffer = Vec::new();
-        assert_eq!(device.write(0, 2000, buffer.clone()).await.is_err(), true);
-        assert_eq!(device.write(0, 0, buffer.clone()).await.is_err(), true);
+        assert!(device.write(0, 2000, buffer.clone()).await.is_err());
+        assert!(device.write(0, 0, buffer.clone()).await.is_err());
 
         tokio::fs::remove_file(device_name)
             .await
@@ -188,10 +295,11 @@ mod tests {
     #[traced_test]
     async fn write_async_should_fail_when_not_enough_buffer_is_provided() {
         let device_name = "write_async_should_fail_when_not_enough_buffer_is_provided".to_string();
-        let mut device = AsyncSimpleFakeDevice::new(
+        let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
@@ -199,9 +307,9 @@ mod tests {
 
         let mut buffer = Vec::new();
         for offset in 0..5 {
-            buffer.push(DataBlock([offset as u8; BLOCK_SIZE]));
+            buffer.push(DataBlock::from(vec![offset as u8; DEFAULT_BLOCK_SIZE]));
         }
-        assert_eq!(device.write(0, 10, buffer.clone()).await.is_err(), true);
+        assert!(device.write(0, 10, buffer.clone()).await.is_err());
 
         tokio::fs::remove_file(device_name)
             .await
@@ -211,17 +319,18 @@ mod tests {
     #[tokio::test]
     async fn read_async_with_invalid_lba_range_should_fail() {
         let device_name = "read_async_with_invalid_lba_range_should_fail".to_string();
-        let mut device = AsyncSimpleFakeDevice::new(
+        let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
         .expect("Failed to create fake device");
 
-        assert_eq!(device.read(0, 2000).await.is_err(), true);
-        assert_eq!(device.read(0, 0).await.is_err(), true);
+        assert!(device.read(0, 2000).await.is_err());
+        assert!(device.read(0, 0).await.is_err());
 
         tokio::fs::remove_file(device_name)
             .await
@@ -232,10 +341,11 @@ mod tests {
     #[traced_test]
     async fn reading_unwritten_lbas_async_should_return_unmap_data() {
         let device_name = "reading_unwritten_lbas_async_should_return_unmap_data".to_string();
-        let mut device = AsyncSimpleFakeDevice::new(
+        let device = AsyncSimpleFakeDevice::new(
             BlockDeviceType::AsyncSimpleFakeDevice,
             device_name.clone(),
-            BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
             PathBuf::from("."),
         )
         .await
@@ -243,7 +353,83 @@ mod tests {
 
         let read_data = device.read(0, 1).await.expect("Failed to read data");
         assert_eq!(read_data.len(), 1);
-        assert_eq!(*read_data.get(0).unwrap(), UNMAP_BLOCK);
+        assert_eq!(
+            *read_data.first().unwrap(),
+            DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+        );
+
+        tokio::fs::remove_file(device_name)
+            .await
+            .expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn unmapped_lbas_async_should_return_unmap_data() {
+        let device_name = "unmapped_lbas_async_should_return_unmap_data".to_string();
+        let device = AsyncSimpleFakeDevice::new(
+            BlockDeviceType::AsyncSimpleFakeDevice,
+            device_name.clone(),
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create fake device");
+
+        device
+            .write(
+                0,
+                2,
+                vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2],
+            )
+            .await
+            .expect("Failed to write data");
+        device.unmap(1, 1).await.expect("Failed to unmap");
+        assert!(device.unmap(1000, 100).await.is_err());
+
+        let read_data = device.read(0, 2).await.expect("Failed to read data");
+        assert_eq!(
+            read_data,
+            vec![
+                DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+            ]
+        );
+
+        tokio::fs::remove_file(device_name)
+            .await
+            .expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn write_zeroes_async_should_fill_blocks_with_zero() {
+        let device_name = "write_zeroes_async_should_fill_blocks_with_zero".to_string();
+        let device = AsyncSimpleFakeDevice::new(
+            BlockDeviceType::AsyncSimpleFakeDevice,
+            device_name.clone(),
+            DEFAULT_BLOCK_SIZE as u64 * 1024,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create fake device");
+
+        device
+            .write_zeroes(0, 1)
+            .await
+            .expect("Failed to write zeroes");
+        assert!(device.write_zeroes(1000, 100).await.is_err());
+
+        let read_data = device.read(0, 2).await.expect("Failed to read data");
+        assert_eq!(
+            read_data,
+            vec![
+                DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+            ]
+        );
 
         tokio::fs::remove_file(device_name)
             .await
@@ -257,10 +443,11 @@ mod tests {
 
         // Write data and flush all
         {
-            let mut device = AsyncSimpleFakeDevice::new(
+            let device = AsyncSimpleFakeDevice::new(
                 BlockDeviceType::AsyncSimpleFakeDevice,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .await
@@ -271,9 +458,9 @@ mod tests {
                     0,
                     3,
                     vec![
-                        DataBlock([0xA; BLOCK_SIZE]),
-                        DataBlock([0xB; BLOCK_SIZE]),
-                        DataBlock([0xC; BLOCK_SIZE]),
+                        DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                        DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+                        DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE]),
                     ],
                 )
                 .await
@@ -287,7 +474,8 @@ mod tests {
             let mut device = AsyncSimpleFakeDevice::new(
                 BlockDeviceType::AsyncSimpleFakeDevice,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .await
@@ -299,9 +487,9 @@ mod tests {
             assert_eq!(
                 read_data,
                 vec![
-                    DataBlock([0xA; BLOCK_SIZE]),
-                    DataBlock([0xB; BLOCK_SIZE]),
-                    DataBlock([0xC; BLOCK_SIZE])
+                    DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                    DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+                    DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE])
                 ]
             );
         }
@@ -318,10 +506,11 @@ mod tests {
             "async_device_should_be_able_to_provide_device_info_after_load".to_string();
 
         {
-            let mut device = AsyncSimpleFakeDevice::new(
+            let device = AsyncSimpleFakeDevice::new(
                 BlockDeviceType::AsyncSimpleFakeDevice,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .await
@@ -335,6 +524,7 @@ mod tests {
                 BlockDeviceType::AsyncSimpleFakeDevice,
                 device_name.clone(),
                 0,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .await
@@ -343,7 +533,10 @@ mod tests {
             device.load().await.expect("Failed to load data");
 
             assert_eq!(device.info().name(), &device_name);
-            assert_eq!(device.info().device_size(), BLOCK_SIZE as u64 * 1024);
+            assert_eq!(
+                device.info().device_size(),
+                DEFAULT_BLOCK_SIZE as u64 * 1024
+            );
         }
 
         std::fs::remove_file(&device_name).expect("Failed to remove test file");
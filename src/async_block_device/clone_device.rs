@@ -0,0 +1,417 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::protected_device::lock_journal;
+use super::snapshot_device::SnapshotDevice;
+use super::AsyncBlockDevice;
+use crate::block_device::write_ahead_log::{WriteAheadLog, DEFAULT_CHECKPOINT_THRESHOLD};
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Extension of the file keeping the blocks written to a clone next to the device
+pub const CLONE_EXTENSION: &str = "clone";
+
+/// Blocks from the lba changed in the clone, logged before the change is acknowledged
+#[derive(Serialize, Deserialize)]
+struct WrittenRecord {
+    lba: u64,
+    num_blocks: u64,
+}
+
+/// Writable clone of a snapshot of other device. Written blocks are kept in the inner device, and
+/// the other blocks are read from the snapshot, so the clone only stores the blocks it changed.
+/// Which blocks are written is persisted into a separate file on flush. If the writes to the
+/// device survive a crash without flush, the written blocks are also logged after the map in the
+/// file before the writes are acknowledged, and the log is replayed on load.
+pub struct CloneDevice {
+    device: Box<dyn AsyncBlockDevice>,
+    origin: Arc<SnapshotDevice>,
+    snapshot_name: String,
+    /// Whether each block is written, unmapped or zeroed in the clone
+    written: Arc<RwLock<Vec<bool>>>,
+    /// Only exists if the device persists writes without flush, once the file is flushed or loaded
+    journal: Arc<Mutex<Option<WriteAheadLog>>>,
+    filepath: PathBuf,
+}
+
+impl CloneDevice {
+    /// The inner device should have the same size and block size with the origin
+    pub fn new(
+        device: Box<dyn AsyncBlockDevice>,
+        origin: Arc<SnapshotDevice>,
+        snapshot_name: String,
+        location: &Path,
+    ) -> Result<Self> {
+        if device.info().device_size() != origin.info().device_size()
+            || device.info().block_size() != origin.info().block_size()
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Clone should have the same size with the origin, name={}, origin={}",
+                device.info().name(),
+                origin.info().name()
+            )));
+        }
+        let filepath = clone_path(location, device.info().name());
+        Ok(CloneDevice {
+            written: Arc::new(RwLock::new(vec![
+                false;
+                device.info().num_blocks() as usize
+            ])),
+            device,
+            origin,
+            snapshot_name,
+            journal: Arc::new(Mutex::new(None)),
+            filepath,
+        })
+    }
+
+    fn is_journaled(&self) -> bool {
+        self.device.info().device_type().persists_without_flush()
+    }
+
+    /// The blocks are logged and marked under the lock of the log, so that flush does not miss
+    /// them while the file is replaced
+    async fn mark_written(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        if !self.is_journaled() {
+            mark(&mut self.written_mut()?, lba, num_blocks);
+            return Ok(());
+        }
+
+        let journal = self.journal.clone();
+        let written = self.written.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut journal = lock_journal(&journal)?;
+            if let Some(journal) = journal.as_mut() {
+                journal.append(&WrittenRecord { lba, num_blocks })?;
+            }
+            mark(&mut lock_written(&written)?, lba, num_blocks);
+            Ok(())
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+
+    fn written(&self) -> Result<RwLockReadGuard<'_, Vec<bool>>> {
+        self.written
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn written_mut(&self) -> Result<RwLockWriteGuard<'_, Vec<bool>>> {
+        lock_written(&self.written)
+    }
+}
+
+fn mark(written: &mut [bool], lba: u64, num_blocks: u64) {
+    written[lba as usize..(lba + num_blocks) as usize].fill(true);
+}
+
+fn lock_written(written: &RwLock<Vec<bool>>) -> Result<RwLockWriteGuard<'_, Vec<bool>>> {
+    written
+        .write()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+pub fn clone_path(location: &Path, name: &str) -> PathBuf {
+    location.join(format!("{}.{}", name, CLONE_EXTENSION))
+}
+
+#[async_trait]
+impl AsyncBlockDevice for CloneDevice {
+    fn info(&self) -> &DeviceInfo {
+        self.device.info()
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device.write(lba, num_blocks, buffer).await?;
+        self.mark_written(lba, num_blocks).await
+    }
+
+    /// Reads the inner device and the snapshot at most once each
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.info().check_lba_range(lba, num_blocks)?;
+        let written = self.written()?[lba as usize..(lba + num_blocks) as usize].to_vec();
+
+        let mut buffer = match written.iter().any(|written| !written) {
+            true => {
+                self.origin
+                    .read_snapshot(&self.snapshot_name, lba, num_blocks)
+                    .await?
+            }
+            false => Vec::new(),
+        };
+        if written.iter().any(|written| *written) {
+            let blocks = self.device.read(lba, num_blocks).await?;
+            if buffer.is_empty() {
+                return Ok(blocks);
+            }
+            for ((block, written), read) in blocks.into_iter().zip(written).zip(&mut buffer) {
+                if written {
+                    *read = block;
+                }
+            }
+        }
+        Ok(buffer)
+    }
+
+    /// Unmapped blocks are kept as written, so that they are not read from the snapshot
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device.unmap(lba, num_blocks).await?;
+        self.mark_written(lba, num_blocks).await
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device.write_zeroes(lba, num_blocks).await?;
+        self.mark_written(lba, num_blocks).await
+    }
+
+    async fn load(&mut self) -> Result<()> {
+        self.device.load().await?;
+
+        let serialized = tokio::fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (snapshot_name, mut written): (String, Vec<bool>) = bincode::deserialize(&serialized)?;
+        if snapshot_name != self.snapshot_name
+            || written.len() as u64 != self.device.info().num_blocks()
+        {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Clone mismatch, snapshot={}, num_blocks={}, expected_snapshot={}, expected_num_blocks={}",
+                    snapshot_name,
+                    written.len(),
+                    self.snapshot_name,
+                    self.device.info().num_blocks()
+                ),
+            });
+        }
+        if !self
+            .origin
+            .list_snapshots()
+            .await
+            .iter()
+            .any(|snapshot| snapshot.name == self.snapshot_name)
+        {
+            return Err(MinistoreError::SnapshotNotFound {
+                name: self.origin.info().name().clone(),
+                snapshot: self.snapshot_name.clone(),
+            });
+        }
+
+        if self.is_journaled() {
+            let image_len = bincode::serialized_size(&(&snapshot_name, &written))?;
+            let filepath = self.filepath.clone();
+            let (journal, records) = tokio::task::spawn_blocking(move || {
+                WriteAheadLog::replay(&filepath, image_len, DEFAULT_CHECKPOINT_THRESHOLD)
+            })
+            .await
+            .map_err(|e| {
+                MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+            })??;
+            for WrittenRecord { lba, num_blocks } in records {
+                if lba.saturating_add(num_blocks) > written.len() as u64 {
+                    return Err(MinistoreError::Corrupted {
+                        reason: format!(
+                            "Logged block out of the clone, path={:?}, lba={}, num_blocks={}",
+                            self.filepath, lba, num_blocks
+                        ),
+                    });
+                }
+                mark(&mut written, lba, num_blocks);
+            }
+            self.journal = Arc::new(Mutex::new(Some(journal)));
+        }
+
+        self.written = Arc::new(RwLock::new(written));
+        Ok(())
+    }
+
+    /// The blocks are flushed before the written map, and the map is replaced atomically. The
+    /// log starts again in the new file.
+    async fn flush(&self) -> Result<()> {
+        self.device.flush().await?;
+
+        let snapshot_name = self.snapshot_name.clone();
+        let written = self.written.clone();
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let filepath = self.filepath.clone();
+        let journal = self.journal.clone();
+        let is_journaled = self.is_journaled();
+        tokio::task::spawn_blocking(move || {
+            // Blocks are marked under the lock of the log, so none of them is logged into the
+            // replaced file
+            let mut journal = lock_journal(&journal)?;
+            let serialized = bincode::serialize(&(
+                &snapshot_name,
+                &*written
+                    .read()
+                    .map_err(|e| MinistoreError::internal(e.to_string()))?,
+            ))?;
+            std::fs::write(&tmp_path, serialized)
+                .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                })?;
+            std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+            })?;
+            sync_parent_dir(&filepath)?;
+            if is_journaled {
+                *journal = Some(WriteAheadLog::open(
+                    &filepath,
+                    DEFAULT_CHECKPOINT_THRESHOLD,
+                )?);
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::test_utils::blocks;
+    use crate::block_device_common::BlockDeviceType;
+    use tracing_test::traced_test;
+
+    async fn create_device(name: &str) -> Box<dyn AsyncBlockDevice> {
+        create_device_of_type(BlockDeviceType::SimpleFakeDevice, name).await
+    }
+
+    async fn create_device_of_type(
+        device_type: BlockDeviceType,
+        name: &str,
+    ) -> Box<dyn AsyncBlockDevice> {
+        create_async_block_device(
+            device_type,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device")
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn clone_should_share_unmodified_blocks_with_origin() {
+        let name = "clone_should_share_unmodified_blocks_with_origin";
+        let origin_name = format!("{}_origin", name);
+        let origin = Arc::new(SnapshotDevice::new(
+            create_device(&origin_name).await,
+            Path::new("."),
+        ));
+        origin.write(0, 4, blocks(0xA, 4)).await.unwrap();
+        origin.create_snapshot("golden").await.unwrap();
+
+        let clone = CloneDevice::new(
+            create_device(name).await,
+            origin.clone(),
+            "golden".to_string(),
+            Path::new("."),
+        )
+        .unwrap();
+        clone.write(1, 1, blocks(0xB, 1)).await.unwrap();
+        clone.unmap(2, 1).await.unwrap();
+        // Writes to the origin after the snapshot are not seen by the clone
+        origin.write(3, 1, blocks(0xC, 1)).await.unwrap();
+
+        let expected = [
+            blocks(0xA, 1),
+            blocks(0xB, 1),
+            vec![DataBlock::unmapped(DEFAULT_BLOCK_SIZE)],
+            blocks(0xA, 1),
+        ]
+        .concat();
+        assert_eq!(clone.read(0, 4).await.unwrap(), expected);
+        assert_eq!(origin.read(1, 1).await.unwrap(), blocks(0xA, 1));
+        clone.flush().await.expect("Failed to flush");
+
+        let mut clone = CloneDevice::new(
+            create_device(name).await,
+            origin.clone(),
+            "golden".to_string(),
+            Path::new("."),
+        )
+        .unwrap();
+        clone.load().await.expect("Failed to load");
+        assert_eq!(clone.read(0, 4).await.unwrap(), expected);
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+        std::fs::remove_file(clone_path(Path::new("."), name)).expect("Failed to remove file");
+        std::fs::remove_file(origin_name).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn written_blocks_should_be_recovered_with_blocks_persisted_without_flush() {
+        let name = "written_blocks_should_be_recovered_with_blocks_persisted_without_flush";
+        let origin_name = format!("{}_origin", name);
+        let origin = Arc::new(SnapshotDevice::new(
+            create_device(&origin_name).await,
+            Path::new("."),
+        ));
+        origin.write(0, 4, blocks(0xA, 4)).await.unwrap();
+        origin.create_snapshot("golden").await.unwrap();
+
+        for device_type in [
+            BlockDeviceType::SimpleFakeWalDevice,
+            BlockDeviceType::IoUringFakeDevice,
+            BlockDeviceType::MmapFakeDevice,
+        ] {
+            {
+                let clone = CloneDevice::new(
+                    create_device_of_type(device_type.clone(), name).await,
+                    origin.clone(),
+                    "golden".to_string(),
+                    Path::new("."),
+                )
+                .unwrap();
+                clone.flush().await.expect("Failed to flush");
+
+                // The clone crashes without flushing the following changes
+                clone.write(0, 1, blocks(0xB, 1)).await.unwrap();
+                clone.write_zeroes(1, 1).await.unwrap();
+                clone.unmap(2, 1).await.unwrap();
+            }
+
+            let mut clone = CloneDevice::new(
+                create_device_of_type(device_type, name).await,
+                origin.clone(),
+                "golden".to_string(),
+                Path::new("."),
+            )
+            .unwrap();
+            clone.load().await.expect("Failed to load");
+            assert_eq!(
+                clone.read(0, 4).await.unwrap(),
+                [
+                    blocks(0xB, 1),
+                    vec![
+                        DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                        DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+                    ],
+                    blocks(0xA, 1),
+                ]
+                .concat()
+            );
+
+            std::fs::remove_file(name).expect("Failed to remove file");
+            std::fs::remove_file(clone_path(Path::new("."), name)).expect("Failed to remove file");
+        }
+        std::fs::remove_file(origin_name).expect("Failed to remove file");
+    }
+}
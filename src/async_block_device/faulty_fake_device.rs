@@ -0,0 +1,332 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use super::AsyncBlockDevice;
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LbaRange {
+    pub lba: u64,
+    pub num_blocks: u64,
+}
+
+impl LbaRange {
+    /// Returns the first lba of the I/O in this range
+    fn first_overlap(&self, lba: u64, num_blocks: u64) -> Option<u64> {
+        let start = std::cmp::max(self.lba, lba);
+        let end = std::cmp::min(self.lba + self.num_blocks, lba + num_blocks);
+        (start < end).then_some(start)
+    }
+}
+
+/// Faults injected into the I/Os. The default injects nothing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultRules {
+    /// Reads overlapping any of the ranges fail
+    pub read_error_ranges: Vec<LbaRange>,
+    /// Writes, unmaps and write zeroes overlapping any of the ranges fail
+    pub write_error_ranges: Vec<LbaRange>,
+    /// Probability from 0 to 1 that an I/O fails
+    pub error_probability: f64,
+    /// Delay before each I/O
+    pub latency: Duration,
+    /// Failed writes store the blocks before the failure instead of nothing
+    pub torn_writes: bool,
+    /// I/Os fail after this number of I/Os since the rules are set
+    pub fail_after_ops: Option<u64>,
+}
+
+impl FaultRules {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.error_probability) {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Error probability should be from 0 to 1, error_probability={}",
+                self.error_probability
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum IoKind {
+    Read,
+    /// Writes, unmaps, write zeroes and flushes
+    Write,
+}
+
+/// Rules of a FaultyFakeDevice, which can be replaced while the device is in use
+#[derive(Default)]
+pub struct FaultInjector {
+    rules: RwLock<FaultRules>,
+    /// I/Os issued since the rules are set
+    ops: AtomicU64,
+}
+
+impl FaultInjector {
+    pub fn rules(&self) -> Result<FaultRules> {
+        self.rules
+            .read()
+            .map(|rules| rules.clone())
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    pub fn set_rules(&self, rules: FaultRules) -> Result<()> {
+        rules.validate()?;
+        *self
+            .rules
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))? = rules;
+        self.ops.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Waits for the latency and returns the lba where the I/O fails, if any. Random failures and
+    /// failures after N ops fail at the middle of the I/O, so that a torn write stores half of it.
+    async fn inject(&self, kind: IoKind, lba: u64, num_blocks: u64) -> Result<Option<u64>> {
+        let rules = self.rules()?;
+        if !rules.latency.is_zero() {
+            tokio::time::sleep(rules.latency).await;
+        }
+
+        let ops = self.ops.fetch_add(1, Ordering::SeqCst);
+        let ranges = match kind {
+            IoKind::Read => &rules.read_error_ranges,
+            IoKind::Write => &rules.write_error_ranges,
+        };
+        if let Some(lba) = ranges
+            .iter()
+            .filter_map(|range| range.first_overlap(lba, num_blocks))
+            .min()
+        {
+            return Ok(Some(lba));
+        }
+        let fails_after_ops = rules.fail_after_ops.is_some_and(|n| ops >= n);
+        let fails_randomly =
+            rules.error_probability > 0.0 && rand::thread_rng().gen_bool(rules.error_probability);
+        match fails_after_ops || fails_randomly {
+            true => Ok(Some(lba + num_blocks / 2)),
+            false => Ok(None),
+        }
+    }
+}
+
+fn injected_fault(operation: &str, lba: u64) -> MinistoreError {
+    MinistoreError::io(
+        format!("Injected {} fault, lba={}", operation, lba),
+        std::io::Error::other("injected fault"),
+    )
+}
+
+/// Fails the I/Os of the inner device by the rules of its FaultInjector, so that clients can test
+/// their error handling. Injected failures are I/O errors like those of real devices.
+pub struct FaultyFakeDevice {
+    device: Box<dyn AsyncBlockDevice>,
+    injector: Arc<FaultInjector>,
+}
+
+impl FaultyFakeDevice {
+    pub fn new(device: Box<dyn AsyncBlockDevice>) -> Self {
+        FaultyFakeDevice {
+            device,
+            injector: Arc::new(FaultInjector::default()),
+        }
+    }
+
+    pub fn injector(&self) -> Arc<FaultInjector> {
+        self.injector.clone()
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for FaultyFakeDevice {
+    fn info(&self) -> &DeviceInfo {
+        self.device.info()
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, mut buffer: Vec<DataBlock>) -> Result<()> {
+        let Some(fault_lba) = self.injector.inject(IoKind::Write, lba, num_blocks).await? else {
+            return self.device.write(lba, num_blocks, buffer).await;
+        };
+
+        if self.injector.rules()?.torn_writes && fault_lba > lba {
+            let num_written = fault_lba - lba;
+            buffer.truncate(num_written as usize);
+            self.device.write(lba, num_written, buffer).await?;
+        }
+        Err(injected_fault("write", fault_lba))
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        match self.injector.inject(IoKind::Read, lba, num_blocks).await? {
+            Some(fault_lba) => Err(injected_fault("read", fault_lba)),
+            None => self.device.read(lba, num_blocks).await,
+        }
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        match self.injector.inject(IoKind::Write, lba, num_blocks).await? {
+            Some(fault_lba) => Err(injected_fault("unmap", fault_lba)),
+            None => self.device.unmap(lba, num_blocks).await,
+        }
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        match self.injector.inject(IoKind::Write, lba, num_blocks).await? {
+            Some(fault_lba) => Err(injected_fault("write zeroes", fault_lba)),
+            None => self.device.write_zeroes(lba, num_blocks).await,
+        }
+    }
+
+    /// Rules are not persisted, so the device is loaded without faults
+    async fn load(&mut self) -> Result<()> {
+        self.device.load().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        match self.injector.inject(IoKind::Write, 0, 0).await? {
+            Some(_) => Err(injected_fault("flush", 0)),
+            None => self.device.flush().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::BlockDeviceType;
+    use std::path::PathBuf;
+    use tracing_test::traced_test;
+
+    async fn create_faulty_device(name: &str) -> FaultyFakeDevice {
+        let device = create_async_block_device(
+            BlockDeviceType::SimpleFakeDevice,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        FaultyFakeDevice::new(device)
+    }
+
+    fn blocks(num_blocks: usize) -> Vec<DataBlock> {
+        (0..num_blocks)
+            .map(|value| DataBlock::from(vec![value as u8; DEFAULT_BLOCK_SIZE]))
+            .collect()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn ios_overlapping_error_ranges_should_fail() {
+        let name = "ios_overlapping_error_ranges_should_fail";
+        let device = create_faulty_device(name).await;
+        device.write(0, 4, blocks(4)).await.unwrap();
+
+        device
+            .injector()
+            .set_rules(FaultRules {
+                read_error_ranges: vec![LbaRange {
+                    lba: 2,
+                    num_blocks: 2,
+                }],
+                write_error_ranges: vec![LbaRange {
+                    lba: 8,
+                    num_blocks: 1,
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(device.read(0, 2).await.unwrap(), blocks(2));
+        assert!(matches!(
+            device.read(1, 2).await,
+            Err(MinistoreError::Io { .. })
+        ));
+        assert!(device.write(8, 1, blocks(1)).await.is_err());
+        assert!(device.unmap(7, 2).await.is_err());
+        assert!(device.write_zeroes(8, 1).await.is_err());
+        assert!(device.write(9, 1, blocks(1)).await.is_ok());
+
+        device.injector().set_rules(FaultRules::default()).unwrap();
+        assert_eq!(device.read(0, 4).await.unwrap(), blocks(4));
+        assert!(device.write(8, 1, blocks(1)).await.is_ok());
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn torn_write_should_store_blocks_before_the_failure() {
+        let name = "torn_write_should_store_blocks_before_the_failure";
+        let device = create_faulty_device(name).await;
+        device
+            .injector()
+            .set_rules(FaultRules {
+                write_error_ranges: vec![LbaRange {
+                    lba: 3,
+                    num_blocks: 1,
+                }],
+                torn_writes: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert!(device.write(0, 4, blocks(4)).await.is_err());
+        device.injector().set_rules(FaultRules::default()).unwrap();
+        let mut expected = blocks(3);
+        expected.push(DataBlock::unmapped(DEFAULT_BLOCK_SIZE));
+        assert_eq!(device.read(0, 4).await.unwrap(), expected);
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn ios_should_fail_by_count_and_probability() {
+        let name = "ios_should_fail_by_count_and_probability";
+        let device = create_faulty_device(name).await;
+
+        device
+            .injector()
+            .set_rules(FaultRules {
+                fail_after_ops: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(device.write(0, 1, blocks(1)).await.is_ok());
+        assert!(device.read(0, 1).await.is_ok());
+        assert!(device.read(0, 1).await.is_err());
+        assert!(device.flush().await.is_err());
+
+        device
+            .injector()
+            .set_rules(FaultRules {
+                error_probability: 1.0,
+                latency: Duration::from_millis(20),
+                ..Default::default()
+            })
+            .unwrap();
+        let start = std::time::Instant::now();
+        assert!(device.read(0, 1).await.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        assert!(matches!(
+            device.injector().set_rules(FaultRules {
+                error_probability: 1.5,
+                ..Default::default()
+            }),
+            Err(MinistoreError::InvalidArgument { .. })
+        ));
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+}
@@ -0,0 +1,685 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use super::volume::Member;
+use super::AsyncBlockDevice;
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::block_device_common::range_lock::{LockMode, RangeLock};
+use crate::block_device_common::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Number of blocks copied at once on rebuild. Only this range is locked against writes while it
+/// is copied.
+pub const REBUILD_CHUNK_BLOCKS: u64 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MemberState {
+    Active,
+    /// Failed an I/O, and not used until it is replaced
+    Failed,
+    /// Written but not read until the rebuild is completed
+    Rebuilding,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorMemberStatus {
+    pub name: String,
+    pub state: MemberState,
+    /// Copied onto the member so far, only while rebuilding
+    pub rebuilt_blocks: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorStatus {
+    pub name: String,
+    pub size: u64,
+    pub block_size: u64,
+    pub members: Vec<MirrorMemberStatus>,
+}
+
+impl MirrorStatus {
+    pub fn is_degraded(&self) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.state != MemberState::Active)
+    }
+}
+
+struct MirrorMember {
+    member: Member,
+    state: MemberState,
+    rebuilt_blocks: u64,
+}
+
+/// RAID-1 mirror keeping the same blocks on every member. Writes go to all members which have not
+/// failed, and reads are balanced across the active members. A member failing an I/O is marked as
+/// failed, and the mirror keeps working in degraded mode until the member is replaced and rebuilt.
+/// The member states are persisted into a file on flush.
+pub struct MirrorDevice {
+    info: DeviceInfo,
+    members: RwLock<Vec<MirrorMember>>,
+    next_read: AtomicUsize,
+    /// Set when the mirror is deleted, which stops the rebuild
+    stopped: AtomicBool,
+    filepath: PathBuf,
+}
+
+impl MirrorDevice {
+    /// Members should have the same block size, and the smallest member is the size of the mirror
+    pub fn new(name: String, members: Vec<Member>, location: &Path) -> Result<Self> {
+        let block_size = members
+            .first()
+            .ok_or_else(|| MinistoreError::invalid_argument("Mirror should have a member"))?
+            .device
+            .info()
+            .block_size();
+        if let Some(member) = members
+            .iter()
+            .find(|member| member.device.info().block_size() != block_size)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Members should have the same block size, name={}, block_size={}, expected={}",
+                member.name,
+                member.device.info().block_size(),
+                block_size
+            )));
+        }
+        let size = members
+            .iter()
+            .map(|member| member.device.info().device_size())
+            .min()
+            .unwrap_or_default();
+
+        Ok(MirrorDevice {
+            info: DeviceInfo::new(BlockDeviceType::Mirror, name.clone(), size, block_size)?,
+            members: RwLock::new(
+                members
+                    .into_iter()
+                    .map(|member| MirrorMember {
+                        member,
+                        state: MemberState::Active,
+                        rebuilt_blocks: 0,
+                    })
+                    .collect(),
+            ),
+            next_read: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+            filepath: location.join(name),
+        })
+    }
+
+    pub fn member_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .members()?
+            .iter()
+            .map(|member| member.member.name.clone())
+            .collect())
+    }
+
+    pub fn status(&self) -> Result<MirrorStatus> {
+        Ok(MirrorStatus {
+            name: self.info.name().clone(),
+            size: self.info.device_size(),
+            block_size: self.info.block_size(),
+            members: self
+                .members()?
+                .iter()
+                .map(|member| MirrorMemberStatus {
+                    name: member.member.name.clone(),
+                    state: member.state,
+                    rebuilt_blocks: member.rebuilt_blocks,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn is_rebuilding(&self) -> Result<bool> {
+        Ok(self
+            .members()?
+            .iter()
+            .any(|member| member.state == MemberState::Rebuilding))
+    }
+
+    /// Replaces the member with the new device, which should be rebuilt before it is read. Only
+    /// one member is rebuilt at a time.
+    pub fn replace_member(&self, old_name: &str, new_member: Member) -> Result<()> {
+        if new_member.device.info().block_size() != self.info.block_size()
+            || new_member.device.info().device_size() < self.info.device_size()
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "New member should have the same block size and enough size, name={}, size={}, block_size={}",
+                new_member.name,
+                new_member.device.info().device_size(),
+                new_member.device.info().block_size()
+            )));
+        }
+
+        let mut members = self.members_mut()?;
+        if members
+            .iter()
+            .any(|member| member.member.name == new_member.name)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Device is already a member, name={}",
+                new_member.name
+            )));
+        }
+        if members
+            .iter()
+            .any(|member| member.state == MemberState::Rebuilding)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Mirror is already rebuilding, name={}",
+                self.info.name()
+            )));
+        }
+        let Some(index) = members
+            .iter()
+            .position(|member| member.member.name == old_name)
+        else {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Device is not a member, name={}",
+                old_name
+            )));
+        };
+        if !members
+            .iter()
+            .enumerate()
+            .any(|(other, member)| other != index && member.state == MemberState::Active)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "No other active member to rebuild from, name={}",
+                self.info.name()
+            )));
+        }
+
+        tracing::info!(
+            "Replaced mirror member, name={}, old={}, new={}",
+            self.info.name(),
+            old_name,
+            new_member.name
+        );
+        members[index] = MirrorMember {
+            member: new_member,
+            state: MemberState::Rebuilding,
+            rebuilt_blocks: 0,
+        };
+        Ok(())
+    }
+
+    /// Copies every block onto the rebuilding member, and makes it active. Each chunk is locked
+    /// by the range lock of the mirror while it is copied, so that writes are not lost.
+    pub async fn rebuild(&self, range_lock: &RangeLock) -> Result<()> {
+        let num_blocks = self.info.num_blocks();
+        let mut lba = 0;
+        loop {
+            if self.is_stopped() {
+                return Ok(());
+            }
+            // The member may fail meanwhile
+            let Some((index, member)) = self.rebuilding_member()? else {
+                return Ok(());
+            };
+            if lba >= num_blocks {
+                self.finish_rebuild(index, &member.name)?;
+                return Ok(());
+            }
+
+            let chunk_blocks = std::cmp::min(REBUILD_CHUNK_BLOCKS, num_blocks - lba);
+            {
+                let _guard = range_lock
+                    .lock(lba, chunk_blocks, LockMode::Exclusive)
+                    .await;
+                let blocks = self.read(lba, chunk_blocks).await?;
+                if let Err(e) = member.device.write(lba, chunk_blocks, blocks).await {
+                    self.fail(index, &member.name, &e)?;
+                    return Err(e);
+                }
+            }
+            lba += chunk_blocks;
+            self.update_member(index, &member.name, |member| member.rebuilt_blocks = lba)?;
+        }
+    }
+
+    /// Stops the rebuild before the mirror is deleted
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn rebuilding_member(&self) -> Result<Option<(usize, Member)>> {
+        Ok(self
+            .members()?
+            .iter()
+            .enumerate()
+            .find(|(_, member)| member.state == MemberState::Rebuilding)
+            .map(|(index, member)| (index, member.member.clone())))
+    }
+
+    fn finish_rebuild(&self, index: usize, name: &str) -> Result<()> {
+        self.update_member(index, name, |member| {
+            member.state = MemberState::Active;
+            member.rebuilt_blocks = 0;
+        })?;
+        tracing::info!(
+            "Finished rebuilding mirror member, name={}, member={}",
+            self.info.name(),
+            name
+        );
+        Ok(())
+    }
+
+    /// Marks the member as failed, unless it is replaced meanwhile
+    fn fail(&self, index: usize, name: &str, e: &MinistoreError) -> Result<()> {
+        tracing::warn!(
+            "Mirror member failed, the mirror is degraded, name={}, member={}, err={}",
+            self.info.name(),
+            name,
+            e
+        );
+        self.update_member(index, name, |member| {
+            member.state = MemberState::Failed;
+            member.rebuilt_blocks = 0;
+        })
+    }
+
+    fn update_member<F>(&self, index: usize, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut MirrorMember),
+    {
+        if let Some(member) = self
+            .members_mut()?
+            .get_mut(index)
+            .filter(|member| member.member.name == name)
+        {
+            f(member);
+        }
+        Ok(())
+    }
+
+    /// Members which are written, with whether they are active
+    fn writable_members(&self) -> Result<Vec<(usize, Member, bool)>> {
+        Ok(self
+            .members()?
+            .iter()
+            .enumerate()
+            .filter(|(_, member)| member.state != MemberState::Failed)
+            .map(|(index, member)| {
+                (
+                    index,
+                    member.member.clone(),
+                    member.state == MemberState::Active,
+                )
+            })
+            .collect())
+    }
+
+    /// Picks the next active member in turn
+    fn next_active_member(&self) -> Result<Option<(usize, Member)>> {
+        let members = self.members()?;
+        let active: Vec<usize> = members
+            .iter()
+            .enumerate()
+            .filter(|(_, member)| member.state == MemberState::Active)
+            .map(|(index, _)| index)
+            .collect();
+        if active.is_empty() {
+            return Ok(None);
+        }
+        let index = active[self.next_read.fetch_add(1, Ordering::Relaxed) % active.len()];
+        Ok(Some((index, members[index].member.clone())))
+    }
+
+    /// Issues the I/O to every member which has not failed concurrently. It succeeds if any active
+    /// member succeeds, and the members which fail are marked as failed.
+    async fn write_all<F, Fut>(&self, f: F) -> Result<()>
+    where
+        F: Fn(Arc<dyn AsyncBlockDevice>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut ios = JoinSet::new();
+        let mut members = Vec::new();
+        for (index, member, active) in self.writable_members()? {
+            let io = f(member.device.clone());
+            let position = members.len();
+            ios.spawn(async move { (position, io.await) });
+            members.push((index, member, active));
+        }
+
+        let mut written = false;
+        let mut last_error = None;
+        while let Some(joined) = ios.join_next().await {
+            let (position, result) = joined.map_err(|e| MinistoreError::internal(e.to_string()))?;
+            let (index, member, active) = &members[position];
+            match result {
+                Ok(()) => written |= active,
+                Err(e) => {
+                    self.fail(*index, &member.name, &e)?;
+                    last_error = Some(e);
+                }
+            }
+        }
+        match (written, last_error) {
+            (true, _) => Ok(()),
+            (false, Some(e)) => Err(e),
+            (false, None) => Err(self.no_active_member()),
+        }
+    }
+
+    fn no_active_member(&self) -> MinistoreError {
+        MinistoreError::io(
+            format!("Mirror has no active member, name={}", self.info.name()),
+            std::io::ErrorKind::Other.into(),
+        )
+    }
+
+    fn members(&self) -> Result<RwLockReadGuard<'_, Vec<MirrorMember>>> {
+        self.members
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn members_mut(&self) -> Result<RwLockWriteGuard<'_, Vec<MirrorMember>>> {
+        self.members
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for MirrorDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.info.check_block_size(&buffer)?;
+
+        self.write_all(|device| {
+            let buffer = buffer.clone();
+            async move { device.write(lba, num_blocks, buffer).await }
+        })
+        .await
+    }
+
+    /// Reads from the other active members if the member fails
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.info.check_lba_range(lba, num_blocks)?;
+
+        let mut last_error = None;
+        while let Some((index, member)) = self.next_active_member()? {
+            match member.device.read(lba, num_blocks).await {
+                Ok(blocks) => return Ok(blocks),
+                Err(e) => {
+                    self.fail(index, &member.name, &e)?;
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| self.no_active_member()))
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        self.write_all(|device| async move { device.unmap(lba, num_blocks).await })
+            .await
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        self.write_all(|device| async move { device.write_zeroes(lba, num_blocks).await })
+            .await
+    }
+
+    /// Members should be given in the same order as they are persisted. A member which was
+    /// rebuilding, or replaced after the last flush, is rebuilt again from the beginning.
+    async fn load(&mut self) -> Result<()> {
+        let serialized = tokio::fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (info, members): (DeviceInfo, Vec<(String, MemberState)>) =
+            bincode::deserialize(&serialized)?;
+        let member_names: Vec<String> = members.iter().map(|(name, _)| name.clone()).collect();
+        let expected = self.member_names()?;
+        let num_replaced = member_names
+            .iter()
+            .zip(&expected)
+            .filter(|(name, expected)| name != expected)
+            .count();
+        if info.device_type() != BlockDeviceType::Mirror
+            || member_names.len() != expected.len()
+            || num_replaced > 1
+        {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Mirror mismatch, type={}, members={:?}, expected={:?}",
+                    info.device_type(),
+                    member_names,
+                    expected
+                ),
+            });
+        }
+        if info.device_size() > self.info.device_size() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Mirror is larger than the members, size={}, members_size={}",
+                    info.device_size(),
+                    self.info.device_size()
+                ),
+            });
+        }
+
+        self.info = info;
+        for (member, (name, state)) in self.members_mut()?.iter_mut().zip(members) {
+            member.state = match member.member.name == name {
+                true => state,
+                false => MemberState::Rebuilding,
+            };
+        }
+        Ok(())
+    }
+
+    /// The members which have not failed are flushed before the member states
+    async fn flush(&self) -> Result<()> {
+        self.write_all(|device| async move { device.flush().await })
+            .await?;
+
+        let serialized = {
+            let members: Vec<(String, MemberState)> = self
+                .members()?
+                .iter()
+                .map(|member| (member.member.name.clone(), member.state))
+                .collect();
+            bincode::serialize(&(&self.info, members))?
+        };
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let filepath = self.filepath.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(&tmp_path, serialized)
+                .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                })?;
+            std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+            })?;
+            sync_parent_dir(&filepath)
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::async_block_device::faulty_fake_device::{
+        FaultInjector, FaultRules, FaultyFakeDevice,
+    };
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::test_utils::blocks;
+    use tracing_test::traced_test;
+
+    const NUM_BLOCKS: u64 = REBUILD_CHUNK_BLOCKS * 2 + 1;
+
+    async fn create_member(name: &str) -> (Member, Arc<FaultInjector>) {
+        let device = create_async_block_device(
+            BlockDeviceType::SimpleFakeDevice,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * NUM_BLOCKS,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        let device = FaultyFakeDevice::new(device);
+        let injector = device.injector();
+        (
+            Member {
+                name: name.to_string(),
+                device: Arc::new(device),
+            },
+            injector,
+        )
+    }
+
+    fn states(mirror: &MirrorDevice) -> Vec<MemberState> {
+        mirror
+            .status()
+            .unwrap()
+            .members
+            .into_iter()
+            .map(|member| member.state)
+            .collect()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn mirror_should_keep_working_when_member_fails_and_rebuild_replacement() {
+        let name = "mirror_should_keep_working_when_member_fails_and_rebuild_replacement";
+        let (first, first_injector) = create_member(&format!("{}_0", name)).await;
+        let (second, _) = create_member(&format!("{}_1", name)).await;
+        let (replacement, _) = create_member(&format!("{}_2", name)).await;
+        let mirror = MirrorDevice::new(
+            name.to_string(),
+            vec![first.clone(), second.clone()],
+            Path::new("."),
+        )
+        .unwrap();
+
+        mirror
+            .write(0, NUM_BLOCKS, blocks(0xA, NUM_BLOCKS as usize))
+            .await
+            .unwrap();
+        for member in [&first, &second] {
+            assert_eq!(
+                member.device.read(0, NUM_BLOCKS).await.unwrap(),
+                blocks(0xA, NUM_BLOCKS as usize)
+            );
+        }
+
+        // Reads are served by the other member after the first one fails
+        first_injector
+            .set_rules(FaultRules {
+                error_probability: 1.0,
+                ..Default::default()
+            })
+            .unwrap();
+        for _ in 0..2 {
+            assert_eq!(mirror.read(1, 1).await.unwrap(), blocks(0xA, 1));
+        }
+        assert_eq!(
+            states(&mirror),
+            vec![MemberState::Failed, MemberState::Active]
+        );
+        assert!(mirror.status().unwrap().is_degraded());
+        mirror.write(1, 1, blocks(0xB, 1)).await.unwrap();
+
+        mirror
+            .replace_member(&first.name, replacement.clone())
+            .unwrap();
+        assert_eq!(
+            states(&mirror),
+            vec![MemberState::Rebuilding, MemberState::Active]
+        );
+        mirror.rebuild(&RangeLock::new()).await.unwrap();
+        assert_eq!(
+            states(&mirror),
+            vec![MemberState::Active, MemberState::Active]
+        );
+        assert_eq!(
+            replacement.device.read(0, 3).await.unwrap(),
+            [blocks(0xA, 1), blocks(0xB, 1), blocks(0xA, 1)].concat()
+        );
+
+        // The states are kept after reload
+        mirror.flush().await.unwrap();
+        let mut mirror = MirrorDevice::new(
+            name.to_string(),
+            vec![replacement.clone(), second.clone()],
+            Path::new("."),
+        )
+        .unwrap();
+        mirror.load().await.expect("Failed to load");
+        assert_eq!(
+            states(&mirror),
+            vec![MemberState::Active, MemberState::Active]
+        );
+
+        for filename in [name.to_string(), first.name, second.name, replacement.name] {
+            std::fs::remove_file(filename).expect("Failed to remove file");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[traced_test]
+    async fn writes_should_be_issued_to_members_concurrently() {
+        let name = "writes_should_be_issued_to_members_concurrently";
+        let latency = std::time::Duration::from_millis(200);
+        let mut members = Vec::new();
+        for index in 0..2 {
+            let (member, injector) = create_member(&format!("{}_{}", name, index)).await;
+            injector
+                .set_rules(FaultRules {
+                    latency,
+                    ..Default::default()
+                })
+                .unwrap();
+            members.push(member);
+        }
+        let mirror = MirrorDevice::new(name.to_string(), members.clone(), Path::new(".")).unwrap();
+
+        let start = std::time::Instant::now();
+        mirror.write(0, 1, blocks(0xA, 1)).await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= latency);
+        assert!(elapsed < latency * 2, "elapsed={:?}", elapsed);
+
+        for member in members {
+            assert_eq!(member.device.read(0, 1).await.unwrap(), blocks(0xA, 1));
+            std::fs::remove_file(member.name).expect("Failed to remove file");
+        }
+    }
+}
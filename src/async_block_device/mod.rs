@@ -1 +1,180 @@
+use std::path::PathBuf;
+
+use crate::block_device::create_block_device;
+use crate::block_device_common::{data_type::DataBlock, device_info::DeviceInfo, BlockDeviceType};
+use crate::error::{MinistoreError, Result};
+use async_simple_fake_device::AsyncSimpleFakeDevice;
+use async_trait::async_trait;
+use sync_block_device_adapter::SyncBlockDeviceAdapter;
+
 pub mod async_simple_fake_device;
+pub mod clone_device;
+pub mod faulty_fake_device;
+pub mod mirror_device;
+pub mod protected_device;
+pub mod shaped_device;
+pub mod snapshot_device;
+pub mod striped_device;
+pub mod sync_block_device_adapter;
+pub mod volume;
+
+/// I/Os take &self, so that they can be issued concurrently. Callers are responsible for
+/// ordering overlapping I/Os (see RangeLock).
+#[async_trait]
+pub trait AsyncBlockDevice: Send + Sync {
+    fn info(&self) -> &DeviceInfo;
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()>;
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>>;
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()>;
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()>;
+    async fn load(&mut self) -> Result<()>;
+    async fn flush(&self) -> Result<()>;
+}
+
+/// Creates an AsyncBlockDevice for any BlockDeviceType.
+/// Sync block devices are wrapped with SyncBlockDeviceAdapter.
+pub async fn create_async_block_device(
+    device_type: BlockDeviceType,
+    name: String,
+    size: u64,
+    block_size: u64,
+    filepath: PathBuf,
+) -> Result<Box<dyn AsyncBlockDevice>> {
+    if device_type.is_async() {
+        return match device_type {
+            BlockDeviceType::AsyncSimpleFakeDevice => {
+                let fake =
+                    AsyncSimpleFakeDevice::new(device_type, name, size, block_size, filepath)
+                        .await?;
+                Ok(Box::new(fake))
+            }
+            _ => Err(MinistoreError::InvalidDeviceType {
+                device_type: device_type.to_string(),
+            }),
+        };
+    }
+
+    let device = tokio::task::spawn_blocking(move || {
+        create_block_device(device_type, name, size, block_size, filepath)
+    })
+    .await
+    .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))??;
+    Ok(Box::new(SyncBlockDeviceAdapter::new(device)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use strum::IntoEnumIterator;
+    use tracing_test::traced_test;
+
+    fn fake_device_types() -> Vec<BlockDeviceType> {
+        BlockDeviceType::iter()
+            .filter(|device_type| device_type.is_fake())
+            .collect()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn async_block_device_can_be_created_for_every_fake_device_type() {
+        for device_type in fake_device_types() {
+            let device_name = format!(
+                "async_block_device_can_be_created_for_every_fake_device_type_{}",
+                device_type
+            );
+            let device = create_async_block_device(
+                device_type.clone(),
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * 1000,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .await
+            .unwrap_or_else(|e| panic!("Failed to create device, type={}, err={}", device_type, e));
+
+            assert_eq!(device.info().name(), &device_name);
+            assert_eq!(device.info().num_blocks(), 1000);
+            assert_eq!(device.info().device_type(), device_type);
+
+            tokio::fs::remove_file(&device_name)
+                .await
+                .expect("Failed to remove file");
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn async_block_device_should_write_read_flush_and_load_for_every_fake_device_type() {
+        for device_type in fake_device_types() {
+            let device_name = format!(
+                "async_block_device_should_write_read_flush_and_load_{}",
+                device_type
+            );
+            let blocks = vec![
+                DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+            ];
+
+            {
+                let device = create_async_block_device(
+                    device_type.clone(),
+                    device_name.clone(),
+                    DEFAULT_BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64,
+                    PathBuf::from("."),
+                )
+                .await
+                .expect("Failed to create device");
+
+                device
+                    .write(10, 2, blocks.clone())
+                    .await
+                    .expect("Failed to write data");
+                assert_eq!(
+                    device.read(9, 3).await.expect("Failed to read data"),
+                    vec![
+                        DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                        blocks[0].clone(),
+                        blocks[1].clone()
+                    ],
+                    "device_type={}",
+                    device_type
+                );
+                assert!(device.read(1024, 1).await.is_err());
+
+                device.flush().await.expect("Failed to flush");
+            }
+
+            {
+                let mut device = create_async_block_device(
+                    device_type.clone(),
+                    device_name.clone(),
+                    0,
+                    DEFAULT_BLOCK_SIZE as u64,
+                    PathBuf::from("."),
+                )
+                .await
+                .expect("Failed to create device");
+
+                device.load().await.expect("Failed to load");
+                assert_eq!(
+                    device.info().num_blocks(),
+                    1024,
+                    "device_type={}",
+                    device_type
+                );
+                assert_eq!(
+                    device.read(10, 2).await.expect("Failed to read data"),
+                    blocks,
+                    "device_type={}",
+                    device_type
+                );
+            }
+
+            tokio::fs::remove_file(&device_name)
+                .await
+                .expect("Failed to remove file");
+        }
+    }
+}
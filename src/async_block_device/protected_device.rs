@@ -0,0 +1,507 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::AsyncBlockDevice;
+use crate::block_device::write_ahead_log::{WriteAheadLog, DEFAULT_CHECKPOINT_THRESHOLD};
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Extension of the file keeping the protection information next to the device
+pub const PROTECTION_INFO_EXTENSION: &str = "pi";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ProtectionMode {
+    #[default]
+    None,
+    /// CRC32C of each block
+    Checksum,
+    /// CRC32C and the lba of each block, which also catches blocks read from a wrong lba
+    ChecksumAndReferenceTag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct BlockTag {
+    checksum: u32,
+    reference_tag: Option<u32>,
+}
+
+/// Tags of the blocks from the lba, logged before the blocks are changed
+#[derive(Debug, Serialize, Deserialize)]
+struct TagRecord {
+    lba: u64,
+    tags: Vec<Option<BlockTag>>,
+}
+
+/// Keeps the protection information of each block and verifies it on every read.
+/// The information is persisted into a separate file on flush, like the blocks of fake devices.
+/// If the writes to the device survive a crash without flush, the changed tags are also logged
+/// after the information in the file before each change, and the log is replayed on load.
+pub struct ProtectedDevice {
+    device: Box<dyn AsyncBlockDevice>,
+    mode: ProtectionMode,
+    /// None if the block is not written or unmapped
+    tags: RwLock<Vec<Option<BlockTag>>>,
+    /// Only exists if the device persists writes without flush, once the file is flushed or loaded
+    journal: Arc<Mutex<Option<WriteAheadLog>>>,
+    filepath: PathBuf,
+}
+
+impl ProtectedDevice {
+    /// The information is kept in `<name>.pi` in the location
+    pub fn new(device: Box<dyn AsyncBlockDevice>, mode: ProtectionMode, location: &Path) -> Self {
+        let filepath = protection_info_path(location, device.info().name());
+        ProtectedDevice {
+            tags: RwLock::new(vec![None; device.info().num_blocks() as usize]),
+            device,
+            mode,
+            journal: Arc::new(Mutex::new(None)),
+            filepath,
+        }
+    }
+
+    fn is_journaled(&self) -> bool {
+        self.device.info().device_type().persists_without_flush()
+    }
+
+    /// Replaces the tags of the blocks from the lba and returns the old ones. The new tags are
+    /// logged first, so that they are not lost if the blocks are written before a crash.
+    async fn set_tags(
+        &self,
+        lba: u64,
+        new_tags: Vec<Option<BlockTag>>,
+    ) -> Result<Vec<Option<BlockTag>>> {
+        if self.is_journaled() {
+            let journal = self.journal.clone();
+            let record = TagRecord {
+                lba,
+                tags: new_tags.clone(),
+            };
+            tokio::task::spawn_blocking(move || match lock_journal(&journal)?.as_mut() {
+                Some(journal) => journal.append(&record),
+                None => Ok(()),
+            })
+            .await
+            .map_err(|e| {
+                MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+            })??;
+        }
+
+        let range = lba as usize..lba as usize + new_tags.len();
+        let mut tags = self.tags_mut()?;
+        let old_tags = tags[range.clone()].to_vec();
+        tags[range].clone_from_slice(&new_tags);
+        Ok(old_tags)
+    }
+
+    /// Puts back the tags of a failed change, whose blocks are left unchanged or torn
+    async fn restore_tags(&self, lba: u64, old_tags: Vec<Option<BlockTag>>) {
+        if let Err(e) = self.set_tags(lba, old_tags).await {
+            tracing::warn!(
+                "Failed to restore tags, name={}, lba={}, err={}",
+                self.info().name(),
+                lba,
+                e
+            );
+        }
+    }
+
+    /// The tags may have been logged without the blocks written before a crash, so each logged
+    /// block takes the last of its tags which matches the block
+    async fn recover_tags(&self, records: Vec<TagRecord>) -> Result<()> {
+        let mut logged: BTreeMap<u64, Vec<Option<BlockTag>>> = BTreeMap::new();
+        for record in records {
+            for (lba, tag) in (record.lba..).zip(record.tags) {
+                logged.entry(lba).or_default().push(tag);
+            }
+        }
+        if let Some((&lba, _)) = logged.range(self.info().num_blocks()..).next() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Logged tag out of the device, path={:?}, lba={}",
+                    self.filepath, lba
+                ),
+            });
+        }
+
+        for (lba, logged_tags) in logged {
+            let checksum = self.device.read(lba, 1).await?[0].checksum();
+            let mut tags = self.tags_mut()?;
+            let slot = &mut tags[lba as usize];
+            *slot = std::iter::once(*slot)
+                .chain(logged_tags.iter().copied())
+                .rev()
+                .find(|tag| tag.is_none_or(|tag| tag.checksum == checksum))
+                .unwrap_or(logged_tags[logged_tags.len() - 1]);
+        }
+        Ok(())
+    }
+
+    fn tag(&self, lba: u64, block: &DataBlock) -> BlockTag {
+        BlockTag {
+            checksum: block.checksum(),
+            reference_tag: match self.mode {
+                ProtectionMode::ChecksumAndReferenceTag => Some(lba as u32),
+                _ => None,
+            },
+        }
+    }
+
+    fn verify(&self, lba: u64, blocks: &[DataBlock]) -> Result<()> {
+        let tags = self.tags()?;
+        for (offset, block) in blocks.iter().enumerate() {
+            let lba = lba + offset as u64;
+            let Some(tag) = tags[lba as usize] else {
+                continue;
+            };
+
+            let actual = block.checksum();
+            if actual != tag.checksum {
+                return Err(MinistoreError::ChecksumMismatch {
+                    lba,
+                    expected: tag.checksum,
+                    actual,
+                });
+            }
+            match tag.reference_tag {
+                Some(reference_tag) if reference_tag != lba as u32 => {
+                    return Err(MinistoreError::ReferenceTagMismatch { lba, reference_tag });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn tags(&self) -> Result<RwLockReadGuard<'_, Vec<Option<BlockTag>>>> {
+        self.tags
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn tags_mut(&self) -> Result<RwLockWriteGuard<'_, Vec<Option<BlockTag>>>> {
+        self.tags
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+pub(super) fn lock_journal(
+    journal: &Mutex<Option<WriteAheadLog>>,
+) -> Result<MutexGuard<'_, Option<WriteAheadLog>>> {
+    journal
+        .lock()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+pub fn protection_info_path(location: &Path, name: &str) -> PathBuf {
+    location.join(format!("{}.{}", name, PROTECTION_INFO_EXTENSION))
+}
+
+#[async_trait]
+impl AsyncBlockDevice for ProtectedDevice {
+    fn info(&self) -> &DeviceInfo {
+        self.device.info()
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        let tags = buffer
+            .iter()
+            .enumerate()
+            .map(|(offset, block)| Some(self.tag(lba + offset as u64, block)))
+            .collect();
+
+        let old_tags = self.set_tags(lba, tags).await?;
+        if let Err(e) = self.device.write(lba, num_blocks, buffer).await {
+            self.restore_tags(lba, old_tags).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        let blocks = self.device.read(lba, num_blocks).await?;
+        self.verify(lba, &blocks)?;
+        Ok(blocks)
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+
+        let old_tags = self.set_tags(lba, vec![None; num_blocks as usize]).await?;
+        if let Err(e) = self.device.unmap(lba, num_blocks).await {
+            self.restore_tags(lba, old_tags).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+
+        let zero_block = DataBlock::zeroed(self.info().block_size() as usize);
+        let tags = (lba..lba + num_blocks)
+            .map(|lba| Some(self.tag(lba, &zero_block)))
+            .collect();
+        let old_tags = self.set_tags(lba, tags).await?;
+        if let Err(e) = self.device.write_zeroes(lba, num_blocks).await {
+            self.restore_tags(lba, old_tags).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn load(&mut self) -> Result<()> {
+        self.device.load().await?;
+
+        let serialized = tokio::fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (mode, tags): (ProtectionMode, Vec<Option<BlockTag>>) =
+            bincode::deserialize(&serialized)?;
+        if tags.len() as u64 != self.device.info().num_blocks() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Protection information mismatch, num_blocks={}, num_tags={}",
+                    self.device.info().num_blocks(),
+                    tags.len()
+                ),
+            });
+        }
+
+        let image_len = bincode::serialized_size(&(mode, &tags))?;
+        self.mode = mode;
+        self.tags = RwLock::new(tags);
+        if !self.is_journaled() {
+            return Ok(());
+        }
+
+        let filepath = self.filepath.clone();
+        let (journal, records) = tokio::task::spawn_blocking(move || {
+            WriteAheadLog::replay(&filepath, image_len, DEFAULT_CHECKPOINT_THRESHOLD)
+        })
+        .await
+        .map_err(|e| {
+            MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+        })??;
+        self.recover_tags(records).await?;
+        self.journal = Arc::new(Mutex::new(Some(journal)));
+        Ok(())
+    }
+
+    /// The blocks are flushed before the tags, and the tags are replaced atomically. The log
+    /// starts again in the new file.
+    async fn flush(&self) -> Result<()> {
+        self.device.flush().await?;
+
+        let serialized = bincode::serialize(&(self.mode, &*self.tags()?))?;
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let filepath = self.filepath.clone();
+        let journal = self.is_journaled().then(|| self.journal.clone());
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(&tmp_path, serialized)
+                .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                })?;
+            std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+            })?;
+            sync_parent_dir(&filepath)?;
+            if let Some(journal) = journal {
+                *lock_journal(&journal)? = Some(WriteAheadLog::open(
+                    &filepath,
+                    DEFAULT_CHECKPOINT_THRESHOLD,
+                )?);
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::BlockDeviceType;
+    use tracing_test::traced_test;
+
+    async fn create_protected_device(name: &str, mode: ProtectionMode) -> ProtectedDevice {
+        create_protected_device_of_type(BlockDeviceType::SimpleFakeDevice, name, mode).await
+    }
+
+    async fn create_protected_device_of_type(
+        device_type: BlockDeviceType,
+        name: &str,
+        mode: ProtectionMode,
+    ) -> ProtectedDevice {
+        let device = create_async_block_device(
+            device_type,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        ProtectedDevice::new(device, mode, Path::new("."))
+    }
+
+    fn remove_files(name: &str) {
+        std::fs::remove_file(name).expect("Failed to remove file");
+        std::fs::remove_file(protection_info_path(Path::new("."), name))
+            .expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn corrupted_block_should_fail_to_read() {
+        let name = "corrupted_block_should_fail_to_read";
+        let block = DataBlock::from(vec![0xAB; DEFAULT_BLOCK_SIZE]);
+        {
+            let device = create_protected_device(name, ProtectionMode::Checksum).await;
+            device.write(3, 1, vec![block.clone()]).await.unwrap();
+            device.write_zeroes(4, 1).await.unwrap();
+            device.unmap(5, 1).await.unwrap();
+            assert_eq!(
+                device.read(3, 3).await.unwrap(),
+                vec![
+                    block.clone(),
+                    DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+                ]
+            );
+            device.flush().await.expect("Failed to flush");
+        }
+
+        // Flip a byte of the block in the image
+        let mut image = std::fs::read(name).unwrap();
+        let position = image
+            .windows(DEFAULT_BLOCK_SIZE)
+            .position(|window| window == block.0)
+            .expect("Block should be in the image");
+        image[position] ^= 0xFF;
+        std::fs::write(name, image).unwrap();
+
+        let mut device = create_protected_device(name, ProtectionMode::None).await;
+        device.load().await.expect("Failed to load");
+        assert!(matches!(
+            device.read(0, 8).await,
+            Err(MinistoreError::ChecksumMismatch { lba: 3, .. })
+        ));
+        assert!(device.read(4, 12).await.is_ok());
+
+        remove_files(name);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn misplaced_tag_should_fail_to_read() {
+        for mode in [
+            ProtectionMode::Checksum,
+            ProtectionMode::ChecksumAndReferenceTag,
+        ] {
+            let name = "misplaced_tag_should_fail_to_read";
+            let device = create_protected_device(name, mode).await;
+            let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+            device.write(0, 2, blocks.clone()).await.unwrap();
+
+            // Only the reference tag can tell that the tag of the same data is misplaced
+            device.tags_mut().unwrap().swap(0, 1);
+            let result = device.read(0, 2).await;
+            match mode {
+                ProtectionMode::ChecksumAndReferenceTag => assert!(matches!(
+                    result,
+                    Err(MinistoreError::ReferenceTagMismatch {
+                        lba: 0,
+                        reference_tag: 1
+                    })
+                )),
+                _ => assert_eq!(result.unwrap(), blocks),
+            }
+
+            std::fs::remove_file(name).expect("Failed to remove file");
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn tags_should_be_recovered_with_blocks_persisted_without_flush() {
+        // Every device type which persists blocks without flush should keep the tags as well
+        for device_type in [
+            BlockDeviceType::SimpleFakeWalDevice,
+            BlockDeviceType::IoUringFakeDevice,
+            BlockDeviceType::MmapFakeDevice,
+        ] {
+            assert!(device_type.persists_without_flush());
+            let name = "tags_should_be_recovered_with_blocks_persisted_without_flush";
+            let old_block = DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]);
+            let new_block = DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]);
+            {
+                let device = create_protected_device_of_type(
+                    device_type.clone(),
+                    name,
+                    ProtectionMode::ChecksumAndReferenceTag,
+                )
+                .await;
+                device
+                    .write(0, 2, vec![old_block.clone(); 2])
+                    .await
+                    .unwrap();
+                device.flush().await.expect("Failed to flush");
+
+                device.write(0, 1, vec![new_block.clone()]).await.unwrap();
+                device.write_zeroes(1, 1).await.unwrap();
+                device.write(2, 1, vec![new_block.clone()]).await.unwrap();
+                device.unmap(2, 1).await.unwrap();
+                device.write(4, 1, vec![new_block.clone()]).await.unwrap();
+
+                // The tag is logged, but the device crashes before the block is written
+                let tag = device.tag(3, &new_block);
+                device.set_tags(3, vec![Some(tag)]).await.unwrap();
+            }
+
+            let mut device =
+                create_protected_device_of_type(device_type, name, ProtectionMode::None).await;
+            device.load().await.expect("Failed to load");
+            assert_eq!(
+                device.read(0, 5).await.unwrap(),
+                vec![
+                    new_block.clone(),
+                    DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                    new_block,
+                ]
+            );
+            assert_eq!(device.tags().unwrap()[3], None);
+
+            // Misplaced tags are still caught after the recovery
+            device.tags_mut().unwrap().swap(0, 1);
+            assert!(matches!(
+                device.read(0, 1).await,
+                Err(MinistoreError::ChecksumMismatch { lba: 0, .. })
+            ));
+
+            remove_files(name);
+        }
+    }
+}
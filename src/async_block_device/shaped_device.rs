@@ -0,0 +1,268 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::AsyncBlockDevice;
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+
+/// Delays are capped, so that a profile with huge values cannot overflow the time arithmetic
+const MAX_DELAY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Jitter {
+    #[default]
+    None,
+    /// From 0 to jitter_us
+    Uniform,
+    /// Exponential distribution whose mean is jitter_us, which has a long tail
+    Exponential,
+}
+
+/// Performance of a fake device. The default adds nothing, so the device responds instantly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerformanceProfile {
+    /// Fixed latency of each I/O
+    pub latency_us: u64,
+    /// Latency added for each block read or written
+    pub per_block_latency_us: u64,
+    pub jitter: Jitter,
+    pub jitter_us: u64,
+    /// I/Os per second, unlimited if 0
+    pub iops_limit: u64,
+    /// Bytes read or written per second, unlimited if 0
+    pub bandwidth_limit: u64,
+}
+
+impl PerformanceProfile {
+    pub fn is_instant(&self) -> bool {
+        *self == PerformanceProfile::default()
+    }
+
+    fn latency(&self, num_blocks: u64) -> Duration {
+        let jitter_us = self.jitter_us as f64;
+        let jitter_us = match self.jitter {
+            Jitter::None => 0.0,
+            Jitter::Uniform => rand::thread_rng().gen_range(0.0..=jitter_us),
+            Jitter::Exponential => -jitter_us * (1.0 - rand::thread_rng().gen::<f64>()).ln(),
+        };
+        let latency_us = self
+            .per_block_latency_us
+            .saturating_mul(num_blocks)
+            .saturating_add(self.latency_us);
+        let latency = Duration::from_micros(latency_us)
+            .saturating_add(Duration::from_secs_f64(jitter_us / 1_000_000.0));
+        latency.min(MAX_DELAY)
+    }
+
+    /// Time the I/O occupies the device under the IOPS and bandwidth limits
+    fn occupancy(&self, num_bytes: u64) -> Duration {
+        let iops_secs = match self.iops_limit {
+            0 => 0.0,
+            iops_limit => 1.0 / iops_limit as f64,
+        };
+        let bandwidth_secs = match self.bandwidth_limit {
+            0 => 0.0,
+            bandwidth_limit => num_bytes as f64 / bandwidth_limit as f64,
+        };
+        Duration::try_from_secs_f64(iops_secs.max(bandwidth_secs))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY)
+    }
+}
+
+/// Delays the I/Os of the inner device by its PerformanceProfile, so that fake devices behave
+/// like real ones. I/Os are started one after another under the IOPS and bandwidth limits, and
+/// each of them waits for its latency concurrently.
+pub struct ShapedDevice {
+    device: Box<dyn AsyncBlockDevice>,
+    profile: PerformanceProfile,
+    /// When the next I/O can be started under the limits
+    next_start: Mutex<Instant>,
+}
+
+impl ShapedDevice {
+    pub fn new(device: Box<dyn AsyncBlockDevice>, profile: PerformanceProfile) -> Self {
+        ShapedDevice {
+            device,
+            profile,
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Unmaps and write zeroes do not transfer blocks, so only the fixed latency and the IOPS
+    /// limit are applied to them with num_blocks of 0
+    async fn shape(&self, num_blocks: u64) -> Result<()> {
+        let num_bytes = num_blocks.saturating_mul(self.info().block_size());
+        let occupancy = self.profile.occupancy(num_bytes);
+        if !occupancy.is_zero() {
+            let start = {
+                let mut next_start = self
+                    .next_start
+                    .lock()
+                    .map_err(|e| MinistoreError::internal(e.to_string()))?;
+                let start = std::cmp::max(*next_start, Instant::now());
+                *next_start = start + occupancy;
+                start
+            };
+            tokio::time::sleep_until(start).await;
+        }
+
+        let latency = self.profile.latency(num_blocks);
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for ShapedDevice {
+    fn info(&self) -> &DeviceInfo {
+        self.device.info()
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.shape(num_blocks).await?;
+        self.device.write(lba, num_blocks, buffer).await
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.shape(num_blocks).await?;
+        self.device.read(lba, num_blocks).await
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.shape(0).await?;
+        self.device.unmap(lba, num_blocks).await
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.shape(0).await?;
+        self.device.write_zeroes(lba, num_blocks).await
+    }
+
+    async fn load(&mut self) -> Result<()> {
+        self.device.load().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.device.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::BlockDeviceType;
+    use std::path::PathBuf;
+    use tracing_test::traced_test;
+
+    async fn create_shaped_device(name: &str, profile: PerformanceProfile) -> ShapedDevice {
+        let device = create_async_block_device(
+            BlockDeviceType::SimpleFakeDevice,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        ShapedDevice::new(device, profile)
+    }
+
+    #[test]
+    fn jitter_should_follow_the_distribution() {
+        let mut profile = PerformanceProfile {
+            latency_us: 100,
+            per_block_latency_us: 10,
+            jitter: Jitter::Uniform,
+            jitter_us: 50,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let latency = profile.latency(2);
+            assert!(latency >= Duration::from_micros(120));
+            assert!(latency <= Duration::from_micros(170));
+        }
+
+        profile.jitter = Jitter::Exponential;
+        let total: Duration = (0..10000).map(|_| profile.latency(0)).sum();
+        let mean_us = total.as_micros() / 10000;
+        assert!((140..160).contains(&mean_us), "mean_us={}", mean_us);
+    }
+
+    #[test]
+    fn huge_profile_should_not_overflow() {
+        let profile = PerformanceProfile {
+            latency_us: u64::MAX,
+            per_block_latency_us: u64::MAX,
+            jitter: Jitter::Exponential,
+            jitter_us: u64::MAX,
+            iops_limit: 1,
+            bandwidth_limit: 1,
+        };
+        assert_eq!(profile.latency(u64::MAX), MAX_DELAY);
+        assert_eq!(profile.occupancy(u64::MAX), MAX_DELAY);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn ios_should_be_delayed_by_latency_and_limits() {
+        let name = "ios_should_be_delayed_by_latency_and_limits";
+        let block = DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]);
+
+        let device = create_shaped_device(
+            name,
+            PerformanceProfile {
+                latency_us: 10_000,
+                per_block_latency_us: 5_000,
+                ..Default::default()
+            },
+        )
+        .await;
+        let start = Instant::now();
+        device.write(0, 2, vec![block.clone(); 2]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // 11 I/Os take 10 intervals of the limit
+        let device = create_shaped_device(
+            name,
+            PerformanceProfile {
+                iops_limit: 200,
+                ..Default::default()
+            },
+        )
+        .await;
+        let start = Instant::now();
+        for _ in 0..11 {
+            device.read(0, 1).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // 4 blocks at 10 blocks per second take 0.3 seconds after the first block
+        let device = create_shaped_device(
+            name,
+            PerformanceProfile {
+                bandwidth_limit: DEFAULT_BLOCK_SIZE as u64 * 10,
+                ..Default::default()
+            },
+        )
+        .await;
+        let start = Instant::now();
+        for lba in 0..4 {
+            device.write(lba, 1, vec![block.clone()]).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::protected_device::lock_journal;
+use super::AsyncBlockDevice;
+use crate::block_device::write_ahead_log::{WriteAheadLog, DEFAULT_CHECKPOINT_THRESHOLD};
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Extension of the file keeping the snapshots next to the device
+pub const SNAPSHOTS_EXTENSION: &str = "snapshots";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    /// Number of the old blocks kept by the snapshot
+    pub num_preserved_blocks: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    name: String,
+    created_at: u64,
+    /// Blocks overwritten after the snapshot is taken and before the next snapshot is taken
+    blocks: BTreeMap<u64, DataBlock>,
+}
+
+/// Change of the snapshots, logged before the blocks are changed
+#[derive(Serialize, Deserialize)]
+enum SnapshotRecord {
+    Create {
+        name: String,
+        created_at: u64,
+    },
+    Delete {
+        name: String,
+    },
+    /// Old blocks from the lba saved into the newest snapshot
+    Preserve {
+        lba: u64,
+        blocks: Vec<DataBlock>,
+    },
+}
+
+/// Keeps copy-on-write snapshots of the inner device. Taking a snapshot only adds an empty map,
+/// and the first write to a block after that saves the old block into the newest snapshot.
+/// A block of a snapshot is found in the snapshot or in a newer one, or is still in the device.
+/// The snapshots are persisted into a separate file on flush, like the blocks of fake devices.
+/// If the writes to the device survive a crash without flush, the changes of the snapshots are
+/// also logged after the snapshots in the file before the blocks are changed, and the log is
+/// replayed on load.
+pub struct SnapshotDevice {
+    device: Box<dyn AsyncBlockDevice>,
+    /// Oldest first
+    snapshots: RwLock<Vec<Snapshot>>,
+    /// Whether the snapshots are changed after the last flush, or the file is not written yet
+    /// while the log needs it
+    dirty: AtomicBool,
+    /// Only exists if the device persists writes without flush, once the file is flushed or loaded
+    journal: Arc<Mutex<Option<WriteAheadLog>>>,
+    filepath: PathBuf,
+}
+
+impl SnapshotDevice {
+    /// The snapshots are kept in `<name>.snapshots` in the location
+    pub fn new(device: Box<dyn AsyncBlockDevice>, location: &Path) -> Self {
+        let filepath = snapshots_path(location, device.info().name());
+        let is_journaled = device.info().device_type().persists_without_flush();
+        SnapshotDevice {
+            device,
+            snapshots: RwLock::new(Vec::new()),
+            dirty: AtomicBool::new(is_journaled),
+            journal: Arc::new(Mutex::new(None)),
+            filepath,
+        }
+    }
+
+    fn is_journaled(&self) -> bool {
+        self.device.info().device_type().persists_without_flush()
+    }
+
+    /// Logs the change and applies it to the snapshots. Callers should hold the lock of the
+    /// snapshots, so that the changes are logged in the order they are applied.
+    async fn change(&self, snapshots: &mut Vec<Snapshot>, record: SnapshotRecord) -> Result<()> {
+        let record = match self.is_journaled() {
+            true => {
+                let journal = self.journal.clone();
+                tokio::task::spawn_blocking(move || -> Result<SnapshotRecord> {
+                    if let Some(journal) = lock_journal(&journal)?.as_mut() {
+                        journal.append(&record)?;
+                    }
+                    Ok(record)
+                })
+                .await
+                .map_err(|e| {
+                    MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+                })??
+            }
+            false => record,
+        };
+        apply(snapshots, record);
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Callers should wait for the in-flight writes, so that the snapshot is taken at a point
+    pub async fn create_snapshot(&self, snapshot_name: &str) -> Result<()> {
+        if snapshot_name.is_empty() {
+            return Err(MinistoreError::invalid_argument("Snapshot name is empty"));
+        }
+        let mut snapshots = self.snapshots.write().await;
+        if snapshots
+            .iter()
+            .any(|snapshot| snapshot.name == snapshot_name)
+        {
+            return Err(MinistoreError::SnapshotAlreadyExists {
+                name: self.info().name().clone(),
+                snapshot: snapshot_name.to_string(),
+            });
+        }
+
+        let record = SnapshotRecord::Create {
+            name: snapshot_name.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        };
+        self.change(&mut snapshots, record).await
+    }
+
+    /// Oldest first
+    pub async fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snapshots
+            .read()
+            .await
+            .iter()
+            .map(|snapshot| SnapshotInfo {
+                name: snapshot.name.clone(),
+                created_at: snapshot.created_at,
+                num_preserved_blocks: snapshot.blocks.len() as u64,
+            })
+            .collect()
+    }
+
+    /// Reads the blocks as they were when the snapshot was taken
+    pub async fn read_snapshot(
+        &self,
+        snapshot_name: &str,
+        lba: u64,
+        num_blocks: u64,
+    ) -> Result<Vec<DataBlock>> {
+        // Writes wait for this lock to save the blocks, so the device is not changed under it
+        let snapshots = self.snapshots.read().await;
+        let index = self.find(&snapshots, snapshot_name)?;
+        let mut buffer = self.device.read(lba, num_blocks).await?;
+        for snapshot in snapshots[index..].iter().rev() {
+            for (block_lba, block) in snapshot.blocks.range(lba..lba + num_blocks) {
+                buffer[(block_lba - lba) as usize] = block.clone();
+            }
+        }
+        Ok(buffer)
+    }
+
+    /// Writes back the blocks changed after the snapshot. The snapshot and the newer ones are
+    /// kept, since the changed blocks are saved into the newest snapshot again. Callers should
+    /// block the I/Os to the device while it is restored.
+    pub async fn restore_snapshot(&self, snapshot_name: &str) -> Result<()> {
+        let blocks = {
+            let snapshots = self.snapshots.read().await;
+            let index = self.find(&snapshots, snapshot_name)?;
+            let mut blocks = BTreeMap::new();
+            for snapshot in snapshots[index..].iter().rev() {
+                blocks.extend(
+                    snapshot
+                        .blocks
+                        .iter()
+                        .map(|(lba, block)| (*lba, block.clone())),
+                );
+            }
+            blocks
+        };
+
+        let unmapped = DataBlock::unmapped(self.info().block_size() as usize);
+        for (lba, block) in blocks {
+            self.preserve(lba, 1).await?;
+            match block == unmapped {
+                true => self.device.unmap(lba, 1).await?,
+                false => self.device.write(lba, 1, vec![block]).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The blocks of the snapshot are moved into the previous one, which may still need them
+    pub async fn delete_snapshot(&self, snapshot_name: &str) -> Result<()> {
+        let mut snapshots = self.snapshots.write().await;
+        self.find(&snapshots, snapshot_name)?;
+        let record = SnapshotRecord::Delete {
+            name: snapshot_name.to_string(),
+        };
+        self.change(&mut snapshots, record).await
+    }
+
+    fn find(&self, snapshots: &[Snapshot], snapshot_name: &str) -> Result<usize> {
+        snapshots
+            .iter()
+            .position(|snapshot| snapshot.name == snapshot_name)
+            .ok_or_else(|| MinistoreError::SnapshotNotFound {
+                name: self.info().name().clone(),
+                snapshot: snapshot_name.to_string(),
+            })
+    }
+
+    /// Saves the blocks about to be changed into the newest snapshot, unless saved already
+    async fn preserve(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        {
+            let snapshots = self.snapshots.read().await;
+            match snapshots.last() {
+                None => return Ok(()),
+                Some(newest)
+                    if newest.blocks.range(lba..lba + num_blocks).count() as u64 == num_blocks =>
+                {
+                    return Ok(())
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut snapshots = self.snapshots.write().await;
+        if snapshots.is_empty() {
+            return Ok(());
+        }
+        let blocks = self.device.read(lba, num_blocks).await?;
+        self.change(&mut snapshots, SnapshotRecord::Preserve { lba, blocks })
+            .await
+    }
+}
+
+/// Replaying a change more than once leaves the same snapshots, like the records of the log
+fn apply(snapshots: &mut Vec<Snapshot>, record: SnapshotRecord) {
+    match record {
+        SnapshotRecord::Create { name, created_at } => {
+            if snapshots.iter().all(|snapshot| snapshot.name != name) {
+                snapshots.push(Snapshot {
+                    name,
+                    created_at,
+                    blocks: BTreeMap::new(),
+                });
+            }
+        }
+        SnapshotRecord::Delete { name } => {
+            let Some(index) = snapshots.iter().position(|snapshot| snapshot.name == name) else {
+                return;
+            };
+            let deleted = snapshots.remove(index);
+            if let Some(previous) = index.checked_sub(1).map(|index| &mut snapshots[index]) {
+                for (lba, block) in deleted.blocks {
+                    previous.blocks.entry(lba).or_insert(block);
+                }
+            }
+        }
+        SnapshotRecord::Preserve { lba, blocks } => {
+            if let Some(newest) = snapshots.last_mut() {
+                for (lba, block) in (lba..).zip(blocks) {
+                    newest.blocks.entry(lba).or_insert(block);
+                }
+            }
+        }
+    }
+}
+
+pub fn snapshots_path(location: &Path, name: &str) -> PathBuf {
+    location.join(format!("{}.{}", name, SNAPSHOTS_EXTENSION))
+}
+
+#[async_trait]
+impl AsyncBlockDevice for SnapshotDevice {
+    fn info(&self) -> &DeviceInfo {
+        self.device.info()
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+        self.preserve(lba, num_blocks).await?;
+        self.device.write(lba, num_blocks, buffer).await
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device.read(lba, num_blocks).await
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+        self.preserve(lba, num_blocks).await?;
+        self.device.unmap(lba, num_blocks).await
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info().check_lba_range(lba, num_blocks)?;
+        self.preserve(lba, num_blocks).await?;
+        self.device.write_zeroes(lba, num_blocks).await
+    }
+
+    /// Devices flushed before snapshots are supported have no snapshot file
+    async fn load(&mut self) -> Result<()> {
+        self.device.load().await?;
+
+        let serialized = match tokio::fs::read(&self.filepath).await {
+            Ok(serialized) => serialized,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(MinistoreError::io(
+                    format!("Failed to read file, path={:?}", self.filepath),
+                    e,
+                ))
+            }
+        };
+        let mut snapshots: Vec<Snapshot> = bincode::deserialize(&serialized)?;
+        if self.is_journaled() {
+            let image_len = bincode::serialized_size(&snapshots)?;
+            let filepath = self.filepath.clone();
+            let (journal, records) = tokio::task::spawn_blocking(move || {
+                WriteAheadLog::replay(&filepath, image_len, DEFAULT_CHECKPOINT_THRESHOLD)
+            })
+            .await
+            .map_err(|e| {
+                MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+            })??;
+            for record in records {
+                apply(&mut snapshots, record);
+            }
+            self.journal = Arc::new(Mutex::new(Some(journal)));
+        }
+
+        let num_blocks = self.info().num_blocks();
+        if let Some((snapshot, lba)) = snapshots.iter().find_map(|snapshot| {
+            snapshot
+                .blocks
+                .range(num_blocks..)
+                .next()
+                .map(|(lba, _)| (&snapshot.name, lba))
+        }) {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Snapshot block out of the device, snapshot={}, num_blocks={}, lba={}",
+                    snapshot, num_blocks, lba
+                ),
+            });
+        }
+
+        self.snapshots = RwLock::new(snapshots);
+        self.dirty = AtomicBool::new(false);
+        Ok(())
+    }
+
+    /// The snapshots are replaced atomically before the blocks are flushed. If the blocks are
+    /// only persisted on flush, the blocks saved by the snapshots are never newer than the
+    /// flushed blocks, and otherwise the saved blocks are logged before the blocks are changed.
+    /// The log starts again in the new file.
+    async fn flush(&self) -> Result<()> {
+        // Changes wait for the lock, so that none of them is logged into the replaced file
+        let snapshots = self.snapshots.read().await;
+        if self.dirty.swap(false, Ordering::SeqCst) {
+            let serialized = bincode::serialize(&*snapshots)?;
+            let mut tmp_path = self.filepath.clone().into_os_string();
+            tmp_path.push(".tmp");
+            let tmp_path = PathBuf::from(tmp_path);
+
+            let filepath = self.filepath.clone();
+            let journal = self.is_journaled().then(|| self.journal.clone());
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                std::fs::write(&tmp_path, serialized)
+                    .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                    .map_err(|e| {
+                        MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                    })?;
+                std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                    MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+                })?;
+                sync_parent_dir(&filepath)?;
+                if let Some(journal) = journal {
+                    *lock_journal(&journal)? = Some(WriteAheadLog::open(
+                        &filepath,
+                        DEFAULT_CHECKPOINT_THRESHOLD,
+                    )?);
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| {
+                MinistoreError::internal(format!("Failed to run blocking task, err={}", e))
+            })?
+            .inspect_err(|_| self.dirty.store(true, Ordering::SeqCst))?;
+        }
+        drop(snapshots);
+
+        self.device.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::test_utils::blocks;
+    use crate::block_device_common::BlockDeviceType;
+    use tracing_test::traced_test;
+
+    async fn create_snapshot_device(name: &str) -> SnapshotDevice {
+        create_snapshot_device_of_type(BlockDeviceType::SimpleFakeDevice, name).await
+    }
+
+    async fn create_snapshot_device_of_type(
+        device_type: BlockDeviceType,
+        name: &str,
+    ) -> SnapshotDevice {
+        let device = create_async_block_device(
+            device_type,
+            name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        SnapshotDevice::new(device, Path::new("."))
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn snapshots_should_keep_blocks_at_the_time_they_are_taken() {
+        let name = "snapshots_should_keep_blocks_at_the_time_they_are_taken";
+        let device = create_snapshot_device(name).await;
+        let unmapped = DataBlock::unmapped(DEFAULT_BLOCK_SIZE);
+        device.write(0, 2, blocks(0xA, 2)).await.unwrap();
+
+        device.create_snapshot("first").await.unwrap();
+        device.write(1, 2, blocks(0xB, 2)).await.unwrap();
+        device.create_snapshot("second").await.unwrap();
+        device.write_zeroes(0, 1).await.unwrap();
+        device.unmap(1, 1).await.unwrap();
+        assert!(matches!(
+            device.create_snapshot("first").await,
+            Err(MinistoreError::SnapshotAlreadyExists { .. })
+        ));
+
+        assert_eq!(
+            device.read_snapshot("first", 0, 3).await.unwrap(),
+            [blocks(0xA, 2), vec![unmapped.clone()]].concat()
+        );
+        assert_eq!(
+            device.read_snapshot("second", 0, 3).await.unwrap(),
+            [blocks(0xA, 1), blocks(0xB, 2)].concat()
+        );
+        let snapshots = device.list_snapshots().await;
+        assert_eq!(
+            snapshots
+                .iter()
+                .map(|snapshot| (snapshot.name.as_str(), snapshot.num_preserved_blocks))
+                .collect::<Vec<_>>(),
+            vec![("first", 2), ("second", 2)]
+        );
+
+        // Blocks of the deleted snapshot are kept for the previous one
+        device.delete_snapshot("second").await.unwrap();
+        assert_eq!(
+            device.read_snapshot("first", 0, 3).await.unwrap(),
+            [blocks(0xA, 2), vec![unmapped.clone()]].concat()
+        );
+
+        device.restore_snapshot("first").await.unwrap();
+        assert_eq!(
+            device.read(0, 3).await.unwrap(),
+            [blocks(0xA, 2), vec![unmapped]].concat()
+        );
+        assert!(matches!(
+            device.restore_snapshot("second").await,
+            Err(MinistoreError::SnapshotNotFound { .. })
+        ));
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn snapshots_should_be_kept_after_flush_and_load() {
+        let name = "snapshots_should_be_kept_after_flush_and_load";
+        {
+            let device = create_snapshot_device(name).await;
+            device.write(0, 1, blocks(0xA, 1)).await.unwrap();
+            device.create_snapshot("snapshot").await.unwrap();
+            device.write(0, 1, blocks(0xB, 1)).await.unwrap();
+            device.flush().await.expect("Failed to flush");
+        }
+
+        let mut device = create_snapshot_device(name).await;
+        device.load().await.expect("Failed to load");
+        assert_eq!(device.read(0, 1).await.unwrap(), blocks(0xB, 1));
+        assert_eq!(
+            device.read_snapshot("snapshot", 0, 1).await.unwrap(),
+            blocks(0xA, 1)
+        );
+
+        std::fs::remove_file(name).expect("Failed to remove file");
+        std::fs::remove_file(snapshots_path(Path::new("."), name)).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn saved_blocks_should_be_recovered_with_blocks_persisted_without_flush() {
+        for device_type in [
+            BlockDeviceType::SimpleFakeWalDevice,
+            BlockDeviceType::IoUringFakeDevice,
+            BlockDeviceType::MmapFakeDevice,
+        ] {
+            let name = "saved_blocks_should_be_recovered_with_blocks_persisted_without_flush";
+            {
+                let device = create_snapshot_device_of_type(device_type.clone(), name).await;
+                device.write(0, 2, blocks(0xA, 2)).await.unwrap();
+                device.create_snapshot("deleted").await.unwrap();
+                device.flush().await.expect("Failed to flush");
+
+                // The device crashes without flushing the following changes
+                device.create_snapshot("snapshot").await.unwrap();
+                device.write(0, 1, blocks(0xB, 1)).await.unwrap();
+                device.delete_snapshot("deleted").await.unwrap();
+                device.unmap(1, 1).await.unwrap();
+            }
+
+            let mut device = create_snapshot_device_of_type(device_type, name).await;
+            device.load().await.expect("Failed to load");
+            assert_eq!(
+                device.read(0, 2).await.unwrap(),
+                [
+                    blocks(0xB, 1),
+                    vec![DataBlock::unmapped(DEFAULT_BLOCK_SIZE)]
+                ]
+                .concat()
+            );
+            assert_eq!(
+                device
+                    .list_snapshots()
+                    .await
+                    .iter()
+                    .map(|snapshot| (snapshot.name.as_str(), snapshot.num_preserved_blocks))
+                    .collect::<Vec<_>>(),
+                vec![("snapshot", 2)]
+            );
+            assert_eq!(
+                device.read_snapshot("snapshot", 0, 2).await.unwrap(),
+                blocks(0xA, 2)
+            );
+
+            std::fs::remove_file(name).expect("Failed to remove file");
+            std::fs::remove_file(snapshots_path(Path::new("."), name))
+                .expect("Failed to remove file");
+        }
+    }
+}
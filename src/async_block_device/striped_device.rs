@@ -0,0 +1,1146 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::mirror_device::MemberState;
+use super::volume::Member;
+use super::AsyncBlockDevice;
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::block_device_common::range_lock::{LockMode, RangeLock};
+use crate::block_device_common::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Times a member is read before it is failed, so that a transient error does not fail it
+const READ_ATTEMPTS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RaidLevel {
+    /// Striping without redundancy
+    Raid0,
+    /// Striping with a parity stripe rotating across the members in each row
+    Raid5,
+}
+
+impl RaidLevel {
+    pub fn device_type(&self) -> BlockDeviceType {
+        match self {
+            RaidLevel::Raid0 => BlockDeviceType::Raid0,
+            RaidLevel::Raid5 => BlockDeviceType::Raid5,
+        }
+    }
+
+    pub fn from_device_type(device_type: &BlockDeviceType) -> Option<RaidLevel> {
+        match device_type {
+            BlockDeviceType::Raid0 => Some(RaidLevel::Raid0),
+            BlockDeviceType::Raid5 => Some(RaidLevel::Raid5),
+            _ => None,
+        }
+    }
+
+    fn min_members(&self) -> usize {
+        match self {
+            RaidLevel::Raid0 => 2,
+            RaidLevel::Raid5 => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayStatus {
+    pub name: String,
+    pub level: RaidLevel,
+    pub size: u64,
+    pub block_size: u64,
+    pub stripe_size: u64,
+    pub members: Vec<String>,
+    /// Members whose blocks are reconstructed from the others, only in RAID-5 arrays
+    pub failed_members: Vec<String>,
+    /// Member replacing a failed one, whose blocks are reconstructed until it is rebuilt
+    pub rebuilding_member: Option<String>,
+    /// Rebuilt onto the rebuilding member so far
+    pub rebuilt_blocks: u64,
+}
+
+impl ArrayStatus {
+    pub fn is_degraded(&self) -> bool {
+        !self.failed_members.is_empty() || self.rebuilding_member.is_some()
+    }
+}
+
+struct ArrayMember {
+    member: Member,
+    state: MemberState,
+    /// Rows rebuilt onto the member so far, only while rebuilding
+    rebuilt_rows: u64,
+}
+
+/// Part of an I/O within a stripe
+struct Segment {
+    row: u64,
+    member: usize,
+    /// Member keeping the parity of the row, only in RAID-5 arrays
+    parity: Option<usize>,
+    physical_lba: u64,
+    num_blocks: u64,
+}
+
+/// RAID-0 or RAID-5 array striping the blocks across the member devices. Each row has a stripe of
+/// the stripe size on every member, and a RAID-5 array keeps the parity of the other stripes on
+/// one of them, rotating from the last member. A RAID-5 array keeps working when a member fails,
+/// reconstructing its blocks from the other members, until the member is replaced and rebuilt.
+/// The member states are persisted into a file on flush.
+pub struct StripedDevice {
+    info: DeviceInfo,
+    level: RaidLevel,
+    stripe_blocks: u64,
+    num_members: usize,
+    members: RwLock<Vec<ArrayMember>>,
+    /// Locks the rows while their parity is updated or used for reconstruction
+    row_lock: RangeLock,
+    /// Set when the array is deleted, which stops the rebuild
+    stopped: AtomicBool,
+    filepath: PathBuf,
+}
+
+impl StripedDevice {
+    /// Members should have the same block size, and only the rows which fit in the smallest
+    /// member are used
+    pub fn new(
+        name: String,
+        level: RaidLevel,
+        stripe_size: u64,
+        members: Vec<Member>,
+        location: &Path,
+    ) -> Result<Self> {
+        if members.len() < level.min_members() {
+            return Err(MinistoreError::invalid_argument(format!(
+                "{:?} array should have at least {} members, num_members={}",
+                level,
+                level.min_members(),
+                members.len()
+            )));
+        }
+        let block_size = members[0].device.info().block_size();
+        if let Some(member) = members
+            .iter()
+            .find(|member| member.device.info().block_size() != block_size)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Members should have the same block size, name={}, block_size={}, expected={}",
+                member.name,
+                member.device.info().block_size(),
+                block_size
+            )));
+        }
+        if stripe_size == 0 || !stripe_size.is_multiple_of(block_size) {
+            return Err(MinistoreError::UnalignedSize {
+                size: stripe_size,
+                block_size,
+            });
+        }
+
+        let stripe_blocks = stripe_size / block_size;
+        let num_rows = members
+            .iter()
+            .map(|member| member.device.info().num_blocks() / stripe_blocks)
+            .min()
+            .unwrap_or_default();
+        if num_rows == 0 {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Members should be larger than the stripe size, stripe_size={}",
+                stripe_size
+            )));
+        }
+        let num_data_members = match level {
+            RaidLevel::Raid0 => members.len(),
+            RaidLevel::Raid5 => members.len() - 1,
+        } as u64;
+        let size = num_rows * stripe_size * num_data_members;
+
+        Ok(StripedDevice {
+            info: DeviceInfo::new(level.device_type(), name.clone(), size, block_size)?,
+            level,
+            stripe_blocks,
+            num_members: members.len(),
+            members: RwLock::new(
+                members
+                    .into_iter()
+                    .map(|member| ArrayMember {
+                        member,
+                        state: MemberState::Active,
+                        rebuilt_rows: 0,
+                    })
+                    .collect(),
+            ),
+            row_lock: RangeLock::new(),
+            stopped: AtomicBool::new(false),
+            filepath: location.join(name),
+        })
+    }
+
+    pub fn member_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .members()?
+            .iter()
+            .map(|member| member.member.name.clone())
+            .collect())
+    }
+
+    pub fn status(&self) -> Result<ArrayStatus> {
+        let members = self.members()?;
+        let rebuilding = members
+            .iter()
+            .find(|member| member.state == MemberState::Rebuilding);
+        Ok(ArrayStatus {
+            name: self.info.name().clone(),
+            level: self.level,
+            size: self.info.device_size(),
+            block_size: self.info.block_size(),
+            stripe_size: self.stripe_blocks * self.info.block_size(),
+            members: members
+                .iter()
+                .map(|member| member.member.name.clone())
+                .collect(),
+            failed_members: members
+                .iter()
+                .filter(|member| member.state == MemberState::Failed)
+                .map(|member| member.member.name.clone())
+                .collect(),
+            rebuilding_member: rebuilding.map(|member| member.member.name.clone()),
+            rebuilt_blocks: rebuilding
+                .map(|member| member.rebuilt_rows * self.stripe_blocks)
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn is_rebuilding(&self) -> Result<bool> {
+        Ok(self
+            .members()?
+            .iter()
+            .any(|member| member.state == MemberState::Rebuilding))
+    }
+
+    /// Unmaps the members, and makes the parity consistent with the unmapped blocks. It should
+    /// be called once when the array is created.
+    pub async fn initialize(&self) -> Result<()> {
+        let num_rows = self.num_rows();
+        for index in 0..self.num_members {
+            self.member(index)?
+                .device
+                .unmap(0, num_rows * self.stripe_blocks)
+                .await?;
+        }
+
+        // Parity of an even number of unmapped stripes is zero
+        if self.level == RaidLevel::Raid5 && (self.num_members - 1).is_multiple_of(2) {
+            for row in 0..num_rows {
+                self.member(self.parity_member(row))?
+                    .device
+                    .write_zeroes(row * self.stripe_blocks, self.stripe_blocks)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the member of a RAID-5 array with the new device, which should be rebuilt before
+    /// its blocks are read. The other members should be active to rebuild it from.
+    pub fn replace_member(&self, old_name: &str, new_member: Member) -> Result<()> {
+        if self.level != RaidLevel::Raid5 {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Only RAID-5 array can be rebuilt, name={}, level={:?}",
+                self.info.name(),
+                self.level
+            )));
+        }
+        if new_member.device.info().block_size() != self.info.block_size()
+            || new_member.device.info().num_blocks() < self.num_rows() * self.stripe_blocks
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "New member should have the same block size and enough size, name={}, size={}, block_size={}",
+                new_member.name,
+                new_member.device.info().device_size(),
+                new_member.device.info().block_size()
+            )));
+        }
+
+        let mut members = self.members_mut()?;
+        if members
+            .iter()
+            .any(|member| member.member.name == new_member.name)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Device is already a member, name={}",
+                new_member.name
+            )));
+        }
+        let Some(index) = members
+            .iter()
+            .position(|member| member.member.name == old_name)
+        else {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Device is not a member, name={}",
+                old_name
+            )));
+        };
+        if members
+            .iter()
+            .enumerate()
+            .any(|(other, member)| other != index && member.state != MemberState::Active)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Other members should be active to rebuild from, name={}",
+                self.info.name()
+            )));
+        }
+
+        tracing::info!(
+            "Replaced array member, name={}, old={}, new={}",
+            self.info.name(),
+            old_name,
+            new_member.name
+        );
+        members[index] = ArrayMember {
+            member: new_member,
+            state: MemberState::Rebuilding,
+            rebuilt_rows: 0,
+        };
+        Ok(())
+    }
+
+    /// Reconstructs every row onto the rebuilding member, and makes it active. Each row is locked
+    /// while it is rebuilt, and written normally once it is rebuilt.
+    pub async fn rebuild(&self) -> Result<()> {
+        let num_rows = self.num_rows();
+        let mut row = 0;
+        loop {
+            if self.is_stopped() {
+                return Ok(());
+            }
+            // The member may fail meanwhile
+            let Some((index, member)) = self.rebuilding_member()? else {
+                return Ok(());
+            };
+            if row >= num_rows {
+                self.finish_rebuild(index, &member.name)?;
+                return Ok(());
+            }
+
+            let _guard = self.row_lock.lock(row, 1, LockMode::Exclusive).await;
+            let segment = Segment {
+                row,
+                member: index,
+                parity: Some(self.parity_member(row)),
+                physical_lba: row * self.stripe_blocks,
+                num_blocks: self.stripe_blocks,
+            };
+            let blocks = self.reconstruct(&segment).await?;
+            if let Err(e) = member
+                .device
+                .write(segment.physical_lba, segment.num_blocks, blocks)
+                .await
+            {
+                let message = e.to_string();
+                self.fail(index, &member.name, e)?;
+                return Err(MinistoreError::io(
+                    format!(
+                        "Failed to rebuild array member, name={}, member={}, err={}",
+                        self.info.name(),
+                        member.name,
+                        message
+                    ),
+                    std::io::ErrorKind::Other.into(),
+                ));
+            }
+            row += 1;
+            self.update_member(index, &member.name, |member| member.rebuilt_rows = row)?;
+        }
+    }
+
+    /// Stops the rebuild before the array is deleted
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn rebuilding_member(&self) -> Result<Option<(usize, Member)>> {
+        Ok(self
+            .members()?
+            .iter()
+            .enumerate()
+            .find(|(_, member)| member.state == MemberState::Rebuilding)
+            .map(|(index, member)| (index, member.member.clone())))
+    }
+
+    fn finish_rebuild(&self, index: usize, name: &str) -> Result<()> {
+        self.update_member(index, name, |member| {
+            member.state = MemberState::Active;
+            member.rebuilt_rows = 0;
+        })?;
+        tracing::info!(
+            "Finished rebuilding array member, name={}, member={}",
+            self.info.name(),
+            name
+        );
+        Ok(())
+    }
+
+    fn num_data_members(&self) -> u64 {
+        match self.level {
+            RaidLevel::Raid0 => self.num_members as u64,
+            RaidLevel::Raid5 => self.num_members as u64 - 1,
+        }
+    }
+
+    fn num_rows(&self) -> u64 {
+        self.info.num_blocks() / (self.stripe_blocks * self.num_data_members())
+    }
+
+    fn parity_member(&self, row: u64) -> usize {
+        let num_members = self.num_members as u64;
+        (num_members - 1 - row % num_members) as usize
+    }
+
+    /// Splits the range at the stripe boundaries
+    fn segments(&self, lba: u64, num_blocks: u64) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut lba = lba;
+        let end = lba + num_blocks;
+        while lba < end {
+            let stripe = lba / self.stripe_blocks;
+            let offset = lba % self.stripe_blocks;
+            let num_blocks = std::cmp::min(self.stripe_blocks - offset, end - lba);
+
+            let row = stripe / self.num_data_members();
+            let index = (stripe % self.num_data_members()) as usize;
+            let (member, parity) = match self.level {
+                RaidLevel::Raid0 => (index, None),
+                RaidLevel::Raid5 => {
+                    let parity = self.parity_member(row);
+                    ((parity + 1 + index) % self.num_members, Some(parity))
+                }
+            };
+            segments.push(Segment {
+                row,
+                member,
+                parity,
+                physical_lba: row * self.stripe_blocks + offset,
+                num_blocks,
+            });
+            lba += num_blocks;
+        }
+        segments
+    }
+
+    /// Reads the blocks of the segment, reconstructing them if the member has failed. The row
+    /// should be locked by the caller in RAID-5 arrays.
+    async fn read_segment(&self, segment: &Segment) -> Result<Vec<DataBlock>> {
+        if self.is_available(segment.member, segment.row)? {
+            let member = self.member(segment.member)?;
+            match read_member(&member, segment.physical_lba, segment.num_blocks).await {
+                Ok(blocks) => return Ok(blocks),
+                Err(e) if self.level == RaidLevel::Raid0 => return Err(e),
+                Err(e) => self.fail(segment.member, &member.name, e)?,
+            }
+        }
+        self.reconstruct(segment).await
+    }
+
+    /// XOR of the same blocks of the other members
+    async fn reconstruct(&self, segment: &Segment) -> Result<Vec<DataBlock>> {
+        let mut buffer =
+            vec![vec![0; self.info.block_size() as usize]; segment.num_blocks as usize];
+        for index in 0..self.num_members {
+            if index == segment.member {
+                continue;
+            }
+            if !self.is_available(index, segment.row)? {
+                return Err(self.too_many_failures());
+            }
+            let member = self.member(index)?;
+            let blocks = match read_member(&member, segment.physical_lba, segment.num_blocks).await
+            {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    self.fail(index, &member.name, e)?;
+                    return Err(self.too_many_failures());
+                }
+            };
+            xor(&mut buffer, &blocks);
+        }
+        Ok(buffer.into_iter().map(DataBlock::from).collect())
+    }
+
+    /// Updates the parity with the difference between the old and the new blocks. The blocks of
+    /// a member which is not available are only kept in the parity.
+    async fn write_raid5_segment(&self, segment: &Segment, blocks: Vec<DataBlock>) -> Result<()> {
+        let Some(parity) = segment.parity else {
+            return Err(MinistoreError::internal("RAID-5 segment has no parity"));
+        };
+        let _guard = self
+            .row_lock
+            .lock(segment.row, 1, LockMode::Exclusive)
+            .await;
+
+        let old_blocks = self.read_segment(segment).await?;
+        let parity_member = self.member(parity)?;
+        let old_parity = match self.is_available(parity, segment.row)? {
+            false => None,
+            true => {
+                match read_member(&parity_member, segment.physical_lba, segment.num_blocks).await {
+                    Ok(old_parity) => Some(old_parity),
+                    Err(e) => {
+                        self.fail(parity, &parity_member.name, e)?;
+                        None
+                    }
+                }
+            }
+        };
+
+        let new_parity = old_parity.map(|old_parity| {
+            let mut new_parity: Vec<Vec<u8>> = old_parity
+                .iter()
+                .map(|block| block.as_slice().to_vec())
+                .collect();
+            xor(&mut new_parity, &old_blocks);
+            xor(&mut new_parity, &blocks);
+            new_parity.into_iter().map(DataBlock::from).collect()
+        });
+        if self.is_available(segment.member, segment.row)? {
+            let member = self.member(segment.member)?;
+            if let Err(e) = member
+                .device
+                .write(segment.physical_lba, segment.num_blocks, blocks)
+                .await
+            {
+                self.fail(segment.member, &member.name, e)?;
+            }
+        }
+        if let Some(new_parity) = new_parity {
+            if let Err(e) = parity_member
+                .device
+                .write(segment.physical_lba, segment.num_blocks, new_parity)
+                .await
+            {
+                self.fail(parity, &parity_member.name, e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the member as failed unless it is replaced meanwhile, and returns the error if the
+    /// array cannot keep working, i.e. other member has failed or is rebuilding
+    fn fail(&self, index: usize, name: &str, e: MinistoreError) -> Result<()> {
+        let mut members = self.members_mut()?;
+        if members[index].member.name != name || members[index].state == MemberState::Failed {
+            return Ok(());
+        }
+        members[index].state = MemberState::Failed;
+        members[index].rebuilt_rows = 0;
+        tracing::warn!(
+            "Array member failed, name={}, member={}, err={}",
+            self.info.name(),
+            name,
+            e
+        );
+        match members
+            .iter()
+            .filter(|member| member.state != MemberState::Active)
+            .count()
+        {
+            1 => Ok(()),
+            _ => Err(e),
+        }
+    }
+
+    fn too_many_failures(&self) -> MinistoreError {
+        MinistoreError::io(
+            format!(
+                "Array has more than one failed member, name={}",
+                self.info.name()
+            ),
+            std::io::ErrorKind::Other.into(),
+        )
+    }
+
+    /// Whether the blocks of the member in the row are read and written. Rows of a rebuilding
+    /// member are available once they are rebuilt.
+    fn is_available(&self, index: usize, row: u64) -> Result<bool> {
+        let members = self.members()?;
+        Ok(match members[index].state {
+            MemberState::Active => true,
+            MemberState::Failed => false,
+            MemberState::Rebuilding => row < members[index].rebuilt_rows,
+        })
+    }
+
+    fn member(&self, index: usize) -> Result<Member> {
+        Ok(self.members()?[index].member.clone())
+    }
+
+    fn update_member<F>(&self, index: usize, name: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut ArrayMember),
+    {
+        if let Some(member) = self
+            .members_mut()?
+            .get_mut(index)
+            .filter(|member| member.member.name == name)
+        {
+            f(member);
+        }
+        Ok(())
+    }
+
+    fn members(&self) -> Result<RwLockReadGuard<'_, Vec<ArrayMember>>> {
+        self.members
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn members_mut(&self) -> Result<RwLockWriteGuard<'_, Vec<ArrayMember>>> {
+        self.members
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+/// Reads the member again on failure, so that a transient error does not fail it
+async fn read_member(member: &Member, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+    let mut attempt = 1;
+    loop {
+        match member.device.read(lba, num_blocks).await {
+            Ok(blocks) => return Ok(blocks),
+            Err(e) if attempt >= READ_ATTEMPTS => return Err(e),
+            Err(e) => tracing::warn!(
+                "Failed to read array member, retrying, member={}, lba={}, num_blocks={}, err={}",
+                member.name,
+                lba,
+                num_blocks,
+                e
+            ),
+        }
+        attempt += 1;
+    }
+}
+
+fn xor(buffer: &mut [Vec<u8>], blocks: &[DataBlock]) {
+    for (data, block) in buffer.iter_mut().zip(blocks) {
+        for (byte, other) in data.iter_mut().zip(block.as_slice()) {
+            *byte ^= other;
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for StripedDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.info.check_block_size(&buffer)?;
+
+        let mut buffer = buffer.into_iter();
+        for segment in self.segments(lba, num_blocks) {
+            let blocks = buffer.by_ref().take(segment.num_blocks as usize).collect();
+            match self.level {
+                RaidLevel::Raid0 => {
+                    self.member(segment.member)?
+                        .device
+                        .write(segment.physical_lba, segment.num_blocks, blocks)
+                        .await?
+                }
+                RaidLevel::Raid5 => self.write_raid5_segment(&segment, blocks).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.info.check_lba_range(lba, num_blocks)?;
+
+        let mut buffer = Vec::with_capacity(num_blocks as usize);
+        for segment in self.segments(lba, num_blocks) {
+            let _guard = match self.level {
+                RaidLevel::Raid0 => None,
+                RaidLevel::Raid5 => {
+                    Some(self.row_lock.lock(segment.row, 1, LockMode::Shared).await)
+                }
+            };
+            buffer.extend(self.read_segment(&segment).await?);
+        }
+        Ok(buffer)
+    }
+
+    /// Unmapped blocks are written to RAID-5 arrays, so that the parity is updated
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        if self.level == RaidLevel::Raid5 {
+            let unmapped = DataBlock::unmapped(self.info.block_size() as usize);
+            return self
+                .write(lba, num_blocks, vec![unmapped; num_blocks as usize])
+                .await;
+        }
+
+        for segment in self.segments(lba, num_blocks) {
+            self.member(segment.member)?
+                .device
+                .unmap(segment.physical_lba, segment.num_blocks)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        if self.level == RaidLevel::Raid5 {
+            let zeroed = DataBlock::zeroed(self.info.block_size() as usize);
+            return self
+                .write(lba, num_blocks, vec![zeroed; num_blocks as usize])
+                .await;
+        }
+
+        for segment in self.segments(lba, num_blocks) {
+            self.member(segment.member)?
+                .device
+                .write_zeroes(segment.physical_lba, segment.num_blocks)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Members should be given in the same order as they are persisted. A member which was
+    /// rebuilding, or replaced after the last flush, is rebuilt again from the beginning.
+    async fn load(&mut self) -> Result<()> {
+        let serialized = tokio::fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (info, stripe_blocks, members): (DeviceInfo, u64, Vec<(String, MemberState)>) =
+            bincode::deserialize(&serialized)?;
+        let member_names: Vec<String> = members.iter().map(|(name, _)| name.clone()).collect();
+        let expected = self.member_names()?;
+        let num_replaced = member_names
+            .iter()
+            .zip(&expected)
+            .filter(|(name, expected)| name != expected)
+            .count();
+        if info.device_type() != self.info.device_type()
+            || info.device_size() != self.info.device_size()
+            || stripe_blocks != self.stripe_blocks
+            || member_names.len() != expected.len()
+            || num_replaced > 1
+        {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Array mismatch, type={}, size={}, stripe_blocks={}, members={:?}, expected_type={}, expected_size={}, expected_stripe_blocks={}, expected_members={:?}",
+                    info.device_type(),
+                    info.device_size(),
+                    stripe_blocks,
+                    member_names,
+                    self.info.device_type(),
+                    self.info.device_size(),
+                    self.stripe_blocks,
+                    expected
+                ),
+            });
+        }
+
+        for (member, (name, state)) in self.members_mut()?.iter_mut().zip(members) {
+            member.state = match member.member.name == name {
+                true => state,
+                false => MemberState::Rebuilding,
+            };
+            member.rebuilt_rows = 0;
+        }
+        Ok(())
+    }
+
+    /// The members which have not failed are flushed before the member states are persisted
+    async fn flush(&self) -> Result<()> {
+        for index in 0..self.num_members {
+            let member = {
+                let members = self.members()?;
+                match members[index].state {
+                    MemberState::Failed => continue,
+                    _ => members[index].member.clone(),
+                }
+            };
+            member.device.flush().await?;
+        }
+
+        let serialized = {
+            let members: Vec<(String, MemberState)> = self
+                .members()?
+                .iter()
+                .map(|member| (member.member.name.clone(), member.state))
+                .collect();
+            bincode::serialize(&(&self.info, self.stripe_blocks, members))?
+        };
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let filepath = self.filepath.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(&tmp_path, serialized)
+                .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                })?;
+            std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+            })?;
+            sync_parent_dir(&filepath)
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::async_block_device::faulty_fake_device::{
+        FaultInjector, FaultRules, FaultyFakeDevice,
+    };
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+    use tracing_test::traced_test;
+
+    const STRIPE_BLOCKS: u64 = 2;
+    const STRIPE_SIZE: u64 = DEFAULT_BLOCK_SIZE as u64 * STRIPE_BLOCKS;
+
+    async fn create_members(name: &str, num_members: usize) -> Vec<(Member, Arc<FaultInjector>)> {
+        let mut members = Vec::new();
+        for index in 0..num_members {
+            let member_name = format!("{}_{}", name, index);
+            let device = create_async_block_device(
+                BlockDeviceType::SimpleFakeDevice,
+                member_name.clone(),
+                STRIPE_SIZE * 4,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .await
+            .expect("Failed to create device");
+            let device = FaultyFakeDevice::new(device);
+            let injector = device.injector();
+            members.push((
+                Member {
+                    name: member_name,
+                    device: Arc::new(device),
+                },
+                injector,
+            ));
+        }
+        members
+    }
+
+    /// Fails the given number of reads, like a device with transient errors
+    struct FlakyDevice {
+        device: Arc<dyn AsyncBlockDevice>,
+        failing_reads: AtomicU64,
+    }
+
+    #[async_trait]
+    impl AsyncBlockDevice for FlakyDevice {
+        fn info(&self) -> &DeviceInfo {
+            self.device.info()
+        }
+
+        async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+            self.device.write(lba, num_blocks, buffer).await
+        }
+
+        async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+            let failing = self
+                .failing_reads
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            match failing {
+                true => Err(MinistoreError::io(
+                    "Transient read error",
+                    std::io::ErrorKind::Other.into(),
+                )),
+                false => self.device.read(lba, num_blocks).await,
+            }
+        }
+
+        async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+            self.device.unmap(lba, num_blocks).await
+        }
+
+        async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+            self.device.write_zeroes(lba, num_blocks).await
+        }
+
+        async fn load(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            self.device.flush().await
+        }
+    }
+
+    /// Distinct block of each lba
+    fn blocks(lba: u64, num_blocks: u64) -> Vec<DataBlock> {
+        (lba..lba + num_blocks)
+            .map(|lba| DataBlock::from(vec![lba as u8 + 1; DEFAULT_BLOCK_SIZE]))
+            .collect()
+    }
+
+    fn fail_always() -> FaultRules {
+        FaultRules {
+            error_probability: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn remove_files(name: &str, members: Vec<(Member, Arc<FaultInjector>)>) {
+        for (member, _) in members {
+            std::fs::remove_file(member.name).expect("Failed to remove file");
+        }
+        if Path::new(name).exists() {
+            std::fs::remove_file(name).expect("Failed to remove file");
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn raid0_should_stripe_blocks_across_members() {
+        let name = "raid0_should_stripe_blocks_across_members";
+        let members = create_members(name, 2).await;
+        let array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid0,
+            STRIPE_SIZE,
+            members.iter().map(|(member, _)| member.clone()).collect(),
+            Path::new("."),
+        )
+        .unwrap();
+        array.initialize().await.unwrap();
+        assert_eq!(array.info().num_blocks(), STRIPE_BLOCKS * 4 * 2);
+
+        array.write(1, 6, blocks(1, 6)).await.unwrap();
+        assert_eq!(array.read(1, 6).await.unwrap(), blocks(1, 6));
+        // Stripes 0 and 2 are on the first member, and 1 and 3 on the second one
+        let unmapped = DataBlock::unmapped(DEFAULT_BLOCK_SIZE);
+        assert_eq!(
+            members[0].0.device.read(0, 4).await.unwrap(),
+            [vec![unmapped.clone()], blocks(1, 1), blocks(4, 2)].concat()
+        );
+        assert_eq!(
+            members[1].0.device.read(0, 4).await.unwrap(),
+            [blocks(2, 2), blocks(6, 1), vec![unmapped]].concat()
+        );
+
+        // Without redundancy, the blocks of a failed member cannot be read
+        members[1].1.set_rules(fail_always()).unwrap();
+        assert!(array.read(0, 2).await.is_ok());
+        assert!(array.read(2, 2).await.is_err());
+
+        remove_files(name, members);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn raid5_should_reconstruct_blocks_of_failed_member() {
+        let name = "raid5_should_reconstruct_blocks_of_failed_member";
+        let members = create_members(name, 3).await;
+        let array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid5,
+            STRIPE_SIZE,
+            members.iter().map(|(member, _)| member.clone()).collect(),
+            Path::new("."),
+        )
+        .unwrap();
+        array.initialize().await.unwrap();
+        let num_blocks = array.info().num_blocks();
+        assert_eq!(num_blocks, STRIPE_BLOCKS * 4 * 2);
+
+        array
+            .write(0, num_blocks, blocks(0, num_blocks))
+            .await
+            .unwrap();
+        array.unmap(3, 2).await.unwrap();
+        let mut expected = blocks(0, num_blocks);
+        expected[3..5].fill(DataBlock::unmapped(DEFAULT_BLOCK_SIZE));
+        assert_eq!(array.read(0, num_blocks).await.unwrap(), expected);
+
+        // Every member keeps data and parity, so any of them can be reconstructed
+        members[1].1.set_rules(fail_always()).unwrap();
+        assert_eq!(array.read(0, num_blocks).await.unwrap(), expected);
+        assert_eq!(
+            array.status().unwrap().failed_members,
+            vec![members[1].0.name.clone()]
+        );
+
+        // Writes in degraded mode are kept in the parity
+        array
+            .write(0, num_blocks, blocks(1, num_blocks))
+            .await
+            .unwrap();
+        assert_eq!(
+            array.read(0, num_blocks).await.unwrap(),
+            blocks(1, num_blocks)
+        );
+
+        // The failed member is kept after reload
+        members[1].1.set_rules(FaultRules::default()).unwrap();
+        array.flush().await.unwrap();
+        let mut array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid5,
+            STRIPE_SIZE,
+            members.iter().map(|(member, _)| member.clone()).collect(),
+            Path::new("."),
+        )
+        .unwrap();
+        array.load().await.expect("Failed to load");
+        assert_eq!(
+            array.read(0, num_blocks).await.unwrap(),
+            blocks(1, num_blocks)
+        );
+
+        // Second failure cannot be tolerated
+        members[0].1.set_rules(fail_always()).unwrap();
+        assert!(array.read(0, num_blocks).await.is_err());
+
+        remove_files(name, members);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn raid5_should_retry_transient_read_error() {
+        let name = "raid5_should_retry_transient_read_error";
+        let members = create_members(name, 3).await;
+        let flaky = Arc::new(FlakyDevice {
+            device: members[0].0.device.clone(),
+            failing_reads: AtomicU64::new(0),
+        });
+        let mut array_members: Vec<Member> =
+            members.iter().map(|(member, _)| member.clone()).collect();
+        array_members[0].device = flaky.clone();
+        let array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid5,
+            STRIPE_SIZE,
+            array_members,
+            Path::new("."),
+        )
+        .unwrap();
+        array.initialize().await.unwrap();
+        let num_blocks = array.info().num_blocks();
+        array
+            .write(0, num_blocks, blocks(0, num_blocks))
+            .await
+            .unwrap();
+
+        // A single error is retried, and the member is kept
+        flaky.failing_reads.store(1, Ordering::SeqCst);
+        assert_eq!(
+            array.read(0, num_blocks).await.unwrap(),
+            blocks(0, num_blocks)
+        );
+        assert!(!array.status().unwrap().is_degraded());
+
+        // The member fails if the retry fails too
+        flaky
+            .failing_reads
+            .store(READ_ATTEMPTS as u64, Ordering::SeqCst);
+        assert_eq!(
+            array.read(0, num_blocks).await.unwrap(),
+            blocks(0, num_blocks)
+        );
+        assert_eq!(
+            array.status().unwrap().failed_members,
+            vec![members[0].0.name.clone()]
+        );
+
+        remove_files(name, members);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn raid5_should_rebuild_replaced_member() {
+        let name = "raid5_should_rebuild_replaced_member";
+        let members = create_members(name, 4).await;
+        let array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid5,
+            STRIPE_SIZE,
+            members[..3]
+                .iter()
+                .map(|(member, _)| member.clone())
+                .collect(),
+            Path::new("."),
+        )
+        .unwrap();
+        array.initialize().await.unwrap();
+        let num_blocks = array.info().num_blocks();
+        array
+            .write(0, num_blocks, blocks(0, num_blocks))
+            .await
+            .unwrap();
+
+        members[1].1.set_rules(fail_always()).unwrap();
+        array.write(0, 4, blocks(1, 4)).await.unwrap();
+        assert!(matches!(
+            array.replace_member(&members[0].0.name, members[3].0.clone()),
+            Err(MinistoreError::InvalidArgument { .. })
+        ));
+        array
+            .replace_member(&members[1].0.name, members[3].0.clone())
+            .unwrap();
+        let status = array.status().unwrap();
+        assert_eq!(status.rebuilding_member, Some(members[3].0.name.clone()));
+        assert!(status.failed_members.is_empty());
+
+        // Writes in degraded mode are reconstructed onto the new member
+        let mut expected = blocks(0, num_blocks);
+        expected.splice(0..4, blocks(1, 4));
+        array.rebuild().await.unwrap();
+        assert!(!array.status().unwrap().is_degraded());
+        members[0].1.set_rules(fail_always()).unwrap();
+        assert_eq!(array.read(0, num_blocks).await.unwrap(), expected);
+
+        // The new member is kept after reload
+        members[0].1.set_rules(FaultRules::default()).unwrap();
+        array.flush().await.unwrap();
+        let mut array = StripedDevice::new(
+            name.to_string(),
+            RaidLevel::Raid5,
+            STRIPE_SIZE,
+            [&members[0], &members[3], &members[2]]
+                .iter()
+                .map(|(member, _)| member.clone())
+                .collect(),
+            Path::new("."),
+        )
+        .unwrap();
+        array.load().await.expect("Failed to load");
+        assert!(!array.is_rebuilding().unwrap());
+
+        remove_files(name, members);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, RwLock};
+
+use super::AsyncBlockDevice;
+use crate::block_device::BlockDevice;
+use crate::block_device_common::{data_type::DataBlock, device_info::DeviceInfo};
+use crate::error::{MinistoreError, Result};
+use async_trait::async_trait;
+
+/// Adapter to use a sync BlockDevice as an AsyncBlockDevice.
+/// Every operation is executed with spawn_blocking, so it does not block the tokio runtime. I/Os
+/// share the device and run at the same time, while load and flush wait for them to complete.
+pub struct SyncBlockDeviceAdapter {
+    device_info: DeviceInfo,
+    device: Arc<RwLock<Box<dyn BlockDevice>>>,
+}
+
+impl SyncBlockDeviceAdapter {
+    pub fn new(device: Box<dyn BlockDevice>) -> Self {
+        SyncBlockDeviceAdapter {
+            device_info: device.info().clone(),
+            device: Arc::new(RwLock::new(device)),
+        }
+    }
+
+    async fn run_blocking<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn BlockDevice) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let device = self.device.clone();
+        tokio::task::spawn_blocking(move || {
+            let device = device
+                .read()
+                .map_err(|e| MinistoreError::internal(e.to_string()))?;
+            f(device.as_ref())
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+
+    async fn run_blocking_exclusive<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut dyn BlockDevice) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let device = self.device.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut device = device
+                .write()
+                .map_err(|e| MinistoreError::internal(e.to_string()))?;
+            f(device.as_mut())
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for SyncBlockDeviceAdapter {
+    fn info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.run_blocking(move |device| device.write(lba, num_blocks, buffer))
+            .await
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.run_blocking(move |device| device.read(lba, num_blocks))
+            .await
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.run_blocking(move |device| device.unmap(lba, num_blocks))
+            .await
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.run_blocking(move |device| device.write_zeroes(lba, num_blocks))
+            .await
+    }
+
+    async fn load(&mut self) -> Result<()> {
+        // Device info can be changed by load
+        self.device_info = self
+            .run_blocking_exclusive(|device| {
+                device.load()?;
+                Ok(device.info().clone())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.run_blocking_exclusive(|device| device.flush()).await
+    }
+}
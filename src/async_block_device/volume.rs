@@ -0,0 +1,611 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::AsyncBlockDevice;
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::block_device_common::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Size of the extents allocated to volumes on the first write
+pub const EXTENT_SIZE: u64 = 1024 * 1024;
+
+/// A device which volumes allocate their extents from
+#[derive(Clone)]
+pub struct Member {
+    pub name: String,
+    pub device: Arc<dyn AsyncBlockDevice>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct PhysicalExtent {
+    /// Index of the member in the volume
+    member: usize,
+    extent: u64,
+}
+
+/// Extents of the member devices allocated to any volume. It is shared by all volumes, so that
+/// volumes on the same device do not allocate the same extent.
+#[derive(Default)]
+pub struct ExtentAllocator {
+    allocated: Mutex<HashMap<String, BTreeSet<u64>>>,
+}
+
+impl ExtentAllocator {
+    /// Allocates the lowest free extent of the member with the most free extents
+    fn allocate(&self, members: &[Member], extent_blocks: u64) -> Result<PhysicalExtent> {
+        let mut allocated = self.allocated()?;
+        let (member, num_free) = members
+            .iter()
+            .enumerate()
+            .map(|(index, member)| {
+                let num_extents = member.device.info().num_blocks() / extent_blocks;
+                let num_allocated = allocated.get(&member.name).map_or(0, BTreeSet::len);
+                (index, num_extents.saturating_sub(num_allocated as u64))
+            })
+            .max_by_key(|&(index, num_free)| (num_free, Reverse(index)))
+            .ok_or_else(|| MinistoreError::internal("Volume has no member"))?;
+        if num_free == 0 {
+            return Err(MinistoreError::io(
+                "No free extent in the members of the volume",
+                std::io::ErrorKind::StorageFull.into(),
+            ));
+        }
+
+        let extents = allocated.entry(members[member].name.clone()).or_default();
+        let extent = (0..).find(|extent| !extents.contains(extent)).unwrap_or(0);
+        extents.insert(extent);
+        Ok(PhysicalExtent { member, extent })
+    }
+
+    /// Marks the extent of a loaded volume as allocated
+    fn reserve(&self, member_name: &str, extent: u64) -> Result<()> {
+        let mut allocated = self.allocated()?;
+        if !allocated
+            .entry(member_name.to_string())
+            .or_default()
+            .insert(extent)
+        {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Extent is allocated twice, member={}, extent={}",
+                    member_name, extent
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    fn release(&self, member_name: &str, extent: u64) -> Result<()> {
+        if let Some(extents) = self.allocated()?.get_mut(member_name) {
+            extents.remove(&extent);
+        }
+        Ok(())
+    }
+
+    fn allocated(&self) -> Result<MutexGuard<'_, HashMap<String, BTreeSet<u64>>>> {
+        self.allocated
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+struct Extents {
+    /// Size of the volume, which may be changed by a resize after an I/O is checked
+    num_blocks: u64,
+    map: BTreeMap<u64, PhysicalExtent>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumeStatus {
+    pub name: String,
+    pub size: u64,
+    pub block_size: u64,
+    pub allocated_size: u64,
+    pub members: Vec<String>,
+}
+
+/// Thin-provisioned volume whose extents are allocated from the member devices on the first
+/// write. Unallocated blocks are read as unmapped blocks, and extents unmapped as a whole are
+/// released. The extent map is persisted into a file on flush, like the blocks of fake devices.
+pub struct Volume {
+    info: DeviceInfo,
+    members: Vec<Member>,
+    allocator: Arc<ExtentAllocator>,
+    /// Shared with the resized volume
+    extents: Arc<tokio::sync::Mutex<Extents>>,
+    filepath: PathBuf,
+}
+
+impl Volume {
+    /// Members should have the same block size, which is the block size of the volume
+    pub fn new(
+        name: String,
+        size: u64,
+        members: Vec<Member>,
+        allocator: Arc<ExtentAllocator>,
+        location: &Path,
+    ) -> Result<Self> {
+        let block_size = members
+            .first()
+            .ok_or_else(|| MinistoreError::invalid_argument("Volume should have a member"))?
+            .device
+            .info()
+            .block_size();
+        if let Some(member) = members
+            .iter()
+            .find(|member| member.device.info().block_size() != block_size)
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Members should have the same block size, name={}, block_size={}, expected={}",
+                member.name,
+                member.device.info().block_size(),
+                block_size
+            )));
+        }
+
+        let info = DeviceInfo::new(BlockDeviceType::Volume, name.clone(), size, block_size)?;
+        Ok(Volume {
+            extents: Arc::new(tokio::sync::Mutex::new(Extents {
+                num_blocks: info.num_blocks(),
+                map: BTreeMap::new(),
+            })),
+            info,
+            members,
+            allocator,
+            filepath: location.join(name),
+        })
+    }
+
+    pub fn member_names(&self) -> Vec<String> {
+        self.members
+            .iter()
+            .map(|member| member.name.clone())
+            .collect()
+    }
+
+    pub async fn status(&self) -> VolumeStatus {
+        let num_extents = self.extents.lock().await.map.len() as u64;
+        VolumeStatus {
+            name: self.info.name().clone(),
+            size: self.info.device_size(),
+            block_size: self.info.block_size(),
+            allocated_size: num_extents * EXTENT_SIZE,
+            members: self.member_names(),
+        }
+    }
+
+    /// Returns the volume of the new size sharing the extents with this volume. Extents beyond
+    /// the size are released, and the rest of the last extent is unmapped, so that the blocks are
+    /// read as unmapped when the volume grows again.
+    pub async fn resize(&self, size: u64) -> Result<Volume> {
+        let info = DeviceInfo::new(
+            BlockDeviceType::Volume,
+            self.info.name().clone(),
+            size,
+            self.info.block_size(),
+        )?;
+        let num_blocks = info.num_blocks();
+        let extent_blocks = self.extent_blocks();
+
+        let mut extents = self.extents.lock().await;
+        if num_blocks < extents.num_blocks {
+            let released = extents.map.split_off(&num_blocks.div_ceil(extent_blocks));
+            for physical in released.into_values() {
+                self.release(physical).await?;
+            }
+
+            let offset = num_blocks % extent_blocks;
+            if let Some(&physical) = extents.map.get(&(num_blocks / extent_blocks)) {
+                if offset > 0 {
+                    self.members[physical.member]
+                        .device
+                        .unmap(self.physical_lba(physical, offset), extent_blocks - offset)
+                        .await?;
+                }
+            }
+        }
+        extents.num_blocks = num_blocks;
+        drop(extents);
+
+        Ok(Volume {
+            info,
+            members: self.members.clone(),
+            allocator: self.allocator.clone(),
+            extents: self.extents.clone(),
+            filepath: self.filepath.clone(),
+        })
+    }
+
+    /// Releases every extent before the volume is deleted
+    pub async fn release_all(&self) -> Result<()> {
+        let mut extents = self.extents.lock().await;
+        for physical in std::mem::take(&mut extents.map).into_values() {
+            self.release(physical).await?;
+        }
+        Ok(())
+    }
+
+    /// EXTENT_SIZE is a multiple of every valid block size
+    fn extent_blocks(&self) -> u64 {
+        EXTENT_SIZE / self.info.block_size()
+    }
+
+    fn physical_lba(&self, physical: PhysicalExtent, offset: u64) -> u64 {
+        physical.extent * self.extent_blocks() + offset
+    }
+
+    /// Splits the range at the extent boundaries into (extent, offset in the extent, num_blocks)
+    fn segments(&self, lba: u64, num_blocks: u64) -> Vec<(u64, u64, u64)> {
+        let extent_blocks = self.extent_blocks();
+        let mut segments = Vec::new();
+        let mut lba = lba;
+        let end = lba + num_blocks;
+        while lba < end {
+            let offset = lba % extent_blocks;
+            let num_blocks = std::cmp::min(extent_blocks - offset, end - lba);
+            segments.push((lba / extent_blocks, offset, num_blocks));
+            lba += num_blocks;
+        }
+        segments
+    }
+
+    async fn lookup(&self, extent: u64) -> Option<PhysicalExtent> {
+        self.extents.lock().await.map.get(&extent).copied()
+    }
+
+    /// Allocates the extent on the first write. The physical extent is unmapped, so that the data
+    /// of the volume which used it before is not read. The range is checked again, since the
+    /// volume may be shrunk after the I/O is checked.
+    async fn lookup_or_allocate(
+        &self,
+        extent: u64,
+        offset: u64,
+        num_blocks: u64,
+    ) -> Result<PhysicalExtent> {
+        let mut extents = self.extents.lock().await;
+        let extent_blocks = self.extent_blocks();
+        let lba = extent * extent_blocks + offset;
+        if lba + num_blocks > extents.num_blocks {
+            return Err(MinistoreError::InvalidLbaRange {
+                lba,
+                num_blocks,
+                device_num_blocks: extents.num_blocks,
+            });
+        }
+        if let Some(&physical) = extents.map.get(&extent) {
+            return Ok(physical);
+        }
+
+        let physical = self.allocator.allocate(&self.members, extent_blocks)?;
+        let member = &self.members[physical.member];
+        if let Err(e) = member
+            .device
+            .unmap(self.physical_lba(physical, 0), extent_blocks)
+            .await
+        {
+            self.allocator.release(&member.name, physical.extent)?;
+            return Err(e);
+        }
+        extents.map.insert(extent, physical);
+        tracing::debug!(
+            "Allocated extent, volume={}, extent={}, member={}, physical_extent={}",
+            self.info.name(),
+            extent,
+            member.name,
+            physical.extent
+        );
+        Ok(physical)
+    }
+
+    async fn release(&self, physical: PhysicalExtent) -> Result<()> {
+        let member = &self.members[physical.member];
+        member
+            .device
+            .unmap(self.physical_lba(physical, 0), self.extent_blocks())
+            .await?;
+        self.allocator.release(&member.name, physical.extent)
+    }
+}
+
+#[async_trait]
+impl AsyncBlockDevice for Volume {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    async fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.info.check_block_size(&buffer)?;
+
+        let mut buffer = buffer.into_iter();
+        for (extent, offset, num_blocks) in self.segments(lba, num_blocks) {
+            let physical = self.lookup_or_allocate(extent, offset, num_blocks).await?;
+            let blocks = buffer.by_ref().take(num_blocks as usize).collect();
+            self.members[physical.member]
+                .device
+                .write(self.physical_lba(physical, offset), num_blocks, blocks)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.info.check_lba_range(lba, num_blocks)?;
+
+        let unmapped = DataBlock::unmapped(self.info.block_size() as usize);
+        let mut buffer = Vec::with_capacity(num_blocks as usize);
+        for (extent, offset, num_blocks) in self.segments(lba, num_blocks) {
+            match self.lookup(extent).await {
+                Some(physical) => buffer.extend(
+                    self.members[physical.member]
+                        .device
+                        .read(self.physical_lba(physical, offset), num_blocks)
+                        .await?,
+                ),
+                None => buffer.extend(std::iter::repeat_n(unmapped.clone(), num_blocks as usize)),
+            }
+        }
+        Ok(buffer)
+    }
+
+    async fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+
+        for (extent, offset, num_blocks) in self.segments(lba, num_blocks) {
+            if num_blocks == self.extent_blocks() {
+                let physical = self.extents.lock().await.map.remove(&extent);
+                if let Some(physical) = physical {
+                    self.release(physical).await?;
+                }
+            } else if let Some(physical) = self.lookup(extent).await {
+                self.members[physical.member]
+                    .device
+                    .unmap(self.physical_lba(physical, offset), num_blocks)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.info.check_lba_range(lba, num_blocks)?;
+
+        for (extent, offset, num_blocks) in self.segments(lba, num_blocks) {
+            let physical = self.lookup_or_allocate(extent, offset, num_blocks).await?;
+            self.members[physical.member]
+                .device
+                .write_zeroes(self.physical_lba(physical, offset), num_blocks)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Members should be given in the same order as they are created
+    async fn load(&mut self) -> Result<()> {
+        let serialized = tokio::fs::read(&self.filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to read file, path={:?}", self.filepath), e)
+        })?;
+        let (info, member_names, map): (DeviceInfo, Vec<String>, BTreeMap<u64, PhysicalExtent>) =
+            bincode::deserialize(&serialized)?;
+        if info.device_type() != BlockDeviceType::Volume || member_names != self.member_names() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Volume mismatch, type={}, members={:?}, expected={:?}",
+                    info.device_type(),
+                    member_names,
+                    self.member_names()
+                ),
+            });
+        }
+
+        self.info = info;
+        let extent_blocks = self.extent_blocks();
+        for physical in map.values() {
+            let member = self.members.get(physical.member).filter(|member| {
+                (physical.extent + 1) * extent_blocks <= member.device.info().num_blocks()
+            });
+            let Some(member) = member else {
+                return Err(MinistoreError::Corrupted {
+                    reason: format!(
+                        "Extent out of the members, member={}, extent={}",
+                        physical.member, physical.extent
+                    ),
+                });
+            };
+            self.allocator.reserve(&member.name, physical.extent)?;
+        }
+        self.extents = Arc::new(tokio::sync::Mutex::new(Extents {
+            num_blocks: self.info.num_blocks(),
+            map,
+        }));
+        Ok(())
+    }
+
+    /// The members are flushed before the extent map, and the map is replaced atomically
+    async fn flush(&self) -> Result<()> {
+        for member in &self.members {
+            member.device.flush().await?;
+        }
+
+        let serialized = {
+            let extents = self.extents.lock().await;
+            bincode::serialize(&(&self.info, self.member_names(), &extents.map))?
+        };
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let filepath = self.filepath.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(&tmp_path, serialized)
+                .and_then(|_| std::fs::File::open(&tmp_path)?.sync_all())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to write file, path={:?}", tmp_path), e)
+                })?;
+            std::fs::rename(&tmp_path, &filepath).map_err(|e| {
+                MinistoreError::io(format!("Failed to rename file, path={:?}", filepath), e)
+            })?;
+            sync_parent_dir(&filepath)
+        })
+        .await
+        .map_err(|e| MinistoreError::internal(format!("Failed to run blocking task, err={}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::create_async_block_device;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::block_device_common::test_utils::blocks;
+    use tracing_test::traced_test;
+
+    const EXTENT_BLOCKS: u64 = EXTENT_SIZE / DEFAULT_BLOCK_SIZE as u64;
+
+    async fn create_member(name: &str, num_extents: u64) -> Member {
+        let device = create_async_block_device(
+            BlockDeviceType::SimpleFakeDevice,
+            name.to_string(),
+            EXTENT_SIZE * num_extents,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .await
+        .expect("Failed to create device");
+        Member {
+            name: name.to_string(),
+            device: device.into(),
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn extents_should_be_allocated_on_first_write() {
+        let name = "extents_should_be_allocated_on_first_write";
+        let members = vec![
+            create_member(&format!("{}_0", name), 2).await,
+            create_member(&format!("{}_1", name), 2).await,
+        ];
+        let allocator = Arc::new(ExtentAllocator::default());
+        let volume = Volume::new(
+            name.to_string(),
+            EXTENT_SIZE * 100,
+            members.clone(),
+            allocator.clone(),
+            Path::new("."),
+        )
+        .unwrap();
+        assert_eq!(volume.status().await.allocated_size, 0);
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS * 50, 1).await.unwrap(),
+            vec![DataBlock::unmapped(DEFAULT_BLOCK_SIZE)]
+        );
+
+        // A write across two extents allocates one from each member
+        volume
+            .write(EXTENT_BLOCKS * 50 - 1, 2, blocks(0xA, 2))
+            .await
+            .unwrap();
+        volume.write_zeroes(EXTENT_BLOCKS - 1, 2).await.unwrap();
+        assert_eq!(volume.status().await.allocated_size, EXTENT_SIZE * 4);
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS * 50 - 2, 3).await.unwrap(),
+            [blocks(0xFF, 1), blocks(0xA, 2)].concat()
+        );
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS - 2, 2).await.unwrap(),
+            [blocks(0xFF, 1), blocks(0, 1)].concat()
+        );
+
+        // Members are full, until an extent is released by unmapping all of it
+        assert!(matches!(
+            volume.write(EXTENT_BLOCKS * 99, 1, blocks(0xB, 1)).await,
+            Err(MinistoreError::Io { .. })
+        ));
+        volume.unmap(0, EXTENT_BLOCKS).await.unwrap();
+        volume
+            .write(EXTENT_BLOCKS * 99, 1, blocks(0xB, 1))
+            .await
+            .unwrap();
+        assert_eq!(volume.status().await.allocated_size, EXTENT_SIZE * 4);
+
+        // The extent map is kept after flush and load
+        volume.flush().await.unwrap();
+        let mut volume = Volume::new(
+            name.to_string(),
+            0,
+            members.clone(),
+            Arc::new(ExtentAllocator::default()),
+            Path::new("."),
+        )
+        .unwrap();
+        volume.load().await.expect("Failed to load");
+        assert_eq!(volume.info().device_size(), EXTENT_SIZE * 100);
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS * 99, 1).await.unwrap(),
+            blocks(0xB, 1)
+        );
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS * 50 - 1, 2).await.unwrap(),
+            blocks(0xA, 2)
+        );
+
+        for member in members {
+            std::fs::remove_file(member.name).expect("Failed to remove file");
+        }
+        std::fs::remove_file(name).expect("Failed to remove file");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn shrunk_blocks_should_be_read_as_unmapped_after_growing() {
+        let name = "shrunk_blocks_should_be_read_as_unmapped_after_growing";
+        let member = create_member(&format!("{}_0", name), 4).await;
+        let volume = Volume::new(
+            name.to_string(),
+            EXTENT_SIZE * 4,
+            vec![member.clone()],
+            Arc::new(ExtentAllocator::default()),
+            Path::new("."),
+        )
+        .unwrap();
+        volume
+            .write(0, 4, blocks(0xA, 4))
+            .await
+            .expect("Failed to write");
+        volume
+            .write(EXTENT_BLOCKS * 3, 1, blocks(0xA, 1))
+            .await
+            .expect("Failed to write");
+
+        let volume = volume.resize(DEFAULT_BLOCK_SIZE as u64 * 2).await.unwrap();
+        assert_eq!(volume.info().num_blocks(), 2);
+        assert_eq!(volume.status().await.allocated_size, EXTENT_SIZE);
+        assert!(volume.read(2, 1).await.is_err());
+
+        let volume = volume.resize(EXTENT_SIZE * 4).await.unwrap();
+        assert_eq!(
+            volume.read(0, 4).await.unwrap(),
+            [blocks(0xA, 2), blocks(0xFF, 2)].concat()
+        );
+        assert_eq!(
+            volume.read(EXTENT_BLOCKS * 3, 1).await.unwrap(),
+            blocks(0xFF, 1)
+        );
+
+        std::fs::remove_file(member.name).expect("Failed to remove file");
+    }
+}
@@ -1,56 +1,508 @@
 use super::{BlockDevice, BlockDeviceType};
-use crate::block_device_common::data_type::{DataBlock, BLOCK_SIZE, UNMAP_BLOCK};
+use crate::block_device_common::data_type::{
+    AlignedBuffer, DataBlock, DEFAULT_BLOCK_SIZE, UNMAP_BYTE,
+};
 use crate::block_device_common::device_info::DeviceInfo;
-use std::io::{Seek, Write};
+use crate::error::{MinistoreError, Result};
+use bytes::Bytes;
+#[cfg(target_os = "linux")]
+use io_uring::{opcode, squeue, types, IoUring};
+use std::fs::{File, OpenOptions};
 use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
 
 const URING_SIZE: u32 = 8;
+/// Maximum number of blocks in a single SQE, limited by IOV_MAX of readv/writev
+const MAX_BLOCKS_PER_SQE: usize = 1024;
 
+/// IoUringFakeDevice persists its data in a backing file using io_uring.
+///
+/// Layout of the backing file is
+/// | superblock (1 block) | bitmap of written blocks (block aligned) | data blocks |
+/// The superblock and the bitmap are aligned to DEFAULT_BLOCK_SIZE whatever the block size of the
+/// device is, so that they can be found before the device info is loaded.
+/// Blocks which have never been written are read as unmapped blocks.
+/// The bytes of the bitmap changed by an I/O are written back before the I/O completes, so the
+/// written blocks are found after a crash without flush.
 #[cfg(target_os = "linux")]
 pub struct IoUringFakeDevice {
-    ring: io_uring::IoUring,
+    device_info: DeviceInfo,
+    /// Idle rings. Each I/O takes a ring of its own, so that I/Os are submitted at the same time,
+    /// and a new ring is set up when all of them are in use.
+    rings: Mutex<Vec<IoUring>>,
+    file: File,
+    written: Vec<AtomicU8>,
+    /// Serializes writing the bitmap back, so that an older copy of a byte does not overwrite
+    /// a newer one
+    bitmap_lock: Mutex<()>,
+}
+
+/// An I/O to be submitted to the ring and the number of bytes it should transfer
+#[cfg(target_os = "linux")]
+struct UringIo {
+    entry: squeue::Entry,
+    len: usize,
 }
 
 #[cfg(target_os = "linux")]
 impl IoUringFakeDevice {
-    pub fn new(name: String, size: u64) -> Result<Self, String> {
-        todo!()
+    pub fn new(name: String, size: u64, block_size: u64, filepath: PathBuf) -> Result<Self> {
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::IoUringFakeDevice,
+            name.clone(),
+            size,
+            block_size,
+        )?;
+        let filepath = filepath.join(&name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", filepath), e)
+            })?;
+
+        // Backing file is sparse, so the blocks are allocated on the first write
+        let file_len = data_offset(device_info.num_blocks()) + size;
+        let current_len = file.metadata()?.len();
+        if current_len < file_len {
+            file.set_len(file_len).map_err(|e| {
+                MinistoreError::io(format!("Failed to resize file, path={:?}", filepath), e)
+            })?;
+        }
+
+        let ring = IoUring::new(URING_SIZE)
+            .map_err(|e| MinistoreError::io("Failed to setup io_uring", e))?;
+
+        Ok(IoUringFakeDevice {
+            written: new_bitmap(vec![0; bitmap_len(device_info.num_blocks())]),
+            device_info,
+            rings: Mutex::new(vec![ring]),
+            file,
+            bitmap_lock: Mutex::new(()),
+        })
+    }
+
+    fn is_written(&self, lba: u64) -> bool {
+        self.written[(lba / 8) as usize].load(Ordering::Acquire) & (1 << (lba % 8)) != 0
+    }
+
+    fn set_written(&self, lba: u64, num_blocks: u64) {
+        for lba in lba..lba + num_blocks {
+            self.written[(lba / 8) as usize].fetch_or(1 << (lba % 8), Ordering::Release);
+        }
+    }
+
+    fn clear_written(&self, lba: u64, num_blocks: u64) {
+        for lba in lba..lba + num_blocks {
+            self.written[(lba / 8) as usize].fetch_and(!(1 << (lba % 8)), Ordering::Release);
+        }
+    }
+
+    /// Writes the bytes of the bitmap covering the range back to the backing file
+    fn persist_written(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        if num_blocks == 0 {
+            return Ok(());
+        }
+        let _guard = self
+            .bitmap_lock
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?;
+        let first = (lba / 8) as usize;
+        let last = ((lba + num_blocks - 1) / 8) as usize;
+        let bytes: Vec<u8> = self.written[first..=last]
+            .iter()
+            .map(|byte| byte.load(Ordering::Acquire))
+            .collect();
+        let io = UringIo {
+            entry: opcode::Write::new(self.fd(), bytes.as_ptr(), bytes.len() as u32)
+                .offset((DEFAULT_BLOCK_SIZE + first) as i64)
+                .build(),
+            len: bytes.len(),
+        };
+        self.submit_and_wait_all(vec![io])
+    }
+
+    fn data_offset_of(&self, lba: u64) -> u64 {
+        data_offset(self.device_info.num_blocks()) + lba * self.device_info.block_size()
+    }
+
+    /// Submits the I/Os to an idle ring, which is returned to the pool afterwards
+    fn submit_and_wait_all(&self, ios: Vec<UringIo>) -> Result<()> {
+        let idle = self
+            .rings
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?
+            .pop();
+        let mut ring = match idle {
+            Some(ring) => ring,
+            None => IoUring::new(URING_SIZE)
+                .map_err(|e| MinistoreError::io("Failed to setup io_uring", e))?,
+        };
+        let result = submit_and_wait_all(&mut ring, ios);
+        // A ring with I/Os left in its submission queue is dropped, as they should not be
+        // submitted later
+        if ring.submission().is_empty() {
+            self.rings
+                .lock()
+                .map_err(|e| MinistoreError::internal(e.to_string()))?
+                .push(ring);
+        }
+        result
+    }
+
+    fn fd(&self) -> types::Fd {
+        types::Fd(self.file.as_raw_fd())
+    }
+}
+
+/// Submits all I/Os keeping up to URING_SIZE of them in flight, and waits for all of them to
+/// be completed. Buffers of the I/Os should be alive until this function returns, so it does not
+/// return while the kernel may still access them, even if submitting fails. I/Os which are left
+/// in the submission queue are never submitted, and the ring should not be used again.
+#[cfg(target_os = "linux")]
+fn submit_and_wait_all(ring: &mut IoUring, ios: Vec<UringIo>) -> Result<()> {
+    let expected_lens: Vec<usize> = ios.iter().map(|io| io.len).collect();
+    let mut pending = ios.into_iter().enumerate();
+    let mut in_flight = 0;
+    let mut result = Ok(());
+
+    'submit: loop {
+        while in_flight < URING_SIZE as usize {
+            let Some((index, io)) = pending.next() else {
+                break;
+            };
+            let entry = io.entry.user_data(index as u64);
+            // SAFETY: the buffers are alive until all submitted I/Os are completed
+            if unsafe { ring.submission().push(&entry) }.is_err() {
+                result = Err(MinistoreError::internal("Submission queue is full"));
+                break 'submit;
+            }
+            in_flight += 1;
+        }
+
+        if in_flight == 0 {
+            break;
+        }
+
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(e) if is_transient(&e) => continue,
+            Err(e) => {
+                result = Err(MinistoreError::io("Failed to submit I/O to io_uring", e));
+                break;
+            }
+        }
+        reap_completions(ring, &expected_lens, &mut in_flight, &mut result);
+    }
+
+    // The kernel has not taken the I/Os left in the submission queue, so only the others are
+    // waited for
+    in_flight -= ring.submission().len();
+    while in_flight > 0 {
+        reap_completions(ring, &expected_lens, &mut in_flight, &mut result);
+        if in_flight == 0 {
+            break;
+        }
+        // SAFETY: nothing is submitted, and no argument is passed
+        let waited = unsafe {
+            ring.submitter()
+                .enter::<libc::sigset_t>(0, 1, IORING_ENTER_GETEVENTS, None)
+        };
+        if let Err(e) = waited {
+            tracing::warn!(
+                "Failed to wait for I/Os in flight, in_flight={}, err={}",
+                in_flight,
+                e
+            );
+        }
+    }
+
+    result
+}
+
+/// Flag of io_uring_enter to wait for completions
+#[cfg(target_os = "linux")]
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+#[cfg(target_os = "linux")]
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY)
+    )
+}
+
+/// Checks the completed I/Os, keeping the first error
+#[cfg(target_os = "linux")]
+fn reap_completions(
+    ring: &mut IoUring,
+    expected_lens: &[usize],
+    in_flight: &mut usize,
+    result: &mut Result<()>,
+) {
+    for cqe in ring.completion() {
+        *in_flight -= 1;
+        let expected_len = expected_lens[cqe.user_data() as usize];
+        let cqe_result = if cqe.result() < 0 {
+            Err(MinistoreError::io(
+                "I/O failed",
+                std::io::Error::from_raw_os_error(-cqe.result()),
+            ))
+        } else if cqe.result() as usize != expected_len {
+            Err(MinistoreError::internal(format!(
+                "Short I/O, expected={}, actual={}",
+                expected_len,
+                cqe.result()
+            )))
+        } else {
+            Ok(())
+        };
+        if result.is_ok() {
+            *result = cqe_result;
+        }
     }
 }
 
 #[cfg(target_os = "linux")]
 impl BlockDevice for IoUringFakeDevice {
     fn info(&self) -> &DeviceInfo {
-        todo!()
+        &self.device_info
     }
 
-    fn write(&mut self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<(), String> {
-        todo!()
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+        let block_size = self.device_info.block_size() as usize;
+
+        let iovecs: Vec<Vec<libc::iovec>> = buffer
+            .chunks(MAX_BLOCKS_PER_SQE)
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .map(|block| libc::iovec {
+                        iov_base: block.0.as_ptr() as *mut libc::c_void,
+                        iov_len: block_size,
+                    })
+                    .collect()
+            })
+            .collect();
+        let ios = iovecs
+            .iter()
+            .enumerate()
+            .map(|(index, iovec)| {
+                let offset = self.data_offset_of(lba + (index * MAX_BLOCKS_PER_SQE) as u64);
+                UringIo {
+                    entry: opcode::Writev::new(self.fd(), iovec.as_ptr(), iovec.len() as u32)
+                        .offset(offset as i64)
+                        .build(),
+                    len: iovec.len() * block_size,
+                }
+            })
+            .collect();
+
+        self.submit_and_wait_all(ios)?;
+        self.set_written(lba, num_blocks);
+        self.persist_written(lba, num_blocks)
+    }
+
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        // Find contiguous ranges of written blocks, each of them is read by a single SQE
+        let mut ranges = Vec::new();
+        let mut offset = 0;
+        while offset < num_blocks {
+            if !self.is_written(lba + offset) {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            while offset < num_blocks
+                && offset - start < MAX_BLOCKS_PER_SQE as u64
+                && self.is_written(lba + offset)
+            {
+                offset += 1;
+            }
+            ranges.push((start as usize, offset as usize));
+        }
+
+        // Blocks are read into a single aligned buffer, which is split into blocks without copying
+        let block_size = self.device_info.block_size() as usize;
+        let mut buffer = AlignedBuffer::new(num_blocks as usize * block_size)?;
+        buffer.as_mut_slice().fill(UNMAP_BYTE);
+        let buffer_ptr = buffer.as_mut_slice().as_mut_ptr();
+        let ios = ranges
+            .iter()
+            .map(|(start, end)| {
+                let len = (end - start) * block_size;
+                // SAFETY: the range is within the buffer
+                let ptr = unsafe { buffer_ptr.add(start * block_size) };
+                UringIo {
+                    entry: opcode::Read::new(self.fd(), ptr, len as u32)
+                        .offset(self.data_offset_of(lba + *start as u64) as i64)
+                        .build(),
+                    len,
+                }
+            })
+            .collect();
+
+        self.submit_and_wait_all(ios)?;
+        Ok(DataBlock::split(Bytes::from_owner(buffer), block_size))
     }
 
-    fn read(&mut self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>, String> {
-        todo!()
+    /// Punches a hole in the backing file to release the space of the blocks. The blocks are
+    /// marked as unmapped first, so that they are not read from the hole after a crash.
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        self.clear_written(lba, num_blocks);
+        self.persist_written(lba, num_blocks)?;
+
+        let len = num_blocks * self.device_info.block_size();
+        let punch_hole = UringIo {
+            entry: opcode::Fallocate64::new(self.fd(), len as i64)
+                .offset(self.data_offset_of(lba) as i64)
+                .mode(libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE)
+                .build(),
+            len: 0,
+        };
+        self.submit_and_wait_all(vec![punch_hole])
+    }
+
+    /// Zeroes the range of the backing file without writing data
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let len = num_blocks * self.device_info.block_size();
+        let zero_range = UringIo {
+            entry: opcode::Fallocate64::new(self.fd(), len as i64)
+                .offset(self.data_offset_of(lba) as i64)
+                .mode(libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE)
+                .build(),
+            len: 0,
+        };
+        self.submit_and_wait_all(vec![zero_range])?;
+        self.set_written(lba, num_blocks);
+        self.persist_written(lba, num_blocks)
     }
 
-    fn load(&mut self) -> Result<(), String> {
-        todo!()
+    fn load(&mut self) -> Result<()> {
+        let mut superblock = vec![0u8; DEFAULT_BLOCK_SIZE];
+        let io = UringIo {
+            entry: opcode::Read::new(
+                self.fd(),
+                superblock.as_mut_ptr(),
+                DEFAULT_BLOCK_SIZE as u32,
+            )
+            .offset(0)
+            .build(),
+            len: DEFAULT_BLOCK_SIZE,
+        };
+        self.submit_and_wait_all(vec![io])?;
+
+        let device_info: DeviceInfo = bincode::deserialize(&superblock)?;
+        if device_info.device_type() != BlockDeviceType::IoUringFakeDevice {
+            return Err(MinistoreError::Corrupted {
+                reason: format!("Invalid device type, type={}", device_info.device_type()),
+            });
+        }
+
+        let mut bitmap = vec![0u8; bitmap_region_len(device_info.num_blocks())];
+        if !bitmap.is_empty() {
+            let io = UringIo {
+                entry: opcode::Read::new(self.fd(), bitmap.as_mut_ptr(), bitmap.len() as u32)
+                    .offset(DEFAULT_BLOCK_SIZE as i64)
+                    .build(),
+                len: bitmap.len(),
+            };
+            self.submit_and_wait_all(vec![io])?;
+        }
+        bitmap.truncate(bitmap_len(device_info.num_blocks()));
+
+        self.device_info = device_info;
+        self.written = new_bitmap(bitmap);
+        Ok(())
     }
 
-    fn flush(&mut self) -> Result<(), String> {
-        todo!()
+    fn flush(&mut self) -> Result<()> {
+        let mut superblock = bincode::serialize(&self.device_info)?;
+        if superblock.len() > DEFAULT_BLOCK_SIZE {
+            return Err(MinistoreError::internal(format!(
+                "Device info is too large to be stored, len={}",
+                superblock.len()
+            )));
+        }
+        superblock.resize(DEFAULT_BLOCK_SIZE, 0);
+
+        let mut bitmap: Vec<u8> = self
+            .written
+            .iter()
+            .map(|byte| byte.load(Ordering::Acquire))
+            .collect();
+        bitmap.resize(bitmap_region_len(self.device_info.num_blocks()), 0);
+
+        let mut ios = vec![UringIo {
+            entry: opcode::Write::new(self.fd(), superblock.as_ptr(), superblock.len() as u32)
+                .offset(0)
+                .build(),
+            len: superblock.len(),
+        }];
+        if !bitmap.is_empty() {
+            ios.push(UringIo {
+                entry: opcode::Write::new(self.fd(), bitmap.as_ptr(), bitmap.len() as u32)
+                    .offset(DEFAULT_BLOCK_SIZE as i64)
+                    .build(),
+                len: bitmap.len(),
+            });
+        }
+        self.submit_and_wait_all(ios)?;
+
+        // Completions are unordered, so fsync is submitted after all writes are completed
+        let fsync = UringIo {
+            entry: opcode::Fsync::new(self.fd()).build(),
+            len: 0,
+        };
+        self.submit_and_wait_all(vec![fsync])
     }
 }
 
+fn new_bitmap(bitmap: Vec<u8>) -> Vec<AtomicU8> {
+    bitmap.into_iter().map(AtomicU8::new).collect()
+}
+
+pub(super) fn bitmap_len(num_blocks: u64) -> usize {
+    num_blocks.div_ceil(8) as usize
+}
+
+pub(super) fn bitmap_region_len(num_blocks: u64) -> usize {
+    bitmap_len(num_blocks).div_ceil(DEFAULT_BLOCK_SIZE) * DEFAULT_BLOCK_SIZE
+}
+
+pub(super) fn data_offset(num_blocks: u64) -> u64 {
+    (DEFAULT_BLOCK_SIZE + bitmap_region_len(num_blocks)) as u64
+}
+
 #[cfg(target_os = "linux")]
 #[cfg(test)]
 mod tests {
+    use super::*;
     use io_uring::{opcode, types, IoUring};
     use std::fs;
     use std::os::unix::io::AsRawFd;
     use std::panic;
     use std::path::Path;
 
-    fn panic_hook(info: &panic::PanicInfo<'_>) {
+    fn panic_hook(info: &panic::PanicHookInfo<'_>) {
         println!("Panic occurred: {:?}", info);
         let path = Path::new("text.txt");
         if path.try_exists().unwrap() {
@@ -69,7 +521,8 @@ mod tests {
             .read(true)
             .write(true)
             .create(true)
-            .open(file_name.clone())
+            .truncate(true)
+            .open(file_name)
             .expect("Failed to open file");
         // Write data to the file
         {
@@ -112,4 +565,150 @@ mod tests {
             fs::remove_file(file_name).unwrap();
         }
     }
+
+    #[test]
+    fn io_uring_fake_device_should_split_large_io_into_multiple_sqes() {
+        let device_name = "io_uring_fake_device_should_split_large_io_into_multiple_sqes";
+        let num_blocks = (MAX_BLOCKS_PER_SQE * URING_SIZE as usize * 2 + 10) as u64;
+        let device = IoUringFakeDevice::new(
+            device_name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * num_blocks,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .expect("Failed to create device");
+
+        let buffer: Vec<DataBlock> = (0..num_blocks)
+            .map(|lba| DataBlock::from(vec![lba as u8; DEFAULT_BLOCK_SIZE]))
+            .collect();
+        device
+            .write(0, num_blocks, buffer.clone())
+            .expect("Failed to write data");
+        assert_eq!(
+            device.read(0, num_blocks).expect("Failed to read data"),
+            buffer
+        );
+
+        fs::remove_file(device_name).unwrap();
+    }
+
+    #[test]
+    fn io_uring_fake_device_should_read_written_and_unwritten_ranges_together() {
+        let device_name = "io_uring_fake_device_should_read_written_and_unwritten_ranges_together";
+        let mut device = IoUringFakeDevice::new(
+            device_name.to_string(),
+            DEFAULT_BLOCK_SIZE as u64 * 100,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .expect("Failed to create device");
+
+        device
+            .write(
+                1,
+                2,
+                vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2],
+            )
+            .expect("Failed to write data");
+        device
+            .write(5, 1, vec![DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE])])
+            .expect("Failed to write data");
+
+        let expected = vec![
+            DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+            DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+            DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+            DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+            DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+            DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+        ];
+        assert_eq!(device.read(0, 7).expect("Failed to read data"), expected);
+
+        // Written blocks should be kept after flush and load
+        device.flush().expect("Failed to flush");
+        let mut device = IoUringFakeDevice::new(
+            device_name.to_string(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .expect("Failed to create device");
+        device.load().expect("Failed to load");
+        assert_eq!(device.read(0, 7).expect("Failed to read data"), expected);
+
+        fs::remove_file(device_name).unwrap();
+    }
+
+    #[test]
+    fn io_uring_fake_device_should_keep_written_blocks_without_flush() {
+        let device_name = "io_uring_fake_device_should_keep_written_blocks_without_flush";
+        let block = DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]);
+        {
+            let mut device = IoUringFakeDevice::new(
+                device_name.to_string(),
+                DEFAULT_BLOCK_SIZE as u64 * 100,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create device");
+            device
+                .write(0, 4, vec![block.clone(); 4])
+                .expect("Failed to write data");
+            device.flush().expect("Failed to flush");
+
+            // The device crashes without flushing the following I/Os
+            device.unmap(1, 1).expect("Failed to unmap");
+            device.write_zeroes(2, 1).expect("Failed to write zeroes");
+            device
+                .write(9, 1, vec![block.clone()])
+                .expect("Failed to write data");
+        }
+
+        let mut device = IoUringFakeDevice::new(
+            device_name.to_string(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .expect("Failed to create device");
+        device.load().expect("Failed to load");
+        assert_eq!(
+            device.read(0, 10).expect("Failed to read data"),
+            vec![
+                block.clone(),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                block.clone(),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                block,
+            ]
+        );
+
+        fs::remove_file(device_name).unwrap();
+    }
+
+    #[test]
+    fn failed_io_should_be_returned_and_leave_ring_reusable() {
+        let mut ring = IoUring::new(URING_SIZE).expect("Failed to create IoUring");
+        let mut buffer = vec![0u8; DEFAULT_BLOCK_SIZE];
+        let ios = (0..URING_SIZE * 2)
+            .map(|_| UringIo {
+                entry: opcode::Read::new(types::Fd(-1), buffer.as_mut_ptr(), buffer.len() as u32)
+                    .build(),
+                len: buffer.len(),
+            })
+            .collect();
+
+        assert!(matches!(
+            submit_and_wait_all(&mut ring, ios),
+            Err(MinistoreError::Io { .. })
+        ));
+        assert!(ring.submission().is_empty());
+        assert!(ring.completion().is_empty());
+    }
 }
@@ -0,0 +1,376 @@
+use super::io_uring_fake_device::data_offset;
+use super::{BlockDevice, BlockDeviceType};
+use crate::block_device_common::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use bytes::Bytes;
+use memmap2::MmapRaw;
+use std::fs::{File, OpenOptions};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, RwLock};
+
+/// Number of locks which the blocks are striped over
+const NUM_BLOCK_LOCKS: u64 = 64;
+
+/// MmapFakeDevice maps its backing file into memory, so that reads and writes are memory copies
+/// and flush only syncs the ranges written since the last flush.
+///
+/// The backing file has the same layout as IoUringFakeDevice
+/// | superblock (1 block) | bitmap of written blocks (block aligned) | data blocks |
+/// and the bitmap is kept in the mapping as well.
+pub struct MmapFakeDevice {
+    device_info: DeviceInfo,
+    filepath: PathBuf,
+    /// Each block of the mapping is only copied while its lock in block_locks is held, and the
+    /// bitmap is only accessed atomically
+    mmap: MmapRaw,
+    block_locks: Vec<RwLock<()>>,
+    /// Byte ranges of the mapping modified after the last flush
+    dirty: Mutex<Vec<Range<usize>>>,
+}
+
+impl MmapFakeDevice {
+    pub fn new(name: String, size: u64, block_size: u64, filepath: PathBuf) -> Result<Self> {
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::MmapFakeDevice,
+            name.clone(),
+            size,
+            block_size,
+        )?;
+        let filepath = filepath.join(&name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", filepath), e)
+            })?;
+        let mmap = map_file(&file, &device_info, &filepath)?;
+
+        Ok(MmapFakeDevice {
+            device_info,
+            filepath,
+            mmap,
+            block_locks: (0..NUM_BLOCK_LOCKS).map(|_| RwLock::new(())).collect(),
+            dirty: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn block_lock(&self, lba: u64) -> &RwLock<()> {
+        &self.block_locks[(lba % NUM_BLOCK_LOCKS) as usize]
+    }
+
+    /// Blocks sharing a byte of the bitmap may be updated at the same time, so the byte is atomic
+    fn bitmap_byte(&self, lba: u64) -> &AtomicU8 {
+        let offset = DEFAULT_BLOCK_SIZE + (lba / 8) as usize;
+        // SAFETY: the bitmap is in the mapping, which lives as long as self, and the bytes of the
+        // bitmap are never accessed other than atomically
+        unsafe { AtomicU8::from_ptr(self.mmap.as_mut_ptr().add(offset)) }
+    }
+
+    fn is_written(&self, lba: u64) -> bool {
+        self.bitmap_byte(lba).load(Ordering::Acquire) & (1 << (lba % 8)) != 0
+    }
+
+    fn set_written(&self, lba: u64, written: bool) {
+        match written {
+            true => self
+                .bitmap_byte(lba)
+                .fetch_or(1 << (lba % 8), Ordering::Release),
+            false => self
+                .bitmap_byte(lba)
+                .fetch_and(!(1 << (lba % 8)), Ordering::Release),
+        };
+    }
+
+    fn bitmap_range(&self, lba: u64, num_blocks: u64) -> Range<usize> {
+        let start = DEFAULT_BLOCK_SIZE + (lba / 8) as usize;
+        let end = DEFAULT_BLOCK_SIZE + ((lba + num_blocks - 1) / 8) as usize + 1;
+        start..end
+    }
+
+    /// Updates each block while holding its lock
+    fn update_blocks(
+        &self,
+        lba: u64,
+        num_blocks: u64,
+        written: bool,
+        mut update: impl FnMut(u64, *mut u8),
+    ) -> Result<()> {
+        for lba in lba..lba + num_blocks {
+            let _guard = self
+                .block_lock(lba)
+                .write()
+                .map_err(|e| MinistoreError::internal(e.to_string()))?;
+            update(lba, self.block_ptr(lba));
+            self.set_written(lba, written);
+        }
+        self.mark_dirty(self.bitmap_range(lba, num_blocks))
+    }
+
+    fn block_ptr(&self, lba: u64) -> *mut u8 {
+        // SAFETY: the lba is checked against the device, and the mapping covers all the blocks
+        unsafe { self.mmap.as_mut_ptr().add(self.data_range(lba, 1).start) }
+    }
+
+    fn mark_dirty(&self, range: Range<usize>) -> Result<()> {
+        self.dirty
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?
+            .push(range);
+        Ok(())
+    }
+
+    fn data_range(&self, lba: u64, num_blocks: u64) -> Range<usize> {
+        let block_size = self.device_info.block_size();
+        let start = data_offset(self.device_info.num_blocks()) + lba * block_size;
+        start as usize..(start + num_blocks * block_size) as usize
+    }
+}
+
+/// Extends the file to hold the whole device and maps all of it
+fn map_file(file: &File, device_info: &DeviceInfo, filepath: &Path) -> Result<MmapRaw> {
+    // Backing file is sparse, so the blocks are allocated on the first write
+    let file_len = data_offset(device_info.num_blocks()) + device_info.device_size();
+    let current_len = file.metadata()?.len();
+    if current_len < file_len {
+        file.set_len(file_len).map_err(|e| {
+            MinistoreError::io(format!("Failed to resize file, path={:?}", filepath), e)
+        })?;
+    }
+
+    // SAFETY: the file is only accessed through this mapping while the device is open
+    MmapRaw::map_raw(file)
+        .map_err(|e| MinistoreError::io(format!("Failed to map file, path={:?}", filepath), e))
+}
+
+/// Sorts the ranges and merges the overlapping or adjacent ones
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+impl BlockDevice for MmapFakeDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+        if num_blocks == 0 {
+            return Ok(());
+        }
+
+        let block_size = self.device_info.block_size() as usize;
+        let mut buffer = buffer.iter();
+        self.update_blocks(lba, num_blocks, true, |_, ptr| {
+            if let Some(block) = buffer.next() {
+                // SAFETY: the block is locked, and both sides are block_size bytes long
+                unsafe { std::ptr::copy_nonoverlapping(block.as_slice().as_ptr(), ptr, block_size) }
+            }
+        })?;
+        self.mark_dirty(self.data_range(lba, num_blocks))
+    }
+
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let block_size = self.device_info.block_size() as usize;
+        let unmapped = DataBlock::unmapped(block_size);
+        (lba..lba + num_blocks)
+            .map(|lba| {
+                let _guard = self
+                    .block_lock(lba)
+                    .read()
+                    .map_err(|e| MinistoreError::internal(e.to_string()))?;
+                if !self.is_written(lba) {
+                    return Ok(unmapped.clone());
+                }
+                // SAFETY: the block is locked, so it is not written while it is copied
+                let block = unsafe { std::slice::from_raw_parts(self.block_ptr(lba), block_size) };
+                Ok(DataBlock::from(Bytes::copy_from_slice(block)))
+            })
+            .collect()
+    }
+
+    /// Only the bitmap is updated, and the stale data is never read again
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if num_blocks == 0 {
+            return Ok(());
+        }
+        self.update_blocks(lba, num_blocks, false, |_, _| {})
+    }
+
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if num_blocks == 0 {
+            return Ok(());
+        }
+
+        let block_size = self.device_info.block_size() as usize;
+        // SAFETY: the block is locked, and it is block_size bytes long
+        self.update_blocks(lba, num_blocks, true, |_, ptr| unsafe {
+            std::ptr::write_bytes(ptr, 0, block_size)
+        })?;
+        self.mark_dirty(self.data_range(lba, num_blocks))
+    }
+
+    fn load(&mut self) -> Result<()> {
+        // SAFETY: the device is taken exclusively, so no I/O writes the mapping
+        let superblock =
+            unsafe { std::slice::from_raw_parts(self.mmap.as_ptr(), DEFAULT_BLOCK_SIZE) };
+        let device_info: DeviceInfo = bincode::deserialize(superblock)?;
+        if device_info.device_type() != BlockDeviceType::MmapFakeDevice {
+            return Err(MinistoreError::Corrupted {
+                reason: format!("Invalid device type, type={}", device_info.device_type()),
+            });
+        }
+
+        // The mapping covers the whole file, which may be larger than the device given to new()
+        let file_len = data_offset(device_info.num_blocks()) + device_info.device_size();
+        if (self.mmap.len() as u64) < file_len {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "File is smaller than the device, path={:?}, file_len={}, expected={}",
+                    self.filepath,
+                    self.mmap.len(),
+                    file_len
+                ),
+            });
+        }
+
+        self.device_info = device_info;
+        self.dirty
+            .get_mut()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?
+            .clear();
+        Ok(())
+    }
+
+    /// Writes the superblock into the mapping and syncs it with the dirty ranges
+    fn flush(&mut self) -> Result<()> {
+        let superblock = bincode::serialize(&self.device_info)?;
+        if superblock.len() > DEFAULT_BLOCK_SIZE {
+            return Err(MinistoreError::internal(format!(
+                "Device info is too large to be stored, len={}",
+                superblock.len()
+            )));
+        }
+        // SAFETY: the device is taken exclusively, and the superblock fits in the first block
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                superblock.as_ptr(),
+                self.mmap.as_mut_ptr(),
+                superblock.len(),
+            )
+        };
+        let dirty = self
+            .dirty
+            .get_mut()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?;
+        dirty.push(0..superblock.len());
+
+        for range in merge_ranges(std::mem::take(dirty)) {
+            self.mmap
+                .flush_range(range.start, range.len())
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to sync file, path={:?}", self.filepath), e)
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device::io_uring_fake_device::bitmap_len;
+    use tracing_test::traced_test;
+
+    #[test]
+    fn dirty_ranges_should_be_merged() {
+        assert_eq!(
+            merge_ranges(vec![8..10, 0..2, 4..6, 1..3, 6..7]),
+            vec![0..3, 4..7, 8..10]
+        );
+        assert!(merge_ranges(Vec::new()).is_empty());
+    }
+
+    #[traced_test]
+    #[test]
+    fn flush_should_only_sync_dirty_ranges() {
+        let device_name = "flush_should_only_sync_dirty_ranges".to_string();
+        let num_blocks = 1024;
+        let block = DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]);
+        {
+            let mut device = MmapFakeDevice::new(
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * num_blocks,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create device");
+            device.write(0, 1, vec![block.clone()]).unwrap();
+            device.write(1, 1, vec![block.clone()]).unwrap();
+            device.write_zeroes(num_blocks - 1, 1).unwrap();
+
+            // Superblock, bitmap bytes of lba 0..2 and num_blocks - 1, and lba 0..2
+            let data_start = data_offset(num_blocks) as usize;
+            assert_eq!(
+                merge_ranges(device.dirty.lock().unwrap().clone()),
+                vec![
+                    DEFAULT_BLOCK_SIZE..DEFAULT_BLOCK_SIZE + 1,
+                    DEFAULT_BLOCK_SIZE + bitmap_len(num_blocks) - 1
+                        ..DEFAULT_BLOCK_SIZE + bitmap_len(num_blocks),
+                    data_start..data_start + 2 * DEFAULT_BLOCK_SIZE,
+                    device.data_range(num_blocks - 1, 1),
+                ]
+            );
+            device.flush().expect("Failed to flush");
+            assert!(device.dirty.lock().unwrap().is_empty());
+        }
+
+        let mut device = MmapFakeDevice::new(
+            device_name.clone(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            ".".into(),
+        )
+        .unwrap();
+        device.load().expect("Failed to load");
+        assert_eq!(device.info().num_blocks(), num_blocks);
+        assert_eq!(
+            device.read(0, 3).unwrap(),
+            vec![
+                block.clone(),
+                block,
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+            ]
+        );
+        assert_eq!(
+            device.read(num_blocks - 1, 1).unwrap(),
+            vec![DataBlock::zeroed(DEFAULT_BLOCK_SIZE)]
+        );
+
+        std::fs::remove_file(device_name).expect("Failed to remove file");
+    }
+}
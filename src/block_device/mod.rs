@@ -3,53 +3,120 @@ use std::path::PathBuf;
 use crate::block_device_common::data_type::DataBlock;
 use crate::block_device_common::device_info::DeviceInfo;
 use crate::block_device_common::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
 
+use mmap_fake_device::MmapFakeDevice;
 use simple_fake_device::SimpleFakeDevice;
+use sparse_fake_device::SparseFakeDevice;
 
 pub mod io_uring_fake_device;
+pub mod mmap_fake_device;
+#[cfg(target_os = "linux")]
+pub mod raw_block_device;
 pub mod simple_fake_device;
+pub mod sparse_fake_device;
+pub mod write_ahead_log;
 
+/// I/Os take &self, so that they can run on multiple threads at the same time. Each device locks
+/// only the blocks which an I/O touches, and callers are responsible for ordering overlapping I/Os
+/// (see RangeLock). Load and flush take the device exclusively.
 pub trait BlockDevice: Send + Sync {
     fn info(&self) -> &DeviceInfo;
-    fn write(&mut self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<(), String>;
-    fn read(&mut self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>, String>;
-    fn load(&mut self) -> Result<(), String>;
-    fn flush(&mut self) -> Result<(), String>;
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()>;
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>>;
+    /// Discards the blocks, so that reading them returns unmapped blocks
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()>;
+    /// Fills the blocks with zeroes without transferring data
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()>;
+    fn load(&mut self) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
 }
 
 pub fn create_block_device(
     device_type: BlockDeviceType,
     name: String,
     size: u64,
+    block_size: u64,
     filepath: PathBuf,
-) -> Result<Box<dyn BlockDevice>, String> {
+) -> Result<Box<dyn BlockDevice>> {
     match device_type {
         BlockDeviceType::SimpleFakeDevice => {
-            let fake = SimpleFakeDevice::new(name, size, filepath)?;
+            let fake = SimpleFakeDevice::new(name, size, block_size, filepath)?;
+            Ok(Box::new(fake))
+        }
+        BlockDeviceType::SimpleFakeWalDevice => {
+            let fake = SimpleFakeDevice::new_with_wal(name, size, block_size, filepath)?;
             Ok(Box::new(fake))
         }
-        BlockDeviceType::AsyncSimpleFakeDevice => {
-            Err("Cannot create BlockDevice trait for AsyncSimpleFakeDevice".to_string())
+        BlockDeviceType::AsyncSimpleFakeDevice => Err(MinistoreError::NotSupported {
+            reason: "Cannot create BlockDevice trait for AsyncSimpleFakeDevice".to_string(),
+        }),
+        BlockDeviceType::IoUringFakeDevice => {
+            create_io_uring_fake_device(name, size, block_size, filepath)
+        }
+        BlockDeviceType::RawBlockDevice => create_raw_block_device(name, size, filepath),
+        BlockDeviceType::SparseFakeDevice => {
+            let fake = SparseFakeDevice::new(name, size, block_size, filepath)?;
+            Ok(Box::new(fake))
+        }
+        BlockDeviceType::MmapFakeDevice => {
+            let fake = MmapFakeDevice::new(name, size, block_size, filepath)?;
+            Ok(Box::new(fake))
         }
+        BlockDeviceType::Volume => Err(MinistoreError::NotSupported {
+            reason: "Cannot create BlockDevice trait for Volume".to_string(),
+        }),
+        BlockDeviceType::Mirror => Err(MinistoreError::NotSupported {
+            reason: "Cannot create BlockDevice trait for Mirror".to_string(),
+        }),
+        BlockDeviceType::Raid0 | BlockDeviceType::Raid5 => Err(MinistoreError::NotSupported {
+            reason: "Cannot create BlockDevice trait for RAID array".to_string(),
+        }),
     }
 }
 
+/// Opens an existing block device at the filepath. The size is discovered from the device, and the
+/// default block size is used.
+#[cfg(target_os = "linux")]
+fn create_raw_block_device(
+    name: String,
+    _size: u64,
+    filepath: PathBuf,
+) -> Result<Box<dyn BlockDevice>> {
+    let device = raw_block_device::RawBlockDevice::open(name, filepath)?;
+    Ok(Box::new(device))
+}
+#[cfg(not(target_os = "linux"))]
+fn create_raw_block_device(
+    _name: String,
+    _size: u64,
+    _filepath: PathBuf,
+) -> Result<Box<dyn BlockDevice>> {
+    Err(MinistoreError::NotSupported {
+        reason: "Cannot create raw block device".to_string(),
+    })
+}
+
 #[cfg(target_os = "linux")]
 fn create_io_uring_fake_device(
     name: String,
     size: u64,
-    _filepath: PathBuf,
-) -> Result<Box<dyn BlockDevice>, String> {
-    let device = io_uring_fake_device::IoUringFakeDevice::new(name, size)?;
+    block_size: u64,
+    filepath: PathBuf,
+) -> Result<Box<dyn BlockDevice>> {
+    let device = io_uring_fake_device::IoUringFakeDevice::new(name, size, block_size, filepath)?;
     Ok(Box::new(device))
 }
 #[cfg(not(target_os = "linux"))]
 fn create_io_uring_fake_device(
     _name: String,
     _size: u64,
+    _block_size: u64,
     _filepath: PathBuf,
-) -> Result<Box<dyn BlockDevice>, String> {
-    Err("Cannot create io uring fake device".to_string())
+) -> Result<Box<dyn BlockDevice>> {
+    Err(MinistoreError::NotSupported {
+        reason: "Cannot create io uring fake device".to_string(),
+    })
 }
 
 #[cfg(test)]
@@ -71,16 +138,26 @@ mod tests {
         // Use SimpleFakeDevice instead when target os is not a linux
         match device_type {
             BlockDeviceType::SimpleFakeDevice => BlockDeviceType::SimpleFakeDevice,
+            BlockDeviceType::SimpleFakeWalDevice => BlockDeviceType::SimpleFakeWalDevice,
             BlockDeviceType::AsyncSimpleFakeDevice => panic!("async type cannot be used here"),
+            BlockDeviceType::IoUringFakeDevice => BlockDeviceType::SimpleFakeDevice,
+            BlockDeviceType::RawBlockDevice => panic!("raw device cannot be used here"),
+            BlockDeviceType::SparseFakeDevice => BlockDeviceType::SparseFakeDevice,
+            BlockDeviceType::MmapFakeDevice => BlockDeviceType::MmapFakeDevice,
+            BlockDeviceType::Volume => panic!("volume cannot be used here"),
+            BlockDeviceType::Mirror => panic!("mirror cannot be used here"),
+            BlockDeviceType::Raid0 => panic!("array cannot be used here"),
+            BlockDeviceType::Raid5 => panic!("array cannot be used here"),
         }
     }
 
     fn for_each_block_device_type<F>(mut f: F)
     where
-        F: FnMut(BlockDeviceType) -> () + std::panic::UnwindSafe,
+        F: FnMut(BlockDeviceType) + std::panic::UnwindSafe,
     {
         for device_type in BlockDeviceType::iter() {
-            if device_type.is_sync() {
+            // Raw devices are not created by ministore, see raw_block_device tests
+            if device_type.is_sync() && device_type.is_fake() {
                 let device_type = translate_device_type(device_type);
                 if let Err(e) = catch_assertion_failure(std::panic::AssertUnwindSafe(|| {
                     f(device_type.clone());
@@ -91,9 +168,9 @@ mod tests {
         }
     }
 
-    fn catch_assertion_failure<F>(f: F) -> Result<(), String>
+    fn catch_assertion_failure<F>(f: F) -> std::result::Result<(), String>
     where
-        F: FnOnce() -> () + std::panic::UnwindSafe,
+        F: FnOnce() + std::panic::UnwindSafe,
     {
         let result = std::panic::catch_unwind(|| {
             f();
@@ -105,7 +182,7 @@ mod tests {
                 None => "",
             };
 
-            return Err(message.clone().into());
+            return Err(message.into());
         }
         Ok(())
     }
@@ -118,10 +195,11 @@ mod tests {
                 device_type,
                 "block_device_should_provide_correct_device_info".to_string(),
                 1000000,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             );
 
-            assert!(device.is_err() == true);
+            assert!(device.is_err());
         });
     }
 
@@ -133,12 +211,13 @@ mod tests {
             let _device = create_block_device(
                 device_type.clone(),
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1000,
+                DEFAULT_BLOCK_SIZE as u64 * 1000,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
-            .expect(&format!("Failed to create a device, type={}", device_type));
+            .unwrap_or_else(|_| panic!("Failed to create a device, type={}", device_type));
 
-            assert_eq!(Path::new(&device_name).exists(), true);
+            assert!(Path::new(&device_name).exists());
             std::fs::remove_file(device_name).expect("Failed to remove file");
         })
     }
@@ -151,14 +230,15 @@ mod tests {
             let device = create_block_device(
                 device_type.clone(),
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1000,
+                DEFAULT_BLOCK_SIZE as u64 * 1000,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
-            .expect(&format!("Failed to create a device, type={}", device_type));
+            .unwrap_or_else(|_| panic!("Failed to create a device, type={}", device_type));
 
             let info = device.info();
             assert_eq!(info.name(), &device_name);
-            assert_eq!(info.device_size(), BLOCK_SIZE as u64 * 1000);
+            assert_eq!(info.device_size(), DEFAULT_BLOCK_SIZE as u64 * 1000);
             assert_eq!(info.num_blocks(), 1000);
             assert_eq!(info.device_type(), device_type);
 
@@ -171,10 +251,11 @@ mod tests {
     fn write_and_read_should_success() {
         for_each_block_device_type(|device_type| {
             let device_name = "write_and_read_should_success".to_string();
-            let mut device = create_block_device(
+            let device = create_block_device(
                 device_type,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .expect("Failed to create fake device");
@@ -183,40 +264,93 @@ mod tests {
             let num_blocks = 5;
             let mut buffers = Vec::new();
             for num in 0..num_blocks {
-                let block_buffer = DataBlock([num as u8 as u8; BLOCK_SIZE]);
+                let block_buffer = DataBlock::from(vec![num as u8; DEFAULT_BLOCK_SIZE]);
                 buffers.push(block_buffer);
             }
-            assert_eq!(
-                device
-                    .write(lba, num_blocks, buffers.clone().to_vec())
-                    .is_ok(),
-                true
-            );
+            assert!(device
+                .write(lba, num_blocks, buffers.clone().to_vec())
+                .is_ok());
 
             let read_result = device.read(lba, num_blocks);
-            assert_eq!(read_result.is_ok(), true);
+            assert!(read_result.is_ok());
             assert_eq!(read_result.unwrap(), buffers);
 
             std::fs::remove_file(device_name).expect("Failed to remove file");
         });
     }
 
+    #[traced_test]
+    #[test]
+    fn devices_with_other_block_sizes_should_write_and_read() {
+        for_each_block_device_type(|device_type| {
+            for block_size in [512, 16 * 1024] {
+                let device_name =
+                    "devices_with_other_block_sizes_should_write_and_read".to_string();
+                let mut device = create_block_device(
+                    device_type.clone(),
+                    device_name.clone(),
+                    block_size as u64 * 64,
+                    block_size as u64,
+                    PathBuf::from("."),
+                )
+                .expect("Failed to create fake device");
+                assert_eq!(device.info().block_size(), block_size as u64);
+                assert_eq!(device.info().num_blocks(), 64);
+
+                let blocks = vec![
+                    DataBlock::from(vec![0xA; block_size]),
+                    DataBlock::from(vec![0xB; block_size]),
+                ];
+                device.write(1, 2, blocks.clone()).expect("Failed to write");
+                device.write_zeroes(3, 1).expect("Failed to write zeroes");
+                assert!(matches!(
+                    device.write(0, 1, vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])]),
+                    Err(MinistoreError::InvalidArgument { .. })
+                ));
+                let expected = vec![
+                    DataBlock::unmapped(block_size),
+                    blocks[0].clone(),
+                    blocks[1].clone(),
+                    DataBlock::zeroed(block_size),
+                ];
+                assert_eq!(device.read(0, 4).expect("Failed to read"), expected);
+
+                // Block size should be kept after flush and load
+                device.flush().expect("Failed to flush");
+                let mut device = create_block_device(
+                    device_type.clone(),
+                    device_name.clone(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                    DEFAULT_BLOCK_SIZE as u64,
+                    PathBuf::from("."),
+                )
+                .expect("Failed to create fake device");
+                device.load().expect("Failed to load");
+                assert_eq!(device.info().block_size(), block_size as u64);
+                assert_eq!(device.read(0, 4).expect("Failed to read"), expected);
+
+                std::fs::remove_file(device_name).expect("Failed to remove file");
+            }
+        });
+    }
+
     #[traced_test]
     #[test]
     fn write_with_invalid_lba_range_should_fail() {
         for_each_block_device_type(|device_type| {
             let device_name = "write_with_invalid_lba_range_should_fail".to_string();
-            let mut device = create_block_device(
+            let device = create_block_device(
                 device_type,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .expect("Failed to create fake device");
 
             let buffer = Vec::new();
-            assert_eq!(device.write(0, 2000, buffer.clone()).is_err(), true);
-            assert_eq!(device.write(0, 0, buffer.clone()).is_err(), true);
+            assert!(device.write(0, 2000, buffer.clone()).is_err());
+            assert!(device.write(0, 0, buffer.clone()).is_err());
 
             std::fs::remove_file(device_name).expect("Failed to remove file");
         });
@@ -227,19 +361,20 @@ mod tests {
     fn write_should_fail_when_not_enough_buffer_is_provided() {
         for_each_block_device_type(|device_type| {
             let device_name = "write_should_fail_when_not_enough_buffer_is_provided".to_string();
-            let mut device = create_block_device(
+            let device = create_block_device(
                 device_type,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .expect("Failed to create fake device");
 
             let mut buffer = Vec::new();
             for offset in 0..5 {
-                buffer.push(DataBlock([offset as u8; BLOCK_SIZE]));
+                buffer.push(DataBlock::from(vec![offset as u8; DEFAULT_BLOCK_SIZE]));
             }
-            assert_eq!(device.write(0, 10, buffer.clone()).is_err(), true);
+            assert!(device.write(0, 10, buffer.clone()).is_err());
 
             std::fs::remove_file(device_name).expect("Failed to remove file");
         });
@@ -250,16 +385,17 @@ mod tests {
     fn read_with_invalid_lba_range_should_fail() {
         for_each_block_device_type(|device_type| {
             let device_name = "read_with_invalid_lba_range_should_fail".to_string();
-            let mut device = create_block_device(
+            let device = create_block_device(
                 device_type,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .expect("Failed to create fake device");
 
-            assert_eq!(device.read(0, 2000).is_err(), true);
-            assert_eq!(device.read(0, 0).is_err(), true);
+            assert!(device.read(0, 2000).is_err());
+            assert!(device.read(0, 0).is_err());
 
             std::fs::remove_file(device_name).expect("Failed to remove file");
         });
@@ -270,17 +406,121 @@ mod tests {
     fn reading_unwritten_lbas_should_return_unmap_data() {
         for_each_block_device_type(|device_type| {
             let device_name = "reading_unwritten_lbas_should_return_unmap_data".to_string();
-            let mut device = create_block_device(
+            let device = create_block_device(
                 device_type,
                 device_name.clone(),
-                BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
                 PathBuf::from("."),
             )
             .expect("Failed to create fake device");
 
             let read_data = device.read(0, 1).expect("Failed to read data");
             assert_eq!(read_data.len(), 1);
-            assert_eq!(*read_data.get(0).unwrap(), UNMAP_BLOCK);
+            assert_eq!(
+                *read_data.first().unwrap(),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+            );
+
+            std::fs::remove_file(device_name).expect("Failed to remove file");
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn unmapped_lbas_should_return_unmap_data() {
+        for_each_block_device_type(|device_type| {
+            let device_name = "unmapped_lbas_should_return_unmap_data".to_string();
+            let device = create_block_device(
+                device_type,
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create fake device");
+
+            device
+                .write(
+                    10,
+                    4,
+                    vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 4],
+                )
+                .expect("Failed to write data");
+            device.unmap(11, 2).expect("Failed to unmap");
+
+            let read_data = device.read(10, 4).expect("Failed to read data");
+            assert_eq!(
+                read_data,
+                vec![
+                    DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                    DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])
+                ]
+            );
+
+            std::fs::remove_file(device_name).expect("Failed to remove file");
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn unmap_with_invalid_lba_range_should_fail() {
+        for_each_block_device_type(|device_type| {
+            let device_name = "unmap_with_invalid_lba_range_should_fail".to_string();
+            let device = create_block_device(
+                device_type,
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create fake device");
+
+            assert!(device.unmap(0, 2000).is_err());
+            assert!(device.unmap(1000, 100).is_err());
+            assert!(device.unmap(0, 0).is_err());
+
+            std::fs::remove_file(device_name).expect("Failed to remove file");
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn write_zeroes_should_fill_blocks_with_zero() {
+        for_each_block_device_type(|device_type| {
+            let device_name = "write_zeroes_should_fill_blocks_with_zero".to_string();
+            let device = create_block_device(
+                device_type,
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * 1024,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create fake device");
+
+            device
+                .write(
+                    10,
+                    2,
+                    vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2],
+                )
+                .expect("Failed to write data");
+            device.write_zeroes(11, 2).expect("Failed to write zeroes");
+            assert!(device.write_zeroes(1000, 100).is_err());
+            assert!(device.write_zeroes(0, 0).is_err());
+
+            let read_data = device.read(10, 4).expect("Failed to read data");
+            assert_eq!(
+                read_data,
+                vec![
+                    DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                    DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                    DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                    DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+                ]
+            );
 
             std::fs::remove_file(device_name).expect("Failed to remove file");
         });
@@ -297,7 +537,8 @@ mod tests {
                 let mut device = create_block_device(
                     device_type.clone(),
                     device_name.clone(),
-                    BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64,
                     PathBuf::from("."),
                 )
                 .expect("Failed to create block device");
@@ -307,9 +548,9 @@ mod tests {
                         0,
                         3,
                         vec![
-                            DataBlock([0xA; BLOCK_SIZE]),
-                            DataBlock([0xB; BLOCK_SIZE]),
-                            DataBlock([0xC; BLOCK_SIZE]),
+                            DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                            DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+                            DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE]),
                         ],
                     )
                     .expect("Failed to write data");
@@ -322,7 +563,8 @@ mod tests {
                 let mut device = create_block_device(
                     device_type,
                     device_name.clone(),
-                    BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64,
                     PathBuf::from("."),
                 )
                 .expect("Failed to create block device");
@@ -333,9 +575,9 @@ mod tests {
                 assert_eq!(
                     read_data,
                     vec![
-                        DataBlock([0xA; BLOCK_SIZE]),
-                        DataBlock([0xB; BLOCK_SIZE]),
-                        DataBlock([0xC; BLOCK_SIZE])
+                        DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                        DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+                        DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE])
                     ]
                 );
             }
@@ -354,7 +596,8 @@ mod tests {
                 let mut device = create_block_device(
                     device_type.clone(),
                     device_name.clone(),
-                    BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64 * 1024,
+                    DEFAULT_BLOCK_SIZE as u64,
                     PathBuf::from("."),
                 )
                 .expect("Failed to create block device");
@@ -367,13 +610,17 @@ mod tests {
                     device_type.clone(),
                     device_name.clone(),
                     0,
+                    DEFAULT_BLOCK_SIZE as u64,
                     PathBuf::from("."),
                 )
                 .expect("Failed to load a device");
                 device.load().expect("Failed to load data");
 
                 assert_eq!(device.info().name(), &device_name);
-                assert_eq!(device.info().device_size(), BLOCK_SIZE as u64 * 1024);
+                assert_eq!(
+                    device.info().device_size(),
+                    DEFAULT_BLOCK_SIZE as u64 * 1024
+                );
             }
 
             std::fs::remove_file(&device_name).expect("Failed to remove test file");
@@ -0,0 +1,450 @@
+use super::{BlockDevice, BlockDeviceType};
+use crate::block_device_common::data_type::{AlignedBuffer, DataBlock, DEFAULT_BLOCK_SIZE};
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileExt, FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Maximum number of blocks in a single pwritev, limited by IOV_MAX
+const MAX_BLOCKS_PER_WRITE: usize = 1024;
+
+/// RawBlockDevice does I/O directly to a linux block device (e.g. /dev/nvme0n1).
+/// A regular file can be used instead of a block device, e.g. for tests.
+pub struct RawBlockDevice {
+    device_info: DeviceInfo,
+    file: File,
+    path: PathBuf,
+}
+
+impl RawBlockDevice {
+    pub fn open(name: String, path: PathBuf) -> Result<Self> {
+        let mut file = open_direct(&path)?;
+        let size = get_device_size(&mut file, &path)?;
+
+        // Only the block aligned part of the device is used
+        let aligned_size = size - size % DEFAULT_BLOCK_SIZE as u64;
+        if aligned_size != size {
+            tracing::warn!(
+                "Device size is not aligned with block size, path={:?}, size={}, usable_size={}",
+                path,
+                size,
+                aligned_size
+            );
+        }
+
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::RawBlockDevice,
+            name,
+            aligned_size,
+            DEFAULT_BLOCK_SIZE as u64,
+        )?;
+
+        Ok(RawBlockDevice {
+            device_info,
+            file,
+            path,
+        })
+    }
+
+    fn fallocate(&self, mode: i32, lba: u64, num_blocks: u64) -> std::io::Result<()> {
+        // SAFETY: fd is valid while the file is open
+        let ret = unsafe {
+            libc::fallocate(
+                self.file.as_raw_fd(),
+                mode,
+                (lba * DEFAULT_BLOCK_SIZE as u64) as libc::off_t,
+                (num_blocks * DEFAULT_BLOCK_SIZE as u64) as libc::off_t,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for RawBlockDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+
+        // O_DIRECT needs aligned buffers, so blocks which are not aligned (e.g. sliced out of a
+        // gRPC message) are copied into an aligned buffer
+        let offset = lba * DEFAULT_BLOCK_SIZE as u64;
+        let written = match buffer.iter().all(DataBlock::is_aligned) {
+            true => write_blocks_at(&self.file, &buffer, offset),
+            false => {
+                let mut aligned = AlignedBuffer::new(num_blocks as usize * DEFAULT_BLOCK_SIZE)?;
+                for (chunk, block) in aligned
+                    .as_mut_slice()
+                    .chunks_exact_mut(DEFAULT_BLOCK_SIZE)
+                    .zip(buffer.iter())
+                {
+                    chunk.copy_from_slice(&block.0);
+                }
+                self.file.write_all_at(aligned.as_slice(), offset)
+            }
+        };
+        written.map_err(|e| MinistoreError::io(format!("Failed to write, path={:?}", self.path), e))
+    }
+
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let mut aligned = AlignedBuffer::new(num_blocks as usize * DEFAULT_BLOCK_SIZE)?;
+        self.file
+            .read_exact_at(aligned.as_mut_slice(), lba * DEFAULT_BLOCK_SIZE as u64)
+            .map_err(|e| MinistoreError::io(format!("Failed to read, path={:?}", self.path), e))?;
+
+        // Blocks share the aligned buffer
+        Ok(DataBlock::split(
+            Bytes::from_owner(aligned),
+            self.device_info.block_size() as usize,
+        ))
+    }
+
+    /// Punches a hole in the device. Note that the data of discarded blocks is defined by the
+    /// device (usually zeroes) rather than unmapped blocks.
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        self.fallocate(
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            lba,
+            num_blocks,
+        )
+        .map_err(|e| MinistoreError::io(format!("Failed to unmap, path={:?}", self.path), e))
+    }
+
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        self.fallocate(
+            libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE,
+            lba,
+            num_blocks,
+        )
+        .map_err(|e| MinistoreError::io(format!("Failed to write zeroes, path={:?}", self.path), e))
+    }
+
+    /// Data is always on the device, so only the device size is refreshed
+    fn load(&mut self) -> Result<()> {
+        let reopened = RawBlockDevice::open(self.device_info.name().clone(), self.path.clone())?;
+        *self = reopened;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file
+            .sync_data()
+            .map_err(|e| MinistoreError::io(format!("Failed to flush, path={:?}", self.path), e))
+    }
+}
+
+/// Writes the blocks from the offset with pwritev, so that they are not copied
+fn write_blocks_at(file: &File, blocks: &[DataBlock], mut offset: u64) -> std::io::Result<()> {
+    for chunk in blocks.chunks(MAX_BLOCKS_PER_WRITE) {
+        let mut iovecs: Vec<libc::iovec> = chunk
+            .iter()
+            .map(|block| libc::iovec {
+                iov_base: block.0.as_ptr() as *mut libc::c_void,
+                iov_len: block.len(),
+            })
+            .collect();
+        let mut iovecs = &mut iovecs[..];
+        while !iovecs.is_empty() {
+            // SAFETY: the iovecs point into the blocks, which are alive during the call
+            let ret = unsafe {
+                libc::pwritev(
+                    file.as_raw_fd(),
+                    iovecs.as_ptr(),
+                    iovecs.len() as libc::c_int,
+                    offset as libc::off_t,
+                )
+            };
+            if ret < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            if ret == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+
+            // Skip the written part after a short write
+            let mut written = ret as usize;
+            offset += written as u64;
+            while written > 0 {
+                if written >= iovecs[0].iov_len {
+                    written -= iovecs[0].iov_len;
+                    iovecs = &mut iovecs[1..];
+                } else {
+                    // SAFETY: the written part is within the block
+                    iovecs[0].iov_base = unsafe { iovecs[0].iov_base.add(written) };
+                    iovecs[0].iov_len -= written;
+                    written = 0;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Opens the device with O_DIRECT to bypass the page cache. Some filesystems (e.g. tmpfs) do not
+/// support O_DIRECT, in which case the device is opened with buffered I/O.
+fn open_direct(path: &Path) -> Result<File> {
+    let direct = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path);
+
+    match direct {
+        Ok(file) => Ok(file),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            tracing::warn!("O_DIRECT is not supported, path={:?}", path);
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .map_err(|e| {
+                    MinistoreError::io(format!("Failed to open device, path={:?}", path), e)
+                })
+        }
+        Err(e) => Err(MinistoreError::io(
+            format!("Failed to open device, path={:?}", path),
+            e,
+        )),
+    }
+}
+
+/// Block devices report zero length in their metadata, so seek to the end to get its capacity
+fn get_device_size(file: &mut File, path: &Path) -> Result<u64> {
+    let metadata = file
+        .metadata()
+        .map_err(|e| MinistoreError::io(format!("Failed to get metadata, path={:?}", path), e))?;
+
+    if metadata.file_type().is_block_device() {
+        file.seek(SeekFrom::End(0)).map_err(|e| {
+            MinistoreError::io(format!("Failed to get device size, path={:?}", path), e)
+        })
+    } else if metadata.is_file() {
+        Ok(metadata.len())
+    } else {
+        Err(MinistoreError::invalid_argument(format!(
+            "Not a block device or a regular file, path={:?}",
+            path
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    fn create_device_file(filename: &str, size: u64) {
+        let file = File::create(filename).expect("Failed to create file");
+        file.set_len(size).expect("Failed to set file length");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_should_discover_device_size() {
+        let filename = "raw_block_device_should_discover_device_size";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+        assert_eq!(device.info().name(), filename);
+        assert_eq!(device.info().device_size(), DEFAULT_BLOCK_SIZE as u64 * 100);
+        assert_eq!(device.info().num_blocks(), 100);
+        assert_eq!(device.info().device_type(), BlockDeviceType::RawBlockDevice);
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_should_ignore_unaligned_tail() {
+        let filename = "raw_block_device_should_ignore_unaligned_tail";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 10 + 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+        assert_eq!(device.info().num_blocks(), 10);
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn opening_raw_block_device_which_does_not_exist_should_fail() {
+        let filename = "opening_raw_block_device_which_does_not_exist_should_fail";
+        assert!(RawBlockDevice::open(filename.to_string(), PathBuf::from(filename)).is_err());
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_write_and_read_should_success() {
+        let filename = "raw_block_device_write_and_read_should_success";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+
+        let buffer = vec![
+            DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE]),
+        ];
+        device
+            .write(10, 3, buffer.clone())
+            .expect("Failed to write data");
+        assert_eq!(device.read(10, 3).expect("Failed to read data"), buffer);
+
+        assert!(device.write(99, 2, buffer.clone()).is_err());
+        assert!(device.write(10, 2, buffer).is_err());
+        assert!(device.read(0, 101).is_err());
+        assert!(device.read(0, 0).is_err());
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_unmap_should_discard_data() {
+        let filename = "raw_block_device_unmap_should_discard_data";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+        device
+            .write(
+                0,
+                2,
+                vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2],
+            )
+            .expect("Failed to write data");
+        device.unmap(0, 1).expect("Failed to unmap");
+        assert!(device.unmap(99, 2).is_err());
+
+        assert_eq!(
+            device.read(0, 2).expect("Failed to read data"),
+            vec![
+                DataBlock::from(vec![0; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])
+            ]
+        );
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_write_zeroes_should_zero_data() {
+        let filename = "raw_block_device_write_zeroes_should_zero_data";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+        device
+            .write(
+                0,
+                2,
+                vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2],
+            )
+            .expect("Failed to write data");
+        device.write_zeroes(1, 1).expect("Failed to write zeroes");
+        assert!(device.write_zeroes(99, 2).is_err());
+
+        assert_eq!(
+            device.read(0, 2).expect("Failed to read data"),
+            vec![
+                DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0; DEFAULT_BLOCK_SIZE])
+            ]
+        );
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_should_keep_data_after_reopen() {
+        let filename = "raw_block_device_should_keep_data_after_reopen";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        {
+            let mut device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+                .expect("Failed to open raw block device");
+            device
+                .write(0, 1, vec![DataBlock::from(vec![0xD; DEFAULT_BLOCK_SIZE])])
+                .expect("Failed to write data");
+            device.flush().expect("Failed to flush");
+        }
+
+        {
+            let mut device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+                .expect("Failed to open raw block device");
+            device.load().expect("Failed to load");
+            assert_eq!(
+                device.read(0, 1).expect("Failed to read data"),
+                vec![DataBlock::from(vec![0xD; DEFAULT_BLOCK_SIZE])]
+            );
+        }
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn raw_block_device_should_write_aligned_blocks_without_copying() {
+        let filename = "raw_block_device_should_write_aligned_blocks_without_copying";
+        create_device_file(filename, DEFAULT_BLOCK_SIZE as u64 * 100);
+
+        let device = RawBlockDevice::open(filename.to_string(), PathBuf::from(filename))
+            .expect("Failed to open raw block device");
+        device
+            .write(
+                0,
+                2,
+                vec![DataBlock::from(vec![0xE; DEFAULT_BLOCK_SIZE]); 2],
+            )
+            .expect("Failed to write data");
+
+        // Blocks read from the device are aligned, so they are written as they are
+        let blocks = device.read(0, 2).expect("Failed to read data");
+        assert!(blocks.iter().all(DataBlock::is_aligned));
+        device.write(10, 2, blocks).expect("Failed to write data");
+        device
+            .write(12, 1, vec![DataBlock::zeroed(DEFAULT_BLOCK_SIZE)])
+            .expect("Failed to write data");
+
+        assert_eq!(
+            device.read(10, 3).expect("Failed to read data"),
+            vec![
+                DataBlock::from(vec![0xE; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xE; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0; DEFAULT_BLOCK_SIZE])
+            ]
+        );
+
+        std::fs::remove_file(filename).expect("Failed to remove file");
+    }
+}
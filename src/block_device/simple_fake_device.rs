@@ -1,37 +1,454 @@
+use super::write_ahead_log::{WalRecord, WriteAheadLog, DEFAULT_CHECKPOINT_THRESHOLD};
 use super::{BlockDevice, BlockDeviceType};
-use crate::block_device_common::data_type::{DataBlock, UNMAP_BLOCK};
+use crate::block_device_common::data_type::DataBlock;
 use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
 
-use serde::{Deserialize, Serialize};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::{fs::OpenOptions, path::Path};
 
-pub struct SimpleFakeDevice {}
+/// SimpleFakeDevice keeps all blocks in memory and persists them into a file on flush.
+/// In WAL mode, the changes after the last flush are also logged at the end of the file.
+pub struct SimpleFakeDevice {
+    device_info: DeviceInfo,
+    /// Each block is locked separately, so that I/Os to other blocks run at the same time
+    data: Vec<RwLock<DataBlock>>,
+    filepath: PathBuf,
+    /// Only exists in WAL mode. Changes are logged and applied while the log is locked, so that
+    /// a checkpoint includes every logged change.
+    wal: Option<Mutex<WriteAheadLog>>,
+}
 
 impl SimpleFakeDevice {
-    pub fn new(name: String, size: u64, filepath: PathBuf) -> Result<Self, String> {
-        todo!()
+    pub fn new(name: String, size: u64, block_size: u64, filepath: PathBuf) -> Result<Self> {
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::SimpleFakeDevice,
+            name.clone(),
+            size,
+            block_size,
+        )?;
+        let filepath = filepath.join(&name);
+        create_file_if_not_exists(&filepath)?;
+
+        Ok(SimpleFakeDevice {
+            data: unmapped_blocks(block_size as usize, device_info.num_blocks()),
+            device_info,
+            filepath,
+            wal: None,
+        })
+    }
+
+    /// Every change is synced to the log before it is acknowledged, so that it is not lost by a
+    /// crash before flush. The log is checkpointed into the image when it grows too large.
+    pub fn new_with_wal(
+        name: String,
+        size: u64,
+        block_size: u64,
+        filepath: PathBuf,
+    ) -> Result<Self> {
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::SimpleFakeWalDevice,
+            name.clone(),
+            size,
+            block_size,
+        )?;
+        let filepath = filepath.join(&name);
+        create_file_if_not_exists(&filepath)?;
+
+        let mut device = SimpleFakeDevice {
+            data: unmapped_blocks(block_size as usize, device_info.num_blocks()),
+            device_info,
+            filepath,
+            wal: None,
+        };
+        // Records are appended after an image, so a new device starts with an empty image
+        let file_len = std::fs::metadata(&device.filepath)
+            .map_err(|e| {
+                MinistoreError::io(
+                    format!("Failed to get metadata, path={:?}", device.filepath),
+                    e,
+                )
+            })?
+            .len();
+        if file_len == 0 {
+            device.write_image()?;
+        }
+        device.wal = Some(Mutex::new(WriteAheadLog::open(
+            &device.filepath,
+            DEFAULT_CHECKPOINT_THRESHOLD,
+        )?));
+        Ok(device)
+    }
+
+    /// Only used in WAL mode
+    pub fn set_checkpoint_threshold(&mut self, checkpoint_threshold: u64) {
+        if let Some(Ok(wal)) = self.wal.as_mut().map(Mutex::get_mut) {
+            wal.set_checkpoint_threshold(checkpoint_threshold);
+        }
+    }
+
+    /// Logs the change in WAL mode, and then applies it
+    fn commit(&self, record: WalRecord) -> Result<()> {
+        let Some(wal) = &self.wal else {
+            return self.apply(record);
+        };
+        let mut wal = lock_wal(wal)?;
+        wal.append(&record)?;
+        let need_checkpoint = wal.needs_checkpoint();
+        self.apply(record)?;
+
+        // The change is already durable in the log, so a failed checkpoint is retried later
+        if need_checkpoint {
+            if let Err(e) = self.checkpoint(&mut wal) {
+                tracing::warn!(
+                    "Failed to checkpoint the log, path={:?}, err={}",
+                    self.filepath,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the image, and starts the log again in the new file which replaced the old one
+    fn checkpoint(&self, wal: &mut WriteAheadLog) -> Result<()> {
+        self.write_image()?;
+        *wal = WriteAheadLog::open(&self.filepath, wal.checkpoint_threshold())?;
+        Ok(())
+    }
+
+    fn apply(&self, record: WalRecord) -> Result<()> {
+        let (lba, num_blocks) = match &record {
+            WalRecord::Write { lba, blocks } => (*lba, blocks.len() as u64),
+            WalRecord::Unmap { lba, num_blocks } | WalRecord::WriteZeroes { lba, num_blocks } => {
+                (*lba, *num_blocks)
+            }
+        };
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let start = lba as usize;
+        let end = start + num_blocks as usize;
+        let block_size = self.device_info.block_size() as usize;
+        let blocks = match record {
+            WalRecord::Write { blocks, .. } => {
+                self.device_info.check_block_size(&blocks)?;
+                blocks
+            }
+            WalRecord::Unmap { .. } => vec![DataBlock::unmapped(block_size); end - start],
+            WalRecord::WriteZeroes { .. } => vec![DataBlock::zeroed(block_size); end - start],
+        };
+        for (data, block) in self.data[start..end].iter().zip(blocks) {
+            *write_block(data)? = block;
+        }
+        Ok(())
+    }
+
+    /// Writes all blocks into a temporary file and renames it, so that a crash leaves either
+    /// the old or the new image. The log after the old image is dropped together.
+    fn write_image(&self) -> Result<()> {
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", tmp_path), e)
+            })?;
+
+        let data = self
+            .data
+            .iter()
+            .map(|data| Ok(read_block(data)?.clone()))
+            .collect::<Result<Vec<DataBlock>>>()?;
+        let mut writer = BufWriter::new(&file);
+        bincode::serialize_into(&mut writer, &(&self.device_info, &data))?;
+        writer.flush()?;
+        drop(writer);
+
+        file.sync_all().map_err(|e| {
+            MinistoreError::io(format!("Failed to sync file, path={:?}", tmp_path), e)
+        })?;
+        std::fs::rename(&tmp_path, &self.filepath).map_err(|e| {
+            MinistoreError::io(
+                format!("Failed to rename file, path={:?}", self.filepath),
+                e,
+            )
+        })?;
+        sync_parent_dir(&self.filepath)
     }
 }
 
 impl BlockDevice for SimpleFakeDevice {
-    fn write(&mut self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<(), String> {
-        todo!()
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+
+        self.commit(WalRecord::Write {
+            lba,
+            blocks: buffer,
+        })
+    }
+
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let start = lba as usize;
+        let end = start + num_blocks as usize;
+        self.data[start..end]
+            .iter()
+            .map(|data| Ok(read_block(data)?.clone()))
+            .collect()
+    }
+
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        self.commit(WalRecord::Unmap { lba, num_blocks })
     }
 
-    fn read(&mut self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>, String> {
-        todo!()
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        self.commit(WalRecord::WriteZeroes { lba, num_blocks })
     }
 
     fn info(&self) -> &DeviceInfo {
-        todo!()
+        &self.device_info
+    }
+
+    /// In WAL mode, the log after the image is replayed
+    fn load(&mut self) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&self.filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to open file, path={:?}", self.filepath), e)
+            })?;
+        let (device_info, data): (DeviceInfo, Vec<DataBlock>) =
+            bincode::deserialize_from(BufReader::new(file))?;
+
+        if data.len() as u64 != device_info.num_blocks() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "num_blocks={}, data_len={}",
+                    device_info.num_blocks(),
+                    data.len()
+                ),
+            });
+        }
+        let image_len = bincode::serialized_size(&(&device_info, &data))?;
+
+        self.device_info = device_info;
+        self.data = data.into_iter().map(RwLock::new).collect();
+        if self.device_info.device_type() != BlockDeviceType::SimpleFakeWalDevice {
+            return Ok(());
+        }
+
+        let checkpoint_threshold = match &self.wal {
+            Some(wal) => lock_wal(wal)?.checkpoint_threshold(),
+            None => DEFAULT_CHECKPOINT_THRESHOLD,
+        };
+        let (wal, records) =
+            WriteAheadLog::replay(&self.filepath, image_len, checkpoint_threshold)?;
+        for record in records {
+            self.apply(record).map_err(|e| MinistoreError::Corrupted {
+                reason: format!("Invalid log record, path={:?}, err={}", self.filepath, e),
+            })?;
+        }
+        self.wal = Some(Mutex::new(wal));
+        Ok(())
     }
 
-    fn load(&mut self) -> Result<(), String> {
-        todo!()
+    /// In WAL mode, the log is checkpointed into the image
+    fn flush(&mut self) -> Result<()> {
+        match &self.wal {
+            Some(wal) => self.checkpoint(&mut *lock_wal(wal)?),
+            None => self.write_image(),
+        }
     }
+}
+
+fn unmapped_blocks(block_size: usize, num_blocks: u64) -> Vec<RwLock<DataBlock>> {
+    (0..num_blocks)
+        .map(|_| RwLock::new(DataBlock::unmapped(block_size)))
+        .collect()
+}
+
+fn read_block(block: &RwLock<DataBlock>) -> Result<RwLockReadGuard<'_, DataBlock>> {
+    block
+        .read()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+fn write_block(block: &RwLock<DataBlock>) -> Result<RwLockWriteGuard<'_, DataBlock>> {
+    block
+        .write()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+fn lock_wal(wal: &Mutex<WriteAheadLog>) -> Result<MutexGuard<'_, WriteAheadLog>> {
+    wal.lock()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+fn create_file_if_not_exists(filepath: &Path) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(filepath)
+        .map_err(|e| {
+            MinistoreError::io(format!("Failed to create file, path={:?}", filepath), e)
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use tracing_test::traced_test;
+
+    fn file_len(device_name: &str) -> u64 {
+        std::fs::metadata(device_name)
+            .expect("Failed to get metadata")
+            .len()
+    }
+
+    #[traced_test]
+    #[test]
+    fn blocks_should_be_written_and_read_without_copy() {
+        let device_name = "blocks_should_be_written_and_read_without_copy".to_string();
+        let device = SimpleFakeDevice::new(
+            device_name.clone(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            PathBuf::from("."),
+        )
+        .expect("Failed to create device");
+
+        // Blocks sliced out of a message, as the gRPC server does
+        let message = bytes::Bytes::from(vec![0xA; DEFAULT_BLOCK_SIZE * 2]);
+        let blocks = DataBlock::split(message.clone(), DEFAULT_BLOCK_SIZE);
+        device.write(3, 2, blocks).expect("Failed to write");
+
+        let read = device.read(3, 2).expect("Failed to read");
+        assert_eq!(read[0].as_slice().as_ptr(), message.as_ptr());
+        assert_eq!(
+            read[1].as_slice().as_ptr(),
+            message[DEFAULT_BLOCK_SIZE..].as_ptr()
+        );
+
+        std::fs::remove_file(device_name).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn changes_should_survive_crash_without_flush_in_wal_mode() {
+        let device_name = "changes_should_survive_crash_without_flush_in_wal_mode".to_string();
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 4];
+        {
+            let device = SimpleFakeDevice::new_with_wal(
+                device_name.clone(),
+                DEFAULT_BLOCK_SIZE as u64 * 16,
+                DEFAULT_BLOCK_SIZE as u64,
+                ".".into(),
+            )
+            .expect("Failed to create device");
+            device.write(0, 4, blocks.clone()).unwrap();
+            device.unmap(1, 1).unwrap();
+            device.write_zeroes(2, 1).unwrap();
+            // Dropped without flush
+        }
+
+        let mut device = SimpleFakeDevice::new_with_wal(
+            device_name.clone(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            ".".into(),
+        )
+        .expect("Failed to create device");
+        device.load().expect("Failed to load");
+        assert_eq!(device.info().device_size(), DEFAULT_BLOCK_SIZE as u64 * 16);
+        assert_eq!(
+            device.read(0, 5).unwrap(),
+            vec![
+                blocks[0].clone(),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::zeroed(DEFAULT_BLOCK_SIZE),
+                blocks[3].clone(),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE)
+            ]
+        );
+
+        std::fs::remove_file(&device_name).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn log_should_be_checkpointed_into_image() {
+        let device_name = "log_should_be_checkpointed_into_image".to_string();
+        let mut device = SimpleFakeDevice::new_with_wal(
+            device_name.clone(),
+            DEFAULT_BLOCK_SIZE as u64 * 16,
+            DEFAULT_BLOCK_SIZE as u64,
+            ".".into(),
+        )
+        .expect("Failed to create device");
+        let image_len = file_len(&device_name);
+
+        device
+            .write(0, 1, vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])])
+            .unwrap();
+        assert!(file_len(&device_name) > image_len);
+        device.flush().expect("Failed to flush");
+        assert_eq!(file_len(&device_name), image_len);
+
+        // Checkpointed when the log exceeds the threshold
+        device.set_checkpoint_threshold(DEFAULT_BLOCK_SIZE as u64 * 2);
+        device
+            .write(1, 1, vec![DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE])])
+            .unwrap();
+        assert!(file_len(&device_name) > image_len);
+        device
+            .write(2, 1, vec![DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE])])
+            .unwrap();
+        assert_eq!(file_len(&device_name), image_len);
+
+        // Changes after the checkpoint are logged in the new image
+        device
+            .write(3, 1, vec![DataBlock::from(vec![0xD; DEFAULT_BLOCK_SIZE])])
+            .unwrap();
+        drop(device);
+        let mut device = SimpleFakeDevice::new_with_wal(
+            device_name.clone(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            ".".into(),
+        )
+        .expect("Failed to create device");
+        device.load().expect("Failed to load");
+        assert_eq!(
+            device.read(0, 4).unwrap(),
+            vec![
+                DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE]),
+                DataBlock::from(vec![0xD; DEFAULT_BLOCK_SIZE])
+            ]
+        );
 
-    fn flush(&mut self) -> Result<(), String> {
-        todo!()
+        std::fs::remove_file(&device_name).expect("Failed to remove file");
     }
 }
@@ -0,0 +1,324 @@
+use super::{BlockDevice, BlockDeviceType};
+use crate::block_device_common::data_type::DataBlock;
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Number of consecutive blocks kept in the same shard
+const SHARD_BLOCKS: u64 = 1024;
+const NUM_SHARDS: u64 = 64;
+
+/// SparseFakeDevice keeps only the written blocks in memory and persists them into a file on
+/// flush, so the device can be far larger than the memory. Unwritten blocks are read as unmapped
+/// blocks. Zeroed blocks share a single buffer, but each of them is still an entry of the map.
+pub struct SparseFakeDevice {
+    device_info: DeviceInfo,
+    /// Runs of SHARD_BLOCKS blocks are spread over the shards, so that I/Os to other runs lock
+    /// other maps
+    shards: Vec<RwLock<BTreeMap<u64, DataBlock>>>,
+    filepath: PathBuf,
+}
+
+impl SparseFakeDevice {
+    pub fn new(name: String, size: u64, block_size: u64, filepath: PathBuf) -> Result<Self> {
+        let device_info = DeviceInfo::new(
+            BlockDeviceType::SparseFakeDevice,
+            name.clone(),
+            size,
+            block_size,
+        )?;
+        let filepath = filepath.join(&name);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", filepath), e)
+            })?;
+
+        Ok(SparseFakeDevice {
+            device_info,
+            shards: new_shards(),
+            filepath,
+        })
+    }
+
+    pub fn num_written_blocks(&self) -> Result<u64> {
+        self.shards.iter().try_fold(0, |num_blocks, shard| {
+            Ok(num_blocks + read_shard(shard)?.len() as u64)
+        })
+    }
+
+    fn shard(&self, lba: u64) -> &RwLock<BTreeMap<u64, DataBlock>> {
+        &self.shards[(lba / SHARD_BLOCKS % NUM_SHARDS) as usize]
+    }
+
+    /// The shards which keep any block of the range, each of them once
+    fn shards_in_range(
+        &self,
+        lba: u64,
+        num_blocks: u64,
+    ) -> impl Iterator<Item = &RwLock<BTreeMap<u64, DataBlock>>> {
+        let num_runs = match num_blocks {
+            0 => 0,
+            _ => (lba + num_blocks - 1) / SHARD_BLOCKS - lba / SHARD_BLOCKS + 1,
+        };
+        (0..num_runs.min(NUM_SHARDS)).map(move |i| self.shard(lba + i * SHARD_BLOCKS))
+    }
+
+    /// Inserts the blocks from lba on, locking the shard of each run of them once
+    fn insert(
+        &self,
+        lba: u64,
+        num_blocks: u64,
+        blocks: impl Iterator<Item = DataBlock>,
+    ) -> Result<()> {
+        let mut blocks = blocks;
+        let end = lba + num_blocks;
+        let mut start = lba;
+        while start < end {
+            let run_end = ((start / SHARD_BLOCKS + 1) * SHARD_BLOCKS).min(end);
+            write_shard(self.shard(start))?.extend((start..run_end).zip(blocks.by_ref()));
+            start = run_end;
+        }
+        Ok(())
+    }
+}
+
+fn new_shards() -> Vec<RwLock<BTreeMap<u64, DataBlock>>> {
+    (0..NUM_SHARDS)
+        .map(|_| RwLock::new(BTreeMap::new()))
+        .collect()
+}
+
+fn read_shard(
+    shard: &RwLock<BTreeMap<u64, DataBlock>>,
+) -> Result<RwLockReadGuard<'_, BTreeMap<u64, DataBlock>>> {
+    shard
+        .read()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+fn write_shard(
+    shard: &RwLock<BTreeMap<u64, DataBlock>>,
+) -> Result<RwLockWriteGuard<'_, BTreeMap<u64, DataBlock>>> {
+    shard
+        .write()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
+/// Removes the blocks in the range without visiting each lba of it
+fn remove_range(blocks: &mut BTreeMap<u64, DataBlock>, lba: u64, num_blocks: u64) {
+    let mut removed = blocks.split_off(&lba);
+    let mut after = removed.split_off(&(lba + num_blocks));
+    blocks.append(&mut after);
+}
+
+impl BlockDevice for SparseFakeDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        if buffer.len() as u64 != num_blocks {
+            return Err(MinistoreError::BufferSizeMismatch {
+                num_blocks,
+                buffer_len: buffer.len() as u64,
+            });
+        }
+        self.device_info.check_block_size(&buffer)?;
+
+        self.insert(lba, num_blocks, buffer.into_iter())
+    }
+
+    fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let unmapped = DataBlock::unmapped(self.device_info.block_size() as usize);
+        let mut buffer = vec![unmapped; num_blocks as usize];
+        for shard in self.shards_in_range(lba, num_blocks) {
+            for (block_lba, block) in read_shard(shard)?.range(lba..lba + num_blocks) {
+                buffer[(block_lba - lba) as usize] = block.clone();
+            }
+        }
+        Ok(buffer)
+    }
+
+    fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+        for shard in self.shards_in_range(lba, num_blocks) {
+            remove_range(&mut *write_shard(shard)?, lba, num_blocks);
+        }
+        Ok(())
+    }
+
+    fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        self.device_info.check_lba_range(lba, num_blocks)?;
+
+        let zeroed = DataBlock::zeroed(self.device_info.block_size() as usize);
+        self.insert(lba, num_blocks, std::iter::repeat(zeroed))
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&self.filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to open file, path={:?}", self.filepath), e)
+            })?;
+        let (device_info, blocks): (DeviceInfo, BTreeMap<u64, DataBlock>) =
+            bincode::deserialize_from(BufReader::new(file))?;
+
+        if let Some((lba, _)) = blocks.range(device_info.num_blocks()..).next() {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Block out of the device, num_blocks={}, lba={}",
+                    device_info.num_blocks(),
+                    lba
+                ),
+            });
+        }
+        if let Some((lba, block)) = blocks
+            .iter()
+            .find(|(_, block)| block.len() as u64 != device_info.block_size())
+        {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Block size mismatch, block_size={}, lba={}, block_len={}",
+                    device_info.block_size(),
+                    lba,
+                    block.len()
+                ),
+            });
+        }
+
+        let mut shards = new_shards();
+        for (lba, block) in blocks {
+            let shard = (lba / SHARD_BLOCKS % NUM_SHARDS) as usize;
+            // Blocks are loaded in lba order, so each of them is appended at the end of its shard
+            shards[shard]
+                .get_mut()
+                .map_err(|e| MinistoreError::internal(e.to_string()))?
+                .insert(lba, block);
+        }
+        self.device_info = device_info;
+        self.shards = shards;
+        Ok(())
+    }
+
+    /// Only the written blocks are written into a temporary file, which replaces the old one
+    fn flush(&mut self) -> Result<()> {
+        let mut tmp_path = self.filepath.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", tmp_path), e)
+            })?;
+
+        // The shards are merged, so that the file does not depend on how the blocks are sharded
+        let mut blocks = BTreeMap::new();
+        for shard in &self.shards {
+            blocks.extend(
+                read_shard(shard)?
+                    .iter()
+                    .map(|(lba, block)| (*lba, block.clone())),
+            );
+        }
+        let mut writer = BufWriter::new(&file);
+        bincode::serialize_into(&mut writer, &(&self.device_info, &blocks))?;
+        writer.flush()?;
+        drop(writer);
+
+        file.sync_all().map_err(|e| {
+            MinistoreError::io(format!("Failed to sync file, path={:?}", tmp_path), e)
+        })?;
+        std::fs::rename(&tmp_path, &self.filepath).map_err(|e| {
+            MinistoreError::io(
+                format!("Failed to rename file, path={:?}", self.filepath),
+                e,
+            )
+        })?;
+        sync_parent_dir(&self.filepath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use tracing_test::traced_test;
+
+    #[traced_test]
+    #[test]
+    fn large_sparse_device_should_only_store_written_blocks() {
+        let device_name = "large_sparse_device_should_only_store_written_blocks".to_string();
+        // 1 TiB, which cannot be kept in memory
+        let size = 1 << 40;
+        let num_blocks = size / DEFAULT_BLOCK_SIZE as u64;
+        let blocks = vec![
+            DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]),
+        ];
+        {
+            let mut device = SparseFakeDevice::new(
+                device_name.clone(),
+                size,
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from("."),
+            )
+            .expect("Failed to create device");
+            device.write(0, 1, blocks[..1].to_vec()).unwrap();
+            device.write(num_blocks - 2, 2, blocks.clone()).unwrap();
+            device.write_zeroes(1000, 2).unwrap();
+            device.write(1001, 1, blocks[1..].to_vec()).unwrap();
+            device.unmap(1000, 1).unwrap();
+            assert_eq!(device.num_written_blocks().unwrap(), 4);
+            device.flush().expect("Failed to flush");
+        }
+        assert!(std::fs::metadata(&device_name).unwrap().len() < 8 * DEFAULT_BLOCK_SIZE as u64);
+
+        let mut device = SparseFakeDevice::new(
+            device_name.clone(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            ".".into(),
+        )
+        .unwrap();
+        device.load().expect("Failed to load");
+        assert_eq!(device.info().device_size(), size);
+        assert_eq!(device.num_written_blocks().unwrap(), 4);
+        assert_eq!(
+            device.read(0, 2).unwrap(),
+            vec![blocks[0].clone(), DataBlock::unmapped(DEFAULT_BLOCK_SIZE)]
+        );
+        assert_eq!(
+            device.read(999, 3).unwrap(),
+            vec![
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                DataBlock::unmapped(DEFAULT_BLOCK_SIZE),
+                blocks[1].clone()
+            ]
+        );
+        assert_eq!(device.read(num_blocks - 2, 2).unwrap(), blocks);
+
+        // Unmap the whole device without visiting each lba
+        device.unmap(0, num_blocks).unwrap();
+        assert_eq!(device.num_written_blocks().unwrap(), 0);
+
+        std::fs::remove_file(device_name).expect("Failed to remove file");
+    }
+}
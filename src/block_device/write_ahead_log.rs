@@ -0,0 +1,280 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::block_device_common::data_type::DataBlock;
+use crate::error::{MinistoreError, Result};
+
+/// Log is checkpointed into the image when it grows beyond this size
+pub const DEFAULT_CHECKPOINT_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Length and CRC32C of the payload, both in little endian
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Every record overwrites the whole range, so replaying it more than once is harmless
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum WalRecord {
+    Write { lba: u64, blocks: Vec<DataBlock> },
+    Unmap { lba: u64, num_blocks: u64 },
+    WriteZeroes { lba: u64, num_blocks: u64 },
+}
+
+/// Log of the changes after the last checkpoint, appended after the image in the same file.
+/// A record is synced to the file before the change is acknowledged. Records are WalRecords for
+/// the blocks of SimpleFakeDevice, but any serializable record can be logged.
+pub struct WriteAheadLog {
+    file: File,
+    len: u64,
+    /// Offset in the file after the last complete record
+    end: u64,
+    /// Set if a failed append cannot be rolled back, so that no record follows the torn one
+    poisoned: bool,
+    checkpoint_threshold: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the file to append records after its current end
+    pub fn open(filepath: &Path, checkpoint_threshold: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to open file, path={:?}", filepath), e)
+            })?;
+        let end = file.metadata()?.len();
+        Ok(WriteAheadLog {
+            file,
+            len: 0,
+            end,
+            poisoned: false,
+            checkpoint_threshold,
+        })
+    }
+
+    /// Reads the records written from the offset. A torn or corrupted record can only be left by
+    /// a crash before it was acknowledged, so the log is truncated there.
+    pub fn replay<R: DeserializeOwned>(
+        filepath: &Path,
+        offset: u64,
+        checkpoint_threshold: u64,
+    ) -> Result<(Self, Vec<R>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(filepath)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to open file, path={:?}", filepath), e)
+            })?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        while let Some((record, record_len)) = decode_record(&log[valid_len..]) {
+            records.push(record);
+            valid_len += record_len;
+        }
+
+        if valid_len < log.len() {
+            tracing::warn!(
+                "Discard the incomplete tail of the log, path={:?}, valid_len={}, log_len={}",
+                filepath,
+                valid_len,
+                log.len()
+            );
+            file.set_len(offset + valid_len as u64)?;
+            file.sync_all().map_err(|e| {
+                MinistoreError::io(format!("Failed to sync file, path={:?}", filepath), e)
+            })?;
+        }
+
+        let mut wal = WriteAheadLog::open(filepath, checkpoint_threshold)?;
+        wal.len = valid_len as u64;
+        Ok((wal, records))
+    }
+
+    /// A failed append is truncated from the file, and the log cannot be appended any more if it
+    /// fails to be truncated
+    pub fn append<R: Serialize>(&mut self, record: &R) -> Result<()> {
+        if self.poisoned {
+            return Err(MinistoreError::internal(
+                "Log cannot be appended after a failed append",
+            ));
+        }
+        let payload = bincode::serialize(record)?;
+        let mut buffer = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+        buffer.extend_from_slice(&payload);
+
+        let appended = self
+            .file
+            .write_all(&buffer)
+            .map_err(|e| MinistoreError::io("Failed to append log", e))
+            .and_then(|_| {
+                self.file
+                    .sync_data()
+                    .map_err(|e| MinistoreError::io("Failed to sync log", e))
+            });
+        if let Err(e) = appended {
+            self.roll_back();
+            return Err(e);
+        }
+        self.len += buffer.len() as u64;
+        self.end += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Removes the torn bytes of a failed append. The file is opened in append mode, so the next
+    /// record is written at the new end of the file.
+    fn roll_back(&mut self) {
+        if let Err(e) = self
+            .file
+            .set_len(self.end)
+            .and_then(|_| self.file.sync_data())
+        {
+            tracing::error!("Failed to roll back log, end={}, err={}", self.end, e);
+            self.poisoned = true;
+        }
+    }
+
+    pub fn needs_checkpoint(&self) -> bool {
+        self.len >= self.checkpoint_threshold
+    }
+
+    pub fn checkpoint_threshold(&self) -> u64 {
+        self.checkpoint_threshold
+    }
+
+    pub fn set_checkpoint_threshold(&mut self, checkpoint_threshold: u64) {
+        self.checkpoint_threshold = checkpoint_threshold;
+    }
+}
+
+/// Returns the record and its length in the log, or None if it is incomplete or corrupted
+fn decode_record<R: DeserializeOwned>(log: &[u8]) -> Option<(R, usize)> {
+    if log.len() < RECORD_HEADER_SIZE {
+        return None;
+    }
+    let payload_len = u32::from_le_bytes(log[0..4].try_into().ok()?) as usize;
+    let checksum = u32::from_le_bytes(log[4..8].try_into().ok()?);
+    let payload = log.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload_len)?;
+    if crc32c::crc32c(payload) != checksum {
+        return None;
+    }
+
+    let record = bincode::deserialize(payload).ok()?;
+    Some((record, RECORD_HEADER_SIZE + payload_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use std::path::PathBuf;
+    use tracing_test::traced_test;
+
+    #[traced_test]
+    #[test]
+    fn log_should_be_replayed_after_offset() {
+        let filepath = PathBuf::from("log_should_be_replayed_after_offset");
+        std::fs::write(&filepath, b"image").unwrap();
+
+        let records = vec![
+            WalRecord::Write {
+                lba: 1,
+                blocks: vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])],
+            },
+            WalRecord::Unmap {
+                lba: 2,
+                num_blocks: 3,
+            },
+            WalRecord::WriteZeroes {
+                lba: 4,
+                num_blocks: 5,
+            },
+        ];
+        let mut wal = WriteAheadLog::open(&filepath, DEFAULT_CHECKPOINT_THRESHOLD).unwrap();
+        for record in &records {
+            wal.append(record).unwrap();
+        }
+
+        let (wal, replayed) =
+            WriteAheadLog::replay::<WalRecord>(&filepath, 5, DEFAULT_CHECKPOINT_THRESHOLD).unwrap();
+        assert_eq!(replayed, records);
+        assert_eq!(wal.len, std::fs::metadata(&filepath).unwrap().len() - 5);
+
+        std::fs::remove_file(&filepath).expect("Failed to remove file");
+    }
+
+    #[traced_test]
+    #[test]
+    fn torn_or_corrupted_tail_should_be_discarded() {
+        let filepath = PathBuf::from("torn_or_corrupted_tail_should_be_discarded");
+        std::fs::write(&filepath, b"").unwrap();
+
+        let first = WalRecord::Unmap {
+            lba: 0,
+            num_blocks: 1,
+        };
+        let mut wal = WriteAheadLog::open(&filepath, DEFAULT_CHECKPOINT_THRESHOLD).unwrap();
+        wal.append(&first).unwrap();
+        let valid_len = std::fs::metadata(&filepath).unwrap().len();
+
+        // Torn record
+        wal.append(&WalRecord::WriteZeroes {
+            lba: 0,
+            num_blocks: 1,
+        })
+        .unwrap();
+        let file = OpenOptions::new().write(true).open(&filepath).unwrap();
+        file.set_len(std::fs::metadata(&filepath).unwrap().len() - 1)
+            .unwrap();
+
+        let (_, replayed) =
+            WriteAheadLog::replay::<WalRecord>(&filepath, 0, DEFAULT_CHECKPOINT_THRESHOLD).unwrap();
+        assert_eq!(replayed, vec![first]);
+        assert_eq!(std::fs::metadata(&filepath).unwrap().len(), valid_len);
+
+        // Corrupted record
+        let mut log = std::fs::read(&filepath).unwrap();
+        let last = log.len() - 1;
+        log[last] ^= 0xFF;
+        std::fs::write(&filepath, &log).unwrap();
+
+        let (_, replayed) =
+            WriteAheadLog::replay::<WalRecord>(&filepath, 0, DEFAULT_CHECKPOINT_THRESHOLD).unwrap();
+        assert!(replayed.is_empty());
+        assert_eq!(std::fs::metadata(&filepath).unwrap().len(), 0);
+
+        std::fs::remove_file(&filepath).expect("Failed to remove file");
+    }
+
+    /// Writes to /dev/full fail, and it cannot be truncated
+    #[cfg(target_os = "linux")]
+    #[traced_test]
+    #[test]
+    fn log_should_be_poisoned_if_failed_append_cannot_be_rolled_back() {
+        let record = WalRecord::Unmap {
+            lba: 0,
+            num_blocks: 1,
+        };
+        let mut wal = WriteAheadLog::open(Path::new("/dev/full"), DEFAULT_CHECKPOINT_THRESHOLD)
+            .expect("Failed to open log");
+
+        assert!(matches!(
+            wal.append(&record),
+            Err(MinistoreError::Io { .. })
+        ));
+        assert!(wal.poisoned);
+        assert!(matches!(
+            wal.append(&record),
+            Err(MinistoreError::Internal { .. })
+        ));
+        assert_eq!(wal.len, 0);
+    }
+}
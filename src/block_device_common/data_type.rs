@@ -1,14 +1,154 @@
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::alloc::{self, Layout};
+use std::ptr::NonNull;
 
-pub const BLOCK_SIZE: usize = 4096;
-pub const UNMAP_BLOCK: DataBlock = DataBlock([0xFF; BLOCK_SIZE]);
+use crate::error::MinistoreError;
 
-#[derive(Clone, Copy, PartialEq)]
-pub struct DataBlock(pub [u8; BLOCK_SIZE]);
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+pub const MIN_BLOCK_SIZE: usize = 512;
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024;
+/// Every byte of an unmapped block
+pub const UNMAP_BYTE: u8 = 0xFF;
+
+/// A block of the device. Its length is the block size of the device.
+/// The data is reference counted, so cloning a block or slicing blocks out of a larger buffer
+/// (e.g. a gRPC message) does not copy it. Blocks are immutable once created.
+/// Blocks created or read by the devices are aligned to DEFAULT_BLOCK_SIZE, so that they can be
+/// written with O_DIRECT without copying. Blocks sliced out of a message are not aligned.
+#[derive(Clone, PartialEq)]
+pub struct DataBlock(pub Bytes);
+
+impl DataBlock {
+    pub fn unmapped(block_size: usize) -> Self {
+        DataBlock::filled(block_size, UNMAP_BYTE)
+    }
+
+    pub fn zeroed(block_size: usize) -> Self {
+        DataBlock::filled(block_size, 0)
+    }
+
+    fn filled(block_size: usize, byte: u8) -> Self {
+        match AlignedBuffer::new(block_size) {
+            Ok(mut buffer) => {
+                buffer.as_mut_slice().fill(byte);
+                DataBlock(Bytes::from_owner(buffer))
+            }
+            // Only an empty block, which cannot be aligned
+            Err(_) => DataBlock(Bytes::from(vec![byte; block_size])),
+        }
+    }
+
+    /// Splits the buffer into blocks without copying
+    pub fn split(buffer: Bytes, block_size: usize) -> Vec<DataBlock> {
+        (0..buffer.len() / block_size)
+            .map(|index| DataBlock(buffer.slice(index * block_size..(index + 1) * block_size)))
+            .collect()
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        (self.0.as_ptr() as usize).is_multiple_of(DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// CRC32C of the block, which is used to verify the integrity of the block
+    pub fn checksum(&self) -> u32 {
+        crc32c::crc32c(&self.0)
+    }
+}
+
+impl From<Vec<u8>> for DataBlock {
+    fn from(data: Vec<u8>) -> Self {
+        DataBlock(Bytes::from(data))
+    }
+}
+
+impl From<Bytes> for DataBlock {
+    fn from(data: Bytes) -> Self {
+        DataBlock(data)
+    }
+}
+
+/// Buffer aligned to DEFAULT_BLOCK_SIZE, which is required to do I/O with O_DIRECT. Blocks share
+/// it without copying through Bytes::from_owner.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    /// The buffer is zeroed
+    pub fn new(len: usize) -> crate::error::Result<Self> {
+        let layout = Layout::from_size_align(len, DEFAULT_BLOCK_SIZE)
+            .map_err(|e| MinistoreError::internal(e.to_string()))?;
+        if layout.size() == 0 {
+            return Err(MinistoreError::internal(
+                "Cannot allocate zero sized buffer",
+            ));
+        }
+
+        // SAFETY: layout has non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).ok_or_else(|| {
+            MinistoreError::internal(format!("Failed to allocate buffer, len={}", len))
+        })?;
+
+        Ok(AlignedBuffer { ptr, layout })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: ptr is valid for layout.size() bytes until drop
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: ptr is valid for layout.size() bytes until drop
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+// SAFETY: the buffer is owned and not aliased, like Vec<u8>
+unsafe impl Send for AlignedBuffer {}
+
+// SAFETY: the buffer is only mutated through &mut self, like Vec<u8>
+unsafe impl Sync for AlignedBuffer {}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: ptr was allocated with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// Checks if the block size is a power of two between MIN_BLOCK_SIZE and MAX_BLOCK_SIZE
+pub fn is_valid_block_size(block_size: u64) -> bool {
+    block_size.is_power_of_two()
+        && (MIN_BLOCK_SIZE as u64..=MAX_BLOCK_SIZE as u64).contains(&block_size)
+}
 
 impl std::fmt::Debug for DataBlock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("DataBlock").field(&self.0[0]).finish()
+        f.debug_tuple("DataBlock")
+            .field(&self.0.first())
+            .field(&self.0.len())
+            .finish()
     }
 }
 
@@ -34,17 +174,22 @@ impl<'de> Deserialize<'de> for DataBlock {
             type Value = DataBlock;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("byte array of length 4096")
+                formatter.write_str("byte array of a valid block size")
             }
 
             fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
             where
                 E: serde::de::Error,
             {
-                if v.len() == BLOCK_SIZE {
-                    let mut array = [0; BLOCK_SIZE];
-                    array.copy_from_slice(v);
-                    Ok(DataBlock(array))
+                self.visit_byte_buf(v.to_vec())
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                if is_valid_block_size(v.len() as u64) {
+                    Ok(DataBlock::from(v))
                 } else {
                     Err(E::invalid_length(v.len(), &self))
                 }
@@ -1,33 +1,97 @@
-use super::data_type::BLOCK_SIZE;
+use super::data_type::{
+    is_valid_block_size, DataBlock, DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
+};
 use super::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct DeviceInfo {}
+pub struct DeviceInfo {
+    device_type: BlockDeviceType,
+    name: String,
+    size: u64,
+    /// Images written before the block size was configurable use the default
+    #[serde(default = "default_block_size")]
+    block_size: u64,
+}
+
+fn default_block_size() -> u64 {
+    DEFAULT_BLOCK_SIZE as u64
+}
 
 impl DeviceInfo {
     pub fn new(
         device_type: BlockDeviceType,
         device_name: String,
         device_size: u64,
-    ) -> Result<Self, String> {
-        todo!()
+        block_size: u64,
+    ) -> Result<Self> {
+        if !is_valid_block_size(block_size) {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Block size should be a power of two between {} and {}, block_size={}",
+                MIN_BLOCK_SIZE, MAX_BLOCK_SIZE, block_size
+            )));
+        }
+        if !device_size.is_multiple_of(block_size) {
+            return Err(MinistoreError::UnalignedSize {
+                size: device_size,
+                block_size,
+            });
+        }
+
+        Ok(DeviceInfo {
+            device_type,
+            name: device_name,
+            size: device_size,
+            block_size,
+        })
     }
 
     pub fn name(&self) -> &String {
-        todo!()
+        &self.name
     }
 
     pub fn device_size(&self) -> u64 {
-        todo!()
+        self.size
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
     }
 
     pub fn num_blocks(&self) -> u64 {
-        todo!()
+        self.size / self.block_size
     }
 
     pub fn device_type(&self) -> BlockDeviceType {
-        todo!()
+        self.device_type.clone()
+    }
+
+    /// Checks if the given lba range can be accessed in this device
+    pub fn check_lba_range(&self, lba: u64, num_blocks: u64) -> Result<()> {
+        if num_blocks == 0 || lba.saturating_add(num_blocks) > self.num_blocks() {
+            return Err(MinistoreError::InvalidLbaRange {
+                lba,
+                num_blocks,
+                device_num_blocks: self.num_blocks(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks if every block in the buffer is as large as the block size of this device
+    pub fn check_block_size(&self, buffer: &[DataBlock]) -> Result<()> {
+        match buffer
+            .iter()
+            .find(|block| block.0.len() as u64 != self.block_size)
+        {
+            Some(block) => Err(MinistoreError::invalid_argument(format!(
+                "Each block should be {} bytes, block_len={}",
+                self.block_size,
+                block.0.len()
+            ))),
+            None => Ok(()),
+        }
     }
 }
 
@@ -48,8 +112,9 @@ mod test {
             BlockDeviceType::SimpleFakeDevice,
             device_name.to_string(),
             device_size,
+            block_size,
         );
-        assert_eq!(device_info.is_ok(), true);
+        assert!(device_info.is_ok());
 
         assert_eq!(
             device_info.as_ref().unwrap().name(),
@@ -76,8 +141,41 @@ mod test {
             BlockDeviceType::SimpleFakeDevice,
             device_name.to_string(),
             device_size,
+            4096,
         );
 
-        assert_eq!(device_info.is_err(), true);
+        assert!(device_info.is_err());
+    }
+
+    #[traced_test]
+    #[test]
+    fn create_device_info_with_other_block_sizes_should_success() {
+        for block_size in [512, 16384] {
+            let device_info = DeviceInfo::new(
+                BlockDeviceType::SimpleFakeDevice,
+                "create_device_info".to_string(),
+                block_size * 100,
+                block_size,
+            )
+            .unwrap();
+            assert_eq!(device_info.block_size(), block_size);
+            assert_eq!(device_info.num_blocks(), 100);
+            assert!(device_info
+                .check_block_size(&[DataBlock::zeroed(block_size as usize)])
+                .is_ok());
+            assert!(device_info
+                .check_block_size(&[DataBlock::zeroed(DEFAULT_BLOCK_SIZE * 2)])
+                .is_err());
+        }
+
+        for block_size in [0, 1000, 256, 128 * 1024] {
+            assert!(DeviceInfo::new(
+                BlockDeviceType::SimpleFakeDevice,
+                "create_device_info".to_string(),
+                block_size * 100,
+                block_size,
+            )
+            .is_err());
+        }
     }
 }
@@ -1,34 +1,107 @@
 pub mod data_type;
 pub mod device_info;
+pub mod range_lock;
+#[cfg(test)]
+pub mod test_utils;
 
 use serde::{Deserialize, Serialize};
+
+use crate::error::{MinistoreError, Result};
 use strum_macros::{Display, EnumIter};
 
 #[derive(Debug, EnumIter, Clone, Display, PartialEq, Serialize, Deserialize)]
 pub enum BlockDeviceType {
     SimpleFakeDevice,
+    /// SimpleFakeDevice in WAL mode
+    SimpleFakeWalDevice,
     AsyncSimpleFakeDevice,
-    // IoUringFakeDevice,
+    IoUringFakeDevice,
+    RawBlockDevice,
+    /// Only keeps the written blocks
+    SparseFakeDevice,
+    /// Maps its backing file into memory
+    MmapFakeDevice,
+    /// Thin-provisioned volume on other devices, only created by DeviceManager
+    Volume,
+    /// RAID-1 mirror across other devices, only created by DeviceManager
+    Mirror,
+    /// RAID-0 array striping across other devices, only created by DeviceManager
+    Raid0,
+    /// RAID-5 array striping with rotating parity across other devices, only created by
+    /// DeviceManager
+    Raid5,
 }
 
 impl BlockDeviceType {
     pub fn is_async(&self) -> bool {
         match &self {
             BlockDeviceType::SimpleFakeDevice => false,
+            BlockDeviceType::SimpleFakeWalDevice => false,
             BlockDeviceType::AsyncSimpleFakeDevice => true,
+            BlockDeviceType::IoUringFakeDevice => false,
+            BlockDeviceType::RawBlockDevice => false,
+            BlockDeviceType::SparseFakeDevice => false,
+            BlockDeviceType::MmapFakeDevice => false,
+            BlockDeviceType::Volume => true,
+            BlockDeviceType::Mirror => true,
+            BlockDeviceType::Raid0 => true,
+            BlockDeviceType::Raid5 => true,
         }
     }
 
     pub fn is_sync(&self) -> bool {
         !self.is_async()
     }
+
+    /// Fake devices are created and deleted by ministore, others are given by the config
+    pub fn is_fake(&self) -> bool {
+        match &self {
+            BlockDeviceType::SimpleFakeDevice => true,
+            BlockDeviceType::SimpleFakeWalDevice => true,
+            BlockDeviceType::AsyncSimpleFakeDevice => true,
+            BlockDeviceType::IoUringFakeDevice => true,
+            BlockDeviceType::RawBlockDevice => false,
+            BlockDeviceType::SparseFakeDevice => true,
+            BlockDeviceType::MmapFakeDevice => true,
+            BlockDeviceType::Volume => false,
+            BlockDeviceType::Mirror => false,
+            BlockDeviceType::Raid0 => false,
+            BlockDeviceType::Raid5 => false,
+        }
+    }
+
+    /// Writes to these devices survive a crash without flush, including which blocks are mapped,
+    /// so what is kept with the blocks (e.g. protection information) should be persisted on every
+    /// write as well
+    pub fn persists_without_flush(&self) -> bool {
+        match &self {
+            BlockDeviceType::SimpleFakeDevice => false,
+            BlockDeviceType::SimpleFakeWalDevice => true,
+            BlockDeviceType::AsyncSimpleFakeDevice => false,
+            BlockDeviceType::IoUringFakeDevice => true,
+            BlockDeviceType::RawBlockDevice => true,
+            BlockDeviceType::SparseFakeDevice => false,
+            BlockDeviceType::MmapFakeDevice => true,
+            BlockDeviceType::Volume => false,
+            BlockDeviceType::Mirror => false,
+            BlockDeviceType::Raid0 => false,
+            BlockDeviceType::Raid5 => false,
+        }
+    }
 }
 
-pub fn str_to_block_device_type(value: &str) -> Result<BlockDeviceType, String> {
+pub fn str_to_block_device_type(value: &str) -> Result<BlockDeviceType> {
     match value {
         "SimpleFake" => Ok(BlockDeviceType::SimpleFakeDevice),
+        "SimpleFakeWal" => Ok(BlockDeviceType::SimpleFakeWalDevice),
         "AsyncSimpleFake" => Ok(BlockDeviceType::AsyncSimpleFakeDevice),
-        _ => Err(format!("Invalid block device type, type={}", value)),
+        "IoUringFake" => Ok(BlockDeviceType::IoUringFakeDevice),
+        "Raw" => Ok(BlockDeviceType::RawBlockDevice),
+        "SparseFake" => Ok(BlockDeviceType::SparseFakeDevice),
+        "MmapFake" => Ok(BlockDeviceType::MmapFakeDevice),
+        _ => Err(MinistoreError::InvalidDeviceType {
+            device_type: value.to_string(),
+        }),
     }
 }
 
@@ -40,19 +113,73 @@ mod tests {
     #[test]
     fn all_block_device_type_should_be_converted_from_str() {
         assert_eq!(
-            str_to_block_device_type("SimpleFake"),
-            Ok(BlockDeviceType::SimpleFakeDevice)
+            str_to_block_device_type("SimpleFake").unwrap(),
+            BlockDeviceType::SimpleFakeDevice
+        );
+        assert_eq!(
+            str_to_block_device_type("SimpleFakeWal").unwrap(),
+            BlockDeviceType::SimpleFakeWalDevice
+        );
+        assert_eq!(
+            str_to_block_device_type("AsyncSimpleFake").unwrap(),
+            BlockDeviceType::AsyncSimpleFakeDevice
         );
         assert_eq!(
-            str_to_block_device_type("AsyncSimpleFake"),
-            Ok(BlockDeviceType::AsyncSimpleFakeDevice)
+            str_to_block_device_type("IoUringFake").unwrap(),
+            BlockDeviceType::IoUringFakeDevice
         );
+        assert_eq!(
+            str_to_block_device_type("Raw").unwrap(),
+            BlockDeviceType::RawBlockDevice
+        );
+        assert_eq!(
+            str_to_block_device_type("SparseFake").unwrap(),
+            BlockDeviceType::SparseFakeDevice
+        );
+        assert_eq!(
+            str_to_block_device_type("MmapFake").unwrap(),
+            BlockDeviceType::MmapFakeDevice
+        );
+        assert!(matches!(
+            str_to_block_device_type("Unknown"),
+            Err(MinistoreError::InvalidDeviceType { .. })
+        ));
     }
 
     #[test]
     fn all_block_device_type_should_tell_if_it_is_sync_or_async() {
-        assert_eq!(BlockDeviceType::SimpleFakeDevice.is_sync(), true);
-        assert_eq!(BlockDeviceType::SimpleFakeDevice.is_async(), false);
+        assert!(BlockDeviceType::SimpleFakeDevice.is_sync());
+        assert!(!BlockDeviceType::SimpleFakeDevice.is_async());
+
+        assert!(BlockDeviceType::SimpleFakeWalDevice.is_sync());
+        assert!(!BlockDeviceType::SimpleFakeWalDevice.is_async());
+
+        assert!(!BlockDeviceType::AsyncSimpleFakeDevice.is_sync());
+        assert!(BlockDeviceType::AsyncSimpleFakeDevice.is_async());
+
+        assert!(BlockDeviceType::IoUringFakeDevice.is_sync());
+        assert!(!BlockDeviceType::IoUringFakeDevice.is_async());
+
+        assert!(BlockDeviceType::RawBlockDevice.is_sync());
+        assert!(!BlockDeviceType::RawBlockDevice.is_async());
+
+        assert!(BlockDeviceType::SparseFakeDevice.is_sync());
+        assert!(!BlockDeviceType::SparseFakeDevice.is_async());
+
+        assert!(BlockDeviceType::MmapFakeDevice.is_sync());
+        assert!(!BlockDeviceType::MmapFakeDevice.is_async());
+
+        assert!(!BlockDeviceType::Volume.is_sync());
+        assert!(BlockDeviceType::Volume.is_async());
+
+        assert!(!BlockDeviceType::Mirror.is_sync());
+        assert!(BlockDeviceType::Mirror.is_async());
+
+        assert!(!BlockDeviceType::Raid0.is_sync());
+        assert!(BlockDeviceType::Raid0.is_async());
+
+        assert!(!BlockDeviceType::Raid5.is_sync());
+        assert!(BlockDeviceType::Raid5.is_async());
 
         // Add test here when you add new type
     }
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockMode {
+    /// Reads can share a range with other reads
+    Shared,
+    /// Writes (including unmap and write zeroes) need the range for themselves
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct LockedRange {
+    start: u64,
+    end: u64,
+    mode: LockMode,
+}
+
+impl LockedRange {
+    fn conflicts_with(&self, other: &LockedRange) -> bool {
+        let overlapped = self.start < other.end && other.start < self.end;
+        overlapped && (self.mode == LockMode::Exclusive || other.mode == LockMode::Exclusive)
+    }
+}
+
+#[derive(Default)]
+struct RangeLockState {
+    next_ticket: u64,
+    /// Both of holding and waiting ranges, ordered by arrival
+    ranges: BTreeMap<u64, LockedRange>,
+}
+
+/// Lock manager for LBA ranges of a device. Non-overlapping ranges are locked concurrently,
+/// while overlapping ones are granted in arrival order, so that none of them starves.
+#[derive(Clone, Default)]
+pub struct RangeLock {
+    state: Arc<Mutex<RangeLockState>>,
+    released: Arc<Notify>,
+}
+
+impl RangeLock {
+    pub fn new() -> Self {
+        RangeLock::default()
+    }
+
+    /// Waits until all earlier conflicting ranges are released. The range is released when the
+    /// returned guard is dropped.
+    pub async fn lock(&self, lba: u64, num_blocks: u64, mode: LockMode) -> RangeLockGuard {
+        let range = LockedRange {
+            start: lba,
+            end: lba.saturating_add(num_blocks),
+            mode,
+        };
+        let ticket = {
+            let mut state = self.state.lock().expect("range lock state is poisoned");
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.ranges.insert(ticket, range);
+            ticket
+        };
+        // Created before waiting, so the range is released even if the caller is cancelled
+        let guard = RangeLockGuard {
+            lock: self.clone(),
+            ticket,
+        };
+
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if !self.has_earlier_conflict(ticket, &range) {
+                return guard;
+            }
+            released.await;
+        }
+    }
+
+    fn has_earlier_conflict(&self, ticket: u64, range: &LockedRange) -> bool {
+        let state = self.state.lock().expect("range lock state is poisoned");
+        state
+            .ranges
+            .range(..ticket)
+            .any(|(_, earlier)| earlier.conflicts_with(range))
+    }
+
+    fn release(&self, ticket: u64) {
+        self.state
+            .lock()
+            .expect("range lock state is poisoned")
+            .ranges
+            .remove(&ticket);
+        self.released.notify_waiters();
+    }
+}
+
+pub struct RangeLockGuard {
+    lock: RangeLock,
+    ticket: u64,
+}
+
+impl Drop for RangeLockGuard {
+    fn drop(&mut self) {
+        self.lock.release(self.ticket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn is_granted_soon(lock: &RangeLock, lba: u64, num_blocks: u64, mode: LockMode) -> bool {
+        tokio::time::timeout(Duration::from_millis(100), lock.lock(lba, num_blocks, mode))
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn disjoint_ranges_should_be_locked_together() {
+        let lock = RangeLock::new();
+        let _first = lock.lock(0, 10, LockMode::Exclusive).await;
+
+        assert!(is_granted_soon(&lock, 10, 10, LockMode::Exclusive).await);
+        assert!(is_granted_soon(&lock, 100, 1, LockMode::Shared).await);
+    }
+
+    #[tokio::test]
+    async fn overlapped_ranges_should_wait_for_exclusive_lock() {
+        let lock = RangeLock::new();
+        let first = lock.lock(0, 10, LockMode::Exclusive).await;
+
+        assert!(!is_granted_soon(&lock, 9, 10, LockMode::Exclusive).await);
+        assert!(!is_granted_soon(&lock, 5, 1, LockMode::Shared).await);
+
+        drop(first);
+        assert!(is_granted_soon(&lock, 9, 10, LockMode::Exclusive).await);
+    }
+
+    #[tokio::test]
+    async fn shared_locks_should_be_granted_together() {
+        let lock = RangeLock::new();
+        let _first = lock.lock(0, 10, LockMode::Shared).await;
+
+        assert!(is_granted_soon(&lock, 0, 10, LockMode::Shared).await);
+        assert!(!is_granted_soon(&lock, 0, 1, LockMode::Exclusive).await);
+    }
+
+    #[tokio::test]
+    async fn overlapped_ranges_should_be_granted_in_arrival_order() {
+        let lock = RangeLock::new();
+        let first = lock.lock(0, 10, LockMode::Shared).await;
+
+        // A writer is waiting for the first reader
+        let writer_lock = lock.clone();
+        let writer = tokio::spawn(async move {
+            let _guard = writer_lock.lock(0, 10, LockMode::Exclusive).await;
+        });
+        while lock.state.lock().unwrap().ranges.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // Later readers should not overtake the waiting writer
+        assert!(!is_granted_soon(&lock, 0, 10, LockMode::Shared).await);
+
+        drop(first);
+        writer.await.unwrap();
+        assert!(is_granted_soon(&lock, 0, 10, LockMode::Shared).await);
+    }
+}
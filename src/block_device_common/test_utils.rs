@@ -0,0 +1,6 @@
+use super::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
+
+/// Blocks of the default size filled with the value
+pub fn blocks(value: u8, num_blocks: usize) -> Vec<DataBlock> {
+    vec![DataBlock::from(vec![value; DEFAULT_BLOCK_SIZE]); num_blocks]
+}
@@ -1,9 +1,14 @@
-use config::{Config, File};
+use config::{Config, File, FileFormat};
 use serde::Deserialize;
 
+use crate::async_block_device::protected_device::ProtectionMode;
+use crate::async_block_device::shaped_device::PerformanceProfile;
+use crate::error::Result;
+
 #[derive(Debug, Deserialize)]
 pub struct MinistoreConfig {
     pub devices: DeviceConfig,
+    pub scrub: ScrubConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -11,10 +16,32 @@ pub struct DeviceConfig {
     pub use_fake: bool,
     pub fake_device_location: String,
     pub fake_device_type: String,
+    /// Protection information of the fake devices to be created
+    pub protection_mode: ProtectionMode,
+    /// Paths of the block devices (e.g. /dev/nvme0n1) to be registered at startup
+    pub list: Vec<String>,
+    /// Fake devices fail their I/Os by the rules set with the SetFaultRules RPC
+    pub fault_injection: bool,
+    /// Performance of the fake devices created without their own profile
+    pub performance_profile: PerformanceProfile,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScrubConfig {
+    pub enabled: bool,
+    /// Seconds to wait after scrubbing all devices
+    pub interval_secs: u64,
+    /// Scrubbing rate of each device, which is not throttled if 0
+    pub blocks_per_second: u64,
 }
 
-pub fn get_config(config_str: &str) -> Result<MinistoreConfig, String> {
-    todo!()
+pub fn get_config(config_str: &str) -> Result<MinistoreConfig> {
+    Config::builder()
+        .add_source(File::with_name("config/default"))
+        .add_source(File::from_str(config_str, FileFormat::Toml))
+        .build()?
+        .try_deserialize::<MinistoreConfig>()
+        .map_err(Into::into)
 }
 
 #[derive(Debug)]
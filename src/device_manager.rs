@@ -1,59 +1,1855 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::block_device::{create_block_device, BlockDevice};
-use crate::block_device_common::data_type::DataBlock;
-use crate::block_device_common::str_to_block_device_type;
+use crate::async_block_device::clone_device::{clone_path, CloneDevice, CLONE_EXTENSION};
+use crate::async_block_device::faulty_fake_device::{FaultInjector, FaultRules, FaultyFakeDevice};
+use crate::async_block_device::mirror_device::{MirrorDevice, MirrorStatus};
+use crate::async_block_device::protected_device::{
+    protection_info_path, ProtectedDevice, ProtectionMode, PROTECTION_INFO_EXTENSION,
+};
+use crate::async_block_device::shaped_device::{PerformanceProfile, ShapedDevice};
+use crate::async_block_device::snapshot_device::{
+    snapshots_path, SnapshotDevice, SnapshotInfo, SNAPSHOTS_EXTENSION,
+};
+use crate::async_block_device::striped_device::{ArrayStatus, RaidLevel, StripedDevice};
+use crate::async_block_device::sync_block_device_adapter::SyncBlockDeviceAdapter;
+use crate::async_block_device::volume::{ExtentAllocator, Member, Volume, VolumeStatus};
+use crate::async_block_device::{create_async_block_device, AsyncBlockDevice};
+use crate::block_device::create_block_device;
+use crate::block_device_common::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
+use crate::block_device_common::device_info::DeviceInfo;
+use crate::block_device_common::range_lock::{LockMode, RangeLock};
+use crate::block_device_common::{str_to_block_device_type, BlockDeviceType};
 use crate::config::DeviceConfig;
+use crate::device_registry::{CloneOrigin, DeviceRegistry, RegistryEntry, REGISTRY_FILENAME};
+use crate::error::{MinistoreError, Result};
+use crate::scrubber::ScrubStatus;
 
-pub struct DeviceManager {}
+/// Each device has its own range lock, so that I/Os to different devices or to disjoint ranges
+/// of a device do not wait for each other
+struct DeviceEntry {
+    info: DeviceInfo,
+    /// Shared with the volumes whose extents are allocated from the device
+    device: Arc<dyn AsyncBlockDevice>,
+    range_lock: RangeLock,
+    scrub_status: Mutex<ScrubStatus>,
+    /// Only exists if fault injection is enabled for the device
+    fault_injector: Option<Arc<FaultInjector>>,
+    /// Only exists if the device is a volume
+    volume: Option<Arc<Volume>>,
+    /// Only exists if the device is a mirror
+    mirror: Option<Arc<MirrorDevice>>,
+    /// Only exists if the device is a RAID-0 or RAID-5 array
+    array: Option<Arc<StripedDevice>>,
+    /// Only exists if the device is a fake device
+    snapshots: Option<Arc<SnapshotDevice>>,
+}
+
+impl DeviceEntry {
+    fn new(
+        device: Box<dyn AsyncBlockDevice>,
+        fault_injector: Option<Arc<FaultInjector>>,
+    ) -> Arc<Self> {
+        Arc::new(DeviceEntry {
+            info: device.info().clone(),
+            device: device.into(),
+            range_lock: RangeLock::new(),
+            scrub_status: Mutex::new(ScrubStatus::default()),
+            fault_injector,
+            volume: None,
+            mirror: None,
+            array: None,
+            snapshots: None,
+        })
+    }
+
+    fn new_fake(device: SnapshotDevice, fault_injector: Option<Arc<FaultInjector>>) -> Arc<Self> {
+        let device = Arc::new(device);
+        Arc::new(DeviceEntry {
+            info: device.info().clone(),
+            device: device.clone(),
+            range_lock: RangeLock::new(),
+            scrub_status: Mutex::new(ScrubStatus::default()),
+            fault_injector,
+            volume: None,
+            mirror: None,
+            array: None,
+            snapshots: Some(device),
+        })
+    }
+
+    fn new_volume(volume: Volume) -> Arc<Self> {
+        let volume = Arc::new(volume);
+        Arc::new(DeviceEntry {
+            info: volume.info().clone(),
+            device: volume.clone(),
+            range_lock: RangeLock::new(),
+            scrub_status: Mutex::new(ScrubStatus::default()),
+            fault_injector: None,
+            volume: Some(volume),
+            mirror: None,
+            array: None,
+            snapshots: None,
+        })
+    }
+
+    fn new_mirror(mirror: MirrorDevice) -> Arc<Self> {
+        let mirror = Arc::new(mirror);
+        Arc::new(DeviceEntry {
+            info: mirror.info().clone(),
+            device: mirror.clone(),
+            range_lock: RangeLock::new(),
+            scrub_status: Mutex::new(ScrubStatus::default()),
+            fault_injector: None,
+            volume: None,
+            mirror: Some(mirror),
+            array: None,
+            snapshots: None,
+        })
+    }
+
+    fn new_array(array: StripedDevice) -> Arc<Self> {
+        let array = Arc::new(array);
+        Arc::new(DeviceEntry {
+            info: array.info().clone(),
+            device: array.clone(),
+            range_lock: RangeLock::new(),
+            scrub_status: Mutex::new(ScrubStatus::default()),
+            fault_injector: None,
+            volume: None,
+            mirror: None,
+            array: Some(array),
+            snapshots: None,
+        })
+    }
+
+    /// Whether the mirror or the array has a member to rebuild
+    fn is_rebuilding(&self) -> Result<bool> {
+        match (&self.mirror, &self.array) {
+            (Some(mirror), _) => mirror.is_rebuilding(),
+            (_, Some(array)) => array.is_rebuilding(),
+            _ => Ok(false),
+        }
+    }
+
+    fn scrub_status(&self) -> Result<MutexGuard<'_, ScrubStatus>> {
+        self.scrub_status
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+/// DeviceManager can be shared between tasks, the device map is locked only while looking up
+/// or registering a device.
+pub struct DeviceManager {
+    config: DeviceConfig,
+    fake_device_type: Option<BlockDeviceType>,
+    devices: RwLock<HashMap<String, Arc<DeviceEntry>>>,
+    /// Fake devices and volumes to be re-opened on restart, only exists when fake devices are
+    /// enabled
+    registry: Option<Mutex<DeviceRegistry>>,
+    /// Extents of the devices allocated to volumes
+    allocator: Arc<ExtentAllocator>,
+    /// Names of the devices being created, which are reserved before any file is created
+    creating: Mutex<HashSet<String>>,
+    /// Held from checking the origins of clones until they are changed, so that a snapshot or a
+    /// device is not deleted while a clone of it is created
+    origin_lock: tokio::sync::Mutex<()>,
+}
+
+/// Reservation of the name of a device being created, which is released when dropped
+struct NameReservation<'a> {
+    creating: &'a Mutex<HashSet<String>>,
+    name: String,
+}
+
+impl Drop for NameReservation<'_> {
+    fn drop(&mut self) {
+        match self.creating.lock() {
+            Ok(mut creating) => {
+                creating.remove(&self.name);
+            }
+            Err(e) => tracing::error!("Failed to release name, name={}, err={}", self.name, e),
+        }
+    }
+}
 
 impl DeviceManager {
-    pub fn new(config: &DeviceConfig) -> Result<Self, String> {
-        todo!()
+    /// Registers the devices in the config and re-opens the fake devices created before
+    pub async fn new(config: &DeviceConfig) -> Result<Self> {
+        let (fake_device_type, registry) = if config.use_fake {
+            fs::create_dir_all(&config.fake_device_location).map_err(|e| {
+                MinistoreError::io(
+                    format!(
+                        "Failed to create fake device location, location={}",
+                        config.fake_device_location
+                    ),
+                    e,
+                )
+            })?;
+            let registry = DeviceRegistry::open(Path::new(&config.fake_device_location))?;
+            (
+                Some(str_to_block_device_type(&config.fake_device_type)?),
+                Some(Mutex::new(registry)),
+            )
+        } else {
+            (None, None)
+        };
+
+        let device_manager = DeviceManager {
+            config: config.clone(),
+            fake_device_type,
+            devices: RwLock::new(HashMap::new()),
+            registry,
+            allocator: Arc::new(ExtentAllocator::default()),
+            creating: Mutex::new(HashSet::new()),
+            origin_lock: tokio::sync::Mutex::new(()),
+        };
+
+        for device_path in &config.list {
+            device_manager.register_raw_device(device_path)?;
+        }
+        device_manager.replay_registry().await?;
+
+        Ok(device_manager)
+    }
+
+    /// Devices which cannot be re-opened (e.g. the backing file is removed) are forgotten. Clones
+    /// are opened after their origins, and mirrors, arrays and volumes after the devices which
+    /// they are composed of. Mirrors and arrays which were rebuilding are rebuilt again.
+    async fn replay_registry(&self) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Ok(());
+        };
+
+        let (composites, entries): (Vec<RegistryEntry>, Vec<RegistryEntry>) =
+            lock_registry(registry)?
+                .entries()
+                .into_iter()
+                .partition(|entry| {
+                    matches!(
+                        entry.device_type,
+                        BlockDeviceType::Volume
+                            | BlockDeviceType::Mirror
+                            | BlockDeviceType::Raid0
+                            | BlockDeviceType::Raid5
+                    )
+                });
+        let (volumes, mirrors_and_arrays): (Vec<RegistryEntry>, Vec<RegistryEntry>) = composites
+            .into_iter()
+            .partition(|entry| entry.device_type == BlockDeviceType::Volume);
+        for entry in order_by_origin(entries)
+            .into_iter()
+            .chain(mirrors_and_arrays)
+            .chain(volumes)
+        {
+            if self.read_devices()?.contains_key(&entry.name) {
+                tracing::warn!(
+                    "Registered device has the same name with other device, name={}",
+                    entry.name
+                );
+                continue;
+            }
+
+            let opened = match (check_device_name(&entry.name), &entry.device_type) {
+                (Err(e), _) => Err(e),
+                (_, BlockDeviceType::Volume) => self.open_registered_volume(&entry).await,
+                (_, BlockDeviceType::Mirror) => self.open_registered_mirror(&entry).await,
+                (_, BlockDeviceType::Raid0 | BlockDeviceType::Raid5) => {
+                    self.open_registered_array(&entry).await
+                }
+                _ => self.open_registered_fake_device(&entry).await,
+            };
+            match opened {
+                Ok(device_entry) => {
+                    tracing::info!(
+                        "Re-opened registered device, name={}, type={}, size={}",
+                        entry.name,
+                        entry.device_type,
+                        entry.size
+                    );
+                    if device_entry.is_rebuilding()? {
+                        start_rebuild(device_entry.clone());
+                    }
+                    self.write_devices()?
+                        .insert(entry.name.clone(), device_entry);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to re-open registered device, name={}, err={}",
+                        entry.name,
+                        e
+                    );
+                    if entry.device_type.is_fake() && !entry.path.exists() {
+                        let location = entry.path.parent().unwrap_or(Path::new(""));
+                        if let Err(e) = remove_side_files(location, &entry.name).await {
+                            tracing::warn!(
+                                "Failed to remove files of forgotten device, name={}, err={}",
+                                entry.name,
+                                e
+                            );
+                            continue;
+                        }
+                    }
+                    lock_registry(registry)?.remove(&entry.name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn open_registered_fake_device(&self, entry: &RegistryEntry) -> Result<Arc<DeviceEntry>> {
+        let origin = match &entry.origin {
+            Some(origin) => Some((
+                self.get_snapshots(&origin.device)?.1,
+                origin.snapshot.clone(),
+            )),
+            None => None,
+        };
+        let (device, fault_injector) =
+            open_registered_device(entry, self.config.fault_injection, origin).await?;
+        Ok(DeviceEntry::new_fake(device, fault_injector))
+    }
+
+    async fn open_registered_mirror(&self, entry: &RegistryEntry) -> Result<Arc<DeviceEntry>> {
+        let location = entry
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let mut mirror = MirrorDevice::new(
+            entry.name.clone(),
+            self.get_members(&entry.members)?,
+            &location,
+        )?;
+        mirror.load().await?;
+
+        if mirror.info().device_size() != entry.size {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Device size mismatch, name={}, registered_size={}, loaded_size={}",
+                    entry.name,
+                    entry.size,
+                    mirror.info().device_size()
+                ),
+            });
+        }
+        Ok(DeviceEntry::new_mirror(mirror))
+    }
+
+    async fn open_registered_array(&self, entry: &RegistryEntry) -> Result<Arc<DeviceEntry>> {
+        let (Some(level), Some(stripe_size)) = (
+            RaidLevel::from_device_type(&entry.device_type),
+            entry.stripe_size,
+        ) else {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Array has no stripe size, name={}, type={}",
+                    entry.name, entry.device_type
+                ),
+            });
+        };
+        let location = entry
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let mut array = StripedDevice::new(
+            entry.name.clone(),
+            level,
+            stripe_size,
+            self.get_members(&entry.members)?,
+            &location,
+        )?;
+        array.load().await?;
+
+        if array.info().device_size() != entry.size {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Device size mismatch, name={}, registered_size={}, loaded_size={}",
+                    entry.name,
+                    entry.size,
+                    array.info().device_size()
+                ),
+            });
+        }
+        Ok(DeviceEntry::new_array(array))
+    }
+
+    async fn open_registered_volume(&self, entry: &RegistryEntry) -> Result<Arc<DeviceEntry>> {
+        let location = entry
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let mut volume = Volume::new(
+            entry.name.clone(),
+            entry.size,
+            self.get_members(&entry.members)?,
+            self.allocator.clone(),
+            &location,
+        )?;
+        volume.load().await?;
+
+        if volume.info().device_size() != entry.size {
+            return Err(MinistoreError::Corrupted {
+                reason: format!(
+                    "Device size mismatch, name={}, registered_size={}, loaded_size={}",
+                    entry.name,
+                    entry.size,
+                    volume.info().device_size()
+                ),
+            });
+        }
+        Ok(DeviceEntry::new_volume(volume))
     }
 
-    pub fn create_fake_device(
-        &mut self,
+    fn register_raw_device(&self, device_path: &String) -> Result<()> {
+        let path = PathBuf::from(device_path);
+        let device_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                MinistoreError::invalid_argument(format!(
+                    "Invalid device path, path={}",
+                    device_path
+                ))
+            })?
+            .to_string();
+        if self.read_devices()?.contains_key(&device_name) {
+            return Err(MinistoreError::DeviceAlreadyExists { name: device_name });
+        }
+
+        let device = create_block_device(
+            BlockDeviceType::RawBlockDevice,
+            device_name.clone(),
+            0,
+            DEFAULT_BLOCK_SIZE as u64,
+            path,
+        )?;
+        tracing::info!(
+            "Registered raw block device, name={}, path={}, size={}",
+            device_name,
+            device_path,
+            device.info().device_size()
+        );
+        self.write_devices()?.insert(
+            device_name,
+            DeviceEntry::new(Box::new(SyncBlockDeviceAdapter::new(device)), None),
+        );
+        Ok(())
+    }
+
+    /// Creates a fake device with the performance profile in the config
+    pub async fn create_fake_device(
+        &self,
+        device_name: &String,
+        device_size: u64,
+        block_size: u64,
+    ) -> Result<()> {
+        self.create_fake_device_with_profile(device_name, device_size, block_size, None)
+            .await
+    }
+
+    /// The profile in the config is used if the profile is not given
+    pub async fn create_fake_device_with_profile(
+        &self,
         device_name: &String,
         device_size: u64,
-    ) -> Result<(), String> {
-        todo!()
+        block_size: u64,
+        performance_profile: Option<PerformanceProfile>,
+    ) -> Result<()> {
+        let (Some(device_type), Some(registry)) = (self.fake_device_type.clone(), &self.registry)
+        else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        check_device_name(device_name)?;
+        let _reservation = self.reserve_name(device_name)?;
+
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let device = create_async_block_device(
+            device_type.clone(),
+            device_name.clone(),
+            device_size,
+            block_size,
+            location.clone(),
+        )
+        .await?;
+        let performance_profile =
+            performance_profile.unwrap_or_else(|| self.config.performance_profile.clone());
+        let device = shape(device, &performance_profile);
+        let (device, fault_injector) = inject_faults(device, self.config.fault_injection);
+        let device = protect(device, self.config.protection_mode, &location);
+        let device = SnapshotDevice::new(device, &location);
+        // Flush the empty device, so that it can be loaded on restart
+        device.flush().await?;
+
+        let mut devices = self.write_devices()?;
+        if devices.contains_key(device_name) {
+            return Err(MinistoreError::DeviceAlreadyExists {
+                name: device_name.clone(),
+            });
+        }
+        lock_registry(registry)?.insert(RegistryEntry::new(
+            device_name.clone(),
+            device_type,
+            device_size,
+            block_size,
+            location.join(device_name),
+            self.config.protection_mode,
+            performance_profile,
+        ))?;
+        devices.insert(
+            device_name.clone(),
+            DeviceEntry::new_fake(device, fault_injector),
+        );
+        Ok(())
+    }
+
+    /// Deletes the device after the in-flight I/Os to it. A member of a volume, mirror or array
+    /// cannot be deleted until the volume, mirror or array is deleted, and an origin of clones
+    /// until the clones are deleted. The snapshot taken for a clone is deleted with the clone.
+    pub async fn delete_fake_device(&self, device_name: &String) -> Result<()> {
+        let origin_guard = self.origin_lock.lock().await;
+        let (entry, origin) = {
+            let mut devices = self.write_devices()?;
+            let entry = match devices.get(device_name) {
+                None => {
+                    return Err(MinistoreError::DeviceNotFound {
+                        name: device_name.clone(),
+                    })
+                }
+                Some(entry) if !entry.info.device_type().is_fake() => {
+                    return Err(MinistoreError::NotFakeDevice {
+                        name: device_name.clone(),
+                    })
+                }
+                Some(entry) => entry.clone(),
+            };
+            if let Some(user) = find_user(&devices, device_name)? {
+                return Err(MinistoreError::DeviceInUse {
+                    name: device_name.clone(),
+                    user: user.info.name().clone(),
+                });
+            }
+            let origin = match &self.registry {
+                Some(registry) => {
+                    let entries = lock_registry(registry)?.entries();
+                    if let Some(clone) = entries.iter().find(|entry| {
+                        entry
+                            .origin
+                            .as_ref()
+                            .is_some_and(|origin| &origin.device == device_name)
+                    }) {
+                        return Err(MinistoreError::DeviceInUse {
+                            name: device_name.clone(),
+                            user: clone.name.clone(),
+                        });
+                    }
+                    entries
+                        .into_iter()
+                        .find(|entry| &entry.name == device_name)
+                        .and_then(|entry| entry.origin)
+                }
+                None => None,
+            };
+            devices.remove(device_name);
+            (entry, origin)
+        };
+        drop(origin_guard);
+
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+
+        // The device is put back if its backing file is left. Otherwise the registry entry is
+        // kept until the other files are removed, so that they are removed on restart if this
+        // fails, like those of other devices whose backing files are gone.
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let filepath = location.join(device_name);
+        if let Err(e) = tokio::fs::remove_file(&filepath).await {
+            self.write_devices()?
+                .insert(device_name.clone(), entry.clone());
+            return Err(MinistoreError::io(
+                format!("Failed to remove file, path={:?}", filepath),
+                e,
+            ));
+        }
+        remove_side_files(&location, device_name).await?;
+        if let Some(registry) = &self.registry {
+            lock_registry(registry)?.remove(device_name)?;
+        }
+
+        if let Some(CloneOrigin {
+            device,
+            snapshot,
+            owns_snapshot: true,
+        }) = origin
+        {
+            self.delete_snapshot(&device, &snapshot).await?;
+        }
+        Ok(())
+    }
+
+    /// Clones are listed as fake devices too
+    ///
+    /// Returns the name, size and block size of each fake device
+    pub fn list_fake_devices(&self) -> Result<Vec<(String, u64, u64)>> {
+        let mut devices: Vec<(String, u64, u64)> = self
+            .read_devices()?
+            .values()
+            .filter(|entry| entry.info.device_type().is_fake())
+            .map(|entry| {
+                (
+                    entry.info.name().clone(),
+                    entry.info.device_size(),
+                    entry.info.block_size(),
+                )
+            })
+            .collect();
+        devices.sort();
+        Ok(devices)
+    }
+
+    /// Creates a thin-provisioned volume whose extents are allocated from the devices on the
+    /// first write, so the size can be larger than the devices
+    pub async fn create_volume(
+        &self,
+        volume_name: &String,
+        volume_size: u64,
+        member_names: &[String],
+    ) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        check_device_name(volume_name)?;
+        let _reservation = self.reserve_name(volume_name)?;
+
+        check_unused(&*self.read_devices()?, member_names, true)?;
+
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let volume = Volume::new(
+            volume_name.clone(),
+            volume_size,
+            self.get_members(member_names)?,
+            self.allocator.clone(),
+            &location,
+        )?;
+        // Flush the empty extent map, so that it can be loaded on restart
+        volume.flush().await?;
+
+        // Members may be deleted while the volume is flushed
+        let mut devices = self.write_devices()?;
+        if devices.contains_key(volume_name) {
+            return Err(MinistoreError::DeviceAlreadyExists {
+                name: volume_name.clone(),
+            });
+        }
+        if let Some(name) = member_names
+            .iter()
+            .find(|name| !devices.contains_key(*name))
+        {
+            return Err(MinistoreError::DeviceNotFound { name: name.clone() });
+        }
+        check_unused(&devices, member_names, true)?;
+        lock_registry(registry)?.insert(RegistryEntry {
+            members: member_names.to_vec(),
+            ..RegistryEntry::new(
+                volume_name.clone(),
+                BlockDeviceType::Volume,
+                volume_size,
+                volume.info().block_size(),
+                location.join(volume_name),
+                ProtectionMode::None,
+                PerformanceProfile::default(),
+            )
+        })?;
+        tracing::info!(
+            "Created volume, name={}, size={}, members={:?}",
+            volume_name,
+            volume_size,
+            member_names
+        );
+        devices.insert(volume_name.clone(), DeviceEntry::new_volume(volume));
+        Ok(())
+    }
+
+    /// Releases the extents of the volume after the in-flight I/Os to it
+    pub async fn delete_volume(&self, volume_name: &String) -> Result<()> {
+        let entry = {
+            let mut devices = self.write_devices()?;
+            let entry = get_volume_entry(&devices, volume_name)?;
+            if let Some(registry) = &self.registry {
+                lock_registry(registry)?.remove(volume_name)?;
+            }
+            devices.remove(volume_name);
+            entry
+        };
+
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        if let Some(volume) = &entry.volume {
+            volume.release_all().await?;
+        }
+
+        let filepath = PathBuf::from(&self.config.fake_device_location).join(volume_name);
+        tokio::fs::remove_file(&filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to remove file, path={:?}", filepath), e)
+        })
+    }
+
+    pub async fn list_volumes(&self) -> Result<Vec<VolumeStatus>> {
+        let volumes: Vec<Arc<Volume>> = self
+            .read_devices()?
+            .values()
+            .filter_map(|entry| entry.volume.clone())
+            .collect();
+
+        let mut statuses = Vec::with_capacity(volumes.len());
+        for volume in volumes {
+            statuses.push(volume.status().await);
+        }
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(statuses)
+    }
+
+    /// Waits for the in-flight I/Os to the volume, and replaces it with the resized one. Shrinking
+    /// releases the extents beyond the new size.
+    pub async fn resize_volume(&self, volume_name: &String, volume_size: u64) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        let entry = get_volume_entry(&*self.read_devices()?, volume_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        let Some(volume) = &entry.volume else {
+            return Err(MinistoreError::internal("Volume entry has no volume"));
+        };
+        let resized = volume.resize(volume_size).await?;
+        resized.flush().await?;
+
+        let mut devices = self.write_devices()?;
+        // The volume may be deleted while it is resized
+        if !devices
+            .get(volume_name)
+            .is_some_and(|current| Arc::ptr_eq(current, &entry))
+        {
+            return Err(MinistoreError::DeviceNotFound {
+                name: volume_name.clone(),
+            });
+        }
+        let mut registry = lock_registry(registry)?;
+        let registry_entry = registry
+            .entries()
+            .into_iter()
+            .find(|registry_entry| &registry_entry.name == volume_name)
+            .ok_or_else(|| MinistoreError::DeviceNotFound {
+                name: volume_name.clone(),
+            })?;
+        registry.update(RegistryEntry {
+            size: volume_size,
+            ..registry_entry
+        })?;
+        tracing::info!("Resized volume, name={}, size={}", volume_name, volume_size);
+        devices.insert(volume_name.clone(), DeviceEntry::new_volume(resized));
+        Ok(())
+    }
+
+    /// Creates a RAID-1 mirror across the devices, which are not used by other volumes or mirrors.
+    /// The smallest device is the size of the mirror.
+    pub async fn create_mirror(&self, mirror_name: &String, member_names: &[String]) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        check_device_name(mirror_name)?;
+        let _reservation = self.reserve_name(mirror_name)?;
+
+        check_unused(&*self.read_devices()?, member_names, false)?;
+
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let mirror = MirrorDevice::new(
+            mirror_name.clone(),
+            self.get_exclusive_members(member_names)?,
+            &location,
+        )?;
+        // Flush the member states, so that it can be loaded on restart
+        mirror.flush().await?;
+
+        // Members may be deleted or used by others while the mirror is flushed
+        let mut devices = self.write_devices()?;
+        if devices.contains_key(mirror_name) {
+            return Err(MinistoreError::DeviceAlreadyExists {
+                name: mirror_name.clone(),
+            });
+        }
+        if let Some(name) = member_names
+            .iter()
+            .find(|name| !devices.contains_key(*name))
+        {
+            return Err(MinistoreError::DeviceNotFound { name: name.clone() });
+        }
+        check_unused(&devices, member_names, false)?;
+        lock_registry(registry)?.insert(RegistryEntry {
+            members: member_names.to_vec(),
+            ..RegistryEntry::new(
+                mirror_name.clone(),
+                BlockDeviceType::Mirror,
+                mirror.info().device_size(),
+                mirror.info().block_size(),
+                location.join(mirror_name),
+                ProtectionMode::None,
+                PerformanceProfile::default(),
+            )
+        })?;
+        tracing::info!(
+            "Created mirror, name={}, size={}, members={:?}",
+            mirror_name,
+            mirror.info().device_size(),
+            member_names
+        );
+        devices.insert(mirror_name.clone(), DeviceEntry::new_mirror(mirror));
+        Ok(())
+    }
+
+    /// Stops the rebuild and deletes the mirror after the in-flight I/Os to it. The members are
+    /// kept.
+    pub async fn delete_mirror(&self, mirror_name: &String) -> Result<()> {
+        let entry = {
+            let mut devices = self.write_devices()?;
+            let entry = get_mirror_entry(&devices, mirror_name)?;
+            if let Some(user) = find_user(&devices, mirror_name)? {
+                return Err(MinistoreError::DeviceInUse {
+                    name: mirror_name.clone(),
+                    user: user.info.name().clone(),
+                });
+            }
+            if let Some(registry) = &self.registry {
+                lock_registry(registry)?.remove(mirror_name)?;
+            }
+            devices.remove(mirror_name);
+            entry
+        };
+
+        if let Some(mirror) = &entry.mirror {
+            mirror.stop();
+        }
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        let filepath = PathBuf::from(&self.config.fake_device_location).join(mirror_name);
+        tokio::fs::remove_file(&filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to remove file, path={:?}", filepath), e)
+        })
+    }
+
+    pub fn list_mirrors(&self) -> Result<Vec<MirrorStatus>> {
+        let mut statuses = self
+            .read_devices()?
+            .values()
+            .filter_map(|entry| entry.mirror.as_ref().map(|mirror| mirror.status()))
+            .collect::<Result<Vec<MirrorStatus>>>()?;
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(statuses)
+    }
+
+    /// Replaces the member of the mirror with the device, e.g. after the member fails, and
+    /// rebuilds the mirror onto the device in the background
+    pub async fn replace_mirror_member(
+        &self,
+        mirror_name: &String,
+        old_member_name: &str,
+        new_member_name: &String,
+    ) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        let new_member = self
+            .get_exclusive_members(std::slice::from_ref(new_member_name))?
+            .remove(0);
+        let entry = {
+            // The device map is locked, so that the new member is not used by others meanwhile
+            let devices = self.write_devices()?;
+            if !devices.contains_key(new_member_name) {
+                return Err(MinistoreError::DeviceNotFound {
+                    name: new_member_name.clone(),
+                });
+            }
+            let entry = get_mirror_entry(&devices, mirror_name)?;
+            let Some(mirror) = &entry.mirror else {
+                return Err(MinistoreError::internal("Mirror entry has no mirror"));
+            };
+            check_unused(&devices, std::slice::from_ref(new_member_name), false)?;
+            mirror.replace_member(old_member_name, new_member)?;
+
+            // Members replaced after the last flush are rebuilt on restart
+            let mut registry = lock_registry(registry)?;
+            let registry_entry = registry
+                .entries()
+                .into_iter()
+                .find(|registry_entry| &registry_entry.name == mirror_name)
+                .ok_or_else(|| MinistoreError::DeviceNotFound {
+                    name: mirror_name.clone(),
+                })?;
+            registry.update(RegistryEntry {
+                members: mirror.member_names()?,
+                ..registry_entry
+            })?;
+            entry
+        };
+
+        start_rebuild(entry);
+        Ok(())
+    }
+
+    /// Creates a RAID-0 or RAID-5 array striping across the devices, which are not used by other
+    /// volumes, mirrors or arrays. Only the rows which fit in the smallest device are used.
+    pub async fn create_array(
+        &self,
+        array_name: &String,
+        level: RaidLevel,
+        stripe_size: u64,
+        member_names: &[String],
+    ) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        check_device_name(array_name)?;
+        let _reservation = self.reserve_name(array_name)?;
+
+        check_unused(&*self.read_devices()?, member_names, false)?;
+
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let array = StripedDevice::new(
+            array_name.clone(),
+            level,
+            stripe_size,
+            self.get_exclusive_members(member_names)?,
+            &location,
+        )?;
+        array.initialize().await?;
+        // Flush the failed members, so that it can be loaded on restart
+        array.flush().await?;
+
+        // Members may be deleted or used by others while the array is initialized
+        let mut devices = self.write_devices()?;
+        if devices.contains_key(array_name) {
+            return Err(MinistoreError::DeviceAlreadyExists {
+                name: array_name.clone(),
+            });
+        }
+        if let Some(name) = member_names
+            .iter()
+            .find(|name| !devices.contains_key(*name))
+        {
+            return Err(MinistoreError::DeviceNotFound { name: name.clone() });
+        }
+        check_unused(&devices, member_names, false)?;
+        lock_registry(registry)?.insert(RegistryEntry {
+            members: member_names.to_vec(),
+            stripe_size: Some(stripe_size),
+            ..RegistryEntry::new(
+                array_name.clone(),
+                level.device_type(),
+                array.info().device_size(),
+                array.info().block_size(),
+                location.join(array_name),
+                ProtectionMode::None,
+                PerformanceProfile::default(),
+            )
+        })?;
+        tracing::info!(
+            "Created array, name={}, level={:?}, size={}, stripe_size={}, members={:?}",
+            array_name,
+            level,
+            array.info().device_size(),
+            stripe_size,
+            member_names
+        );
+        devices.insert(array_name.clone(), DeviceEntry::new_array(array));
+        Ok(())
+    }
+
+    /// Stops the rebuild and deletes the array after the in-flight I/Os to it. The members are
+    /// kept.
+    pub async fn delete_array(&self, array_name: &String) -> Result<()> {
+        let entry = {
+            let mut devices = self.write_devices()?;
+            let entry = get_array_entry(&devices, array_name)?;
+            if let Some(user) = find_user(&devices, array_name)? {
+                return Err(MinistoreError::DeviceInUse {
+                    name: array_name.clone(),
+                    user: user.info.name().clone(),
+                });
+            }
+            if let Some(registry) = &self.registry {
+                lock_registry(registry)?.remove(array_name)?;
+            }
+            devices.remove(array_name);
+            entry
+        };
+
+        if let Some(array) = &entry.array {
+            array.stop();
+        }
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        let filepath = PathBuf::from(&self.config.fake_device_location).join(array_name);
+        tokio::fs::remove_file(&filepath).await.map_err(|e| {
+            MinistoreError::io(format!("Failed to remove file, path={:?}", filepath), e)
+        })
+    }
+
+    /// Replaces the member of the RAID-5 array with the device, e.g. after the member fails, and
+    /// rebuilds the array onto the device in the background
+    pub async fn replace_array_member(
+        &self,
+        array_name: &String,
+        old_member_name: &str,
+        new_member_name: &String,
+    ) -> Result<()> {
+        let Some(registry) = &self.registry else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        let new_member = self
+            .get_exclusive_members(std::slice::from_ref(new_member_name))?
+            .remove(0);
+        let entry = {
+            // The device map is locked, so that the new member is not used by others meanwhile
+            let devices = self.write_devices()?;
+            if !devices.contains_key(new_member_name) {
+                return Err(MinistoreError::DeviceNotFound {
+                    name: new_member_name.clone(),
+                });
+            }
+            let entry = get_array_entry(&devices, array_name)?;
+            let Some(array) = &entry.array else {
+                return Err(MinistoreError::internal("Array entry has no array"));
+            };
+            check_unused(&devices, std::slice::from_ref(new_member_name), false)?;
+            array.replace_member(old_member_name, new_member)?;
+
+            // Members replaced after the last flush are rebuilt on restart
+            let mut registry = lock_registry(registry)?;
+            let registry_entry = registry
+                .entries()
+                .into_iter()
+                .find(|registry_entry| &registry_entry.name == array_name)
+                .ok_or_else(|| MinistoreError::DeviceNotFound {
+                    name: array_name.clone(),
+                })?;
+            registry.update(RegistryEntry {
+                members: array.member_names()?,
+                ..registry_entry
+            })?;
+            entry
+        };
+
+        start_rebuild(entry);
+        Ok(())
     }
 
-    pub fn delete_fake_device(&mut self, device_name: &String) -> Result<(), String> {
-        todo!()
+    pub fn list_arrays(&self) -> Result<Vec<ArrayStatus>> {
+        let mut statuses = self
+            .read_devices()?
+            .values()
+            .filter_map(|entry| entry.array.as_ref().map(|array| array.status()))
+            .collect::<Result<Vec<ArrayStatus>>>()?;
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(statuses)
     }
 
-    pub fn list_fake_devices(&self) -> Result<Vec<(String, u64)>, String> {
-        todo!()
+    /// Takes a copy-on-write snapshot after the in-flight writes to the device, which keeps the
+    /// old blocks on the following writes. The snapshot is persisted with the device on flush.
+    pub async fn create_snapshot(&self, device_name: &String, snapshot_name: &str) -> Result<()> {
+        let (entry, snapshots) = self.get_snapshots(device_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        snapshots.create_snapshot(snapshot_name).await?;
+        tracing::info!(
+            "Created snapshot, name={}, snapshot={}",
+            device_name,
+            snapshot_name
+        );
+        Ok(())
     }
 
-    pub fn write(
-        &mut self,
+    /// Oldest first
+    pub async fn list_snapshots(&self, device_name: &String) -> Result<Vec<SnapshotInfo>> {
+        let (_, snapshots) = self.get_snapshots(device_name)?;
+        Ok(snapshots.list_snapshots().await)
+    }
+
+    /// Rolls the device back to the snapshot, blocking the I/Os to the device meanwhile
+    pub async fn restore_snapshot(&self, device_name: &String, snapshot_name: &str) -> Result<()> {
+        let (entry, snapshots) = self.get_snapshots(device_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(0, u64::MAX, LockMode::Exclusive)
+            .await;
+        snapshots.restore_snapshot(snapshot_name).await?;
+        tracing::info!(
+            "Restored snapshot, name={}, snapshot={}",
+            device_name,
+            snapshot_name
+        );
+        Ok(())
+    }
+
+    /// Snapshots used by clones cannot be deleted until the clones are deleted
+    pub async fn delete_snapshot(&self, device_name: &String, snapshot_name: &str) -> Result<()> {
+        let _origin_guard = self.origin_lock.lock().await;
+        let (_, snapshots) = self.get_snapshots(device_name)?;
+        if let Some(registry) = &self.registry {
+            if let Some(clone) = lock_registry(registry)?
+                .entries()
+                .into_iter()
+                .find(|entry| {
+                    entry.origin.as_ref().is_some_and(|origin| {
+                        &origin.device == device_name && origin.snapshot == snapshot_name
+                    })
+                })
+            {
+                return Err(MinistoreError::DeviceInUse {
+                    name: device_name.clone(),
+                    user: clone.name,
+                });
+            }
+        }
+        snapshots.delete_snapshot(snapshot_name).await?;
+        tracing::info!(
+            "Deleted snapshot, name={}, snapshot={}",
+            device_name,
+            snapshot_name
+        );
+        Ok(())
+    }
+
+    /// Creates a writable clone of the snapshot of the source device, which shares the blocks not
+    /// written to the clone with the snapshot. If the snapshot is not given, a snapshot of the
+    /// current blocks is taken for the clone, which is deleted with the clone.
+    pub async fn clone_device(
+        &self,
+        source_name: &String,
+        clone_name: &String,
+        snapshot_name: Option<&str>,
+    ) -> Result<()> {
+        let (Some(device_type), Some(registry)) = (self.fake_device_type.clone(), &self.registry)
+        else {
+            return Err(MinistoreError::NotSupported {
+                reason: "Fake device is not enabled".to_string(),
+            });
+        };
+        check_device_name(clone_name)?;
+        let _reservation = self.reserve_name(clone_name)?;
+
+        // Released before the snapshot is deleted on failure
+        let origin_guard = self.origin_lock.lock().await;
+        let (source, snapshots) = self.get_snapshots(source_name)?;
+        let origin = match snapshot_name {
+            Some(snapshot_name) => {
+                if !snapshots
+                    .list_snapshots()
+                    .await
+                    .iter()
+                    .any(|snapshot| snapshot.name == snapshot_name)
+                {
+                    return Err(MinistoreError::SnapshotNotFound {
+                        name: source_name.clone(),
+                        snapshot: snapshot_name.to_string(),
+                    });
+                }
+                CloneOrigin {
+                    device: source_name.clone(),
+                    snapshot: snapshot_name.to_string(),
+                    owns_snapshot: false,
+                }
+            }
+            None => {
+                let snapshot_name = format!("clone-{}", clone_name);
+                self.create_snapshot(source_name, &snapshot_name).await?;
+                CloneOrigin {
+                    device: source_name.clone(),
+                    snapshot: snapshot_name,
+                    owns_snapshot: true,
+                }
+            }
+        };
+
+        let created = self
+            .create_clone(
+                device_type,
+                registry,
+                &source,
+                snapshots,
+                clone_name,
+                &origin,
+            )
+            .await;
+        drop(origin_guard);
+        if created.is_err() && origin.owns_snapshot {
+            if let Err(e) = self.delete_snapshot(source_name, &origin.snapshot).await {
+                tracing::warn!(
+                    "Failed to delete snapshot of failed clone, name={}, snapshot={}, err={}",
+                    source_name,
+                    origin.snapshot,
+                    e
+                );
+            }
+        }
+        created?;
+        tracing::info!(
+            "Cloned device, name={}, source={}, snapshot={}",
+            clone_name,
+            source_name,
+            origin.snapshot
+        );
+        Ok(())
+    }
+
+    async fn create_clone(
+        &self,
+        device_type: BlockDeviceType,
+        registry: &Mutex<DeviceRegistry>,
+        source: &DeviceEntry,
+        snapshots: Arc<SnapshotDevice>,
+        clone_name: &String,
+        origin: &CloneOrigin,
+    ) -> Result<()> {
+        // The snapshot is persisted first, so that the clone can be loaded on restart
+        {
+            let _guard = source.range_lock.lock(0, u64::MAX, LockMode::Shared).await;
+            snapshots.flush().await?;
+        }
+
+        let location = PathBuf::from(&self.config.fake_device_location);
+        let device = create_async_block_device(
+            device_type.clone(),
+            clone_name.clone(),
+            source.info.device_size(),
+            source.info.block_size(),
+            location.clone(),
+        )
+        .await?;
+        let performance_profile = self.config.performance_profile.clone();
+        let device = shape(device, &performance_profile);
+        let (device, fault_injector) = inject_faults(device, self.config.fault_injection);
+        let device = protect(device, self.config.protection_mode, &location);
+        let device = clone_of(
+            device,
+            Some((snapshots, origin.snapshot.clone())),
+            &location,
+        )?;
+        let device = SnapshotDevice::new(device, &location);
+        device.flush().await?;
+
+        let mut devices = self.write_devices()?;
+        if devices.contains_key(clone_name) {
+            return Err(MinistoreError::DeviceAlreadyExists {
+                name: clone_name.clone(),
+            });
+        }
+        lock_registry(registry)?.insert(RegistryEntry {
+            origin: Some(origin.clone()),
+            ..RegistryEntry::new(
+                clone_name.clone(),
+                device_type,
+                source.info.device_size(),
+                source.info.block_size(),
+                location.join(clone_name),
+                self.config.protection_mode,
+                performance_profile,
+            )
+        })?;
+        devices.insert(
+            clone_name.clone(),
+            DeviceEntry::new_fake(device, fault_injector),
+        );
+        Ok(())
+    }
+
+    pub async fn write(
+        &self,
         device_name: &String,
         lba: u64,
         num_blocks: u64,
         blocks: Vec<DataBlock>,
-    ) -> Result<(), String> {
-        todo!()
+    ) -> Result<()> {
+        let entry = self.get_device_for_io(device_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(lba, num_blocks, LockMode::Exclusive)
+            .await;
+        entry.device.write(lba, num_blocks, blocks).await
+    }
+
+    pub async fn read(
+        &self,
+        device_name: &String,
+        lba: u64,
+        num_blocks: u64,
+    ) -> Result<Vec<DataBlock>> {
+        let entry = self.get_device_for_io(device_name)?;
+        read_entry(&entry, lba, num_blocks).await
+    }
+
+    /// Reads the device for the scrubber, including the members of volumes, mirrors and arrays
+    pub async fn read_for_scrub(
+        &self,
+        device_name: &String,
+        lba: u64,
+        num_blocks: u64,
+    ) -> Result<Vec<DataBlock>> {
+        let entry = self.get_device(device_name)?;
+        read_entry(&entry, lba, num_blocks).await
     }
 
-    pub fn read(
-        &mut self,
+    pub async fn unmap(&self, device_name: &String, lba: u64, num_blocks: u64) -> Result<()> {
+        let entry = self.get_device_for_io(device_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(lba, num_blocks, LockMode::Exclusive)
+            .await;
+        entry.device.unmap(lba, num_blocks).await
+    }
+
+    pub async fn write_zeroes(
+        &self,
         device_name: &String,
         lba: u64,
         num_blocks: u64,
-    ) -> Result<Vec<DataBlock>, String> {
-        todo!()
+    ) -> Result<()> {
+        let entry = self.get_device_for_io(device_name)?;
+        let _guard = entry
+            .range_lock
+            .lock(lba, num_blocks, LockMode::Exclusive)
+            .await;
+        entry.device.write_zeroes(lba, num_blocks).await
+    }
+
+    /// Persists the device, waiting for the in-flight writes to the device
+    pub async fn flush(&self, device_name: &String) -> Result<()> {
+        let entry = self.get_device(device_name)?;
+        let _guard = entry.range_lock.lock(0, u64::MAX, LockMode::Shared).await;
+        entry.device.flush().await
+    }
+
+    pub fn device_info(&self, device_name: &String) -> Result<DeviceInfo> {
+        Ok(self.get_device(device_name)?.info.clone())
+    }
+
+    /// Names of all devices including raw devices
+    pub fn device_names(&self) -> Result<Vec<String>> {
+        let mut device_names: Vec<String> = self.read_devices()?.keys().cloned().collect();
+        device_names.sort();
+        Ok(device_names)
+    }
+
+    pub fn scrub_status(&self, device_name: &String) -> Result<ScrubStatus> {
+        Ok(self.get_device(device_name)?.scrub_status()?.clone())
+    }
+
+    pub fn scrub_statuses(&self) -> Result<Vec<(String, ScrubStatus)>> {
+        let mut statuses = self
+            .read_devices()?
+            .iter()
+            .map(|(name, entry)| Ok((name.clone(), entry.scrub_status()?.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(statuses)
+    }
+
+    pub fn update_scrub_status<F>(&self, device_name: &String, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut ScrubStatus),
+    {
+        let entry = self.get_device(device_name)?;
+        let mut status = entry.scrub_status()?;
+        f(&mut status);
+        Ok(())
+    }
+
+    /// Replaces the fault rules of the device. The default rules clear the faults.
+    pub fn set_fault_rules(&self, device_name: &String, rules: FaultRules) -> Result<()> {
+        let entry = self.get_device(device_name)?;
+        let Some(fault_injector) = &entry.fault_injector else {
+            return Err(MinistoreError::NotSupported {
+                reason: format!("Fault injection is not enabled, name={}", device_name),
+            });
+        };
+        fault_injector.set_rules(rules)?;
+        tracing::info!("Set fault rules, name={}", device_name);
+        Ok(())
+    }
+
+    fn get_snapshots(
+        &self,
+        device_name: &String,
+    ) -> Result<(Arc<DeviceEntry>, Arc<SnapshotDevice>)> {
+        let entry = self.get_device(device_name)?;
+        let Some(snapshots) = entry.snapshots.clone() else {
+            return Err(MinistoreError::NotSupported {
+                reason: format!(
+                    "Snapshots are only supported by fake devices, name={}",
+                    device_name
+                ),
+            });
+        };
+        Ok((entry, snapshots))
+    }
+
+    /// Devices which volumes allocate extents from, which cannot be volumes
+    fn get_members(&self, member_names: &[String]) -> Result<Vec<Member>> {
+        if let Some((index, name)) = member_names
+            .iter()
+            .enumerate()
+            .find(|(index, name)| member_names[..*index].contains(name))
+        {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Member is given twice, name={}, index={}",
+                name, index
+            )));
+        }
+
+        member_names
+            .iter()
+            .map(|name| {
+                let entry = self.get_device(name)?;
+                if entry.volume.is_some() {
+                    return Err(MinistoreError::invalid_argument(format!(
+                        "Volume cannot be a member of other volume, name={}",
+                        name
+                    )));
+                }
+                Ok(Member {
+                    name: name.clone(),
+                    device: entry.device.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Mirrors and arrays are not composed of volumes, mirrors or arrays
+    fn get_exclusive_members(&self, member_names: &[String]) -> Result<Vec<Member>> {
+        let members = self.get_members(member_names)?;
+        if let Some(name) = member_names.iter().find(|name| {
+            self.get_device(name)
+                .is_ok_and(|entry| entry.mirror.is_some() || entry.array.is_some())
+        }) {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Mirror or array cannot be a member of other mirror or array, name={}",
+                name
+            )));
+        }
+        Ok(members)
+    }
+
+    fn get_device(&self, device_name: &String) -> Result<Arc<DeviceEntry>> {
+        self.read_devices()?
+            .get(device_name)
+            .cloned()
+            .ok_or_else(|| MinistoreError::DeviceNotFound {
+                name: device_name.clone(),
+            })
+    }
+
+    /// Gets the device for I/O. Members of volumes, mirrors and arrays are only written and read
+    /// through them, so that their data is not changed behind the user.
+    fn get_device_for_io(&self, device_name: &String) -> Result<Arc<DeviceEntry>> {
+        let devices = self.read_devices()?;
+        if let Some(user) = find_user(&devices, device_name)? {
+            return Err(MinistoreError::DeviceInUse {
+                name: device_name.clone(),
+                user: user.info.name().clone(),
+            });
+        }
+        devices
+            .get(device_name)
+            .cloned()
+            .ok_or_else(|| MinistoreError::DeviceNotFound {
+                name: device_name.clone(),
+            })
+    }
+
+    /// Reserves the name until the device is inserted into the device map, so that concurrent
+    /// requests do not create the files of the same device
+    fn reserve_name(&self, name: &String) -> Result<NameReservation<'_>> {
+        let mut creating = self
+            .creating
+            .lock()
+            .map_err(|e| MinistoreError::internal(e.to_string()))?;
+        if creating.contains(name) || self.read_devices()?.contains_key(name) {
+            return Err(MinistoreError::DeviceAlreadyExists { name: name.clone() });
+        }
+        creating.insert(name.clone());
+        Ok(NameReservation {
+            creating: &self.creating,
+            name: name.clone(),
+        })
+    }
+
+    fn read_devices(&self) -> Result<RwLockReadGuard<'_, HashMap<String, Arc<DeviceEntry>>>> {
+        self.devices
+            .read()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+
+    fn write_devices(&self) -> Result<RwLockWriteGuard<'_, HashMap<String, Arc<DeviceEntry>>>> {
+        self.devices
+            .write()
+            .map_err(|e| MinistoreError::internal(e.to_string()))
+    }
+}
+
+/// Removes the files kept next to the backing file, which only exist if the device is protected,
+/// has been snapshotted or is a clone
+async fn remove_side_files(location: &Path, device_name: &str) -> Result<()> {
+    for filepath in [
+        protection_info_path(location, device_name),
+        snapshots_path(location, device_name),
+        clone_path(location, device_name),
+    ] {
+        match tokio::fs::remove_file(&filepath).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(MinistoreError::io(
+                    format!("Failed to remove file, path={:?}", filepath),
+                    e,
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn open_registered_device(
+    entry: &RegistryEntry,
+    fault_injection: bool,
+    origin: Option<(Arc<SnapshotDevice>, String)>,
+) -> Result<(SnapshotDevice, Option<Arc<FaultInjector>>)> {
+    // Creating a device makes an empty backing file, so check that it still exists
+    std::fs::metadata(&entry.path).map_err(|e| {
+        MinistoreError::io(
+            format!("Failed to access backing file, path={:?}", entry.path),
+            e,
+        )
+    })?;
+    let location = entry
+        .path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let device = create_async_block_device(
+        entry.device_type.clone(),
+        entry.name.clone(),
+        entry.size,
+        entry.block_size,
+        location.clone(),
+    )
+    .await?;
+    let device = shape(device, &entry.performance_profile);
+    let (device, fault_injector) = inject_faults(device, fault_injection);
+    let device = protect(device, entry.protection_mode, &location);
+    let device = clone_of(device, origin, &location)?;
+    let mut device = SnapshotDevice::new(device, &location);
+    device.load().await?;
+
+    if device.info().device_size() != entry.size {
+        return Err(MinistoreError::Corrupted {
+            reason: format!(
+                "Device size mismatch, name={}, registered_size={}, loaded_size={}",
+                entry.name,
+                entry.size,
+                device.info().device_size()
+            ),
+        });
+    }
+    Ok((device, fault_injector))
+}
+
+/// Wraps the device to delay its I/Os by the profile, unless the device should respond instantly
+fn shape(
+    device: Box<dyn AsyncBlockDevice>,
+    performance_profile: &PerformanceProfile,
+) -> Box<dyn AsyncBlockDevice> {
+    match performance_profile.is_instant() {
+        true => device,
+        false => Box::new(ShapedDevice::new(device, performance_profile.clone())),
+    }
+}
+
+/// Wraps the device to fail its I/Os by the rules set later, if enabled. It is wrapped before the
+/// protection, so that torn writes are found by the protection information.
+fn inject_faults(
+    device: Box<dyn AsyncBlockDevice>,
+    enabled: bool,
+) -> (Box<dyn AsyncBlockDevice>, Option<Arc<FaultInjector>>) {
+    match enabled {
+        false => (device, None),
+        true => {
+            let device = FaultyFakeDevice::new(device);
+            let fault_injector = device.injector();
+            (Box::new(device), Some(fault_injector))
+        }
+    }
+}
+
+/// Wraps the device to verify the protection information of each block, if enabled
+fn protect(
+    device: Box<dyn AsyncBlockDevice>,
+    mode: ProtectionMode,
+    location: &Path,
+) -> Box<dyn AsyncBlockDevice> {
+    match mode {
+        ProtectionMode::None => device,
+        _ => Box::new(ProtectedDevice::new(device, mode, location)),
     }
 }
 
+/// Wraps the device to read the blocks not written yet from the snapshot, if it is a clone. It is
+/// wrapped after the protection, which only covers the blocks written to the clone.
+fn clone_of(
+    device: Box<dyn AsyncBlockDevice>,
+    origin: Option<(Arc<SnapshotDevice>, String)>,
+    location: &Path,
+) -> Result<Box<dyn AsyncBlockDevice>> {
+    match origin {
+        None => Ok(device),
+        Some((origin, snapshot_name)) => Ok(Box::new(CloneDevice::new(
+            device,
+            origin,
+            snapshot_name,
+            location,
+        )?)),
+    }
+}
+
+/// Orders the entries so that each clone comes after its origin. Clones whose origins are not
+/// registered fail to be opened.
+fn order_by_origin(mut entries: Vec<RegistryEntry>) -> Vec<RegistryEntry> {
+    let mut ordered = Vec::with_capacity(entries.len());
+    while !entries.is_empty() {
+        let pending: HashSet<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+        let (ready, waiting): (Vec<RegistryEntry>, Vec<RegistryEntry>) =
+            entries.into_iter().partition(|entry| {
+                entry
+                    .origin
+                    .as_ref()
+                    .is_none_or(|origin| !pending.contains(&origin.device))
+            });
+        if ready.is_empty() {
+            // Clones of each other, which cannot be opened anyway
+            ordered.extend(waiting);
+            break;
+        }
+        ordered.extend(ready);
+        entries = waiting;
+    }
+    ordered
+}
+
+/// Copies the blocks onto the rebuilding member of the mirror, or reconstructs them onto the
+/// rebuilding member of the array, in the background, and persists the member states when it is
+/// done
+fn start_rebuild(entry: Arc<DeviceEntry>) {
+    tokio::spawn(async move {
+        tracing::info!("Start rebuilding, name={}", entry.info.name());
+        let result = match (&entry.mirror, &entry.array) {
+            (Some(mirror), _) => mirror.rebuild(&entry.range_lock).await,
+            (_, Some(array)) => array.rebuild().await,
+            _ => return,
+        };
+        let result = match result {
+            Ok(()) => {
+                // Exclusive, so that the file is not written by a concurrent flush
+                let _guard = entry
+                    .range_lock
+                    .lock(0, u64::MAX, LockMode::Exclusive)
+                    .await;
+                // The file is removed if the mirror or the array is deleted meanwhile
+                let stopped = match (&entry.mirror, &entry.array) {
+                    (Some(mirror), _) => mirror.is_stopped(),
+                    (_, Some(array)) => array.is_stopped(),
+                    _ => true,
+                };
+                match stopped {
+                    true => Ok(()),
+                    false => entry.device.flush().await,
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to rebuild, name={}, err={}", entry.info.name(), e);
+        }
+    });
+}
+
+async fn read_entry(entry: &DeviceEntry, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+    let _guard = entry
+        .range_lock
+        .lock(lba, num_blocks, LockMode::Shared)
+        .await;
+    entry.device.read(lba, num_blocks).await
+}
+
+/// Volume, mirror or array which the device is a member of
+fn find_user(
+    devices: &HashMap<String, Arc<DeviceEntry>>,
+    device_name: &String,
+) -> Result<Option<Arc<DeviceEntry>>> {
+    for entry in devices.values() {
+        let member_names = match (&entry.volume, &entry.mirror, &entry.array) {
+            (Some(volume), _, _) => volume.member_names(),
+            (_, Some(mirror), _) => mirror.member_names()?,
+            (_, _, Some(array)) => array.member_names()?,
+            _ => continue,
+        };
+        if member_names.contains(device_name) {
+            return Ok(Some(entry.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// Fails if any device is a member of a mirror or an array. Devices can be shared by volumes if allowed.
+fn check_unused(
+    devices: &HashMap<String, Arc<DeviceEntry>>,
+    device_names: &[String],
+    shared_by_volumes: bool,
+) -> Result<()> {
+    for name in device_names {
+        if let Some(user) =
+            find_user(devices, name)?.filter(|user| !shared_by_volumes || user.volume.is_none())
+        {
+            return Err(MinistoreError::DeviceInUse {
+                name: name.clone(),
+                user: user.info.name().clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn get_mirror_entry(
+    devices: &HashMap<String, Arc<DeviceEntry>>,
+    mirror_name: &String,
+) -> Result<Arc<DeviceEntry>> {
+    match devices.get(mirror_name) {
+        None => Err(MinistoreError::DeviceNotFound {
+            name: mirror_name.clone(),
+        }),
+        Some(entry) if entry.mirror.is_none() => Err(MinistoreError::invalid_argument(format!(
+            "Device is not a mirror, name={}",
+            mirror_name
+        ))),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+fn get_array_entry(
+    devices: &HashMap<String, Arc<DeviceEntry>>,
+    array_name: &String,
+) -> Result<Arc<DeviceEntry>> {
+    match devices.get(array_name) {
+        None => Err(MinistoreError::DeviceNotFound {
+            name: array_name.clone(),
+        }),
+        Some(entry) if entry.array.is_none() => Err(MinistoreError::invalid_argument(format!(
+            "Device is not an array, name={}",
+            array_name
+        ))),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+fn get_volume_entry(
+    devices: &HashMap<String, Arc<DeviceEntry>>,
+    volume_name: &String,
+) -> Result<Arc<DeviceEntry>> {
+    match devices.get(volume_name) {
+        None => Err(MinistoreError::DeviceNotFound {
+            name: volume_name.clone(),
+        }),
+        Some(entry) if entry.volume.is_none() => Err(MinistoreError::invalid_argument(format!(
+            "Device is not a volume, name={}",
+            volume_name
+        ))),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+/// Device names are file names in the fake device location, so they cannot point outside of it
+/// or be the names of the files kept next to the fake devices
+fn check_device_name(device_name: &str) -> Result<()> {
+    if device_name.is_empty()
+        || device_name == "."
+        || device_name.contains("..")
+        || device_name.contains(['/', '\\', '\0'])
+    {
+        return Err(MinistoreError::invalid_argument(format!(
+            "Device name should be a file name, name={:?}",
+            device_name
+        )));
+    }
+    if is_reserved_name(device_name) {
+        return Err(MinistoreError::invalid_argument(format!(
+            "Device name is reserved, name={}",
+            device_name
+        )));
+    }
+    Ok(())
+}
+
+/// Names of the files kept next to the fake devices cannot be used
+fn is_reserved_name(device_name: &str) -> bool {
+    let extension = Path::new(device_name).extension();
+    device_name.starts_with(REGISTRY_FILENAME)
+        || extension == Some(PROTECTION_INFO_EXTENSION.as_ref())
+        || extension == Some(SNAPSHOTS_EXTENSION.as_ref())
+        || extension == Some(CLONE_EXTENSION.as_ref())
+        || extension == Some("tmp".as_ref())
+}
+
+fn lock_registry(registry: &Mutex<DeviceRegistry>) -> Result<MutexGuard<'_, DeviceRegistry>> {
+    registry
+        .lock()
+        .map_err(|e| MinistoreError::internal(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::async_block_device::faulty_fake_device::LbaRange;
+    use crate::block_device::BlockDevice;
     use crate::utils::humansize_to_integer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
     use tracing_test::traced_test;
 
     fn test_device_config(dirname: &str) -> DeviceConfig {
@@ -61,15 +1857,19 @@ mod tests {
             use_fake: true,
             fake_device_location: dirname.to_string(),
             fake_device_type: "SimpleFake".to_string(),
+            protection_mode: ProtectionMode::None,
+            list: Vec::new(),
+            fault_injection: false,
+            performance_profile: PerformanceProfile::default(),
         }
     }
 
+    #[tokio::test]
     #[traced_test]
-    #[test]
-    fn device_manager_can_create_and_delete_device() {
+    async fn device_manager_can_create_and_delete_device() {
         let testname = "device_manager_can_create_and_delete_device";
-        let config = test_device_config(&testname);
-        let mut device_manager = DeviceManager::new(&config).unwrap();
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config).await.unwrap();
 
         // type = SimpleFakeDevice
         // name = "device_manager_can_create_and_delete_device"
@@ -77,49 +1877,1289 @@ mod tests {
         let device_name = testname.to_string();
 
         device_manager
-            .create_fake_device(&device_name, humansize_to_integer("1M").unwrap())
+            .create_fake_device(
+                &device_name,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
             .expect("Failed to create fake device");
 
         let devices = device_manager
             .list_fake_devices()
             .expect("Failed to get device list");
         assert_eq!(devices.len(), 1);
-        assert_eq!(devices.get(0).unwrap().0, device_name);
+        assert_eq!(devices.first().unwrap().0, device_name);
         assert_eq!(
-            devices.get(0).unwrap().1,
+            devices.first().unwrap().1,
             humansize_to_integer("1M").unwrap()
         );
 
         device_manager
             .delete_fake_device(&device_name)
+            .await
             .expect("Failed to remove fake device");
 
-        std::fs::remove_dir(&testname).expect("Failed to remove directory");
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
     }
 
+    #[tokio::test]
     #[traced_test]
-    #[test]
-    fn device_manager_cannot_create_device_with_same_name_twice() {
+    async fn device_manager_cannot_create_device_with_same_name_twice() {
         let testname = "device_manager_cannot_create_device_with_same_name_twice";
-        let config = test_device_config(&testname);
-        let mut device_manager =
-            DeviceManager::new(&config).expect("Failed to create device manager");
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
 
         // type = SimpleFakeDevice
         // name = "device_manager_cannot_create_device_with_same_name_twice"
         // size = 1MB
         let device_name = testname.to_string();
         device_manager
-            .create_fake_device(&device_name, humansize_to_integer("1M").unwrap())
+            .create_fake_device(
+                &device_name,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
+            .expect("Failed to create fake device");
+
+        assert!(matches!(
+            device_manager
+                .create_fake_device(
+                    &device_name,
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64
+                )
+                .await,
+            Err(MinistoreError::DeviceAlreadyExists { .. })
+        ));
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[traced_test]
+    async fn concurrent_creations_of_same_name_should_create_one_device() {
+        let testname = "concurrent_creations_of_same_name_should_create_one_device";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+        let device_name = "device".to_string();
+        let size = humansize_to_integer("1M").unwrap();
+
+        // A failed creation releases the name
+        assert!(matches!(
+            device_manager
+                .create_fake_device(&device_name, size, 1000)
+                .await,
+            Err(MinistoreError::InvalidArgument { .. })
+        ));
+
+        let (first, second) = tokio::join!(
+            device_manager.create_fake_device(&device_name, size, DEFAULT_BLOCK_SIZE as u64),
+            device_manager.create_fake_device(&device_name, size, DEFAULT_BLOCK_SIZE as u64)
+        );
+        assert!(first.is_ok() != second.is_ok());
+        assert!(matches!(
+            first.and(second),
+            Err(MinistoreError::DeviceAlreadyExists { .. })
+        ));
+
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])];
+        device_manager
+            .write(&device_name, 0, 1, blocks.clone())
+            .await
+            .expect("Failed to write data");
+        assert_eq!(
+            device_manager.read(&device_name, 0, 1).await.unwrap(),
+            blocks
+        );
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn device_manager_should_reject_names_outside_of_location() {
+        let testname = "device_manager_should_reject_names_outside_of_location";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+
+        for device_name in ["", ".", "..", "../escaped", "/tmp/escaped", "sub/device"] {
+            assert!(
+                matches!(
+                    device_manager
+                        .create_fake_device(
+                            &device_name.to_string(),
+                            humansize_to_integer("1M").unwrap(),
+                            DEFAULT_BLOCK_SIZE as u64
+                        )
+                        .await,
+                    Err(MinistoreError::InvalidArgument { .. })
+                ),
+                "name={}",
+                device_name
+            );
+        }
+        assert!(!Path::new("escaped").exists());
+        assert!(!Path::new("/tmp/escaped").exists());
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn torn_write_should_be_found_by_protection_information() {
+        let testname = "torn_write_should_be_found_by_protection_information";
+        let mut config = test_device_config(testname);
+        config.protection_mode = ProtectionMode::Checksum;
+        config.fault_injection = true;
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+
+        let device_name = testname.to_string();
+        device_manager
+            .create_fake_device(
+                &device_name,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
             .expect("Failed to create fake device");
+        let old_blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 4];
+        device_manager
+            .write(&device_name, 0, 4, old_blocks)
+            .await
+            .unwrap();
+
+        // Only the first two blocks are overwritten
+        device_manager
+            .set_fault_rules(
+                &device_name,
+                FaultRules {
+                    write_error_ranges: vec![LbaRange {
+                        lba: 2,
+                        num_blocks: 1,
+                    }],
+                    torn_writes: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let new_blocks = vec![DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]); 4];
+        assert!(device_manager
+            .write(&device_name, 0, 4, new_blocks)
+            .await
+            .is_err());
+
+        device_manager
+            .set_fault_rules(&device_name, FaultRules::default())
+            .unwrap();
+        assert!(matches!(
+            device_manager.read(&device_name, 0, 4).await,
+            Err(MinistoreError::ChecksumMismatch { lba: 0, .. })
+        ));
+        assert!(device_manager.read(&device_name, 2, 2).await.is_ok());
+
+        // Devices are not wrapped if fault injection is disabled
+        config.fault_injection = false;
+        drop(device_manager);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+        assert!(matches!(
+            device_manager.set_fault_rules(&device_name, FaultRules::default()),
+            Err(MinistoreError::NotSupported { .. })
+        ));
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn performance_profile_should_be_kept_after_restart() {
+        let testname = "performance_profile_should_be_kept_after_restart";
+        let mut config = test_device_config(testname);
+        config.performance_profile.latency_us = 20_000;
+        let latency = Duration::from_millis(20);
+
+        let (slow, instant) = ("slow".to_string(), "instant".to_string());
+        {
+            let device_manager = DeviceManager::new(&config).await.unwrap();
+            device_manager
+                .create_fake_device(&slow, DEFAULT_BLOCK_SIZE as u64, DEFAULT_BLOCK_SIZE as u64)
+                .await
+                .unwrap();
+            device_manager
+                .create_fake_device_with_profile(
+                    &instant,
+                    DEFAULT_BLOCK_SIZE as u64,
+                    DEFAULT_BLOCK_SIZE as u64,
+                    Some(PerformanceProfile::default()),
+                )
+                .await
+                .unwrap();
+        }
+
+        // The profile in the config is not applied to the registered devices
+        config.performance_profile = PerformanceProfile::default();
+        let device_manager = DeviceManager::new(&config).await.unwrap();
+        let start = Instant::now();
+        device_manager.read(&slow, 0, 1).await.unwrap();
+        assert!(start.elapsed() >= latency);
+
+        let start = Instant::now();
+        device_manager.read(&instant, 0, 1).await.unwrap();
+        assert!(start.elapsed() < latency);
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn device_manager_should_register_devices_in_the_list() {
+        let testname = "device_manager_should_register_devices_in_the_list";
+        std::fs::create_dir_all(testname).expect("Failed to create directory");
+
+        let mut config = test_device_config(testname);
+        config.use_fake = false;
+        for device_name in ["nvme0n1", "nvme1n1"] {
+            let device_path = format!("{}/{}", testname, device_name);
+            let file = std::fs::File::create(&device_path).expect("Failed to create file");
+            file.set_len(humansize_to_integer("1M").unwrap())
+                .expect("Failed to set file length");
+            config.list.push(device_path);
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])];
+        device_manager
+            .write(&"nvme0n1".to_string(), 0, 1, blocks.clone())
+            .await
+            .expect("Failed to write data");
+        assert_eq!(
+            device_manager
+                .read(&"nvme0n1".to_string(), 0, 1)
+                .await
+                .expect("Failed to read data"),
+            blocks
+        );
+        assert!(device_manager
+            .read(&"nvme1n1".to_string(), 0, 1)
+            .await
+            .is_ok());
+
+        // Raw devices are not fake devices
+        assert_eq!(device_manager.list_fake_devices().unwrap().len(), 0);
+        assert!(matches!(
+            device_manager
+                .delete_fake_device(&"nvme0n1".to_string())
+                .await,
+            Err(MinistoreError::NotFakeDevice { .. })
+        ));
+        assert!(matches!(
+            device_manager
+                .create_fake_device(
+                    &"fake".to_string(),
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64
+                )
+                .await,
+            Err(MinistoreError::NotSupported { .. })
+        ));
+        assert!(matches!(
+            device_manager.read(&"nvme2n1".to_string(), 0, 1).await,
+            Err(MinistoreError::DeviceNotFound { .. })
+        ));
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn device_manager_should_reopen_fake_devices_after_restart() {
+        let testname = "device_manager_should_reopen_fake_devices_after_restart";
+        let config = test_device_config(testname);
+        let first = "first".to_string();
+        let second = "second".to_string();
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            for name in [&first, &second] {
+                device_manager
+                    .create_fake_device(
+                        name,
+                        humansize_to_integer("1M").unwrap(),
+                        DEFAULT_BLOCK_SIZE as u64,
+                    )
+                    .await
+                    .expect("Failed to create fake device");
+            }
+            device_manager
+                .write(&first, 10, 2, blocks.clone())
+                .await
+                .expect("Failed to write data");
+            device_manager
+                .flush(&first)
+                .await
+                .expect("Failed to flush device");
+            assert!(matches!(
+                device_manager
+                    .create_fake_device(
+                        &REGISTRY_FILENAME.to_string(),
+                        DEFAULT_BLOCK_SIZE as u64,
+                        DEFAULT_BLOCK_SIZE as u64
+                    )
+                    .await,
+                Err(MinistoreError::InvalidArgument { .. })
+            ));
+        }
 
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let mut devices = device_manager
+            .list_fake_devices()
+            .expect("Failed to get device list");
+        devices.sort();
+        assert_eq!(
+            devices,
+            vec![
+                (
+                    first.clone(),
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64
+                ),
+                (
+                    second.clone(),
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64
+                )
+            ]
+        );
+        assert_eq!(
+            device_manager
+                .read(&first, 10, 2)
+                .await
+                .expect("Failed to read data"),
+            blocks
+        );
+
+        // Device whose backing file is gone is forgotten
+        device_manager.delete_fake_device(&first).await.unwrap();
+        drop(device_manager);
+        std::fs::remove_file(PathBuf::from(testname).join(&second)).unwrap();
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        assert!(device_manager.list_fake_devices().unwrap().is_empty());
+        assert!(!PathBuf::from(testname).join(REGISTRY_FILENAME).exists());
+
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn volumes_should_be_reopened_and_keep_their_members() {
+        let testname = "volumes_should_be_reopened_and_keep_their_members";
+        let config = test_device_config(testname);
+        let members = vec!["first".to_string(), "second".to_string()];
+        let volume = "volume".to_string();
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+        let last_lba = humansize_to_integer("1G").unwrap() / DEFAULT_BLOCK_SIZE as u64 - 2;
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            for name in &members {
+                device_manager
+                    .create_fake_device(
+                        name,
+                        humansize_to_integer("2M").unwrap(),
+                        DEFAULT_BLOCK_SIZE as u64,
+                    )
+                    .await
+                    .expect("Failed to create fake device");
+            }
+            device_manager
+                .create_volume(&volume, humansize_to_integer("1G").unwrap(), &members)
+                .await
+                .expect("Failed to create volume");
+            assert!(matches!(
+                device_manager
+                    .create_volume(&"nested".to_string(), 4096, std::slice::from_ref(&volume))
+                    .await,
+                Err(MinistoreError::InvalidArgument { .. })
+            ));
+            device_manager
+                .write(&volume, last_lba, 2, blocks.clone())
+                .await
+                .expect("Failed to write data");
+            device_manager
+                .flush(&volume)
+                .await
+                .expect("Failed to flush volume");
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let statuses = device_manager.list_volumes().await.unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].members, members);
+        assert_eq!(
+            statuses[0].allocated_size,
+            humansize_to_integer("1M").unwrap()
+        );
+        assert_eq!(
+            device_manager.read(&volume, last_lba, 2).await.unwrap(),
+            blocks
+        );
+        assert!(!device_manager
+            .list_fake_devices()
+            .unwrap()
+            .iter()
+            .any(|(name, _, _)| name == &volume));
+
+        // Members cannot be deleted, written or read while the volume uses them
+        assert!(matches!(
+            device_manager.delete_fake_device(&members[0]).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager
+                .write(&members[0], 0, 2, blocks.clone())
+                .await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager.read(&members[1], 0, 2).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager.unmap(&members[0], 0, 2).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager.write_zeroes(&members[0], 0, 2).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager.delete_fake_device(&volume).await,
+            Err(MinistoreError::NotFakeDevice { .. })
+        ));
+
+        device_manager
+            .resize_volume(&volume, humansize_to_integer("2M").unwrap())
+            .await
+            .expect("Failed to resize volume");
+        assert_eq!(
+            device_manager.device_info(&volume).unwrap().device_size(),
+            humansize_to_integer("2M").unwrap()
+        );
+        assert_eq!(
+            device_manager.list_volumes().await.unwrap()[0].allocated_size,
+            0
+        );
+
+        device_manager.delete_volume(&volume).await.unwrap();
+        for name in &members {
+            device_manager.delete_fake_device(name).await.unwrap();
+        }
+        assert!(!PathBuf::from(testname).join(REGISTRY_FILENAME).exists());
+
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn snapshots_should_be_kept_after_restart() {
+        let testname = "snapshots_should_be_kept_after_restart";
+        let config = test_device_config(testname);
+        let device_name = "device".to_string();
+        let first = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+        let second = vec![DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]); 2];
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            device_manager
+                .create_fake_device(
+                    &device_name,
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .expect("Failed to create fake device");
+            device_manager
+                .write(&device_name, 0, 2, first.clone())
+                .await
+                .unwrap();
+            device_manager
+                .create_snapshot(&device_name, "snapshot")
+                .await
+                .expect("Failed to create snapshot");
+            device_manager
+                .write(&device_name, 0, 2, second.clone())
+                .await
+                .unwrap();
+            device_manager.flush(&device_name).await.unwrap();
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let snapshots = device_manager.list_snapshots(&device_name).await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].num_preserved_blocks, 2);
+        assert_eq!(
+            device_manager.read(&device_name, 0, 2).await.unwrap(),
+            second
+        );
+
+        device_manager
+            .restore_snapshot(&device_name, "snapshot")
+            .await
+            .expect("Failed to restore snapshot");
         assert_eq!(
+            device_manager.read(&device_name, 0, 2).await.unwrap(),
+            first
+        );
+        device_manager
+            .delete_snapshot(&device_name, "snapshot")
+            .await
+            .expect("Failed to delete snapshot");
+        assert!(matches!(
+            device_manager
+                .restore_snapshot(&device_name, "snapshot")
+                .await,
+            Err(MinistoreError::SnapshotNotFound { .. })
+        ));
+
+        // The snapshot file is removed with the device
+        device_manager.flush(&device_name).await.unwrap();
+        device_manager
+            .delete_fake_device(&device_name)
+            .await
+            .unwrap();
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn clones_should_be_independent_of_their_origins_after_restart() {
+        let testname = "clones_should_be_independent_of_their_origins_after_restart";
+        let config = test_device_config(testname);
+        let origin_name = "origin".to_string();
+        let clone_name = "clone".to_string();
+        let golden = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+        let origin_block = DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]);
+        let clone_block = DataBlock::from(vec![0xC; DEFAULT_BLOCK_SIZE]);
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
             device_manager
-                .create_fake_device(&device_name, humansize_to_integer("1M").unwrap())
-                .is_ok(),
-            false
+                .create_fake_device(
+                    &origin_name,
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .expect("Failed to create fake device");
+            device_manager
+                .write(&origin_name, 0, 2, golden.clone())
+                .await
+                .unwrap();
+            device_manager
+                .clone_device(&origin_name, &clone_name, None)
+                .await
+                .expect("Failed to clone device");
+            assert!(matches!(
+                device_manager
+                    .clone_device(&origin_name, &"other".to_string(), Some("missing"))
+                    .await,
+                Err(MinistoreError::SnapshotNotFound { .. })
+            ));
+
+            device_manager
+                .write(&origin_name, 0, 1, vec![origin_block.clone()])
+                .await
+                .unwrap();
+            device_manager
+                .write(&clone_name, 1, 1, vec![clone_block.clone()])
+                .await
+                .unwrap();
+            device_manager.flush(&origin_name).await.unwrap();
+            device_manager.flush(&clone_name).await.unwrap();
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        assert_eq!(
+            device_manager.list_fake_devices().unwrap(),
+            vec![
+                (clone_name.clone(), 1024 * 1024, DEFAULT_BLOCK_SIZE as u64),
+                (origin_name.clone(), 1024 * 1024, DEFAULT_BLOCK_SIZE as u64),
+            ]
+        );
+        assert_eq!(
+            device_manager.read(&origin_name, 0, 2).await.unwrap(),
+            vec![origin_block, golden[1].clone()]
+        );
+        assert_eq!(
+            device_manager.read(&clone_name, 0, 2).await.unwrap(),
+            vec![golden[0].clone(), clone_block]
+        );
+
+        // The origin and its snapshot are kept while the clone exists
+        assert!(matches!(
+            device_manager.delete_fake_device(&origin_name).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        let snapshots = device_manager.list_snapshots(&origin_name).await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(matches!(
+            device_manager
+                .delete_snapshot(&origin_name, &snapshots[0].name)
+                .await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+
+        device_manager
+            .delete_fake_device(&clone_name)
+            .await
+            .unwrap();
+        assert!(device_manager
+            .list_snapshots(&origin_name)
+            .await
+            .unwrap()
+            .is_empty());
+        device_manager.flush(&origin_name).await.unwrap();
+        device_manager
+            .delete_fake_device(&origin_name)
+            .await
+            .unwrap();
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[traced_test]
+    async fn snapshot_should_not_be_deleted_while_it_is_cloned() {
+        let testname = "snapshot_should_not_be_deleted_while_it_is_cloned";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+        let origin_name = "origin".to_string();
+        let clone_name = "clone".to_string();
+        let snapshot_name = "snapshot";
+        device_manager
+            .create_fake_device(
+                &origin_name,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
+            .expect("Failed to create fake device");
+
+        // Either the clone is created and keeps the snapshot, or the snapshot is deleted first
+        for _ in 0..10 {
+            device_manager
+                .create_snapshot(&origin_name, snapshot_name)
+                .await
+                .expect("Failed to create snapshot");
+            let (cloned, deleted) = tokio::join!(
+                device_manager.clone_device(&origin_name, &clone_name, Some(snapshot_name)),
+                device_manager.delete_snapshot(&origin_name, snapshot_name)
+            );
+            match (cloned, deleted) {
+                (Ok(()), Err(MinistoreError::DeviceInUse { .. })) => {
+                    assert_eq!(
+                        device_manager
+                            .list_snapshots(&origin_name)
+                            .await
+                            .unwrap()
+                            .len(),
+                        1
+                    );
+                    device_manager
+                        .delete_fake_device(&clone_name)
+                        .await
+                        .unwrap();
+                    device_manager
+                        .delete_snapshot(&origin_name, snapshot_name)
+                        .await
+                        .unwrap();
+                }
+                (Err(MinistoreError::SnapshotNotFound { .. }), Ok(())) => {}
+                (cloned, deleted) => panic!("cloned={:?}, deleted={:?}", cloned, deleted),
+            }
+        }
+
+        device_manager
+            .delete_fake_device(&origin_name)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    async fn wait_for_rebuild(device_manager: &DeviceManager, name: &String) {
+        for _ in 0..100 {
+            let mirror = device_manager
+                .list_mirrors()
+                .unwrap()
+                .into_iter()
+                .find(|mirror| &mirror.name == name)
+                .map(|mirror| mirror.is_degraded());
+            let array = device_manager
+                .list_arrays()
+                .unwrap()
+                .into_iter()
+                .find(|array| &array.name == name)
+                .map(|array| array.is_degraded());
+            if !mirror.or(array).expect("Mirror or array not found") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Mirror or array is not rebuilt, name={}", name);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn mirrors_should_be_rebuilt_onto_replaced_member_and_reopened() {
+        let testname = "mirrors_should_be_rebuilt_onto_replaced_member_and_reopened";
+        let config = DeviceConfig {
+            fault_injection: true,
+            ..test_device_config(testname)
+        };
+        let mirror_name = "mirror".to_string();
+        let member_names: Vec<String> = ["first", "second", "spare"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 2];
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            for name in &member_names {
+                device_manager
+                    .create_fake_device(
+                        name,
+                        humansize_to_integer("1M").unwrap(),
+                        DEFAULT_BLOCK_SIZE as u64,
+                    )
+                    .await
+                    .expect("Failed to create fake device");
+            }
+            device_manager
+                .create_mirror(&mirror_name, &member_names[..2])
+                .await
+                .expect("Failed to create mirror");
+            assert!(matches!(
+                device_manager
+                    .create_volume(&"volume".to_string(), 1024 * 1024, &member_names[..1])
+                    .await,
+                Err(MinistoreError::DeviceInUse { .. })
+            ));
+            device_manager
+                .write(&mirror_name, 0, 2, blocks.clone())
+                .await
+                .unwrap();
+
+            // The first member fails, and the mirror is degraded
+            device_manager
+                .set_fault_rules(
+                    &member_names[0],
+                    FaultRules {
+                        error_probability: 1.0,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            for _ in 0..2 {
+                assert_eq!(
+                    device_manager.read(&mirror_name, 0, 2).await.unwrap(),
+                    blocks
+                );
+            }
+            assert!(device_manager.list_mirrors().unwrap()[0].is_degraded());
+
+            device_manager
+                .replace_mirror_member(&mirror_name, &member_names[0], &member_names[2])
+                .await
+                .expect("Failed to replace member");
+            wait_for_rebuild(&device_manager, &mirror_name).await;
+            assert!(matches!(
+                device_manager.read(&member_names[2], 0, 2).await,
+                Err(MinistoreError::DeviceInUse { .. })
+            ));
+            device_manager.flush(&mirror_name).await.unwrap();
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let mirrors = device_manager.list_mirrors().unwrap();
+        assert_eq!(mirrors.len(), 1);
+        assert!(!mirrors[0].is_degraded());
+        assert_eq!(
+            mirrors[0]
+                .members
+                .iter()
+                .map(|member| member.name.clone())
+                .collect::<Vec<String>>(),
+            vec![member_names[2].clone(), member_names[1].clone()]
+        );
+        assert_eq!(
+            device_manager.read(&mirror_name, 0, 2).await.unwrap(),
+            blocks
+        );
+
+        // Members are kept until the mirror is deleted
+        assert!(matches!(
+            device_manager.delete_fake_device(&member_names[1]).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        device_manager.delete_mirror(&mirror_name).await.unwrap();
+        assert_eq!(
+            device_manager.read(&member_names[2], 0, 2).await.unwrap(),
+            blocks
+        );
+        for name in &member_names {
+            device_manager.delete_fake_device(name).await.unwrap();
+        }
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn raid5_arrays_should_keep_failed_member_and_rebuild_replacement_after_restart() {
+        let testname =
+            "raid5_arrays_should_keep_failed_member_and_rebuild_replacement_after_restart";
+        let config = DeviceConfig {
+            fault_injection: true,
+            ..test_device_config(testname)
+        };
+        let array_name = "array".to_string();
+        let member_names: Vec<String> = ["first", "second", "third"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let blocks: Vec<DataBlock> = (0..16)
+            .map(|value| DataBlock::from(vec![value; DEFAULT_BLOCK_SIZE]))
+            .collect();
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            for name in &member_names {
+                device_manager
+                    .create_fake_device(
+                        name,
+                        humansize_to_integer("1M").unwrap(),
+                        DEFAULT_BLOCK_SIZE as u64,
+                    )
+                    .await
+                    .expect("Failed to create fake device");
+            }
+            device_manager
+                .create_array(
+                    &array_name,
+                    RaidLevel::Raid5,
+                    DEFAULT_BLOCK_SIZE as u64 * 4,
+                    &member_names,
+                )
+                .await
+                .expect("Failed to create array");
+            assert!(matches!(
+                device_manager
+                    .create_mirror(&"mirror".to_string(), &member_names[..2])
+                    .await,
+                Err(MinistoreError::DeviceInUse { .. })
+            ));
+            device_manager
+                .write(&array_name, 0, 16, blocks.clone())
+                .await
+                .unwrap();
+
+            device_manager
+                .set_fault_rules(
+                    &member_names[0],
+                    FaultRules {
+                        error_probability: 1.0,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            assert_eq!(
+                device_manager.read(&array_name, 0, 16).await.unwrap(),
+                blocks
+            );
+            device_manager.flush(&array_name).await.unwrap();
+        }
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let arrays = device_manager.list_arrays().unwrap();
+        assert_eq!(arrays.len(), 1);
+        assert_eq!(arrays[0].level, RaidLevel::Raid5);
+        assert_eq!(arrays[0].failed_members, vec![member_names[0].clone()]);
+        assert_eq!(
+            device_manager.read(&array_name, 0, 16).await.unwrap(),
+            blocks
+        );
+
+        assert!(matches!(
+            device_manager.delete_fake_device(&member_names[1]).await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+        assert!(matches!(
+            device_manager
+                .write(&member_names[1], 0, 1, blocks[..1].to_vec())
+                .await,
+            Err(MinistoreError::DeviceInUse { .. })
+        ));
+
+        // The failed member is replaced, and rebuilt from the others
+        let spare = "spare".to_string();
+        device_manager
+            .create_fake_device(
+                &spare,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
+            .expect("Failed to create fake device");
+        device_manager
+            .replace_array_member(&array_name, &member_names[0], &spare)
+            .await
+            .expect("Failed to replace member");
+        wait_for_rebuild(&device_manager, &array_name).await;
+        device_manager.flush(&array_name).await.unwrap();
+        drop(device_manager);
+
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        let arrays = device_manager.list_arrays().unwrap();
+        assert!(!arrays[0].is_degraded());
+        assert_eq!(
+            arrays[0].members,
+            vec![
+                spare.clone(),
+                member_names[1].clone(),
+                member_names[2].clone()
+            ]
+        );
+
+        // Blocks of other failed member are reconstructed with the rebuilt member
+        device_manager
+            .set_fault_rules(
+                &member_names[1],
+                FaultRules {
+                    error_probability: 1.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            device_manager.read(&array_name, 0, 16).await.unwrap(),
+            blocks
+        );
+
+        device_manager.delete_array(&array_name).await.unwrap();
+        for name in member_names.iter().chain([&spare]) {
+            device_manager.delete_fake_device(name).await.unwrap();
+        }
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn device_manager_should_not_block_io_to_other_devices() {
+        let testname = "device_manager_should_not_block_io_to_other_devices";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+
+        let busy_device = "busy".to_string();
+        let idle_device = "idle".to_string();
+        for device_name in [&busy_device, &idle_device] {
+            device_manager
+                .create_fake_device(
+                    device_name,
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .expect("Failed to create fake device");
+        }
+
+        // Keep the busy device locked as if a long I/O is in progress
+        let busy_entry = device_manager.get_device(&busy_device).unwrap();
+        let _busy_guard = busy_entry
+            .range_lock
+            .lock(0, busy_entry.info.num_blocks(), LockMode::Exclusive)
+            .await;
+
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE])];
+        let timeout = std::time::Duration::from_secs(5);
+        tokio::time::timeout(
+            timeout,
+            device_manager.write(&idle_device, 0, 1, blocks.clone()),
+        )
+        .await
+        .expect("I/O to idle device should not wait for busy device")
+        .expect("Failed to write data");
+        assert_eq!(
+            tokio::time::timeout(timeout, device_manager.read(&idle_device, 0, 1))
+                .await
+                .expect("I/O to idle device should not wait for busy device")
+                .expect("Failed to read data"),
+            blocks
+        );
+        assert_eq!(device_manager.list_fake_devices().unwrap().len(), 2);
+
+        // I/O to the busy device waits until the lock is released
+        assert!(tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            device_manager.read(&busy_device, 0, 1)
+        )
+        .await
+        .is_err());
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn fake_device_should_be_deleted_after_in_flight_ios() {
+        let testname = "fake_device_should_be_deleted_after_in_flight_ios";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+        let device_name = "device".to_string();
+        device_manager
+            .create_fake_device(
+                &device_name,
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
+            .expect("Failed to create fake device");
+
+        // Keep the device locked as if a long I/O is in progress
+        let entry = device_manager.get_device(&device_name).unwrap();
+        let guard = entry
+            .range_lock
+            .lock(0, entry.info.num_blocks(), LockMode::Exclusive)
+            .await;
+        let filepath = PathBuf::from(testname).join(&device_name);
+        let (deleted, ()) = tokio::join!(device_manager.delete_fake_device(&device_name), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(filepath.exists());
+            drop(guard);
+        });
+        deleted.expect("Failed to delete fake device");
+        assert!(!filepath.exists());
+
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn fake_device_should_be_cleaned_up_if_its_files_fail_to_be_removed() {
+        let testname = "fake_device_should_be_cleaned_up_if_its_files_fail_to_be_removed";
+        let config = test_device_config(testname);
+        let device_name = "device".to_string();
+        let filepath = PathBuf::from(testname).join(&device_name);
+        let snapshots_filepath = snapshots_path(Path::new(testname), &device_name);
+        let blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 1];
+        {
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            device_manager
+                .create_fake_device(
+                    &device_name,
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .expect("Failed to create fake device");
+            device_manager
+                .write(&device_name, 0, 1, blocks.clone())
+                .await
+                .expect("Failed to write data");
+            device_manager
+                .create_snapshot(&device_name, "snapshot")
+                .await
+                .expect("Failed to create snapshot");
+            device_manager
+                .flush(&device_name)
+                .await
+                .expect("Failed to flush device");
+
+            // The device is kept if its backing file fails to be removed
+            std::fs::remove_file(&filepath).unwrap();
+            std::fs::create_dir(&filepath).unwrap();
+            std::fs::write(filepath.join("file"), b"").unwrap();
+            assert!(matches!(
+                device_manager.delete_fake_device(&device_name).await,
+                Err(MinistoreError::Io { .. })
+            ));
+            assert_eq!(
+                device_manager
+                    .read(&device_name, 0, 1)
+                    .await
+                    .expect("Failed to read data"),
+                blocks
+            );
+
+            // The registry entry is kept if the other files fail to be removed
+            std::fs::remove_dir_all(&filepath).unwrap();
+            std::fs::write(&filepath, b"").unwrap();
+            std::fs::remove_file(&snapshots_filepath).unwrap();
+            std::fs::create_dir(&snapshots_filepath).unwrap();
+            std::fs::write(snapshots_filepath.join("file"), b"").unwrap();
+            assert!(matches!(
+                device_manager.delete_fake_device(&device_name).await,
+                Err(MinistoreError::Io { .. })
+            ));
+            assert!(device_manager.list_fake_devices().unwrap().is_empty());
+            assert!(!filepath.exists());
+            std::fs::remove_dir_all(&snapshots_filepath).unwrap();
+            std::fs::write(&snapshots_filepath, b"").unwrap();
+        }
+
+        // The files left are removed on restart
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to re-create device manager");
+        assert!(device_manager.list_fake_devices().unwrap().is_empty());
+        assert!(!snapshots_filepath.exists());
+        assert!(!PathBuf::from(testname).join(REGISTRY_FILENAME).exists());
+
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    /// SimpleFakeDevice which takes a while for each write and records how many writes run together
+    struct SlowSimpleFakeDevice {
+        device: Box<dyn BlockDevice>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl BlockDevice for SlowSimpleFakeDevice {
+        fn info(&self) -> &DeviceInfo {
+            self.device.info()
+        }
+
+        fn write(&self, lba: u64, num_blocks: u64, buffer: Vec<DataBlock>) -> Result<()> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(200));
+            let result = self.device.write(lba, num_blocks, buffer);
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+
+        fn read(&self, lba: u64, num_blocks: u64) -> Result<Vec<DataBlock>> {
+            self.device.read(lba, num_blocks)
+        }
+
+        fn unmap(&self, lba: u64, num_blocks: u64) -> Result<()> {
+            self.device.unmap(lba, num_blocks)
+        }
+
+        fn write_zeroes(&self, lba: u64, num_blocks: u64) -> Result<()> {
+            self.device.write_zeroes(lba, num_blocks)
+        }
+
+        fn load(&mut self) -> Result<()> {
+            self.device.load()
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.device.flush()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[traced_test]
+    async fn disjoint_writes_to_simple_fake_device_should_overlap_in_time() {
+        let testname = "disjoint_writes_to_simple_fake_device_should_overlap_in_time";
+        let config = test_device_config(testname);
+        let device_manager = DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager");
+
+        let device_name = "slow".to_string();
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let device = SlowSimpleFakeDevice {
+            device: create_block_device(
+                BlockDeviceType::SimpleFakeDevice,
+                device_name.clone(),
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+                PathBuf::from(testname),
+            )
+            .expect("Failed to create device"),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: max_in_flight.clone(),
+        };
+        device_manager.write_devices().unwrap().insert(
+            device_name.clone(),
+            DeviceEntry::new(
+                Box::new(SyncBlockDeviceAdapter::new(Box::new(device))),
+                None,
+            ),
+        );
+
+        let first_blocks = vec![DataBlock::from(vec![0xA; DEFAULT_BLOCK_SIZE]); 4];
+        let second_blocks = vec![DataBlock::from(vec![0xB; DEFAULT_BLOCK_SIZE]); 4];
+
+        // Disjoint writes run together on the blocking threads
+        let (first, second) = tokio::join!(
+            device_manager.write(&device_name, 0, 4, first_blocks.clone()),
+            device_manager.write(&device_name, 4, 4, second_blocks.clone())
+        );
+        first.expect("Failed to write data");
+        second.expect("Failed to write data");
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            device_manager.read(&device_name, 0, 8).await.unwrap(),
+            [first_blocks.clone(), second_blocks.clone()].concat()
+        );
+
+        // Overlapped writes are ordered
+        max_in_flight.store(0, Ordering::SeqCst);
+        let (first, second) = tokio::join!(
+            device_manager.write(&device_name, 0, 4, second_blocks.clone()),
+            device_manager.write(&device_name, 2, 4, first_blocks.clone())
         );
+        first.expect("Failed to write data");
+        second.expect("Failed to write data");
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
 
-        std::fs::remove_dir_all(&testname).expect("Failed to remove directory");
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
     }
 }
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::async_block_device::protected_device::ProtectionMode;
+use crate::async_block_device::shaped_device::PerformanceProfile;
+use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+use crate::block_device_common::BlockDeviceType;
+use crate::error::{MinistoreError, Result};
+use crate::utils::sync_parent_dir;
+
+/// Name of the manifest in the fake device location. Devices cannot use this name.
+pub const REGISTRY_FILENAME: &str = ".ministore_registry.toml";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub device_type: BlockDeviceType,
+    pub size: u64,
+    /// Entries written before the block size was configurable use the default
+    #[serde(default = "default_block_size")]
+    pub block_size: u64,
+    pub path: PathBuf,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    #[serde(default)]
+    pub protection_mode: ProtectionMode,
+    /// Devices which the extents of a volume are allocated from, or which a mirror or an array is
+    /// composed of, empty for other devices
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+    /// Stripe size of an array, none for other devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stripe_size: Option<u64>,
+    /// Snapshot which a clone shares the unmodified blocks with, none for other devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<CloneOrigin>,
+    #[serde(default)]
+    pub performance_profile: PerformanceProfile,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CloneOrigin {
+    pub device: String,
+    pub snapshot: String,
+    /// Whether the snapshot was taken for the clone, which is deleted with the clone
+    pub owns_snapshot: bool,
+}
+
+fn default_block_size() -> u64 {
+    DEFAULT_BLOCK_SIZE as u64
+}
+
+impl RegistryEntry {
+    pub fn new(
+        name: String,
+        device_type: BlockDeviceType,
+        size: u64,
+        block_size: u64,
+        path: PathBuf,
+        protection_mode: ProtectionMode,
+        performance_profile: PerformanceProfile,
+    ) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        RegistryEntry {
+            name,
+            device_type,
+            size,
+            block_size,
+            path,
+            created_at,
+            protection_mode,
+            members: Vec::new(),
+            stripe_size: None,
+            origin: None,
+            performance_profile,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    devices: Vec<RegistryEntry>,
+}
+
+/// Keeps the list of fake devices created by ministore, so that they can be re-opened after a
+/// restart. Every change is written to the manifest atomically.
+pub struct DeviceRegistry {
+    path: PathBuf,
+    entries: BTreeMap<String, RegistryEntry>,
+}
+
+impl DeviceRegistry {
+    /// Opens the registry in the location, which is empty if there is no manifest yet
+    pub fn open(location: &Path) -> Result<Self> {
+        let path = location.join(REGISTRY_FILENAME);
+        let manifest = match fs::read_to_string(&path) {
+            Ok(manifest) => {
+                toml::from_str::<Manifest>(&manifest).map_err(|e| MinistoreError::Corrupted {
+                    reason: format!("Invalid device registry, path={:?}, err={}", path, e),
+                })?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Manifest::default(),
+            Err(e) => {
+                return Err(MinistoreError::io(
+                    format!("Failed to read device registry, path={:?}", path),
+                    e,
+                ))
+            }
+        };
+
+        Ok(DeviceRegistry {
+            path,
+            entries: manifest
+                .devices
+                .into_iter()
+                .map(|entry| (entry.name.clone(), entry))
+                .collect(),
+        })
+    }
+
+    pub fn entries(&self) -> Vec<RegistryEntry> {
+        self.entries.values().cloned().collect()
+    }
+
+    pub fn insert(&mut self, entry: RegistryEntry) -> Result<()> {
+        if self.entries.contains_key(&entry.name) {
+            return Err(MinistoreError::DeviceAlreadyExists { name: entry.name });
+        }
+
+        let name = entry.name.clone();
+        self.entries.insert(name.clone(), entry);
+        self.persist().inspect_err(|_| {
+            self.entries.remove(&name);
+        })
+    }
+
+    /// Replaces the entry of the same name, e.g. after a volume is resized
+    pub fn update(&mut self, entry: RegistryEntry) -> Result<()> {
+        let name = entry.name.clone();
+        let Some(old) = self.entries.insert(name.clone(), entry) else {
+            self.entries.remove(&name);
+            return Err(MinistoreError::DeviceNotFound { name });
+        };
+        self.persist().inspect_err(|_| {
+            self.entries.insert(name, old);
+        })
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if let Some(entry) = self.entries.remove(name) {
+            self.persist().inspect_err(|_| {
+                self.entries.insert(name.to_string(), entry);
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the manifest into a temporary file and renames it, so that a crash leaves either
+    /// the old or the new manifest. The manifest is removed when there is no device.
+    fn persist(&self) -> Result<()> {
+        if self.entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(MinistoreError::io(
+                    format!("Failed to remove device registry, path={:?}", self.path),
+                    e,
+                )),
+                _ => Ok(()),
+            };
+        }
+
+        let manifest = Manifest {
+            devices: self.entries(),
+        };
+        let serialized = toml::to_string(&manifest).map_err(|e| {
+            MinistoreError::internal(format!("Failed to serialize device registry, err={}", e))
+        })?;
+
+        let tmp_path = self.path.with_extension("toml.tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(|e| {
+                MinistoreError::io(format!("Failed to create file, path={:?}", tmp_path), e)
+            })?;
+        file.write_all(serialized.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| {
+            MinistoreError::io(
+                format!("Failed to rename device registry, path={:?}", self.path),
+                e,
+            )
+        })?;
+
+        // Persist the rename itself
+        sync_parent_dir(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::shaped_device::Jitter;
+    use tracing_test::traced_test;
+
+    #[traced_test]
+    #[test]
+    fn registry_should_be_replayed_after_reopen() {
+        let testname = "registry_should_be_replayed_after_reopen";
+        let location = PathBuf::from(testname);
+        fs::create_dir_all(&location).unwrap();
+
+        let mut registry = DeviceRegistry::open(&location).expect("Failed to open registry");
+        assert!(registry.entries().is_empty());
+
+        let first = RegistryEntry::new(
+            "first".to_string(),
+            BlockDeviceType::SimpleFakeDevice,
+            4096,
+            4096,
+            location.join("first"),
+            ProtectionMode::None,
+            PerformanceProfile::default(),
+        );
+        let second = RegistryEntry::new(
+            "second".to_string(),
+            BlockDeviceType::IoUringFakeDevice,
+            8192,
+            512,
+            location.join("second"),
+            ProtectionMode::Checksum,
+            PerformanceProfile {
+                latency_us: 100,
+                jitter: Jitter::Exponential,
+                jitter_us: 50,
+                ..Default::default()
+            },
+        );
+        let first = RegistryEntry {
+            origin: Some(CloneOrigin {
+                device: "second".to_string(),
+                snapshot: "golden".to_string(),
+                owns_snapshot: true,
+            }),
+            ..first
+        };
+        registry.insert(first.clone()).unwrap();
+        registry.insert(second.clone()).unwrap();
+        assert!(matches!(
+            registry.insert(first.clone()),
+            Err(MinistoreError::DeviceAlreadyExists { .. })
+        ));
+        let second = RegistryEntry {
+            size: 16384,
+            members: vec!["first".to_string()],
+            stripe_size: Some(8192),
+            ..second
+        };
+        registry.update(second.clone()).unwrap();
+
+        let mut registry = DeviceRegistry::open(&location).expect("Failed to open registry");
+        assert_eq!(registry.entries(), vec![first.clone(), second]);
+
+        registry.remove("second").unwrap();
+        let mut registry = DeviceRegistry::open(&location).expect("Failed to open registry");
+        assert_eq!(registry.entries(), vec![first]);
+
+        // Manifest is removed with the last device
+        registry.remove("first").unwrap();
+        assert!(!location.join(REGISTRY_FILENAME).exists());
+
+        fs::remove_dir(&location).expect("Failed to remove directory");
+    }
+
+    #[traced_test]
+    #[test]
+    fn corrupted_registry_should_fail_to_open() {
+        let testname = "corrupted_registry_should_fail_to_open";
+        let location = PathBuf::from(testname);
+        fs::create_dir_all(&location).unwrap();
+        fs::write(location.join(REGISTRY_FILENAME), "devices = 1").unwrap();
+
+        assert!(matches!(
+            DeviceRegistry::open(&location),
+            Err(MinistoreError::Corrupted { .. })
+        ));
+
+        fs::remove_dir_all(&location).expect("Failed to remove directory");
+    }
+}
@@ -0,0 +1,91 @@
+use strum_macros::IntoStaticStr;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, MinistoreError>;
+
+/// The variant name in SCREAMING_SNAKE_CASE (e.g. DEVICE_NOT_FOUND) can be obtained with
+/// `<&'static str>::from(&error)`, which is used as a machine readable reason.
+#[derive(Debug, Error, IntoStaticStr)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum MinistoreError {
+    #[error("Invalid lba range, lba={lba}, num_blocks={num_blocks}, device_num_blocks={device_num_blocks}")]
+    InvalidLbaRange {
+        lba: u64,
+        num_blocks: u64,
+        device_num_blocks: u64,
+    },
+    #[error("Buffer size mismatch, num_blocks={num_blocks}, buffer_len={buffer_len}")]
+    BufferSizeMismatch { num_blocks: u64, buffer_len: u64 },
+    #[error("Device not found, name={name}")]
+    DeviceNotFound { name: String },
+    #[error("Device already exists, name={name}")]
+    DeviceAlreadyExists { name: String },
+    #[error("Device is not a fake device, name={name}")]
+    NotFakeDevice { name: String },
+    #[error("Device is used by other device, name={name}, user={user}")]
+    DeviceInUse { name: String, user: String },
+    #[error("Snapshot not found, name={name}, snapshot={snapshot}")]
+    SnapshotNotFound { name: String, snapshot: String },
+    #[error("Snapshot already exists, name={name}, snapshot={snapshot}")]
+    SnapshotAlreadyExists { name: String, snapshot: String },
+    #[error("Device size should be aligned with block size, size={size}, block_size={block_size}")]
+    UnalignedSize { size: u64, block_size: u64 },
+    #[error("Invalid block device type, type={device_type}")]
+    InvalidDeviceType { device_type: String },
+    #[error("Corrupted device image, reason={reason}")]
+    Corrupted { reason: String },
+    #[error("Checksum mismatch, lba={lba}, expected={expected:#010x}, actual={actual:#010x}")]
+    ChecksumMismatch {
+        lba: u64,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("Reference tag mismatch, lba={lba}, reference_tag={reference_tag}")]
+    ReferenceTagMismatch { lba: u64, reference_tag: u32 },
+    #[error("Not supported, reason={reason}")]
+    NotSupported { reason: String },
+    #[error("Invalid argument, reason={reason}")]
+    InvalidArgument { reason: String },
+    #[error("{context}, err={source}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to serialize, err={0}")]
+    Serialization(#[from] bincode::Error),
+    #[error("Invalid config, err={0}")]
+    Config(#[from] config::ConfigError),
+    #[error("Failed to run gRPC server, err={0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Internal error, reason={reason}")]
+    Internal { reason: String },
+}
+
+impl MinistoreError {
+    /// Wraps an I/O error with the operation and target that failed
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        MinistoreError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn invalid_argument(reason: impl Into<String>) -> Self {
+        MinistoreError::InvalidArgument {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn internal(reason: impl Into<String>) -> Self {
+        MinistoreError::Internal {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for MinistoreError {
+    fn from(e: std::io::Error) -> Self {
+        MinistoreError::io("I/O failed", e)
+    }
+}
@@ -1,76 +1,1485 @@
-use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
 use tonic::transport::Server;
 use tonic::Response;
 
 use uuid::Uuid;
 
+use crate::async_block_device::faulty_fake_device::{
+    FaultRules as DeviceFaultRules, LbaRange as DeviceLbaRange,
+};
+use crate::async_block_device::mirror_device::{self, MirrorStatus};
+use crate::async_block_device::shaped_device::{
+    Jitter as DeviceJitter, PerformanceProfile as DevicePerformanceProfile,
+};
+use crate::async_block_device::snapshot_device::SnapshotInfo;
+use crate::async_block_device::striped_device::{self, ArrayStatus};
+use crate::async_block_device::volume::VolumeStatus;
+use crate::block_device_common::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
 use crate::device_manager::DeviceManager;
+use crate::error::MinistoreError;
+use crate::scrubber::{self, ScrubStatus as DeviceScrubStatus};
 
 use self::ministore_proto::mini_service_server::{MiniService, MiniServiceServer};
+use self::ministore_proto::v2::mini_service_server::MiniServiceServer as V2MiniServiceServer;
 use self::ministore_proto::{
-    CreateFakeDeviceRequest, CreateFakeDeviceResponse, DeleteFakeDeviceRequest,
-    DeleteFakeDeviceResponse, FakeDevice, ListFakeDevicesRequest, ListFakeDevicesResponse,
-    ReadRequest, ReadResponse, Status, StatusRequest, StatusResponse, WriteRequest, WriteResponse,
+    Array, BadRange, ClearFaultRulesRequest, ClearFaultRulesResponse, CloneDeviceRequest,
+    CloneDeviceResponse, CreateArrayRequest, CreateArrayResponse, CreateFakeDeviceRequest,
+    CreateFakeDeviceResponse, CreateMirrorRequest, CreateMirrorResponse, CreateSnapshotRequest,
+    CreateSnapshotResponse, CreateVolumeRequest, CreateVolumeResponse, Data, DeleteArrayRequest,
+    DeleteArrayResponse, DeleteFakeDeviceRequest, DeleteFakeDeviceResponse, DeleteMirrorRequest,
+    DeleteMirrorResponse, DeleteSnapshotRequest, DeleteSnapshotResponse, DeleteVolumeRequest,
+    DeleteVolumeResponse, ErrorCode, FakeDevice, FaultRules, GetScrubStatusRequest,
+    GetScrubStatusResponse, Jitter, LbaRange, ListArraysRequest, ListArraysResponse,
+    ListFakeDevicesRequest, ListFakeDevicesResponse, ListMirrorsRequest, ListMirrorsResponse,
+    ListSnapshotsRequest, ListSnapshotsResponse, ListVolumesRequest, ListVolumesResponse,
+    MemberState, Mirror, MirrorMember, PerformanceProfile, RaidLevel, ReadRequest, ReadResponse,
+    ReplaceArrayMemberRequest, ReplaceArrayMemberResponse, ReplaceMirrorMemberRequest,
+    ReplaceMirrorMemberResponse, ResizeVolumeRequest, ResizeVolumeResponse, RestoreSnapshotRequest,
+    RestoreSnapshotResponse, ScrubState, ScrubStatus, SetFaultRulesRequest, SetFaultRulesResponse,
+    Snapshot, Status, StatusRequest, StatusResponse, UnmapRequest, UnmapResponse, Volume,
+    WriteRequest, WriteResponse, WriteZeroesRequest, WriteZeroesResponse,
 };
 
+pub mod v2;
+
 pub mod ministore_proto {
     tonic::include_proto!("ministore");
+
+    pub mod v2 {
+        tonic::include_proto!("ministore.v2");
+    }
+}
+
+/// Size of the blocks in a message of streaming RPCs, which keeps each message under the default
+/// message size limit of tonic
+pub const STREAM_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Number of blocks in a message of streaming RPCs for the block size
+pub fn stream_chunk_blocks(block_size: u64) -> u64 {
+    std::cmp::max(1, STREAM_CHUNK_SIZE / block_size)
 }
 
-pub async fn start_grpc_server(addr: &str, grpc_server: GrpcServer) -> Result<(), String> {
-    todo!()
+/// The default block size is used if not given
+fn requested_block_size(block_size: u64) -> u64 {
+    match block_size {
+        0 => DEFAULT_BLOCK_SIZE as u64,
+        block_size => block_size,
+    }
+}
+
+pub async fn start_grpc_server(addr: &str, grpc_server: GrpcServer) -> Result<(), MinistoreError> {
+    let addr = addr.parse().map_err(|e| {
+        MinistoreError::invalid_argument(format!(
+            "Invalid server address, addr={}, err={}",
+            addr, e
+        ))
+    })?;
+    tracing::info!("Starting gRPC server, addr={}", addr);
+
+    Server::builder()
+        .add_service(MiniServiceServer::new(grpc_server.clone()))
+        .add_service(V2MiniServiceServer::new(grpc_server))
+        .serve(addr)
+        .await?;
+    Ok(())
 }
 
-pub struct GrpcServer {}
+/// Serves both of v1 and v2 services with the same DeviceManager
+#[derive(Clone)]
+pub struct GrpcServer {
+    device_manager: Arc<DeviceManager>,
+}
 
 impl GrpcServer {
-    pub fn new(device_manager: DeviceManager) -> Self {
-        todo!()
+    /// The device manager can be shared with other tasks (e.g. Scrubber)
+    pub fn new(device_manager: impl Into<Arc<DeviceManager>>) -> Self {
+        GrpcServer {
+            device_manager: device_manager.into(),
+        }
+    }
+}
+
+fn to_data_blocks(
+    data: Option<Data>,
+    lba: u64,
+    num_blocks: u64,
+    block_size: u64,
+) -> Result<Vec<DataBlock>, MinistoreError> {
+    let data = data.ok_or_else(|| MinistoreError::invalid_argument("No data provided"))?;
+    let blocks = bytes_to_data_blocks(data.data, num_blocks, block_size)?;
+    verify_checksums(&blocks, &data.checksums, lba)?;
+    Ok(blocks)
+}
+
+fn bytes_to_data_blocks(
+    data: Vec<Bytes>,
+    num_blocks: u64,
+    block_size: u64,
+) -> Result<Vec<DataBlock>, MinistoreError> {
+    if data.len() as u64 != num_blocks {
+        return Err(MinistoreError::BufferSizeMismatch {
+            num_blocks,
+            buffer_len: data.len() as u64,
+        });
+    }
+
+    data.into_iter()
+        .map(|block| {
+            if block.len() as u64 != block_size {
+                return Err(MinistoreError::invalid_argument(format!(
+                    "Each data should be block size, block_size={}, data_size={}",
+                    block_size,
+                    block.len()
+                )));
+            }
+            Ok(DataBlock(block))
+        })
+        .collect()
+}
+
+/// Checksums are optional, but should be given for every block if any
+fn verify_checksums(
+    blocks: &[DataBlock],
+    checksums: &[u32],
+    lba: u64,
+) -> Result<(), MinistoreError> {
+    if checksums.is_empty() {
+        return Ok(());
+    }
+    if checksums.len() != blocks.len() {
+        return Err(MinistoreError::invalid_argument(format!(
+            "Each data should have a checksum, num_blocks={}, num_checksums={}",
+            blocks.len(),
+            checksums.len()
+        )));
+    }
+
+    for (offset, (block, &expected)) in blocks.iter().zip(checksums).enumerate() {
+        let actual = block.checksum();
+        if actual != expected {
+            return Err(MinistoreError::ChecksumMismatch {
+                lba: lba + offset as u64,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn from_data_blocks(blocks: Vec<DataBlock>) -> Data {
+    Data {
+        checksums: data_blocks_to_checksums(&blocks),
+        data: data_blocks_to_bytes(blocks),
+    }
+}
+
+fn data_blocks_to_bytes(blocks: Vec<DataBlock>) -> Vec<Bytes> {
+    blocks.into_iter().map(|block| block.0).collect()
+}
+
+fn data_blocks_to_checksums(blocks: &[DataBlock]) -> Vec<u32> {
+    blocks.iter().map(DataBlock::checksum).collect()
+}
+
+/// Every device if the name is empty
+fn scrub_statuses(
+    device_manager: &DeviceManager,
+    name: &String,
+) -> Result<Vec<(String, DeviceScrubStatus)>, MinistoreError> {
+    if name.is_empty() {
+        device_manager.scrub_statuses()
+    } else {
+        Ok(vec![(name.clone(), device_manager.scrub_status(name)?)])
+    }
+}
+
+fn to_scrub_status(name: String, status: DeviceScrubStatus) -> ScrubStatus {
+    let state = match status.state {
+        scrubber::ScrubState::Idle => ScrubState::Idle,
+        scrubber::ScrubState::Scrubbing => ScrubState::Scrubbing,
+    };
+    ScrubStatus {
+        name,
+        state: state as i32,
+        scrubbed_blocks: status.scrubbed_blocks,
+        total_blocks: status.total_blocks,
+        completed_passes: status.completed_passes,
+        last_completed_at: status.last_completed_at,
+        bad_ranges: status
+            .bad_ranges
+            .into_iter()
+            .map(|range| BadRange {
+                lba: range.lba,
+                num_blocks: range.num_blocks,
+                reason: range.reason,
+            })
+            .collect(),
+    }
+}
+
+fn to_performance_profile(
+    profile: Option<PerformanceProfile>,
+) -> Result<Option<DevicePerformanceProfile>, MinistoreError> {
+    let Some(profile) = profile else {
+        return Ok(None);
+    };
+    let jitter = match Jitter::from_i32(profile.jitter) {
+        Some(Jitter::NoJitter) => DeviceJitter::None,
+        Some(Jitter::Uniform) => DeviceJitter::Uniform,
+        Some(Jitter::Exponential) => DeviceJitter::Exponential,
+        None => {
+            return Err(MinistoreError::invalid_argument(format!(
+                "Invalid jitter, jitter={}",
+                profile.jitter
+            )))
+        }
+    };
+    Ok(Some(DevicePerformanceProfile {
+        latency_us: profile.latency_us,
+        per_block_latency_us: profile.per_block_latency_us,
+        jitter,
+        jitter_us: profile.jitter_us,
+        iops_limit: profile.iops_limit,
+        bandwidth_limit: profile.bandwidth_limit,
+    }))
+}
+
+fn to_fault_rules(rules: Option<FaultRules>) -> Result<DeviceFaultRules, MinistoreError> {
+    let rules = rules.ok_or_else(|| MinistoreError::invalid_argument("No rules provided"))?;
+    let to_lba_ranges = |ranges: Vec<LbaRange>| {
+        ranges
+            .into_iter()
+            .map(|range| DeviceLbaRange {
+                lba: range.lba,
+                num_blocks: range.num_blocks,
+            })
+            .collect()
+    };
+    Ok(DeviceFaultRules {
+        read_error_ranges: to_lba_ranges(rules.read_error_ranges),
+        write_error_ranges: to_lba_ranges(rules.write_error_ranges),
+        error_probability: rules.error_probability,
+        latency: Duration::from_micros(rules.latency_us),
+        torn_writes: rules.torn_writes,
+        fail_after_ops: rules.fail_after_ops,
+    })
+}
+
+fn to_snapshot(info: SnapshotInfo, block_size: u64) -> Snapshot {
+    Snapshot {
+        name: info.name,
+        created_at: info.created_at,
+        preserved_size: info.num_preserved_blocks * block_size,
+    }
+}
+
+fn to_mirror(status: MirrorStatus) -> Mirror {
+    Mirror {
+        degraded: status.is_degraded(),
+        name: status.name,
+        size: status.size,
+        block_size: status.block_size,
+        members: status
+            .members
+            .into_iter()
+            .map(|member| {
+                let state = match member.state {
+                    mirror_device::MemberState::Active => MemberState::Active,
+                    mirror_device::MemberState::Failed => MemberState::Failed,
+                    mirror_device::MemberState::Rebuilding => MemberState::Rebuilding,
+                };
+                MirrorMember {
+                    name: member.name,
+                    state: state as i32,
+                    rebuilt_blocks: member.rebuilt_blocks,
+                }
+            })
+            .collect(),
+    }
+}
+
+fn to_array(status: ArrayStatus) -> Array {
+    let level = match status.level {
+        striped_device::RaidLevel::Raid0 => RaidLevel::Raid0,
+        striped_device::RaidLevel::Raid5 => RaidLevel::Raid5,
+    };
+    Array {
+        degraded: status.is_degraded(),
+        name: status.name,
+        level: level as i32,
+        size: status.size,
+        block_size: status.block_size,
+        stripe_size: status.stripe_size,
+        devices: status.members,
+        failed_devices: status.failed_members,
+        rebuilding_device: status.rebuilding_member,
+        rebuilt_blocks: status.rebuilt_blocks,
+    }
+}
+
+fn to_raid_level(level: i32) -> Result<striped_device::RaidLevel, MinistoreError> {
+    match RaidLevel::from_i32(level) {
+        Some(RaidLevel::Raid0) => Ok(striped_device::RaidLevel::Raid0),
+        Some(RaidLevel::Raid5) => Ok(striped_device::RaidLevel::Raid5),
+        None => Err(MinistoreError::invalid_argument(format!(
+            "Invalid RAID level, level={}",
+            level
+        ))),
+    }
+}
+
+fn to_volume(status: VolumeStatus) -> Volume {
+    Volume {
+        name: status.name,
+        size: status.size,
+        block_size: status.block_size,
+        allocated_size: status.allocated_size,
+        devices: status.members,
+    }
+}
+
+fn to_error_code(e: &MinistoreError) -> ErrorCode {
+    match e {
+        MinistoreError::InvalidLbaRange { .. } => ErrorCode::InvalidLbaRange,
+        MinistoreError::BufferSizeMismatch { .. } => ErrorCode::BufferSizeMismatch,
+        MinistoreError::DeviceNotFound { .. } => ErrorCode::DeviceNotFound,
+        MinistoreError::DeviceAlreadyExists { .. } => ErrorCode::DeviceAlreadyExists,
+        MinistoreError::NotFakeDevice { .. } => ErrorCode::NotFakeDevice,
+        MinistoreError::DeviceInUse { .. } => ErrorCode::DeviceInUse,
+        MinistoreError::SnapshotNotFound { .. } => ErrorCode::SnapshotNotFound,
+        MinistoreError::SnapshotAlreadyExists { .. } => ErrorCode::SnapshotAlreadyExists,
+        MinistoreError::UnalignedSize { .. } => ErrorCode::UnalignedSize,
+        MinistoreError::InvalidDeviceType { .. } => ErrorCode::InvalidDeviceType,
+        MinistoreError::Corrupted { .. } => ErrorCode::Corrupted,
+        MinistoreError::ChecksumMismatch { .. } => ErrorCode::ChecksumMismatch,
+        MinistoreError::ReferenceTagMismatch { .. } => ErrorCode::ReferenceTagMismatch,
+        MinistoreError::NotSupported { .. } => ErrorCode::NotSupported,
+        MinistoreError::InvalidArgument { .. } => ErrorCode::InvalidArgument,
+        MinistoreError::Io { .. } => ErrorCode::IoError,
+        MinistoreError::Serialization(_)
+        | MinistoreError::Config(_)
+        | MinistoreError::Transport(_)
+        | MinistoreError::Internal { .. } => ErrorCode::InternalError,
+    }
+}
+
+fn to_status_code(e: &MinistoreError) -> tonic::Code {
+    match e {
+        MinistoreError::InvalidLbaRange { .. } => tonic::Code::OutOfRange,
+        MinistoreError::BufferSizeMismatch { .. }
+        | MinistoreError::UnalignedSize { .. }
+        | MinistoreError::InvalidDeviceType { .. }
+        | MinistoreError::InvalidArgument { .. } => tonic::Code::InvalidArgument,
+        MinistoreError::DeviceNotFound { .. } | MinistoreError::SnapshotNotFound { .. } => {
+            tonic::Code::NotFound
+        }
+        MinistoreError::DeviceAlreadyExists { .. }
+        | MinistoreError::SnapshotAlreadyExists { .. } => tonic::Code::AlreadyExists,
+        MinistoreError::NotFakeDevice { .. } | MinistoreError::DeviceInUse { .. } => {
+            tonic::Code::FailedPrecondition
+        }
+        MinistoreError::NotSupported { .. } => tonic::Code::Unimplemented,
+        MinistoreError::Corrupted { .. }
+        | MinistoreError::ChecksumMismatch { .. }
+        | MinistoreError::ReferenceTagMismatch { .. } => tonic::Code::DataLoss,
+        MinistoreError::Transport(_) => tonic::Code::Unavailable,
+        MinistoreError::Io { source, .. } if source.kind() == std::io::ErrorKind::StorageFull => {
+            tonic::Code::ResourceExhausted
+        }
+        MinistoreError::Io { .. }
+        | MinistoreError::Serialization(_)
+        | MinistoreError::Config(_)
+        | MinistoreError::Internal { .. } => tonic::Code::Internal,
+    }
+}
+
+impl From<MinistoreError> for tonic::Status {
+    fn from(e: MinistoreError) -> Self {
+        tonic::Status::new(to_status_code(&e), e.to_string())
     }
 }
 
 #[tonic::async_trait]
 impl MiniService for GrpcServer {
+    type ReadStreamStream = Pin<Box<dyn Stream<Item = Result<ReadResponse, tonic::Status>> + Send>>;
+
     async fn status(
         &self,
-        request: tonic::Request<StatusRequest>,
+        _request: tonic::Request<StatusRequest>,
     ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
-        todo!()
+        Ok(Response::new(StatusResponse {
+            status: Status::Ready as i32,
+        }))
     }
 
     async fn read(
         &self,
         request: tonic::Request<ReadRequest>,
     ) -> Result<tonic::Response<ReadResponse>, tonic::Status> {
-        todo!()
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] read, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let result = self
+            .device_manager
+            .read(&request.name, request.lba, request.num_blocks)
+            .await;
+
+        let response = match result {
+            Ok(blocks) => ReadResponse {
+                success: true,
+                data: Some(from_data_blocks(blocks)),
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] read failed, err={}", request_id, e);
+                ReadResponse {
+                    success: false,
+                    data: None,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
     }
 
     async fn write(
         &self,
         request: tonic::Request<WriteRequest>,
     ) -> Result<tonic::Response<WriteResponse>, tonic::Status> {
-        todo!()
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] write, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let result = match self
+            .device_manager
+            .device_info(&request.name)
+            .and_then(|info| {
+                to_data_blocks(
+                    request.data,
+                    request.lba,
+                    request.num_blocks,
+                    info.block_size(),
+                )
+            }) {
+            Ok(blocks) => {
+                self.device_manager
+                    .write(&request.name, request.lba, request.num_blocks, blocks)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok(()) => WriteResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] write failed, err={}", request_id, e);
+                WriteResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn read_stream(
+        &self,
+        request: tonic::Request<ReadRequest>,
+    ) -> Result<tonic::Response<Self::ReadStreamStream>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] read stream, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        // Validate the whole range first, so that the stream does not fail in the middle
+        let validation = self
+            .device_manager
+            .device_info(&request.name)
+            .and_then(|info| {
+                info.check_lba_range(request.lba, request.num_blocks)?;
+                Ok(stream_chunk_blocks(info.block_size()))
+            });
+
+        let (tx, rx) = mpsc::channel(4);
+        let device_manager = self.device_manager.clone();
+        tokio::spawn(async move {
+            let chunk_blocks = match validation {
+                Ok(chunk_blocks) => chunk_blocks,
+                Err(e) => {
+                    tracing::warn!("[{}] read stream failed, err={}", request_id, e);
+                    let _ = tx
+                        .send(Ok(ReadResponse {
+                            success: false,
+                            data: None,
+                            reason: Some(e.to_string()),
+                            error_code: to_error_code(&e) as i32,
+                        }))
+                        .await;
+                    return;
+                }
+            };
+
+            let end = request.lba + request.num_blocks;
+            let mut lba = request.lba;
+            while lba < end {
+                let num_blocks = std::cmp::min(chunk_blocks, end - lba);
+                let result = device_manager.read(&request.name, lba, num_blocks).await;
+
+                let response = match result {
+                    Ok(blocks) => ReadResponse {
+                        success: true,
+                        data: Some(from_data_blocks(blocks)),
+                        reason: None,
+                        error_code: ErrorCode::NoError as i32,
+                    },
+                    Err(e) => {
+                        tracing::warn!(
+                            "[{}] read stream failed, lba={}, err={}",
+                            request_id,
+                            lba,
+                            e
+                        );
+                        ReadResponse {
+                            success: false,
+                            data: None,
+                            reason: Some(e.to_string()),
+                            error_code: to_error_code(&e) as i32,
+                        }
+                    }
+                };
+                let success = response.success;
+
+                // Stop reading when the client is gone or read failed
+                if tx.send(Ok(response)).await.is_err() || !success {
+                    return;
+                }
+                lba += num_blocks;
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn write_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<WriteRequest>>,
+    ) -> Result<tonic::Response<WriteResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let mut stream = request.into_inner();
+        tracing::debug!("[{}] write stream", request_id);
+
+        let mut result = Ok(());
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            tracing::trace!(
+                "[{}] write stream chunk, name={}, lba={}, num_blocks={}",
+                request_id,
+                chunk.name,
+                chunk.lba,
+                chunk.num_blocks
+            );
+
+            result = match self
+                .device_manager
+                .device_info(&chunk.name)
+                .and_then(|info| {
+                    to_data_blocks(chunk.data, chunk.lba, chunk.num_blocks, info.block_size())
+                }) {
+                Ok(blocks) => {
+                    self.device_manager
+                        .write(&chunk.name, chunk.lba, chunk.num_blocks, blocks)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let response = match result {
+            Ok(()) => WriteResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] write stream failed, err={}", request_id, e);
+                WriteResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn unmap(
+        &self,
+        request: tonic::Request<UnmapRequest>,
+    ) -> Result<tonic::Response<UnmapResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] unmap, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let result = self
+            .device_manager
+            .unmap(&request.name, request.lba, request.num_blocks)
+            .await;
+
+        let response = match result {
+            Ok(()) => UnmapResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] unmap failed, err={}", request_id, e);
+                UnmapResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn write_zeroes(
+        &self,
+        request: tonic::Request<WriteZeroesRequest>,
+    ) -> Result<tonic::Response<WriteZeroesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] write zeroes, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let result = self
+            .device_manager
+            .write_zeroes(&request.name, request.lba, request.num_blocks)
+            .await;
+
+        let response = match result {
+            Ok(()) => WriteZeroesResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] write zeroes failed, err={}", request_id, e);
+                WriteZeroesResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_scrub_status(
+        &self,
+        request: tonic::Request<GetScrubStatusRequest>,
+    ) -> Result<tonic::Response<GetScrubStatusResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!("[{}] get scrub status, name={}", request_id, request.name);
+
+        let response = match scrub_statuses(&self.device_manager, &request.name) {
+            Ok(statuses) => GetScrubStatusResponse {
+                success: true,
+                statuses: statuses
+                    .into_iter()
+                    .map(|(name, status)| to_scrub_status(name, status))
+                    .collect(),
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] get scrub status failed, err={}", request_id, e);
+                GetScrubStatusResponse {
+                    success: false,
+                    statuses: Vec::new(),
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn create_volume(
+        &self,
+        request: tonic::Request<CreateVolumeRequest>,
+    ) -> Result<tonic::Response<CreateVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create volume, name={}, size={}, devices={:?}",
+            request_id,
+            request.name,
+            request.size,
+            request.devices
+        );
+
+        let result = self
+            .device_manager
+            .create_volume(&request.name, request.size, &request.devices)
+            .await;
+
+        let response = match result {
+            Ok(()) => CreateVolumeResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] create volume failed, err={}", request_id, e);
+                CreateVolumeResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn delete_volume(
+        &self,
+        request: tonic::Request<DeleteVolumeRequest>,
+    ) -> Result<tonic::Response<DeleteVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete volume, name={}", request_id, request.name);
+
+        let result = self.device_manager.delete_volume(&request.name).await;
+
+        let response = match result {
+            Ok(()) => DeleteVolumeResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] delete volume failed, err={}", request_id, e);
+                DeleteVolumeResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_volumes(
+        &self,
+        _request: tonic::Request<ListVolumesRequest>,
+    ) -> Result<tonic::Response<ListVolumesResponse>, tonic::Status> {
+        let result = self.device_manager.list_volumes().await;
+
+        let response = match result {
+            Ok(volumes) => ListVolumesResponse {
+                success: true,
+                reason: None,
+                volumes: volumes.into_iter().map(to_volume).collect(),
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => ListVolumesResponse {
+                success: false,
+                reason: Some(e.to_string()),
+                volumes: Vec::new(),
+                error_code: to_error_code(&e) as i32,
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn resize_volume(
+        &self,
+        request: tonic::Request<ResizeVolumeRequest>,
+    ) -> Result<tonic::Response<ResizeVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] resize volume, name={}, size={}",
+            request_id,
+            request.name,
+            request.size
+        );
+
+        let result = self
+            .device_manager
+            .resize_volume(&request.name, request.size)
+            .await;
+
+        let response = match result {
+            Ok(()) => ResizeVolumeResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] resize volume failed, err={}", request_id, e);
+                ResizeVolumeResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn create_snapshot(
+        &self,
+        request: tonic::Request<CreateSnapshotRequest>,
+    ) -> Result<tonic::Response<CreateSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        let result = self
+            .device_manager
+            .create_snapshot(&request.name, &request.snapshot_name)
+            .await;
+
+        let response = match result {
+            Ok(()) => CreateSnapshotResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] create snapshot failed, err={}", request_id, e);
+                CreateSnapshotResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_snapshots(
+        &self,
+        request: tonic::Request<ListSnapshotsRequest>,
+    ) -> Result<tonic::Response<ListSnapshotsResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let result = match self.device_manager.device_info(&request.name) {
+            Ok(info) => self
+                .device_manager
+                .list_snapshots(&request.name)
+                .await
+                .map(|snapshots| (snapshots, info.block_size())),
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok((snapshots, block_size)) => ListSnapshotsResponse {
+                success: true,
+                reason: None,
+                snapshots: snapshots
+                    .into_iter()
+                    .map(|snapshot| to_snapshot(snapshot, block_size))
+                    .collect(),
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => ListSnapshotsResponse {
+                success: false,
+                reason: Some(e.to_string()),
+                snapshots: Vec::new(),
+                error_code: to_error_code(&e) as i32,
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn restore_snapshot(
+        &self,
+        request: tonic::Request<RestoreSnapshotRequest>,
+    ) -> Result<tonic::Response<RestoreSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] restore snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        let result = self
+            .device_manager
+            .restore_snapshot(&request.name, &request.snapshot_name)
+            .await;
+
+        let response = match result {
+            Ok(()) => RestoreSnapshotResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] restore snapshot failed, err={}", request_id, e);
+                RestoreSnapshotResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn delete_snapshot(
+        &self,
+        request: tonic::Request<DeleteSnapshotRequest>,
+    ) -> Result<tonic::Response<DeleteSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] delete snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        let result = self
+            .device_manager
+            .delete_snapshot(&request.name, &request.snapshot_name)
+            .await;
+
+        let response = match result {
+            Ok(()) => DeleteSnapshotResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] delete snapshot failed, err={}", request_id, e);
+                DeleteSnapshotResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn clone_device(
+        &self,
+        request: tonic::Request<CloneDeviceRequest>,
+    ) -> Result<tonic::Response<CloneDeviceResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] clone device, source={}, new_name={}, snapshot_name={:?}",
+            request_id,
+            request.source,
+            request.new_name,
+            request.snapshot_name
+        );
+
+        let result = self
+            .device_manager
+            .clone_device(
+                &request.source,
+                &request.new_name,
+                request.snapshot_name.as_deref(),
+            )
+            .await;
+
+        let response = match result {
+            Ok(()) => CloneDeviceResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] clone device failed, err={}", request_id, e);
+                CloneDeviceResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn create_mirror(
+        &self,
+        request: tonic::Request<CreateMirrorRequest>,
+    ) -> Result<tonic::Response<CreateMirrorResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create mirror, name={}, devices={:?}",
+            request_id,
+            request.name,
+            request.devices
+        );
+
+        let result = self
+            .device_manager
+            .create_mirror(&request.name, &request.devices)
+            .await;
+
+        let response = match result {
+            Ok(()) => CreateMirrorResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] create mirror failed, err={}", request_id, e);
+                CreateMirrorResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn delete_mirror(
+        &self,
+        request: tonic::Request<DeleteMirrorRequest>,
+    ) -> Result<tonic::Response<DeleteMirrorResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete mirror, name={}", request_id, request.name);
+
+        let result = self.device_manager.delete_mirror(&request.name).await;
+
+        let response = match result {
+            Ok(()) => DeleteMirrorResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] delete mirror failed, err={}", request_id, e);
+                DeleteMirrorResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_mirrors(
+        &self,
+        _request: tonic::Request<ListMirrorsRequest>,
+    ) -> Result<tonic::Response<ListMirrorsResponse>, tonic::Status> {
+        let result = self.device_manager.list_mirrors();
+
+        let response = match result {
+            Ok(mirrors) => ListMirrorsResponse {
+                success: true,
+                reason: None,
+                mirrors: mirrors.into_iter().map(to_mirror).collect(),
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => ListMirrorsResponse {
+                success: false,
+                reason: Some(e.to_string()),
+                mirrors: Vec::new(),
+                error_code: to_error_code(&e) as i32,
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn replace_mirror_member(
+        &self,
+        request: tonic::Request<ReplaceMirrorMemberRequest>,
+    ) -> Result<tonic::Response<ReplaceMirrorMemberResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] replace mirror member, name={}, old_device={}, new_device={}",
+            request_id,
+            request.name,
+            request.old_device,
+            request.new_device
+        );
+
+        let result = self
+            .device_manager
+            .replace_mirror_member(&request.name, &request.old_device, &request.new_device)
+            .await;
+
+        let response = match result {
+            Ok(()) => ReplaceMirrorMemberResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] replace mirror member failed, err={}", request_id, e);
+                ReplaceMirrorMemberResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn create_array(
+        &self,
+        request: tonic::Request<CreateArrayRequest>,
+    ) -> Result<tonic::Response<CreateArrayResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create array, name={}, level={}, stripe_size={}, devices={:?}",
+            request_id,
+            request.name,
+            request.level,
+            request.stripe_size,
+            request.devices
+        );
+
+        let result = match to_raid_level(request.level) {
+            Ok(level) => {
+                self.device_manager
+                    .create_array(&request.name, level, request.stripe_size, &request.devices)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok(()) => CreateArrayResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] create array failed, err={}", request_id, e);
+                CreateArrayResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn delete_array(
+        &self,
+        request: tonic::Request<DeleteArrayRequest>,
+    ) -> Result<tonic::Response<DeleteArrayResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete array, name={}", request_id, request.name);
+
+        let result = self.device_manager.delete_array(&request.name).await;
+
+        let response = match result {
+            Ok(()) => DeleteArrayResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] delete array failed, err={}", request_id, e);
+                DeleteArrayResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn list_arrays(
+        &self,
+        _request: tonic::Request<ListArraysRequest>,
+    ) -> Result<tonic::Response<ListArraysResponse>, tonic::Status> {
+        let result = self.device_manager.list_arrays();
+
+        let response = match result {
+            Ok(arrays) => ListArraysResponse {
+                success: true,
+                reason: None,
+                arrays: arrays.into_iter().map(to_array).collect(),
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => ListArraysResponse {
+                success: false,
+                reason: Some(e.to_string()),
+                arrays: Vec::new(),
+                error_code: to_error_code(&e) as i32,
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn replace_array_member(
+        &self,
+        request: tonic::Request<ReplaceArrayMemberRequest>,
+    ) -> Result<tonic::Response<ReplaceArrayMemberResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] replace array member, name={}, old_device={}, new_device={}",
+            request_id,
+            request.name,
+            request.old_device,
+            request.new_device
+        );
+
+        let result = self
+            .device_manager
+            .replace_array_member(&request.name, &request.old_device, &request.new_device)
+            .await;
+
+        let response = match result {
+            Ok(()) => ReplaceArrayMemberResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] replace array member failed, err={}", request_id, e);
+                ReplaceArrayMemberResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
     }
 
     async fn create_fake_device(
         &self,
         request: tonic::Request<CreateFakeDeviceRequest>,
     ) -> Result<tonic::Response<CreateFakeDeviceResponse>, tonic::Status> {
-        todo!()
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        let block_size = requested_block_size(request.block_size);
+        tracing::info!(
+            "[{}] create fake device, name={}, size={}, block_size={}",
+            request_id,
+            request.name,
+            request.size,
+            block_size
+        );
+
+        let result = match to_performance_profile(request.performance_profile) {
+            Ok(performance_profile) => {
+                self.device_manager
+                    .create_fake_device_with_profile(
+                        &request.name,
+                        request.size,
+                        block_size,
+                        performance_profile,
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok(()) => CreateFakeDeviceResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] create fake device failed, err={}", request_id, e);
+                CreateFakeDeviceResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
     }
 
     async fn delete_fake_device(
         &self,
         request: tonic::Request<DeleteFakeDeviceRequest>,
     ) -> Result<tonic::Response<DeleteFakeDeviceResponse>, tonic::Status> {
-        todo!()
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete fake device, name={}", request_id, request.name);
+
+        let result = self.device_manager.delete_fake_device(&request.name).await;
+
+        let response = match result {
+            Ok(()) => DeleteFakeDeviceResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] delete fake device failed, err={}", request_id, e);
+                DeleteFakeDeviceResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
     }
 
     async fn list_fake_devices(
         &self,
-        request: tonic::Request<ListFakeDevicesRequest>,
+        _request: tonic::Request<ListFakeDevicesRequest>,
     ) -> Result<tonic::Response<ListFakeDevicesResponse>, tonic::Status> {
-        todo!()
+        let result = self.device_manager.list_fake_devices();
+
+        let response = match result {
+            Ok(devices) => ListFakeDevicesResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+                device_list: devices
+                    .into_iter()
+                    .map(|(name, size, block_size)| FakeDevice {
+                        name,
+                        size,
+                        block_size,
+                    })
+                    .collect(),
+            },
+            Err(e) => ListFakeDevicesResponse {
+                success: false,
+                reason: Some(e.to_string()),
+                error_code: to_error_code(&e) as i32,
+                device_list: Vec::new(),
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn set_fault_rules(
+        &self,
+        request: tonic::Request<SetFaultRulesRequest>,
+    ) -> Result<tonic::Response<SetFaultRulesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] set fault rules, name={}, rules={:?}",
+            request_id,
+            request.name,
+            request.rules
+        );
+
+        let result = to_fault_rules(request.rules)
+            .and_then(|rules| self.device_manager.set_fault_rules(&request.name, rules));
+
+        let response = match result {
+            Ok(()) => SetFaultRulesResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] set fault rules failed, err={}", request_id, e);
+                SetFaultRulesResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn clear_fault_rules(
+        &self,
+        request: tonic::Request<ClearFaultRulesRequest>,
+    ) -> Result<tonic::Response<ClearFaultRulesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] clear fault rules, name={}", request_id, request.name);
+
+        let result = self
+            .device_manager
+            .set_fault_rules(&request.name, DeviceFaultRules::default());
+
+        let response = match result {
+            Ok(()) => ClearFaultRulesResponse {
+                success: true,
+                reason: None,
+                error_code: ErrorCode::NoError as i32,
+            },
+            Err(e) => {
+                tracing::warn!("[{}] clear fault rules failed, err={}", request_id, e);
+                ClearFaultRulesResponse {
+                    success: false,
+                    reason: Some(e.to_string()),
+                    error_code: to_error_code(&e) as i32,
+                }
+            }
+        };
+        Ok(Response::new(response))
     }
 }
 
@@ -79,20 +1488,56 @@ mod tests {
     use tracing_test::traced_test;
 
     use crate::{
-        block_device_common::data_type::BLOCK_SIZE, config::DeviceConfig,
+        async_block_device::protected_device::ProtectionMode, config::DeviceConfig,
         grpc_server::ministore_proto::mini_service_client::MiniServiceClient,
         utils::humansize_to_integer,
     };
 
     use super::*;
 
-    fn test_device_manager() -> DeviceManager {
+    /// Each test uses its own location, so that the device registries do not conflict
+    async fn test_device_manager(testname: &str) -> DeviceManager {
         let config = DeviceConfig {
             use_fake: true,
-            fake_device_location: "fakes".to_string(),
+            fake_device_location: format!("fakes/{}", testname),
             fake_device_type: "SimpleFake".to_string(),
+            protection_mode: ProtectionMode::None,
+            list: Vec::new(),
+            fault_injection: false,
+            performance_profile: DevicePerformanceProfile::default(),
         };
-        DeviceManager::new(&config).expect("Failed to create device manager")
+        DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager")
+    }
+
+    #[test]
+    fn errors_should_be_mapped_to_grpc_status_codes() {
+        let status: tonic::Status = MinistoreError::DeviceNotFound {
+            name: "nvme0n1".to_string(),
+        }
+        .into();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let status: tonic::Status = MinistoreError::InvalidLbaRange {
+            lba: 10,
+            num_blocks: 10,
+            device_num_blocks: 10,
+        }
+        .into();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+        let status: tonic::Status = MinistoreError::invalid_argument("No data provided").into();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("No data provided"));
+
+        let status: tonic::Status = MinistoreError::ChecksumMismatch {
+            lba: 10,
+            expected: 0,
+            actual: 1,
+        }
+        .into();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
     }
 
     /// Be sure to use different port for each test, so that all tests can be executed in parallel.
@@ -100,10 +1545,12 @@ mod tests {
     #[traced_test]
     async fn server_should_response_with_ready_when_started() {
         let addr = "127.0.0.1:8080";
-        let addr_for_client = format!("http://{}", addr.clone());
+        let addr_for_client = format!("http://{}", addr);
 
         let start_server = tokio::spawn(async move {
-            let grpc_server = GrpcServer::new(test_device_manager());
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_response_with_ready_when_started").await,
+            );
             start_grpc_server(addr, grpc_server)
                 .await
                 .expect("Failed to start grpc server");
@@ -130,10 +1577,12 @@ mod tests {
     #[traced_test]
     async fn server_should_be_able_to_create_and_delete_fake_device() {
         let addr = "127.0.0.1:8081";
-        let addr_for_client = format!("http://{}", addr.clone());
+        let addr_for_client = format!("http://{}", addr);
 
         let start_server = tokio::spawn(async move {
-            let grpc_server = GrpcServer::new(test_device_manager());
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_be_able_to_create_and_delete_fake_device").await,
+            );
             start_grpc_server(addr, grpc_server)
                 .await
                 .expect("Failed to start grpc server");
@@ -149,13 +1598,15 @@ mod tests {
             let request = tonic::Request::new(CreateFakeDeviceRequest {
                 name: device_name.clone(),
                 size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
             });
             let response = client
                 .create_fake_device(request)
                 .await
                 .expect("Failed to create fake device");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             let request = tonic::Request::new(ListFakeDevicesRequest {});
             let response = client
@@ -164,11 +1615,11 @@ mod tests {
                 .expect("Failed to get response");
             let response = response.into_inner();
 
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
             assert_eq!(response.device_list.len(), 1);
-            assert_eq!(response.device_list.get(0).unwrap().name, device_name);
+            assert_eq!(response.device_list.first().unwrap().name, device_name);
             assert_eq!(
-                response.device_list.get(0).unwrap().size,
+                response.device_list.first().unwrap().size,
                 humansize_to_integer("1M").unwrap()
             );
 
@@ -181,7 +1632,7 @@ mod tests {
                 .await
                 .expect("Failed to delete fake device");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             let request = tonic::Request::new(ListFakeDevicesRequest {});
             let response = client
@@ -190,7 +1641,7 @@ mod tests {
                 .expect("Failed to get response");
             let response = response.into_inner();
 
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
             assert_eq!(response.device_list.len(), 0);
         });
 
@@ -202,10 +1653,12 @@ mod tests {
     #[traced_test]
     async fn server_should_be_able_to_read_write_fake_device() {
         let addr = "127.0.0.1:8082";
-        let addr_for_client = format!("http://{}", addr.clone());
+        let addr_for_client = format!("http://{}", addr);
 
         let start_server = tokio::spawn(async move {
-            let grpc_server = GrpcServer::new(test_device_manager());
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_be_able_to_read_write_fake_device").await,
+            );
             start_grpc_server(addr, grpc_server)
                 .await
                 .expect("Failed to start grpc server");
@@ -221,23 +1674,32 @@ mod tests {
             let request = tonic::Request::new(CreateFakeDeviceRequest {
                 name: device_name.clone(),
                 size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
             });
             let response = client
                 .create_fake_device(request)
                 .await
                 .expect("Failed to create fake device");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             // Write data to the device
-            let write_data = ministore_proto::Data {
+            let mut write_data = ministore_proto::Data {
                 data: vec![
-                    vec![0xA as u8; BLOCK_SIZE],
-                    vec![0xB as u8; BLOCK_SIZE],
-                    vec![0xC as u8; BLOCK_SIZE],
-                    vec![0xD as u8; BLOCK_SIZE],
+                    Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE]),
+                    Bytes::from(vec![0xB_u8; DEFAULT_BLOCK_SIZE]),
+                    Bytes::from(vec![0xC_u8; DEFAULT_BLOCK_SIZE]),
+                    Bytes::from(vec![0xD_u8; DEFAULT_BLOCK_SIZE]),
                 ],
+                checksums: Vec::new(),
             };
+            // Checksums are verified by the server and returned on read
+            write_data.checksums = write_data
+                .data
+                .iter()
+                .map(|data| crc32c::crc32c(data))
+                .collect();
             let request = tonic::Request::new(WriteRequest {
                 name: "server_should_be_able_to_read_write_fake_device".to_string(),
                 lba: 10,
@@ -246,7 +1708,7 @@ mod tests {
             });
             let response = client.write(request).await.expect("Failed to write data");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             // Read data from the device
             let request = tonic::Request::new(ReadRequest {
@@ -256,7 +1718,7 @@ mod tests {
             });
             let response = client.read(request).await.expect("Failed to read data");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             // Verify read data
             assert_eq!(response.data.unwrap(), write_data);
@@ -271,7 +1733,7 @@ mod tests {
                 .expect("Failed to delete device");
             let response = response.into_inner();
 
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
         });
 
         test.await.unwrap();
@@ -282,10 +1744,15 @@ mod tests {
     #[traced_test]
     async fn server_should_reply_with_error_when_invalid_data_provided_for_write() {
         let addr = "127.0.0.1:8083";
-        let addr_for_client = format!("http://{}", addr.clone());
+        let addr_for_client = format!("http://{}", addr);
 
         let start_server = tokio::spawn(async move {
-            let grpc_server = GrpcServer::new(test_device_manager());
+            let grpc_server = GrpcServer::new(
+                test_device_manager(
+                    "server_should_reply_with_error_when_invalid_data_provided_for_write",
+                )
+                .await,
+            );
             start_grpc_server(addr, grpc_server)
                 .await
                 .expect("Failed to start grpc server");
@@ -301,13 +1768,15 @@ mod tests {
                 name: "server_should_reply_with_error_when_invalid_data_provided_for_write"
                     .to_string(),
                 size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
             });
             let response = client
                 .create_fake_device(request)
                 .await
                 .expect("Failed to create fake device");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
 
             // test 1. write request without data
             let invalid_request = tonic::Request::new(WriteRequest {
@@ -322,11 +1791,13 @@ mod tests {
                 .await
                 .expect("Failed to request write");
             let response = response.into_inner();
-            assert_eq!(response.success, false);
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::InvalidArgument as i32);
 
             // test 2. write request with too-small data (smaller than the block size)
             let invalid_write_data = ministore_proto::Data {
-                data: vec![vec![0xA as u8; 1024]],
+                data: vec![Bytes::from(vec![0xA_u8; 1024])],
+                checksums: Vec::new(),
             };
             let invalid_request = tonic::Request::new(WriteRequest {
                 name: "server_should_reply_with_error_when_invalid_data_provided_for_write"
@@ -340,11 +1811,32 @@ mod tests {
                 .await
                 .expect("Failed to request write");
             let response = response.into_inner();
-            assert_eq!(response.success, false);
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::InvalidArgument as i32);
 
-            // Delete device for wrapup
-            let request = tonic::Request::new(DeleteFakeDeviceRequest {
-                name: "server_should_reply_with_error_when_invalid_data_provided_for_write"
+            // test 3. write request with a wrong checksum
+            let invalid_write_data = ministore_proto::Data {
+                data: vec![Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE])],
+                checksums: vec![crc32c::crc32c(&[0xB_u8; DEFAULT_BLOCK_SIZE])],
+            };
+            let invalid_request = tonic::Request::new(WriteRequest {
+                name: "server_should_reply_with_error_when_invalid_data_provided_for_write"
+                    .to_string(),
+                lba: 0,
+                num_blocks: 1,
+                data: Some(invalid_write_data),
+            });
+            let response = client
+                .write(invalid_request)
+                .await
+                .expect("Failed to request write");
+            let response = response.into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::ChecksumMismatch as i32);
+
+            // Delete device for wrapup
+            let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                name: "server_should_reply_with_error_when_invalid_data_provided_for_write"
                     .to_string(),
             });
             let response = client
@@ -352,7 +1844,1462 @@ mod tests {
                 .await
                 .expect("Failed to delete device");
             let response = response.into_inner();
-            assert_eq!(response.success, true, "{:?}", response);
+            assert!(response.success, "{:?}", response);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_be_able_to_unmap_fake_device() {
+        let addr = "127.0.0.1:8084";
+        let addr_for_client = format!("http://{}", addr);
+
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_be_able_to_unmap_fake_device").await,
+            );
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            // Create device for test
+            let device_name = "server_should_be_able_to_unmap_fake_device".to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Write data and unmap some of them
+            let request = tonic::Request::new(WriteRequest {
+                name: device_name.clone(),
+                lba: 0,
+                num_blocks: 2,
+                data: Some(ministore_proto::Data {
+                    data: vec![
+                        Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE]),
+                        Bytes::from(vec![0xB_u8; DEFAULT_BLOCK_SIZE]),
+                    ],
+                    checksums: Vec::new(),
+                }),
+            });
+            let response = client.write(request).await.expect("Failed to write data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(UnmapRequest {
+                name: device_name.clone(),
+                lba: 1,
+                num_blocks: 1,
+            });
+            let response = client.unmap(request).await.expect("Failed to unmap");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Unmapped block should be read as unmap data
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 0,
+                num_blocks: 2,
+            });
+            let response = client.read(request).await.expect("Failed to read data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(
+                response.data.unwrap().data,
+                vec![
+                    Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE]),
+                    Bytes::from(vec![0xFF_u8; DEFAULT_BLOCK_SIZE])
+                ]
+            );
+
+            // Unmap with invalid range should fail
+            let request = tonic::Request::new(UnmapRequest {
+                name: device_name.clone(),
+                lba: 0,
+                num_blocks: 0,
+            });
+            let response = client.unmap(request).await.expect("Failed to unmap");
+            assert!(!response.into_inner().success);
+
+            // Delete device for wrapup
+            let request = tonic::Request::new(DeleteFakeDeviceRequest { name: device_name });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_be_able_to_write_zeroes_to_fake_device() {
+        let addr = "127.0.0.1:8085";
+        let addr_for_client = format!("http://{}", addr);
+
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_be_able_to_write_zeroes_to_fake_device").await,
+            );
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            // Create device for test
+            let device_name = "server_should_be_able_to_write_zeroes_to_fake_device".to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Write zeroes without data
+            let request = tonic::Request::new(WriteZeroesRequest {
+                name: device_name.clone(),
+                lba: 10,
+                num_blocks: 2,
+            });
+            let response = client
+                .write_zeroes(request)
+                .await
+                .expect("Failed to write zeroes");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 10,
+                num_blocks: 2,
+            });
+            let response = client.read(request).await.expect("Failed to read data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(
+                response.data.unwrap().data,
+                vec![Bytes::from(vec![0_u8; DEFAULT_BLOCK_SIZE]); 2]
+            );
+
+            // Write zeroes to a device which does not exist should fail
+            let request = tonic::Request::new(WriteZeroesRequest {
+                name: "no_such_device".to_string(),
+                lba: 0,
+                num_blocks: 1,
+            });
+            let response = client
+                .write_zeroes(request)
+                .await
+                .expect("Failed to write zeroes");
+            assert!(!response.into_inner().success);
+
+            // Delete device for wrapup
+            let request = tonic::Request::new(DeleteFakeDeviceRequest { name: device_name });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_be_able_to_read_write_fake_device_with_stream() {
+        let addr = "127.0.0.1:8086";
+        let addr_for_client = format!("http://{}", addr);
+
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_be_able_to_read_write_fake_device_with_stream")
+                    .await,
+            );
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            // Create device for test
+            let device_name = "server_should_be_able_to_read_write_fake_device_with_stream";
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.to_string(),
+                size: humansize_to_integer("4M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Write data with chunks
+            let chunk_blocks = stream_chunk_blocks(DEFAULT_BLOCK_SIZE as u64);
+            let num_blocks = chunk_blocks * 2 + 10;
+            let blocks: Vec<Bytes> = (0..num_blocks)
+                .map(|lba| Bytes::from(vec![lba as u8; DEFAULT_BLOCK_SIZE]))
+                .collect();
+            let chunks: Vec<WriteRequest> = blocks
+                .chunks(chunk_blocks as usize)
+                .enumerate()
+                .map(|(index, chunk)| WriteRequest {
+                    name: device_name.to_string(),
+                    lba: 5 + index as u64 * chunk_blocks,
+                    num_blocks: chunk.len() as u64,
+                    data: Some(ministore_proto::Data {
+                        data: chunk.to_vec(),
+                        checksums: Vec::new(),
+                    }),
+                })
+                .collect();
+            let response = client
+                .write_stream(tokio_stream::iter(chunks))
+                .await
+                .expect("Failed to write data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Read data with stream
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.to_string(),
+                lba: 5,
+                num_blocks,
+            });
+            let mut stream = client
+                .read_stream(request)
+                .await
+                .expect("Failed to read data")
+                .into_inner();
+            let mut read_blocks = Vec::new();
+            let mut num_messages = 0;
+            while let Some(response) = stream.next().await {
+                let response = response.expect("Failed to receive data");
+                assert!(response.success, "{:?}", response.reason);
+                read_blocks.extend(response.data.unwrap().data);
+                num_messages += 1;
+            }
+            assert_eq!(num_messages, 3);
+            assert_eq!(read_blocks, blocks);
+
+            // Read stream with invalid range should fail without any data
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.to_string(),
+                lba: 1000,
+                num_blocks: 100,
+            });
+            let mut stream = client
+                .read_stream(request)
+                .await
+                .expect("Failed to read data")
+                .into_inner();
+            let response = stream
+                .next()
+                .await
+                .expect("Stream should have a response")
+                .expect("Failed to receive data");
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::InvalidLbaRange as i32);
+            assert!(stream.next().await.is_none());
+
+            // Delete device for wrapup
+            let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                name: device_name.to_string(),
+            });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_use_block_size_of_each_device() {
+        let addr = "127.0.0.1:8089";
+        let addr_for_client = format!("http://{}", addr);
+
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_use_block_size_of_each_device").await,
+            );
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            // Invalid block size
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: "invalid".to_string(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 1000,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device")
+                .into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::InvalidArgument as i32);
+
+            let devices = [("small", 512_u64), ("large", 16 * 1024)];
+            for (device_name, block_size) in devices {
+                let request = tonic::Request::new(CreateFakeDeviceRequest {
+                    name: device_name.to_string(),
+                    size: block_size * 100,
+                    block_size,
+                    performance_profile: None,
+                });
+                let response = client
+                    .create_fake_device(request)
+                    .await
+                    .expect("Failed to create fake device")
+                    .into_inner();
+                assert!(response.success, "{:?}", response);
+            }
+
+            let response = client
+                .list_fake_devices(tonic::Request::new(ListFakeDevicesRequest {}))
+                .await
+                .expect("Failed to list fake devices")
+                .into_inner();
+            assert_eq!(
+                response.device_list,
+                vec![
+                    FakeDevice {
+                        name: "large".to_string(),
+                        size: 16 * 1024 * 100,
+                        block_size: 16 * 1024,
+                    },
+                    FakeDevice {
+                        name: "small".to_string(),
+                        size: 512 * 100,
+                        block_size: 512,
+                    },
+                ]
+            );
+
+            for (device_name, block_size) in devices {
+                let block_size = block_size as usize;
+
+                // Data of the default block size should be rejected
+                let request = tonic::Request::new(WriteRequest {
+                    name: device_name.to_string(),
+                    lba: 0,
+                    num_blocks: 1,
+                    data: Some(ministore_proto::Data {
+                        data: vec![Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE])],
+                        checksums: Vec::new(),
+                    }),
+                });
+                let response = client
+                    .write(request)
+                    .await
+                    .expect("Failed to write data")
+                    .into_inner();
+                assert!(!response.success);
+                assert_eq!(response.error_code, ErrorCode::InvalidArgument as i32);
+
+                let blocks: Vec<Bytes> = (0..100)
+                    .map(|lba| Bytes::from(vec![lba as u8; block_size]))
+                    .collect();
+                let request = tonic::Request::new(WriteRequest {
+                    name: device_name.to_string(),
+                    lba: 0,
+                    num_blocks: 100,
+                    data: Some(ministore_proto::Data {
+                        data: blocks.clone(),
+                        checksums: Vec::new(),
+                    }),
+                });
+                let response = client
+                    .write(request)
+                    .await
+                    .expect("Failed to write data")
+                    .into_inner();
+                assert!(response.success, "{:?}", response);
+
+                // Each message of the stream should have the same size in bytes
+                let request = tonic::Request::new(ReadRequest {
+                    name: device_name.to_string(),
+                    lba: 0,
+                    num_blocks: 100,
+                });
+                let mut stream = client
+                    .read_stream(request)
+                    .await
+                    .expect("Failed to read data")
+                    .into_inner();
+                let mut read_blocks = Vec::new();
+                let mut num_messages = 0;
+                while let Some(response) = stream.next().await {
+                    let response = response.expect("Failed to receive data");
+                    assert!(response.success, "{:?}", response.reason);
+                    read_blocks.extend(response.data.unwrap().data);
+                    num_messages += 1;
+                }
+                let chunk_blocks = stream_chunk_blocks(block_size as u64);
+                assert_eq!(num_messages, 100_u64.div_ceil(chunk_blocks));
+                assert_eq!(read_blocks, blocks);
+
+                let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                    name: device_name.to_string(),
+                });
+                let response = client
+                    .delete_fake_device(request)
+                    .await
+                    .expect("Failed to delete device")
+                    .into_inner();
+                assert!(response.success, "{:?}", response);
+            }
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_reply_with_scrub_status() {
+        let addr = "127.0.0.1:8088";
+        let addr_for_client = format!("http://{}", addr);
+
+        let device_manager =
+            Arc::new(test_device_manager("server_should_reply_with_scrub_status").await);
+        for device_name in ["scrubbed", "not_scrubbed"] {
+            device_manager
+                .create_fake_device(
+                    &device_name.to_string(),
+                    humansize_to_integer("1M").unwrap(),
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .expect("Failed to create fake device");
+        }
+        let scrubber = scrubber::Scrubber::new(
+            device_manager.clone(),
+            crate::config::ScrubConfig {
+                enabled: true,
+                interval_secs: 0,
+                blocks_per_second: 0,
+            },
+        );
+        scrubber.scrub_all().await;
+        device_manager
+            .delete_fake_device(&"not_scrubbed".to_string())
+            .await
+            .unwrap();
+        device_manager
+            .create_fake_device(
+                &"not_scrubbed".to_string(),
+                humansize_to_integer("1M").unwrap(),
+                DEFAULT_BLOCK_SIZE as u64,
+            )
+            .await
+            .unwrap();
+
+        let server_device_manager = device_manager.clone();
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(server_device_manager);
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            // Every device
+            let request = tonic::Request::new(GetScrubStatusRequest {
+                name: String::new(),
+            });
+            let response = client
+                .get_scrub_status(request)
+                .await
+                .expect("Failed to get scrub status");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.statuses.len(), 2);
+
+            let not_scrubbed = &response.statuses[0];
+            assert_eq!(not_scrubbed.name, "not_scrubbed");
+            assert_eq!(not_scrubbed.completed_passes, 0);
+            assert_eq!(not_scrubbed.last_completed_at, None);
+
+            let scrubbed = &response.statuses[1];
+            assert_eq!(scrubbed.name, "scrubbed");
+            assert_eq!(scrubbed.state, ScrubState::Idle as i32);
+            assert_eq!(scrubbed.completed_passes, 1);
+            assert_eq!(scrubbed.scrubbed_blocks, 256);
+            assert_eq!(scrubbed.total_blocks, 256);
+            assert!(scrubbed.last_completed_at.is_some());
+            assert!(scrubbed.bad_ranges.is_empty());
+
+            // A device
+            let request = tonic::Request::new(GetScrubStatusRequest {
+                name: "scrubbed".to_string(),
+            });
+            let response = client
+                .get_scrub_status(request)
+                .await
+                .expect("Failed to get scrub status");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.statuses, vec![scrubbed.clone()]);
+
+            // Unknown device
+            let request = tonic::Request::new(GetScrubStatusRequest {
+                name: "unknown".to_string(),
+            });
+            let response = client
+                .get_scrub_status(request)
+                .await
+                .expect("Failed to get scrub status");
+            let response = response.into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::DeviceNotFound as i32);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+
+        for device_name in ["scrubbed", "not_scrubbed"] {
+            device_manager
+                .delete_fake_device(&device_name.to_string())
+                .await
+                .expect("Failed to delete device");
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_set_and_clear_fault_rules() {
+        let addr = "127.0.0.1:8090";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_set_and_clear_fault_rules";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: true,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let device_name = testname.to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            assert!(response.into_inner().success);
+
+            let read_request = || {
+                tonic::Request::new(ReadRequest {
+                    name: device_name.clone(),
+                    lba: 0,
+                    num_blocks: 4,
+                })
+            };
+            let request = tonic::Request::new(SetFaultRulesRequest {
+                name: device_name.clone(),
+                rules: Some(FaultRules {
+                    read_error_ranges: vec![LbaRange {
+                        lba: 3,
+                        num_blocks: 1,
+                    }],
+                    ..Default::default()
+                }),
+            });
+            let response = client
+                .set_fault_rules(request)
+                .await
+                .expect("Failed to set fault rules");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let response = client.read(read_request()).await.unwrap().into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::IoError as i32);
+
+            let request = tonic::Request::new(ClearFaultRulesRequest {
+                name: device_name.clone(),
+            });
+            let response = client
+                .clear_fault_rules(request)
+                .await
+                .expect("Failed to clear fault rules");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let response = client.read(read_request()).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Invalid rules are rejected
+            let request = tonic::Request::new(SetFaultRulesRequest {
+                name: device_name.clone(),
+                rules: Some(FaultRules {
+                    error_probability: 2.0,
+                    ..Default::default()
+                }),
+            });
+            let response = client.set_fault_rules(request).await.unwrap().into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::InvalidArgument as i32);
+
+            let request = tonic::Request::new(DeleteFakeDeviceRequest { name: device_name });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            assert!(response.into_inner().success);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_read_and_write_volumes_by_name() {
+        let addr = "127.0.0.1:8091";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_read_and_write_volumes_by_name";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: false,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let member_name = format!("{}_member", testname);
+            let volume_name = testname.to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: member_name.clone(),
+                size: humansize_to_integer("2M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            assert!(response.into_inner().success);
+
+            // Volume is larger than its member
+            let request = tonic::Request::new(CreateVolumeRequest {
+                name: volume_name.clone(),
+                size: humansize_to_integer("1G").unwrap(),
+                devices: vec![member_name.clone()],
+            });
+            let response = client
+                .create_volume(request)
+                .await
+                .expect("Failed to create volume");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let data = Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE]);
+            let write_data = ministore_proto::Data {
+                checksums: vec![crc32c::crc32c(&data)],
+                data: vec![data],
+            };
+            let lba = humansize_to_integer("512M").unwrap() / DEFAULT_BLOCK_SIZE as u64;
+            let request = tonic::Request::new(WriteRequest {
+                name: volume_name.clone(),
+                lba,
+                num_blocks: 1,
+                data: Some(write_data.clone()),
+            });
+            let response = client.write(request).await.expect("Failed to write data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(ReadRequest {
+                name: volume_name.clone(),
+                lba,
+                num_blocks: 1,
+            });
+            let response = client.read(request).await.expect("Failed to read data");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.data.unwrap(), write_data);
+
+            let response = client
+                .list_volumes(tonic::Request::new(ListVolumesRequest {}))
+                .await
+                .expect("Failed to list volumes")
+                .into_inner();
+            assert_eq!(
+                response.volumes,
+                vec![Volume {
+                    name: volume_name.clone(),
+                    size: humansize_to_integer("1G").unwrap(),
+                    block_size: DEFAULT_BLOCK_SIZE as u64,
+                    allocated_size: humansize_to_integer("1M").unwrap(),
+                    devices: vec![member_name.clone()],
+                }]
+            );
+
+            // Member cannot be deleted before the volume
+            let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                name: member_name.clone(),
+            });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::DeviceInUse as i32);
+
+            let request = tonic::Request::new(ResizeVolumeRequest {
+                name: volume_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+            });
+            let response = client
+                .resize_volume(request)
+                .await
+                .expect("Failed to resize volume");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(ReadRequest {
+                name: volume_name.clone(),
+                lba,
+                num_blocks: 1,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert_eq!(response.error_code, ErrorCode::InvalidLbaRange as i32);
+
+            let request = tonic::Request::new(DeleteVolumeRequest { name: volume_name });
+            let response = client
+                .delete_volume(request)
+                .await
+                .expect("Failed to delete volume");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(DeleteFakeDeviceRequest { name: member_name });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            assert!(response.into_inner().success);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_restore_snapshots() {
+        let addr = "127.0.0.1:8092";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_restore_snapshots";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: false,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let device_name = testname.to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            assert!(response.into_inner().success);
+
+            let data = |value: u8| ministore_proto::Data {
+                data: vec![Bytes::from(vec![value; DEFAULT_BLOCK_SIZE])],
+                checksums: Vec::new(),
+            };
+            let write_request = |value: u8| {
+                tonic::Request::new(WriteRequest {
+                    name: device_name.clone(),
+                    lba: 0,
+                    num_blocks: 1,
+                    data: Some(data(value)),
+                })
+            };
+            let response = client.write(write_request(0xA)).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(CreateSnapshotRequest {
+                name: device_name.clone(),
+                snapshot_name: "golden".to_string(),
+            });
+            let response = client
+                .create_snapshot(request)
+                .await
+                .expect("Failed to create snapshot");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let response = client.write(write_request(0xB)).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(ListSnapshotsRequest {
+                name: device_name.clone(),
+            });
+            let response = client
+                .list_snapshots(request)
+                .await
+                .expect("Failed to list snapshots")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.snapshots.len(), 1);
+            assert_eq!(response.snapshots[0].name, "golden");
+            assert_eq!(
+                response.snapshots[0].preserved_size,
+                DEFAULT_BLOCK_SIZE as u64
+            );
+
+            let request = tonic::Request::new(RestoreSnapshotRequest {
+                name: device_name.clone(),
+                snapshot_name: "golden".to_string(),
+            });
+            let response = client
+                .restore_snapshot(request)
+                .await
+                .expect("Failed to restore snapshot");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 0,
+                num_blocks: 1,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert_eq!(response.data.unwrap().data, data(0xA).data);
+
+            let request = tonic::Request::new(DeleteSnapshotRequest {
+                name: device_name.clone(),
+                snapshot_name: "golden".to_string(),
+            });
+            let response = client
+                .delete_snapshot(request)
+                .await
+                .expect("Failed to delete snapshot");
+            let response = response.into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let request = tonic::Request::new(RestoreSnapshotRequest {
+                name: device_name.clone(),
+                snapshot_name: "golden".to_string(),
+            });
+            let response = client.restore_snapshot(request).await.unwrap().into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::SnapshotNotFound as i32);
+
+            let request = tonic::Request::new(DeleteFakeDeviceRequest { name: device_name });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+            assert!(response.into_inner().success);
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_list_clones_as_fake_devices() {
+        let addr = "127.0.0.1:8093";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_list_clones_as_fake_devices";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: false,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let golden_name = format!("{}_golden", testname);
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: golden_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let response = client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+            assert!(response.into_inner().success);
+
+            let data = |value: u8| ministore_proto::Data {
+                data: vec![Bytes::from(vec![value; DEFAULT_BLOCK_SIZE])],
+                checksums: Vec::new(),
+            };
+            let write_request = |name: &String, value: u8| {
+                tonic::Request::new(WriteRequest {
+                    name: name.clone(),
+                    lba: 0,
+                    num_blocks: 1,
+                    data: Some(data(value)),
+                })
+            };
+            let read_request = |name: &String| {
+                tonic::Request::new(ReadRequest {
+                    name: name.clone(),
+                    lba: 0,
+                    num_blocks: 1,
+                })
+            };
+            let response = client
+                .write(write_request(&golden_name, 0xA))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let clone_names: Vec<String> = (0..2)
+                .map(|index| format!("{}_clone{}", testname, index))
+                .collect();
+            for clone_name in &clone_names {
+                let request = tonic::Request::new(CloneDeviceRequest {
+                    source: golden_name.clone(),
+                    new_name: clone_name.clone(),
+                    snapshot_name: None,
+                });
+                let response = client
+                    .clone_device(request)
+                    .await
+                    .expect("Failed to clone device")
+                    .into_inner();
+                assert!(response.success, "{:?}", response);
+            }
+
+            let request = tonic::Request::new(ListFakeDevicesRequest {});
+            let response = client
+                .list_fake_devices(request)
+                .await
+                .expect("Failed to list fake devices")
+                .into_inner();
+            assert_eq!(response.device_list.len(), 3);
+
+            let response = client
+                .write(write_request(&clone_names[0], 0xB))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+            for (name, value) in [
+                (&golden_name, 0xA),
+                (&clone_names[0], 0xB),
+                (&clone_names[1], 0xA),
+            ] {
+                let response = client.read(read_request(name)).await.unwrap().into_inner();
+                assert_eq!(response.data.unwrap().data, data(value).data, "{}", name);
+            }
+
+            let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                name: golden_name.clone(),
+            });
+            let response = client
+                .delete_fake_device(request)
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(!response.success);
+            assert_eq!(response.error_code, ErrorCode::DeviceInUse as i32);
+
+            for name in clone_names.into_iter().chain([golden_name]) {
+                let request = tonic::Request::new(DeleteFakeDeviceRequest { name });
+                let response = client
+                    .delete_fake_device(request)
+                    .await
+                    .expect("Failed to delete device");
+                assert!(response.into_inner().success);
+            }
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_report_rebuild_of_mirror() {
+        let addr = "127.0.0.1:8094";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_report_rebuild_of_mirror";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: true,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let mirror_name = testname.to_string();
+            let device_names: Vec<String> = (0..3)
+                .map(|index| format!("{}_{}", testname, index))
+                .collect();
+            for name in &device_names {
+                let request = tonic::Request::new(CreateFakeDeviceRequest {
+                    name: name.clone(),
+                    size: humansize_to_integer("1M").unwrap(),
+                    block_size: 0,
+                    performance_profile: None,
+                });
+                let response = client
+                    .create_fake_device(request)
+                    .await
+                    .expect("Failed to create fake device");
+                assert!(response.into_inner().success);
+            }
+            let request = tonic::Request::new(CreateMirrorRequest {
+                name: mirror_name.clone(),
+                devices: device_names[..2].to_vec(),
+            });
+            let response = client
+                .create_mirror(request)
+                .await
+                .expect("Failed to create mirror")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let data = ministore_proto::Data {
+                data: vec![Bytes::from(vec![0xA; DEFAULT_BLOCK_SIZE])],
+                checksums: Vec::new(),
+            };
+            let request = tonic::Request::new(WriteRequest {
+                name: mirror_name.clone(),
+                lba: 0,
+                num_blocks: 1,
+                data: Some(data.clone()),
+            });
+            let response = client.write(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Writes fail on the first member, which degrades the mirror
+            let request = tonic::Request::new(SetFaultRulesRequest {
+                name: device_names[0].clone(),
+                rules: Some(FaultRules {
+                    error_probability: 1.0,
+                    ..Default::default()
+                }),
+            });
+            let response = client.set_fault_rules(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+            let request = tonic::Request::new(WriteRequest {
+                name: mirror_name.clone(),
+                lba: 0,
+                num_blocks: 1,
+                data: Some(data.clone()),
+            });
+            let response = client.write(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let response = client
+                .list_mirrors(tonic::Request::new(ListMirrorsRequest {}))
+                .await
+                .expect("Failed to list mirrors")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+            assert!(response.mirrors[0].degraded);
+            assert_eq!(
+                response.mirrors[0].members[0].state,
+                MemberState::Failed as i32
+            );
+
+            let request = tonic::Request::new(ReplaceMirrorMemberRequest {
+                name: mirror_name.clone(),
+                old_device: device_names[0].clone(),
+                new_device: device_names[2].clone(),
+            });
+            let response = client
+                .replace_mirror_member(request)
+                .await
+                .expect("Failed to replace mirror member")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let mut rebuilt = false;
+            for _ in 0..100 {
+                let response = client
+                    .list_mirrors(tonic::Request::new(ListMirrorsRequest {}))
+                    .await
+                    .unwrap()
+                    .into_inner();
+                let member = &response.mirrors[0].members[0];
+                assert_eq!(member.name, device_names[2]);
+                if !response.mirrors[0].degraded {
+                    rebuilt = true;
+                    break;
+                }
+                assert_eq!(member.state, MemberState::Rebuilding as i32);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            assert!(rebuilt);
+
+            // Members are only read through the mirror
+            let request = tonic::Request::new(ReadRequest {
+                name: device_names[2].clone(),
+                lba: 0,
+                num_blocks: 1,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert!(!response.success);
+
+            let request = tonic::Request::new(DeleteMirrorRequest {
+                name: mirror_name.clone(),
+            });
+            let response = client
+                .delete_mirror(request)
+                .await
+                .expect("Failed to delete mirror");
+            assert!(response.into_inner().success);
+
+            let request = tonic::Request::new(ReadRequest {
+                name: device_names[2].clone(),
+                lba: 0,
+                num_blocks: 1,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert_eq!(response.data.unwrap().data, data.data);
+            for name in device_names {
+                let request = tonic::Request::new(DeleteFakeDeviceRequest { name });
+                let response = client
+                    .delete_fake_device(request)
+                    .await
+                    .expect("Failed to delete device");
+                assert!(response.into_inner().success);
+            }
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_rebuild_raid5_array_with_failed_member() {
+        let addr = "127.0.0.1:8095";
+        let addr_for_client = format!("http://{}", addr);
+        let testname = "server_should_rebuild_raid5_array_with_failed_member";
+
+        let start_server = tokio::spawn(async move {
+            let config = DeviceConfig {
+                use_fake: true,
+                fake_device_location: format!("fakes/{}", testname),
+                fake_device_type: "SimpleFake".to_string(),
+                protection_mode: ProtectionMode::None,
+                list: Vec::new(),
+                fault_injection: true,
+                performance_profile: DevicePerformanceProfile::default(),
+            };
+            let device_manager = DeviceManager::new(&config)
+                .await
+                .expect("Failed to create device manager");
+            start_grpc_server(addr, GrpcServer::new(device_manager))
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let array_name = testname.to_string();
+            let device_names: Vec<String> = (0..4)
+                .map(|index| format!("{}_{}", testname, index))
+                .collect();
+            for name in &device_names {
+                let request = tonic::Request::new(CreateFakeDeviceRequest {
+                    name: name.clone(),
+                    size: humansize_to_integer("1M").unwrap(),
+                    block_size: 0,
+                    performance_profile: None,
+                });
+                let response = client
+                    .create_fake_device(request)
+                    .await
+                    .expect("Failed to create fake device");
+                assert!(response.into_inner().success);
+            }
+            let request = tonic::Request::new(CreateArrayRequest {
+                name: array_name.clone(),
+                level: RaidLevel::Raid5 as i32,
+                stripe_size: DEFAULT_BLOCK_SIZE as u64 * 2,
+                devices: device_names[..3].to_vec(),
+            });
+            let response = client
+                .create_array(request)
+                .await
+                .expect("Failed to create array")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let data = ministore_proto::Data {
+                data: (0..8)
+                    .map(|value| Bytes::from(vec![value; DEFAULT_BLOCK_SIZE]))
+                    .collect(),
+                checksums: Vec::new(),
+            };
+            let request = tonic::Request::new(WriteRequest {
+                name: array_name.clone(),
+                lba: 0,
+                num_blocks: 8,
+                data: Some(data.clone()),
+            });
+            let response = client.write(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+
+            // Reads of the failed member are reconstructed from the others
+            let request = tonic::Request::new(SetFaultRulesRequest {
+                name: device_names[1].clone(),
+                rules: Some(FaultRules {
+                    error_probability: 1.0,
+                    ..Default::default()
+                }),
+            });
+            let response = client.set_fault_rules(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+            let request = tonic::Request::new(ReadRequest {
+                name: array_name.clone(),
+                lba: 0,
+                num_blocks: 8,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.data.unwrap().data, data.data);
+
+            let response = client
+                .list_arrays(tonic::Request::new(ListArraysRequest {}))
+                .await
+                .expect("Failed to list arrays")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.arrays[0].level, RaidLevel::Raid5 as i32);
+            assert!(response.arrays[0].degraded);
+            assert_eq!(
+                response.arrays[0].failed_devices,
+                vec![device_names[1].clone()]
+            );
+
+            let request = tonic::Request::new(ReplaceArrayMemberRequest {
+                name: array_name.clone(),
+                old_device: device_names[1].clone(),
+                new_device: device_names[3].clone(),
+            });
+            let response = client
+                .replace_array_member(request)
+                .await
+                .expect("Failed to replace array member")
+                .into_inner();
+            assert!(response.success, "{:?}", response);
+
+            let mut rebuilt = false;
+            for _ in 0..100 {
+                let response = client
+                    .list_arrays(tonic::Request::new(ListArraysRequest {}))
+                    .await
+                    .unwrap()
+                    .into_inner();
+                assert_eq!(response.arrays[0].devices[1], device_names[3]);
+                if !response.arrays[0].degraded {
+                    rebuilt = true;
+                    break;
+                }
+                assert_eq!(
+                    response.arrays[0].rebuilding_device,
+                    Some(device_names[3].clone())
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            assert!(rebuilt);
+
+            // The rebuilt member is used to reconstruct the blocks of another failed member
+            let request = tonic::Request::new(SetFaultRulesRequest {
+                name: device_names[0].clone(),
+                rules: Some(FaultRules {
+                    error_probability: 1.0,
+                    ..Default::default()
+                }),
+            });
+            let response = client.set_fault_rules(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+            let request = tonic::Request::new(ReadRequest {
+                name: array_name.clone(),
+                lba: 0,
+                num_blocks: 8,
+            });
+            let response = client.read(request).await.unwrap().into_inner();
+            assert!(response.success, "{:?}", response);
+            assert_eq!(response.data.unwrap().data, data.data);
+
+            let request = tonic::Request::new(DeleteArrayRequest {
+                name: array_name.clone(),
+            });
+            let response = client
+                .delete_array(request)
+                .await
+                .expect("Failed to delete array");
+            assert!(response.into_inner().success);
+            for name in device_names {
+                let request = tonic::Request::new(DeleteFakeDeviceRequest { name });
+                let response = client
+                    .delete_fake_device(request)
+                    .await
+                    .expect("Failed to delete device");
+                assert!(response.into_inner().success);
+            }
         });
 
         test.await.unwrap();
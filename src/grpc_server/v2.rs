@@ -0,0 +1,946 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::Response;
+
+use prost::Message;
+use uuid::Uuid;
+
+use super::ministore_proto::v2::mini_service_server::MiniService;
+use super::ministore_proto::v2::{
+    ClearFaultRulesRequest, ClearFaultRulesResponse, CloneDeviceRequest, CloneDeviceResponse,
+    CreateArrayRequest, CreateArrayResponse, CreateFakeDeviceRequest, CreateFakeDeviceResponse,
+    CreateMirrorRequest, CreateMirrorResponse, CreateSnapshotRequest, CreateSnapshotResponse,
+    CreateVolumeRequest, CreateVolumeResponse, DeleteArrayRequest, DeleteArrayResponse,
+    DeleteFakeDeviceRequest, DeleteFakeDeviceResponse, DeleteMirrorRequest, DeleteMirrorResponse,
+    DeleteSnapshotRequest, DeleteSnapshotResponse, DeleteVolumeRequest, DeleteVolumeResponse,
+    ErrorInfo, FakeDevice, GetScrubStatusRequest, GetScrubStatusResponse, ListArraysRequest,
+    ListArraysResponse, ListFakeDevicesRequest, ListFakeDevicesResponse, ListMirrorsRequest,
+    ListMirrorsResponse, ListSnapshotsRequest, ListSnapshotsResponse, ListVolumesRequest,
+    ListVolumesResponse, ReadRequest, ReadResponse, ReplaceArrayMemberRequest,
+    ReplaceArrayMemberResponse, ReplaceMirrorMemberRequest, ReplaceMirrorMemberResponse,
+    ResizeVolumeRequest, ResizeVolumeResponse, RestoreSnapshotRequest, RestoreSnapshotResponse,
+    SetFaultRulesRequest, SetFaultRulesResponse, Status, StatusRequest, StatusResponse,
+    UnmapRequest, UnmapResponse, WriteRequest, WriteResponse, WriteZeroesRequest,
+    WriteZeroesResponse,
+};
+use super::{
+    from_data_blocks, requested_block_size, scrub_statuses, stream_chunk_blocks, to_array,
+    to_data_blocks, to_fault_rules, to_mirror, to_performance_profile, to_raid_level,
+    to_scrub_status, to_snapshot, to_status_code, to_volume, GrpcServer,
+};
+use crate::async_block_device::faulty_fake_device::FaultRules as DeviceFaultRules;
+use crate::error::MinistoreError;
+
+pub const ERROR_DOMAIN: &str = "ministore";
+
+/// Converts the error into a status whose details is an encoded ErrorInfo
+fn to_status(e: MinistoreError) -> tonic::Status {
+    let error_info = ErrorInfo {
+        reason: <&'static str>::from(&e).to_string(),
+        domain: ERROR_DOMAIN.to_string(),
+        metadata: error_metadata(&e),
+    };
+    tonic::Status::with_details(
+        to_status_code(&e),
+        e.to_string(),
+        error_info.encode_to_vec().into(),
+    )
+}
+
+fn error_metadata(e: &MinistoreError) -> HashMap<String, String> {
+    let metadata: Vec<(&str, String)> = match e {
+        MinistoreError::InvalidLbaRange {
+            lba,
+            num_blocks,
+            device_num_blocks,
+        } => vec![
+            ("lba", lba.to_string()),
+            ("num_blocks", num_blocks.to_string()),
+            ("device_num_blocks", device_num_blocks.to_string()),
+        ],
+        MinistoreError::BufferSizeMismatch {
+            num_blocks,
+            buffer_len,
+        } => vec![
+            ("num_blocks", num_blocks.to_string()),
+            ("buffer_len", buffer_len.to_string()),
+        ],
+        MinistoreError::DeviceNotFound { name }
+        | MinistoreError::DeviceAlreadyExists { name }
+        | MinistoreError::NotFakeDevice { name } => vec![("name", name.clone())],
+        MinistoreError::DeviceInUse { name, user } => {
+            vec![("name", name.clone()), ("user", user.clone())]
+        }
+        MinistoreError::SnapshotNotFound { name, snapshot }
+        | MinistoreError::SnapshotAlreadyExists { name, snapshot } => {
+            vec![("name", name.clone()), ("snapshot", snapshot.clone())]
+        }
+        MinistoreError::UnalignedSize { size, block_size } => vec![
+            ("size", size.to_string()),
+            ("block_size", block_size.to_string()),
+        ],
+        MinistoreError::InvalidDeviceType { device_type } => {
+            vec![("device_type", device_type.clone())]
+        }
+        MinistoreError::ChecksumMismatch {
+            lba,
+            expected,
+            actual,
+        } => vec![
+            ("lba", lba.to_string()),
+            ("expected", expected.to_string()),
+            ("actual", actual.to_string()),
+        ],
+        MinistoreError::ReferenceTagMismatch { lba, reference_tag } => vec![
+            ("lba", lba.to_string()),
+            ("reference_tag", reference_tag.to_string()),
+        ],
+        _ => Vec::new(),
+    };
+    metadata
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+/// Logs the failure of the request and converts it into a status
+fn failed(request_id: Uuid, operation: &str, e: MinistoreError) -> tonic::Status {
+    tracing::warn!("[{}] {} failed, err={}", request_id, operation, e);
+    to_status(e)
+}
+
+#[tonic::async_trait]
+impl MiniService for GrpcServer {
+    type ReadStreamStream = Pin<Box<dyn Stream<Item = Result<ReadResponse, tonic::Status>> + Send>>;
+
+    async fn status(
+        &self,
+        _request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        Ok(Response::new(StatusResponse {
+            status: Status::Ready as i32,
+        }))
+    }
+
+    async fn read(
+        &self,
+        request: tonic::Request<ReadRequest>,
+    ) -> Result<tonic::Response<ReadResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] read, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let blocks = self
+            .device_manager
+            .read(&request.name, request.lba, request.num_blocks)
+            .await
+            .map_err(|e| failed(request_id, "read", e))?;
+
+        Ok(Response::new(ReadResponse {
+            data: Some(from_data_blocks(blocks)),
+        }))
+    }
+
+    async fn write(
+        &self,
+        request: tonic::Request<WriteRequest>,
+    ) -> Result<tonic::Response<WriteResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] write, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        let blocks = self
+            .device_manager
+            .device_info(&request.name)
+            .and_then(|info| {
+                to_data_blocks(
+                    request.data,
+                    request.lba,
+                    request.num_blocks,
+                    info.block_size(),
+                )
+            })
+            .map_err(|e| failed(request_id, "write", e))?;
+        self.device_manager
+            .write(&request.name, request.lba, request.num_blocks, blocks)
+            .await
+            .map_err(|e| failed(request_id, "write", e))?;
+
+        Ok(Response::new(WriteResponse {}))
+    }
+
+    async fn read_stream(
+        &self,
+        request: tonic::Request<ReadRequest>,
+    ) -> Result<tonic::Response<Self::ReadStreamStream>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] read stream, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        // Validate the whole range first, so that the stream does not fail in the middle
+        let chunk_blocks = self
+            .device_manager
+            .device_info(&request.name)
+            .and_then(|info| {
+                info.check_lba_range(request.lba, request.num_blocks)?;
+                Ok(stream_chunk_blocks(info.block_size()))
+            })
+            .map_err(|e| failed(request_id, "read stream", e))?;
+
+        let (tx, rx) = mpsc::channel(4);
+        let device_manager = self.device_manager.clone();
+        tokio::spawn(async move {
+            let end = request.lba + request.num_blocks;
+            let mut lba = request.lba;
+            while lba < end {
+                let num_blocks = std::cmp::min(chunk_blocks, end - lba);
+                let result = device_manager
+                    .read(&request.name, lba, num_blocks)
+                    .await
+                    .map(|blocks| ReadResponse {
+                        data: Some(from_data_blocks(blocks)),
+                    })
+                    .map_err(|e| failed(request_id, "read stream", e));
+                let success = result.is_ok();
+
+                // Stop reading when the client is gone or read failed
+                if tx.send(result).await.is_err() || !success {
+                    return;
+                }
+                lba += num_blocks;
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn write_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<WriteRequest>>,
+    ) -> Result<tonic::Response<WriteResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let mut stream = request.into_inner();
+        tracing::debug!("[{}] write stream", request_id);
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            tracing::trace!(
+                "[{}] write stream chunk, name={}, lba={}, num_blocks={}",
+                request_id,
+                chunk.name,
+                chunk.lba,
+                chunk.num_blocks
+            );
+
+            let blocks = self
+                .device_manager
+                .device_info(&chunk.name)
+                .and_then(|info| {
+                    to_data_blocks(chunk.data, chunk.lba, chunk.num_blocks, info.block_size())
+                })
+                .map_err(|e| failed(request_id, "write stream", e))?;
+            self.device_manager
+                .write(&chunk.name, chunk.lba, chunk.num_blocks, blocks)
+                .await
+                .map_err(|e| failed(request_id, "write stream", e))?;
+        }
+
+        Ok(Response::new(WriteResponse {}))
+    }
+
+    async fn unmap(
+        &self,
+        request: tonic::Request<UnmapRequest>,
+    ) -> Result<tonic::Response<UnmapResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] unmap, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        self.device_manager
+            .unmap(&request.name, request.lba, request.num_blocks)
+            .await
+            .map_err(|e| failed(request_id, "unmap", e))?;
+
+        Ok(Response::new(UnmapResponse {}))
+    }
+
+    async fn write_zeroes(
+        &self,
+        request: tonic::Request<WriteZeroesRequest>,
+    ) -> Result<tonic::Response<WriteZeroesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!(
+            "[{}] write zeroes, name={}, lba={}, num_blocks={}",
+            request_id,
+            request.name,
+            request.lba,
+            request.num_blocks
+        );
+
+        self.device_manager
+            .write_zeroes(&request.name, request.lba, request.num_blocks)
+            .await
+            .map_err(|e| failed(request_id, "write zeroes", e))?;
+
+        Ok(Response::new(WriteZeroesResponse {}))
+    }
+
+    async fn get_scrub_status(
+        &self,
+        request: tonic::Request<GetScrubStatusRequest>,
+    ) -> Result<tonic::Response<GetScrubStatusResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::debug!("[{}] get scrub status, name={}", request_id, request.name);
+
+        let statuses = scrub_statuses(&self.device_manager, &request.name)
+            .map_err(|e| failed(request_id, "get scrub status", e))?;
+
+        Ok(Response::new(GetScrubStatusResponse {
+            statuses: statuses
+                .into_iter()
+                .map(|(name, status)| to_scrub_status(name, status))
+                .collect(),
+        }))
+    }
+
+    async fn create_volume(
+        &self,
+        request: tonic::Request<CreateVolumeRequest>,
+    ) -> Result<tonic::Response<CreateVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create volume, name={}, size={}, devices={:?}",
+            request_id,
+            request.name,
+            request.size,
+            request.devices
+        );
+
+        self.device_manager
+            .create_volume(&request.name, request.size, &request.devices)
+            .await
+            .map_err(|e| failed(request_id, "create volume", e))?;
+
+        Ok(Response::new(CreateVolumeResponse {}))
+    }
+
+    async fn delete_volume(
+        &self,
+        request: tonic::Request<DeleteVolumeRequest>,
+    ) -> Result<tonic::Response<DeleteVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete volume, name={}", request_id, request.name);
+
+        self.device_manager
+            .delete_volume(&request.name)
+            .await
+            .map_err(|e| failed(request_id, "delete volume", e))?;
+
+        Ok(Response::new(DeleteVolumeResponse {}))
+    }
+
+    async fn list_volumes(
+        &self,
+        _request: tonic::Request<ListVolumesRequest>,
+    ) -> Result<tonic::Response<ListVolumesResponse>, tonic::Status> {
+        let volumes = self
+            .device_manager
+            .list_volumes()
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(ListVolumesResponse {
+            volumes: volumes.into_iter().map(to_volume).collect(),
+        }))
+    }
+
+    async fn resize_volume(
+        &self,
+        request: tonic::Request<ResizeVolumeRequest>,
+    ) -> Result<tonic::Response<ResizeVolumeResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] resize volume, name={}, size={}",
+            request_id,
+            request.name,
+            request.size
+        );
+
+        self.device_manager
+            .resize_volume(&request.name, request.size)
+            .await
+            .map_err(|e| failed(request_id, "resize volume", e))?;
+
+        Ok(Response::new(ResizeVolumeResponse {}))
+    }
+
+    async fn create_snapshot(
+        &self,
+        request: tonic::Request<CreateSnapshotRequest>,
+    ) -> Result<tonic::Response<CreateSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        self.device_manager
+            .create_snapshot(&request.name, &request.snapshot_name)
+            .await
+            .map_err(|e| failed(request_id, "create snapshot", e))?;
+
+        Ok(Response::new(CreateSnapshotResponse {}))
+    }
+
+    async fn list_snapshots(
+        &self,
+        request: tonic::Request<ListSnapshotsRequest>,
+    ) -> Result<tonic::Response<ListSnapshotsResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let block_size = self
+            .device_manager
+            .device_info(&request.name)
+            .map_err(to_status)?
+            .block_size();
+        let snapshots = self
+            .device_manager
+            .list_snapshots(&request.name)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(ListSnapshotsResponse {
+            snapshots: snapshots
+                .into_iter()
+                .map(|snapshot| to_snapshot(snapshot, block_size))
+                .collect(),
+        }))
+    }
+
+    async fn restore_snapshot(
+        &self,
+        request: tonic::Request<RestoreSnapshotRequest>,
+    ) -> Result<tonic::Response<RestoreSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] restore snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        self.device_manager
+            .restore_snapshot(&request.name, &request.snapshot_name)
+            .await
+            .map_err(|e| failed(request_id, "restore snapshot", e))?;
+
+        Ok(Response::new(RestoreSnapshotResponse {}))
+    }
+
+    async fn delete_snapshot(
+        &self,
+        request: tonic::Request<DeleteSnapshotRequest>,
+    ) -> Result<tonic::Response<DeleteSnapshotResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] delete snapshot, name={}, snapshot_name={}",
+            request_id,
+            request.name,
+            request.snapshot_name
+        );
+
+        self.device_manager
+            .delete_snapshot(&request.name, &request.snapshot_name)
+            .await
+            .map_err(|e| failed(request_id, "delete snapshot", e))?;
+
+        Ok(Response::new(DeleteSnapshotResponse {}))
+    }
+
+    async fn clone_device(
+        &self,
+        request: tonic::Request<CloneDeviceRequest>,
+    ) -> Result<tonic::Response<CloneDeviceResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] clone device, source={}, new_name={}, snapshot_name={:?}",
+            request_id,
+            request.source,
+            request.new_name,
+            request.snapshot_name
+        );
+
+        self.device_manager
+            .clone_device(
+                &request.source,
+                &request.new_name,
+                request.snapshot_name.as_deref(),
+            )
+            .await
+            .map_err(|e| failed(request_id, "clone device", e))?;
+
+        Ok(Response::new(CloneDeviceResponse {}))
+    }
+
+    async fn create_mirror(
+        &self,
+        request: tonic::Request<CreateMirrorRequest>,
+    ) -> Result<tonic::Response<CreateMirrorResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create mirror, name={}, devices={:?}",
+            request_id,
+            request.name,
+            request.devices
+        );
+
+        self.device_manager
+            .create_mirror(&request.name, &request.devices)
+            .await
+            .map_err(|e| failed(request_id, "create mirror", e))?;
+
+        Ok(Response::new(CreateMirrorResponse {}))
+    }
+
+    async fn delete_mirror(
+        &self,
+        request: tonic::Request<DeleteMirrorRequest>,
+    ) -> Result<tonic::Response<DeleteMirrorResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete mirror, name={}", request_id, request.name);
+
+        self.device_manager
+            .delete_mirror(&request.name)
+            .await
+            .map_err(|e| failed(request_id, "delete mirror", e))?;
+
+        Ok(Response::new(DeleteMirrorResponse {}))
+    }
+
+    async fn list_mirrors(
+        &self,
+        _request: tonic::Request<ListMirrorsRequest>,
+    ) -> Result<tonic::Response<ListMirrorsResponse>, tonic::Status> {
+        let mirrors = self.device_manager.list_mirrors().map_err(to_status)?;
+
+        Ok(Response::new(ListMirrorsResponse {
+            mirrors: mirrors.into_iter().map(to_mirror).collect(),
+        }))
+    }
+
+    async fn replace_mirror_member(
+        &self,
+        request: tonic::Request<ReplaceMirrorMemberRequest>,
+    ) -> Result<tonic::Response<ReplaceMirrorMemberResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] replace mirror member, name={}, old_device={}, new_device={}",
+            request_id,
+            request.name,
+            request.old_device,
+            request.new_device
+        );
+
+        self.device_manager
+            .replace_mirror_member(&request.name, &request.old_device, &request.new_device)
+            .await
+            .map_err(|e| failed(request_id, "replace mirror member", e))?;
+
+        Ok(Response::new(ReplaceMirrorMemberResponse {}))
+    }
+
+    async fn create_array(
+        &self,
+        request: tonic::Request<CreateArrayRequest>,
+    ) -> Result<tonic::Response<CreateArrayResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] create array, name={}, level={}, stripe_size={}, devices={:?}",
+            request_id,
+            request.name,
+            request.level,
+            request.stripe_size,
+            request.devices
+        );
+
+        let level =
+            to_raid_level(request.level).map_err(|e| failed(request_id, "create array", e))?;
+        self.device_manager
+            .create_array(&request.name, level, request.stripe_size, &request.devices)
+            .await
+            .map_err(|e| failed(request_id, "create array", e))?;
+
+        Ok(Response::new(CreateArrayResponse {}))
+    }
+
+    async fn delete_array(
+        &self,
+        request: tonic::Request<DeleteArrayRequest>,
+    ) -> Result<tonic::Response<DeleteArrayResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete array, name={}", request_id, request.name);
+
+        self.device_manager
+            .delete_array(&request.name)
+            .await
+            .map_err(|e| failed(request_id, "delete array", e))?;
+
+        Ok(Response::new(DeleteArrayResponse {}))
+    }
+
+    async fn list_arrays(
+        &self,
+        _request: tonic::Request<ListArraysRequest>,
+    ) -> Result<tonic::Response<ListArraysResponse>, tonic::Status> {
+        let arrays = self.device_manager.list_arrays().map_err(to_status)?;
+
+        Ok(Response::new(ListArraysResponse {
+            arrays: arrays.into_iter().map(to_array).collect(),
+        }))
+    }
+
+    async fn replace_array_member(
+        &self,
+        request: tonic::Request<ReplaceArrayMemberRequest>,
+    ) -> Result<tonic::Response<ReplaceArrayMemberResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] replace array member, name={}, old_device={}, new_device={}",
+            request_id,
+            request.name,
+            request.old_device,
+            request.new_device
+        );
+
+        self.device_manager
+            .replace_array_member(&request.name, &request.old_device, &request.new_device)
+            .await
+            .map_err(|e| failed(request_id, "replace array member", e))?;
+
+        Ok(Response::new(ReplaceArrayMemberResponse {}))
+    }
+
+    async fn create_fake_device(
+        &self,
+        request: tonic::Request<CreateFakeDeviceRequest>,
+    ) -> Result<tonic::Response<CreateFakeDeviceResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        let block_size = requested_block_size(request.block_size);
+        tracing::info!(
+            "[{}] create fake device, name={}, size={}, block_size={}",
+            request_id,
+            request.name,
+            request.size,
+            block_size
+        );
+
+        let performance_profile = to_performance_profile(request.performance_profile)
+            .map_err(|e| failed(request_id, "create fake device", e))?;
+        self.device_manager
+            .create_fake_device_with_profile(
+                &request.name,
+                request.size,
+                block_size,
+                performance_profile,
+            )
+            .await
+            .map_err(|e| failed(request_id, "create fake device", e))?;
+
+        Ok(Response::new(CreateFakeDeviceResponse {}))
+    }
+
+    async fn delete_fake_device(
+        &self,
+        request: tonic::Request<DeleteFakeDeviceRequest>,
+    ) -> Result<tonic::Response<DeleteFakeDeviceResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] delete fake device, name={}", request_id, request.name);
+
+        self.device_manager
+            .delete_fake_device(&request.name)
+            .await
+            .map_err(|e| failed(request_id, "delete fake device", e))?;
+
+        Ok(Response::new(DeleteFakeDeviceResponse {}))
+    }
+
+    async fn list_fake_devices(
+        &self,
+        _request: tonic::Request<ListFakeDevicesRequest>,
+    ) -> Result<tonic::Response<ListFakeDevicesResponse>, tonic::Status> {
+        let devices = self.device_manager.list_fake_devices().map_err(to_status)?;
+
+        Ok(Response::new(ListFakeDevicesResponse {
+            device_list: devices
+                .into_iter()
+                .map(|(name, size, block_size)| FakeDevice {
+                    name,
+                    size,
+                    block_size,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn set_fault_rules(
+        &self,
+        request: tonic::Request<SetFaultRulesRequest>,
+    ) -> Result<tonic::Response<SetFaultRulesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!(
+            "[{}] set fault rules, name={}, rules={:?}",
+            request_id,
+            request.name,
+            request.rules
+        );
+
+        to_fault_rules(request.rules)
+            .and_then(|rules| self.device_manager.set_fault_rules(&request.name, rules))
+            .map_err(|e| failed(request_id, "set fault rules", e))?;
+
+        Ok(Response::new(SetFaultRulesResponse {}))
+    }
+
+    async fn clear_fault_rules(
+        &self,
+        request: tonic::Request<ClearFaultRulesRequest>,
+    ) -> Result<tonic::Response<ClearFaultRulesResponse>, tonic::Status> {
+        let request_id = Uuid::new_v4();
+        let request = request.into_inner();
+        tracing::info!("[{}] clear fault rules, name={}", request_id, request.name);
+
+        self.device_manager
+            .set_fault_rules(&request.name, DeviceFaultRules::default())
+            .map_err(|e| failed(request_id, "clear fault rules", e))?;
+
+        Ok(Response::new(ClearFaultRulesResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::async_block_device::protected_device::ProtectionMode;
+    use crate::async_block_device::shaped_device::PerformanceProfile as DevicePerformanceProfile;
+    use crate::block_device_common::data_type::DEFAULT_BLOCK_SIZE;
+    use crate::config::DeviceConfig;
+    use crate::device_manager::DeviceManager;
+    use crate::grpc_server::ministore_proto::v2::mini_service_client::MiniServiceClient;
+    use crate::grpc_server::ministore_proto::Data;
+    use crate::grpc_server::start_grpc_server;
+    use crate::utils::humansize_to_integer;
+
+    /// Each test uses its own location, so that the device registries do not conflict
+    async fn test_device_manager(testname: &str) -> DeviceManager {
+        let config = DeviceConfig {
+            use_fake: true,
+            fake_device_location: format!("fakes/{}", testname),
+            fake_device_type: "SimpleFake".to_string(),
+            protection_mode: ProtectionMode::None,
+            list: Vec::new(),
+            fault_injection: false,
+            performance_profile: DevicePerformanceProfile::default(),
+        };
+        DeviceManager::new(&config)
+            .await
+            .expect("Failed to create device manager")
+    }
+
+    fn decode_error_info(status: &tonic::Status) -> ErrorInfo {
+        ErrorInfo::decode(status.details()).expect("Failed to decode error info")
+    }
+
+    #[test]
+    fn status_should_carry_error_info() {
+        let status = to_status(MinistoreError::InvalidLbaRange {
+            lba: 10,
+            num_blocks: 20,
+            device_num_blocks: 16,
+        });
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+        let error_info = decode_error_info(&status);
+        assert_eq!(error_info.reason, "INVALID_LBA_RANGE");
+        assert_eq!(error_info.domain, ERROR_DOMAIN);
+        assert_eq!(error_info.metadata["lba"], "10");
+        assert_eq!(error_info.metadata["num_blocks"], "20");
+        assert_eq!(error_info.metadata["device_num_blocks"], "16");
+
+        let status = to_status(MinistoreError::io(
+            "Failed to write",
+            std::io::Error::from(std::io::ErrorKind::StorageFull),
+        ));
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(decode_error_info(&status).reason, "IO");
+    }
+
+    /// Be sure to use different port for each test, so that all tests can be executed in parallel.
+    #[tokio::test]
+    #[traced_test]
+    async fn server_should_reply_with_status_code_for_each_error() {
+        let addr = "127.0.0.1:8087";
+        let addr_for_client = format!("http://{}", addr);
+
+        let start_server = tokio::spawn(async move {
+            let grpc_server = GrpcServer::new(
+                test_device_manager("server_should_reply_with_status_code_for_each_error").await,
+            );
+            start_grpc_server(addr, grpc_server)
+                .await
+                .expect("Failed to start grpc server");
+        });
+
+        let test = tokio::spawn(async move {
+            let mut client = MiniServiceClient::connect(addr_for_client)
+                .await
+                .expect("Failed to start test client");
+
+            let device_name = "server_should_reply_with_status_code_for_each_error".to_string();
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            client
+                .create_fake_device(request)
+                .await
+                .expect("Failed to create fake device");
+
+            // Create the same device again
+            let request = tonic::Request::new(CreateFakeDeviceRequest {
+                name: device_name.clone(),
+                size: humansize_to_integer("1M").unwrap(),
+                block_size: 0,
+                performance_profile: None,
+            });
+            let status = client.create_fake_device(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::AlreadyExists);
+            assert_eq!(decode_error_info(&status).metadata["name"], device_name);
+
+            // Write and read back
+            let blocks = vec![
+                Bytes::from(vec![0xA_u8; DEFAULT_BLOCK_SIZE]),
+                Bytes::from(vec![0xB_u8; DEFAULT_BLOCK_SIZE]),
+            ];
+            let request = tonic::Request::new(WriteRequest {
+                name: device_name.clone(),
+                lba: 10,
+                num_blocks: 2,
+                data: Some(Data {
+                    data: blocks.clone(),
+                    checksums: Vec::new(),
+                }),
+            });
+            client.write(request).await.expect("Failed to write data");
+
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 10,
+                num_blocks: 2,
+            });
+            let response = client.read(request).await.expect("Failed to read data");
+            assert_eq!(response.into_inner().data.unwrap().data, blocks);
+
+            // Write without data
+            let request = tonic::Request::new(WriteRequest {
+                name: device_name.clone(),
+                lba: 10,
+                num_blocks: 1,
+                data: None,
+            });
+            let status = client.write(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert_eq!(decode_error_info(&status).reason, "INVALID_ARGUMENT");
+
+            // Read out of the device
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 256,
+                num_blocks: 1,
+            });
+            let status = client.read(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::OutOfRange);
+            assert_eq!(decode_error_info(&status).reason, "INVALID_LBA_RANGE");
+
+            // Read stream out of the device fails before streaming
+            let request = tonic::Request::new(ReadRequest {
+                name: device_name.clone(),
+                lba: 200,
+                num_blocks: 100,
+            });
+            let status = client.read_stream(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+            // Delete device and access it
+            let request = tonic::Request::new(DeleteFakeDeviceRequest {
+                name: device_name.clone(),
+            });
+            client
+                .delete_fake_device(request)
+                .await
+                .expect("Failed to delete device");
+
+            let request = tonic::Request::new(UnmapRequest {
+                name: device_name.clone(),
+                lba: 0,
+                num_blocks: 1,
+            });
+            let status = client.unmap(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+            assert_eq!(decode_error_info(&status).reason, "DEVICE_NOT_FOUND");
+        });
+
+        test.await.unwrap();
+        start_server.abort();
+    }
+}
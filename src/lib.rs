@@ -1,4 +1,9 @@
 use crate::config::EnvironmentVariables;
+use crate::error::Result;
+use std::sync::Arc;
+
+use crate::grpc_server::start_grpc_server;
+use crate::scrubber::Scrubber;
 use crate::{device_manager::DeviceManager, grpc_server::GrpcServer};
 
 pub mod async_block_device;
@@ -6,11 +11,14 @@ pub mod block_device;
 pub mod block_device_common;
 pub mod config;
 pub mod device_manager;
+pub mod device_registry;
+pub mod error;
 pub mod grpc_server;
+pub mod scrubber;
 pub mod telemetry;
 pub mod utils;
 
-pub async fn start(configs: (&str, EnvironmentVariables)) -> Result<(), String> {
+pub async fn start(configs: (&str, EnvironmentVariables)) -> Result<()> {
     let config = config::get_config(configs.0)?;
 
     tracing::info!("Starting ministore...");
@@ -18,10 +26,15 @@ pub async fn start(configs: (&str, EnvironmentVariables)) -> Result<(), String>
     tracing::info!("environment variables: {:#?}", configs.1);
 
     // Instantiate building blocks
-    todo!();
+    let device_manager = Arc::new(DeviceManager::new(&config.devices).await?);
+    if config.scrub.enabled {
+        Scrubber::new(device_manager.clone(), config.scrub.clone()).start();
+    }
+    let grpc_server = GrpcServer::new(device_manager);
 
     // Run server
-    todo!();
+    let addr = format!("{}:{}", configs.1.server_addr, configs.1.server_port);
+    start_grpc_server(&addr, grpc_server).await?;
 
     Ok(())
 }
@@ -1,8 +1,9 @@
 use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use dotenv::dotenv;
 use ministore::config::EnvironmentVariables;
+use ministore::error::{MinistoreError, Result};
 
-fn main() -> Result<(), String> {
+fn main() -> Result<()> {
     // Parse arguments
     let matches = cli();
     let devel = matches.get_flag("devel");
@@ -19,7 +20,7 @@ fn main() -> Result<(), String> {
     let start_server = async {
         ministore::telemetry::init_tracing(environment_variables.log_level.as_str())?;
         ministore::start((config_str.as_str(), environment_variables)).await?;
-        Ok::<(), String>(())
+        Ok::<(), MinistoreError>(())
     };
 
     // Run server
@@ -35,11 +36,38 @@ fn main() -> Result<(), String> {
 }
 
 fn cli() -> ArgMatches {
-    todo!()
+    Command::new("ministore")
+        .about("A mini storage service")
+        .arg(
+            Arg::new("devel")
+                .short('d')
+                .long("devel")
+                .help("Run ministore in development mode")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .help("Run ministore in test mode with the given config file")
+                .action(ArgAction::Set),
+        )
+        .group(
+            ArgGroup::new("run_mode")
+                .args(["devel", "config"])
+                .required(false),
+        )
+        .get_matches()
 }
 
 fn get_run_mode(devel: bool, test_configfile: Option<&String>) -> RunMode {
-    todo!()
+    if let Some(configfile) = test_configfile {
+        RunMode::Test(configfile.clone())
+    } else if devel {
+        RunMode::Development
+    } else {
+        RunMode::Production
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -51,13 +79,30 @@ pub enum RunMode {
 }
 
 impl RunMode {
-    fn get_config_str(&self) -> Result<String, String> {
-        todo!()
+    fn get_config_str(&self) -> Result<String> {
+        let configfile = match self {
+            RunMode::Development => "config/development.toml",
+            RunMode::Production => "config/production.toml",
+            RunMode::Test(configfile) => configfile.as_str(),
+        };
+
+        std::fs::read_to_string(configfile).map_err(|e| {
+            MinistoreError::io(
+                format!("Failed to read config file, file={}", configfile),
+                e,
+            )
+        })
     }
 }
 
 pub fn get_environment_values() -> EnvironmentVariables {
-    todo!()
+    dotenv().ok();
+
+    EnvironmentVariables {
+        server_addr: std::env::var("MINISTORE_SERVER_ADDR").unwrap_or("127.0.0.1".to_string()),
+        server_port: std::env::var("MINISTORE_SERVER_PORT").unwrap_or("8100".to_string()),
+        log_level: std::env::var("RUST_LOG").unwrap_or("info".to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,346 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task::JoinHandle;
+
+use crate::config::ScrubConfig;
+use crate::device_manager::DeviceManager;
+use crate::error::{MinistoreError, Result};
+
+/// Number of blocks read at once. Only this range is locked against writes while it is read.
+pub const SCRUB_CHUNK_BLOCKS: u64 = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScrubState {
+    #[default]
+    Idle,
+    Scrubbing,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BadRange {
+    pub lba: u64,
+    pub num_blocks: u64,
+    /// Error of the first block in the range
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScrubStatus {
+    pub state: ScrubState,
+    pub scrubbed_blocks: u64,
+    pub total_blocks: u64,
+    pub completed_passes: u64,
+    /// Seconds since the unix epoch
+    pub last_completed_at: Option<u64>,
+    /// Found in the current pass, or in the last pass if idle
+    pub bad_ranges: Vec<BadRange>,
+}
+
+impl ScrubStatus {
+    fn start_pass(&mut self, total_blocks: u64) {
+        self.state = ScrubState::Scrubbing;
+        self.scrubbed_blocks = 0;
+        self.total_blocks = total_blocks;
+        self.bad_ranges.clear();
+    }
+
+    /// Adjacent bad blocks are merged into a range
+    fn add_bad_block(&mut self, lba: u64, reason: String) {
+        match self.bad_ranges.last_mut() {
+            Some(last) if last.lba + last.num_blocks == lba => last.num_blocks += 1,
+            _ => self.bad_ranges.push(BadRange {
+                lba,
+                num_blocks: 1,
+                reason,
+            }),
+        }
+    }
+
+    fn finish_pass(&mut self) {
+        self.state = ScrubState::Idle;
+        self.completed_passes += 1;
+        self.last_completed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok();
+    }
+}
+
+/// Reads every block of every device to find the blocks which cannot be read or whose checksum
+/// does not match. The progress and results are kept in DeviceManager for each device.
+pub struct Scrubber {
+    device_manager: Arc<DeviceManager>,
+    config: ScrubConfig,
+}
+
+impl Scrubber {
+    pub fn new(device_manager: Arc<DeviceManager>, config: ScrubConfig) -> Self {
+        Scrubber {
+            device_manager,
+            config,
+        }
+    }
+
+    /// Scrubs all devices repeatedly until the task is aborted
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                self.scrub_all().await;
+                tokio::time::sleep(Duration::from_secs(self.config.interval_secs)).await;
+            }
+        })
+    }
+
+    pub async fn scrub_all(&self) {
+        let device_names = match self.device_manager.device_names() {
+            Ok(device_names) => device_names,
+            Err(e) => {
+                tracing::warn!("Failed to get devices to scrub, err={}", e);
+                return;
+            }
+        };
+
+        for device_name in device_names {
+            if let Err(e) = self.scrub_device(&device_name).await {
+                tracing::warn!("Failed to scrub device, name={}, err={}", device_name, e);
+            }
+        }
+    }
+
+    async fn scrub_device(&self, device_name: &String) -> Result<()> {
+        let total_blocks = self.device_manager.device_info(device_name)?.num_blocks();
+        self.device_manager
+            .update_scrub_status(device_name, |status| status.start_pass(total_blocks))?;
+        tracing::info!(
+            "Start scrubbing device, name={}, num_blocks={}",
+            device_name,
+            total_blocks
+        );
+
+        let mut lba = 0;
+        while lba < total_blocks {
+            let num_blocks = std::cmp::min(SCRUB_CHUNK_BLOCKS, total_blocks - lba);
+            match self
+                .device_manager
+                .read_for_scrub(device_name, lba, num_blocks)
+                .await
+            {
+                Ok(_) => {}
+                Err(
+                    e
+                    @ (MinistoreError::DeviceNotFound { .. } | MinistoreError::DeviceInUse { .. }),
+                ) => return Err(e),
+                // Read each block again to find which ones are bad
+                Err(_) => self.scrub_each_block(device_name, lba, num_blocks).await?,
+            }
+
+            lba += num_blocks;
+            self.device_manager
+                .update_scrub_status(device_name, |status| status.scrubbed_blocks = lba)?;
+            self.throttle(num_blocks).await;
+        }
+
+        self.device_manager
+            .update_scrub_status(device_name, ScrubStatus::finish_pass)?;
+        tracing::info!("Finished scrubbing device, name={}", device_name);
+        Ok(())
+    }
+
+    async fn scrub_each_block(
+        &self,
+        device_name: &String,
+        lba: u64,
+        num_blocks: u64,
+    ) -> Result<()> {
+        for lba in lba..lba + num_blocks {
+            match self
+                .device_manager
+                .read_for_scrub(device_name, lba, 1)
+                .await
+            {
+                Ok(_) => {}
+                Err(
+                    e
+                    @ (MinistoreError::DeviceNotFound { .. } | MinistoreError::DeviceInUse { .. }),
+                ) => return Err(e),
+                Err(e) => {
+                    tracing::warn!(
+                        "Found bad block, name={}, lba={}, err={}",
+                        device_name,
+                        lba,
+                        e
+                    );
+                    self.device_manager
+                        .update_scrub_status(device_name, |status| {
+                            status.add_bad_block(lba, e.to_string())
+                        })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn throttle(&self, num_blocks: u64) {
+        if self.config.blocks_per_second > 0 {
+            let seconds = num_blocks as f64 / self.config.blocks_per_second as f64;
+            tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_block_device::protected_device::ProtectionMode;
+    use crate::async_block_device::shaped_device::PerformanceProfile;
+    use crate::block_device_common::data_type::{DataBlock, DEFAULT_BLOCK_SIZE};
+    use crate::config::DeviceConfig;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn scrubber_should_find_corrupted_blocks() {
+        let testname = "scrubber_should_find_corrupted_blocks";
+        let config = DeviceConfig {
+            use_fake: true,
+            fake_device_location: testname.to_string(),
+            fake_device_type: "SimpleFake".to_string(),
+            protection_mode: ProtectionMode::Checksum,
+            list: Vec::new(),
+            fault_injection: false,
+            performance_profile: PerformanceProfile::default(),
+        };
+        let device_name = "scrubbed".to_string();
+        let blocks = vec![
+            DataBlock::from(vec![0xAB; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xAC; DEFAULT_BLOCK_SIZE]),
+            DataBlock::from(vec![0xAD; DEFAULT_BLOCK_SIZE]),
+        ];
+        {
+            let device_manager = DeviceManager::new(&config).await.unwrap();
+            device_manager
+                .create_fake_device(
+                    &device_name,
+                    DEFAULT_BLOCK_SIZE as u64 * 200,
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .unwrap();
+            device_manager
+                .write(&device_name, 100, 3, blocks.clone())
+                .await
+                .unwrap();
+            device_manager.flush(&device_name).await.unwrap();
+        }
+
+        // Corrupt the first two blocks in the image
+        let filepath = std::path::Path::new(testname).join(&device_name);
+        let mut image = std::fs::read(&filepath).unwrap();
+        for block in &blocks[..2] {
+            let position = image
+                .windows(DEFAULT_BLOCK_SIZE)
+                .position(|window| window == block.0)
+                .expect("Block should be in the image");
+            image[position] ^= 0xFF;
+        }
+        std::fs::write(&filepath, image).unwrap();
+
+        let device_manager = Arc::new(DeviceManager::new(&config).await.unwrap());
+        assert_eq!(
+            device_manager.scrub_status(&device_name).unwrap(),
+            ScrubStatus::default()
+        );
+
+        let scrubber = Scrubber::new(
+            device_manager.clone(),
+            ScrubConfig {
+                enabled: true,
+                interval_secs: 0,
+                blocks_per_second: 0,
+            },
+        );
+        scrubber.scrub_all().await;
+
+        let status = device_manager.scrub_status(&device_name).unwrap();
+        assert_eq!(status.state, ScrubState::Idle);
+        assert_eq!(status.scrubbed_blocks, 200);
+        assert_eq!(status.total_blocks, 200);
+        assert_eq!(status.completed_passes, 1);
+        assert!(status.last_completed_at.is_some());
+        assert_eq!(status.bad_ranges.len(), 1);
+        assert_eq!(status.bad_ranges[0].lba, 100);
+        assert_eq!(status.bad_ranges[0].num_blocks, 2);
+        assert!(status.bad_ranges[0].reason.contains("Checksum mismatch"));
+
+        device_manager
+            .delete_fake_device(&device_name)
+            .await
+            .unwrap();
+        std::fs::remove_dir(testname).expect("Failed to remove directory");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn scrubber_should_scrub_members_of_volumes() {
+        let testname = "scrubber_should_scrub_members_of_volumes";
+        let config = DeviceConfig {
+            use_fake: true,
+            fake_device_location: testname.to_string(),
+            fake_device_type: "SimpleFake".to_string(),
+            protection_mode: ProtectionMode::Checksum,
+            list: Vec::new(),
+            fault_injection: false,
+            performance_profile: PerformanceProfile::default(),
+        };
+        let device_manager = Arc::new(DeviceManager::new(&config).await.unwrap());
+        let members = vec!["first".to_string(), "second".to_string()];
+        let volume = "volume".to_string();
+        for name in &members {
+            device_manager
+                .create_fake_device(
+                    name,
+                    DEFAULT_BLOCK_SIZE as u64 * 256,
+                    DEFAULT_BLOCK_SIZE as u64,
+                )
+                .await
+                .unwrap();
+        }
+        device_manager
+            .create_volume(&volume, DEFAULT_BLOCK_SIZE as u64 * 256, &members)
+            .await
+            .unwrap();
+        device_manager
+            .write(
+                &volume,
+                0,
+                1,
+                vec![DataBlock::from(vec![0xAB; DEFAULT_BLOCK_SIZE])],
+            )
+            .await
+            .unwrap();
+
+        let scrubber = Scrubber::new(
+            device_manager.clone(),
+            ScrubConfig {
+                enabled: true,
+                interval_secs: 0,
+                blocks_per_second: 0,
+            },
+        );
+        scrubber.scrub_all().await;
+
+        // Members are read by the scrubber, though not by clients
+        for name in members.iter().chain([&volume]) {
+            let status = device_manager.scrub_status(name).unwrap();
+            assert_eq!(status.completed_passes, 1, "name={}", name);
+            assert!(status.bad_ranges.is_empty(), "name={}, {:?}", name, status);
+        }
+
+        device_manager.delete_volume(&volume).await.unwrap();
+        for name in &members {
+            device_manager.delete_fake_device(name).await.unwrap();
+        }
+        std::fs::remove_dir_all(testname).expect("Failed to remove directory");
+    }
+}
@@ -1,7 +1,16 @@
 use tracing::metadata::LevelFilter;
 
-pub fn init_tracing(level: &str) -> Result<(), String> {
-    todo!();
+use crate::error::{MinistoreError, Result};
+
+pub fn init_tracing(level: &str) -> Result<()> {
+    let level_filter = level.parse::<LevelFilter>().map_err(|e| {
+        MinistoreError::invalid_argument(format!("Invalid log level, level={}, err={}", level, e))
+    })?;
+
+    tracing_subscriber::fmt()
+        .with_max_level(level_filter)
+        .try_init()
+        .map_err(|e| MinistoreError::internal(e.to_string()))?;
 
     Ok(())
 }
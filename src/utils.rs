@@ -1,8 +1,15 @@
-pub fn humansize_to_integer(size_str: &str) -> Result<u64, String> {
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::{MinistoreError, Result};
+
+pub fn humansize_to_integer(size_str: &str) -> Result<u64> {
     let size_int = size_str
         .trim_end_matches(char::is_alphabetic)
         .parse::<u64>()
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| {
+            MinistoreError::invalid_argument(format!("Invalid size, size={}, err={}", size_str, e))
+        })?;
     let multiplier = match size_str.chars().last().unwrap_or_default() {
         'k' | 'K' => 1024,
         'm' | 'M' => 1024 * 1024,
@@ -13,33 +20,32 @@ pub fn humansize_to_integer(size_str: &str) -> Result<u64, String> {
     Ok(size_int * multiplier)
 }
 
+/// Syncs the directory of the path, so that a file created or renamed there survives a crash
+pub fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| MinistoreError::io(format!("Failed to sync directory, path={:?}", parent), e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn converting_kmg_should_success() {
-        assert_eq!(humansize_to_integer(&"20k".to_string()).unwrap(), 20 * 1024);
-        assert_eq!(humansize_to_integer(&"20K".to_string()).unwrap(), 20 * 1024);
-
-        assert_eq!(
-            humansize_to_integer(&"10m".to_string()).unwrap(),
-            10 * 1024 * 1024
-        );
-        assert_eq!(
-            humansize_to_integer(&"10M".to_string()).unwrap(),
-            10 * 1024 * 1024
-        );
-
-        assert_eq!(
-            humansize_to_integer(&"6g".to_string()).unwrap(),
-            6 * 1024 * 1024 * 1024
-        );
-        assert_eq!(
-            humansize_to_integer(&"6G".to_string()).unwrap(),
-            6 * 1024 * 1024 * 1024
-        );
-
-        assert_eq!(humansize_to_integer(&"100000".to_string()).unwrap(), 100000);
+        assert_eq!(humansize_to_integer("20k").unwrap(), 20 * 1024);
+        assert_eq!(humansize_to_integer("20K").unwrap(), 20 * 1024);
+
+        assert_eq!(humansize_to_integer("10m").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(humansize_to_integer("10M").unwrap(), 10 * 1024 * 1024);
+
+        assert_eq!(humansize_to_integer("6g").unwrap(), 6 * 1024 * 1024 * 1024);
+        assert_eq!(humansize_to_integer("6G").unwrap(), 6 * 1024 * 1024 * 1024);
+
+        assert_eq!(humansize_to_integer("100000").unwrap(), 100000);
     }
 }
@@ -9,7 +9,7 @@ use ministore::{
     },
 };
 
-/// We will use 127.0.0.1:81** for gRPC server address of integration tests
+// We will use 127.0.0.1:81** for gRPC server address of integration tests
 
 static LOGGER_INITIALIZED: Once = Once::new();
 
@@ -50,7 +50,7 @@ fn test_simple_io_flow_using_simple_fake_devices() {
         let addr = "http://127.0.0.1:8100";
 
         let mut client = loop {
-            if let Ok(client) = MiniServiceClient::connect(addr.clone()).await {
+            if let Ok(client) = MiniServiceClient::connect(addr).await {
                 break client;
             } else {
                 tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
@@ -61,27 +61,30 @@ fn test_simple_io_flow_using_simple_fake_devices() {
         let request = tonic::Request::new(CreateFakeDeviceRequest {
             name: "test_simple_io_flow_using_simple_fake_devices".to_string(),
             size: 4 * 1024 * 32,
+            block_size: 0,
+            performance_profile: None,
         });
         let response = client.create_fake_device(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
 
         // and check if it's created
         let request = tonic::Request::new(ListFakeDevicesRequest {});
         let response = client.list_fake_devices(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
         assert_eq!(response.device_list.len(), 1);
         assert_eq!(
-            response.device_list.get(0).unwrap().name,
+            response.device_list.first().unwrap().name,
             "test_simple_io_flow_using_simple_fake_devices"
         );
 
         // 2. Write some data
         let write_data = ministore_proto::Data {
-            data: vec![vec![7 as u8; 4096], vec![8 as u8; 4096]],
+            data: vec![vec![7_u8; 4096].into(), vec![8_u8; 4096].into()],
+            checksums: Vec::new(),
         };
         let request = tonic::Request::new(WriteRequest {
             name: "test_simple_io_flow_using_simple_fake_devices".to_string(),
@@ -92,7 +95,7 @@ fn test_simple_io_flow_using_simple_fake_devices() {
         let response = client.write(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
 
         // 3. Read the data
         let request = tonic::Request::new(ReadRequest {
@@ -103,8 +106,8 @@ fn test_simple_io_flow_using_simple_fake_devices() {
         let response = client.read(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
-        assert_eq!(response.data.unwrap(), write_data);
+        assert!(response.success, "{:?}", response);
+        assert_eq!(response.data.unwrap().data, write_data.data);
 
         // 4. Delete the device
         let request = tonic::Request::new(DeleteFakeDeviceRequest {
@@ -113,14 +116,14 @@ fn test_simple_io_flow_using_simple_fake_devices() {
         let response = client.delete_fake_device(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
 
         // and check if it's deleted
         let request = tonic::Request::new(ListFakeDevicesRequest {});
         let response = client.list_fake_devices(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
         assert_eq!(response.device_list.len(), 0);
     });
 
@@ -170,7 +173,7 @@ fn test_concurrent_writes() {
         let addr = "http://127.0.0.1:8101";
 
         let mut client = loop {
-            if let Ok(client) = MiniServiceClient::connect(addr.clone()).await {
+            if let Ok(client) = MiniServiceClient::connect(addr).await {
                 break client;
             } else {
                 tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
@@ -181,21 +184,23 @@ fn test_concurrent_writes() {
         let request = tonic::Request::new(CreateFakeDeviceRequest {
             name: "test_concurrent_writes".to_string(),
             size: 4 * 1024 * 32,
+            block_size: 0,
+            performance_profile: None,
         });
         let response = client.create_fake_device(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
 
         // and check if it's created
         let request = tonic::Request::new(ListFakeDevicesRequest {});
         let response = client.list_fake_devices(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
         assert_eq!(response.device_list.len(), 1);
         assert_eq!(
-            response.device_list.get(0).unwrap().name,
+            response.device_list.first().unwrap().name,
             "test_concurrent_writes"
         );
 
@@ -206,7 +211,8 @@ fn test_concurrent_writes() {
             let handle = tokio::spawn(async move {
                 for task in 0..10 {
                     let write_data = ministore_proto::Data {
-                        data: vec![vec![task as u8; 4096]],
+                        data: vec![vec![task as u8; 4096].into()],
+                        checksums: Vec::new(),
                     };
                     let request = tonic::Request::new(WriteRequest {
                         name: "test_concurrent_writes".to_string(),
@@ -216,7 +222,7 @@ fn test_concurrent_writes() {
                     });
 
                     let mut client = loop {
-                        if let Ok(client) = MiniServiceClient::connect(addr.clone()).await {
+                        if let Ok(client) = MiniServiceClient::connect(addr).await {
                             break client;
                         } else {
                             tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
@@ -226,7 +232,7 @@ fn test_concurrent_writes() {
                     let response = client.write(request).await.unwrap();
                     let response = response.into_inner();
 
-                    assert_eq!(response.success, true, "{:?}", response);
+                    assert!(response.success, "{:?}", response);
                 }
             });
 
@@ -244,14 +250,14 @@ fn test_concurrent_writes() {
         let response = client.delete_fake_device(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
 
         // and check if it's deleted
         let request = tonic::Request::new(ListFakeDevicesRequest {});
         let response = client.list_fake_devices(request).await.unwrap();
         let response = response.into_inner();
 
-        assert_eq!(response.success, true, "{:?}", response);
+        assert!(response.success, "{:?}", response);
         assert_eq!(response.device_list.len(), 0);
     });
 